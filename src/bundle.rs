@@ -0,0 +1,295 @@
+//! Archives of documents given as input (`reports.zip`, `books.tar.gz`)
+//!
+//! The supported documents inside are read into memory and processed as if they were
+//! files, under virtual paths such as `reports.zip/2023/q1.docx`. Nothing is unpacked
+//! to disk, and the members are read through the usual resource limits. Only the names
+//! are read up front; an archive's documents are read when the first of them is
+//! processed, and each is dropped once handed out.
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use zip::ZipArchive;
+
+use crate::common::is_safe_archive_path;
+use crate::extract::ExtractOptions;
+use crate::limits::Size;
+
+/// Kind of archive, judged by the file name
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn kind(path: &Path) -> Option<Kind> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(Kind::TarGz)
+    } else if name.ends_with(".tar") {
+        Some(Kind::Tar)
+    } else if name.ends_with(".zip") {
+        Some(Kind::Zip)
+    } else {
+        None
+    }
+}
+
+/// Whether `path` is an archive whose documents are processed, rather than a document.
+/// With `treat_as_zip`, zip files keep being read as image containers instead.
+pub fn is_document_archive(path: &Path, options: &ExtractOptions) -> bool {
+    if options.extractors.handles(path) {
+        return false;
+    }
+    match kind(path) {
+        Some(Kind::Zip) => !options.treat_as_zip,
+        Some(Kind::Tar | Kind::TarGz) => true,
+        None => false,
+    }
+}
+
+/// Data of each member to process, `None` once handed out
+type Slots = Vec<Option<Vec<u8>>>;
+
+/// An archive of documents given as input
+pub struct Archive {
+    path: PathBuf,
+    /// Paths of the documents to process, each with its position among all the supported
+    /// documents of the archive
+    members: Vec<(usize, PathBuf)>,
+    /// Number of supported documents in the archive, including those left out
+    count: usize,
+    /// Data of the members not handed out yet, once read
+    data: Mutex<Option<Result<Slots, String>>>,
+}
+
+impl Archive {
+    /// Lists the supported documents of the archive at `path`, in archive order, leaving
+    /// out those `keep` rejects. Other members are ignored.
+    pub fn open(
+        path: &Path,
+        options: &ExtractOptions,
+        keep: impl Fn(&Path) -> bool,
+    ) -> Result<Self> {
+        let mut members = Vec::new();
+        let mut count = 0;
+        walk(path, options, &mut |name, _| {
+            let member = path.join(name);
+            if keep(&member) {
+                members.push((count, member));
+            }
+            count += 1;
+            Ok(())
+        })?;
+        Ok(Archive {
+            path: path.to_path_buf(),
+            members,
+            count,
+            data: Mutex::new(None),
+        })
+    }
+
+    /// Paths of the documents to process
+    pub fn members(&self) -> impl Iterator<Item = &Path> {
+        self.members.iter().map(|(_, path)| path.as_path())
+    }
+
+    /// Number of supported documents in the archive, including those `keep` left out
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Hands out the data of the member at `index` in [`Archive::members`], reading all
+    /// of them on the first call. Each member can be taken once.
+    pub fn take(&self, index: usize, options: &ExtractOptions) -> Result<Vec<u8>> {
+        let mut data = self.data.lock().unwrap_or_else(|e| e.into_inner());
+        let data = data.get_or_insert_with(|| self.read(options).map_err(|e| format!("{:#}", e)));
+        let slots = data.as_mut().map_err(|e| anyhow::anyhow!("{}", e))?;
+        slots
+            .get_mut(index)
+            .and_then(Option::take)
+            .with_context(|| {
+                format!(
+                    "{} is no longer in the archive",
+                    self.members[index].1.display()
+                )
+            })
+    }
+
+    /// Reads the data of the documents to process
+    fn read(&self, options: &ExtractOptions) -> Result<Slots> {
+        let mut slots = vec![None; self.members.len()];
+        let mut wanted = self
+            .members
+            .iter()
+            .map(|(position, _)| *position)
+            .enumerate()
+            .peekable();
+        let mut position = 0;
+        walk(&self.path, options, &mut |name, reader| {
+            if let Some((index, _)) = wanted.next_if(|(_, wanted)| *wanted == position) {
+                let data = options.limits.read_entry(reader, name).with_context(|| {
+                    format!("Failed to read {} from {}", name, self.path.display())
+                })?;
+                slots[index] = Some(data);
+            }
+            position += 1;
+            Ok(())
+        })?;
+        Ok(slots)
+    }
+}
+
+/// Calls `visit` with the name and data of each supported document of the archive at
+/// `path`, in archive order
+fn walk(path: &Path, options: &ExtractOptions, visit: &mut Visit) -> Result<()> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Failed to open archive: {}", path.display()))?;
+    match kind(path) {
+        Some(Kind::Zip) => walk_zip(file, path, options, visit),
+        Some(Kind::Tar) => walk_tar(file, path, options, visit),
+        Some(Kind::TarGz) => walk_tar(GzDecoder::new(file), path, options, visit),
+        None => anyhow::bail!("Not an archive of documents: {}", path.display()),
+    }
+}
+
+/// Callback of [`walk`], given a member's name and a reader of its data
+type Visit<'a> = dyn FnMut(&str, &mut dyn Read) -> Result<()> + 'a;
+
+fn walk_zip(
+    file: fs::File,
+    path: &Path,
+    options: &ExtractOptions,
+    visit: &mut Visit,
+) -> Result<()> {
+    let mut archive = ZipArchive::new(file)
+        .with_context(|| format!("Failed to read zip archive: {}", path.display()))?;
+    options.limits.check_archive(&mut archive, path)?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let name = entry.name().to_string();
+        if entry.is_file() && is_member_document(&name, options) {
+            visit(&name, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+fn walk_tar(
+    reader: impl Read,
+    path: &Path,
+    options: &ExtractOptions,
+    visit: &mut Visit,
+) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    let entries = archive
+        .entries()
+        .with_context(|| format!("Failed to read tar archive: {}", path.display()))?;
+    let mut total: u64 = 0;
+    for (count, entry) in entries.enumerate() {
+        if count >= options.limits.max_entries {
+            anyhow::bail!(
+                "{} has more than {} entries (--max-entries)",
+                path.display(),
+                options.limits.max_entries
+            );
+        }
+        let mut entry = entry.with_context(|| format!("Failed to read {}", path.display()))?;
+        // Unlike a zip's, a tar's sizes are only known as its entries go by
+        total = total.saturating_add(entry.size());
+        if total > options.limits.max_total_size {
+            anyhow::bail!(
+                "{} inflates to more than the limit of {} (--max-total-size)",
+                path.display(),
+                Size(options.limits.max_total_size)
+            );
+        }
+        let name = entry.path()?.to_string_lossy().replace('\\', "/");
+        if entry.header().entry_type().is_file() && is_member_document(&name, options) {
+            visit(&name, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether an archive member is a supported document that is safe to name
+fn is_member_document(name: &str, options: &ExtractOptions) -> bool {
+    is_safe_archive_path(name) && options.extractors.handles(Path::new(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::temp_dir;
+    use std::io::{Cursor, Write};
+
+    #[test]
+    fn test_read_zip_and_tar_members() {
+        let dir = temp_dir("bundle");
+        let entries: [(&str, &[u8]); 3] = [
+            ("readme.txt", b"not a document"),
+            ("q1/report.docx", b"docx"),
+            ("book.epub", b"epub"),
+        ];
+
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let zip_path = dir.join("docs.zip");
+        fs::write(&zip_path, writer.finish().unwrap().into_inner()).unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data).unwrap();
+        }
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gz.write_all(&builder.into_inner().unwrap()).unwrap();
+        let tar_path = dir.join("docs.tar.gz");
+        fs::write(&tar_path, gz.finish().unwrap()).unwrap();
+
+        let options = ExtractOptions::default();
+        for path in [&zip_path, &tar_path] {
+            assert!(is_document_archive(path, &options));
+            let archive = Archive::open(path, &options, |_| true).unwrap();
+            let names: Vec<_> = archive.members().collect();
+            assert_eq!(names, [path.join("q1/report.docx"), path.join("book.epub")]);
+            assert_eq!(archive.take(1, &options).unwrap(), b"epub");
+            assert_eq!(archive.take(0, &options).unwrap(), b"docx");
+            assert!(archive.take(0, &options).is_err());
+
+            // Members left out are not read
+            let archive = Archive::open(path, &options, |m| m.ends_with("book.epub")).unwrap();
+            assert_eq!(archive.len(), 2);
+            assert_eq!(
+                archive.members().collect::<Vec<_>>(),
+                [path.join("book.epub")]
+            );
+            assert_eq!(archive.take(0, &options).unwrap(), b"epub");
+        }
+
+        let mut options = ExtractOptions::default();
+        options.limits.max_total_size = 20;
+        let err = Archive::open(&tar_path, &options, |_| true).err().unwrap();
+        assert!(err.to_string().contains("--max-total-size"), "{}", err);
+
+        let options = ExtractOptions {
+            treat_as_zip: true,
+            ..Default::default()
+        };
+        assert!(!is_document_archive(&zip_path, &options));
+        assert!(!is_document_archive(Path::new("a.docx"), &options));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,401 @@
+//! Minimal reader for OLE compound files (the container of legacy `.doc` files and of
+//! password-protected Office packages).
+//!
+//! Only what's needed to read streams at the top level of the file is implemented.
+
+use anyhow::Result;
+use std::collections::HashSet;
+
+/// Signature at the start of every compound file
+pub const CFB_SIGNATURE: [u8; 8] = [0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+
+const HEADER_SIZE: usize = 512;
+const DIRECTORY_ENTRY_SIZE: usize = 128;
+/// Number of FAT sector locations stored in the header itself
+const HEADER_DIFAT_ENTRIES: usize = 109;
+const END_OF_CHAIN: u32 = 0xFFFF_FFFE;
+const NO_STREAM: u32 = 0xFFFF_FFFF;
+const ENTRY_STREAM: u8 = 2;
+
+fn le_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+#[derive(Debug)]
+struct DirEntry {
+    name: String,
+    kind: u8,
+    left: u32,
+    right: u32,
+    child: u32,
+    start: u32,
+    size: u64,
+}
+
+/// A parsed compound file borrowing the file contents
+pub struct CompoundFile<'a> {
+    data: &'a [u8],
+    sector_size: usize,
+    mini_sector_size: usize,
+    mini_cutoff: u64,
+    fat: Vec<u32>,
+    minifat: Vec<u32>,
+    mini_stream: Vec<u8>,
+    entries: Vec<DirEntry>,
+}
+
+impl<'a> CompoundFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.get(..8) != Some(&CFB_SIGNATURE[..]) || data.len() < HEADER_SIZE {
+            anyhow::bail!("Not an OLE compound file");
+        }
+        let field = |at| le_u32(data, at).unwrap_or(0);
+        let sector_shift = le_u16(data, 0x1E).unwrap_or(0);
+        let mini_sector_shift = le_u16(data, 0x20).unwrap_or(0);
+        if !matches!(sector_shift, 9 | 12) || mini_sector_shift != 6 {
+            anyhow::bail!("Unsupported compound file sector size");
+        }
+
+        let mut file = CompoundFile {
+            data,
+            sector_size: 1 << sector_shift,
+            mini_sector_size: 1 << mini_sector_shift,
+            mini_cutoff: field(0x38) as u64,
+            fat: Vec::new(),
+            minifat: Vec::new(),
+            mini_stream: Vec::new(),
+            entries: Vec::new(),
+        };
+
+        // The FAT's own sectors are listed in the header, then in a chain of DIFAT sectors.
+        // Neither the FAT nor the DIFAT chain can have more sectors than the file.
+        let sector_count = data.len() / file.sector_size;
+        let fat_count = (field(0x2C) as usize).min(sector_count);
+        let mut fat_sectors: Vec<u32> = (0..HEADER_DIFAT_ENTRIES)
+            .map(|i| field(0x4C + i * 4))
+            .take(fat_count)
+            .collect();
+        let per_difat = file.sector_size / 4 - 1;
+        let mut difat = field(0x44);
+        let mut visited = HashSet::new();
+        for _ in 0..(field(0x48) as usize).min(sector_count) {
+            if fat_sectors.len() >= fat_count || !visited.insert(difat) {
+                break;
+            }
+            let Some(sector) = file.sector(difat) else {
+                break;
+            };
+            fat_sectors.extend((0..per_difat).filter_map(|i| le_u32(sector, i * 4)));
+            difat = le_u32(sector, per_difat * 4).unwrap_or(END_OF_CHAIN);
+        }
+        fat_sectors.truncate(fat_count);
+        for sector in fat_sectors {
+            let sector = file
+                .sector(sector)
+                .ok_or_else(|| anyhow::anyhow!("Compound file FAT is truncated"))?;
+            file.fat
+                .extend(sector.chunks_exact(4).map(|c| le_u32(c, 0).unwrap_or(0)));
+        }
+
+        let directory = file.read_chain(field(0x30), None);
+        file.entries = directory
+            .chunks_exact(DIRECTORY_ENTRY_SIZE)
+            .map(parse_dir_entry)
+            .collect();
+        let root = file
+            .entries
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("Compound file has no root entry"))?;
+        let (mini_start, mini_size) = (root.start, root.size);
+
+        file.minifat = file
+            .read_chain(field(0x3C), None)
+            .chunks_exact(4)
+            .map(|c| le_u32(c, 0).unwrap_or(0))
+            .collect();
+        file.mini_stream = file.read_chain(mini_start, Some(mini_size));
+        Ok(file)
+    }
+
+    fn sector(&self, index: u32) -> Option<&'a [u8]> {
+        let start = (index as usize + 1).checked_mul(self.sector_size)?;
+        self.data.get(start..start + self.sector_size)
+    }
+
+    /// Concatenates a chain of regular sectors, truncated to `size` if given
+    fn read_chain(&self, start: u32, size: Option<u64>) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut sector = start;
+        // A chain can't have more sectors than the file, and can't visit one twice
+        let mut visited = HashSet::new();
+        for _ in 0..self.data.len() / self.sector_size {
+            if sector == END_OF_CHAIN
+                || size.is_some_and(|s| out.len() as u64 >= s)
+                || !visited.insert(sector)
+            {
+                break;
+            }
+            let Some(bytes) = self.sector(sector) else {
+                break;
+            };
+            out.extend_from_slice(bytes);
+            sector = self
+                .fat
+                .get(sector as usize)
+                .copied()
+                .unwrap_or(END_OF_CHAIN);
+        }
+        if let Some(size) = size {
+            out.truncate(size as usize);
+        }
+        out
+    }
+
+    /// Concatenates a chain of mini sectors from the mini stream
+    fn read_mini_chain(&self, start: u32, size: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut sector = start;
+        let mut visited = HashSet::new();
+        for _ in 0..self.mini_stream.len() / self.mini_sector_size {
+            if sector == END_OF_CHAIN || out.len() as u64 >= size || !visited.insert(sector) {
+                break;
+            }
+            let begin = sector as usize * self.mini_sector_size;
+            let Some(bytes) = self.mini_stream.get(begin..begin + self.mini_sector_size) else {
+                break;
+            };
+            out.extend_from_slice(bytes);
+            sector = self
+                .minifat
+                .get(sector as usize)
+                .copied()
+                .unwrap_or(END_OF_CHAIN);
+        }
+        out.truncate(size as usize);
+        out
+    }
+
+    /// Indices of the entries directly inside the storage at `parent`
+    fn children(&self, parent: usize) -> Vec<usize> {
+        let mut children = Vec::new();
+        let mut stack = vec![self.entries[parent].child];
+        while let Some(id) = stack.pop() {
+            // Each entry can only appear once in a well-formed tree
+            if id == NO_STREAM || children.len() >= self.entries.len() {
+                continue;
+            }
+            let Some(entry) = self.entries.get(id as usize) else {
+                continue;
+            };
+            children.push(id as usize);
+            stack.push(entry.left);
+            stack.push(entry.right);
+        }
+        children
+    }
+
+    /// Reads a stream at the top level of the file (names compare case-insensitively)
+    pub fn read_stream(&self, name: &str) -> Option<Vec<u8>> {
+        let entry = self
+            .children(0)
+            .into_iter()
+            .map(|i| &self.entries[i])
+            .find(|e| e.kind == ENTRY_STREAM && e.name.eq_ignore_ascii_case(name))?;
+        Some(if entry.size < self.mini_cutoff {
+            self.read_mini_chain(entry.start, entry.size)
+        } else {
+            self.read_chain(entry.start, Some(entry.size))
+        })
+    }
+}
+
+fn parse_dir_entry(raw: &[u8]) -> DirEntry {
+    let name_len = (le_u16(raw, 64).unwrap_or(0) as usize).min(64);
+    // The length includes the terminating NUL
+    let units: Vec<u16> = raw[..name_len.saturating_sub(2)]
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    DirEntry {
+        name: String::from_utf16_lossy(&units),
+        kind: raw[66],
+        left: le_u32(raw, 68).unwrap_or(NO_STREAM),
+        right: le_u32(raw, 72).unwrap_or(NO_STREAM),
+        child: le_u32(raw, 76).unwrap_or(NO_STREAM),
+        start: le_u32(raw, 116).unwrap_or(END_OF_CHAIN),
+        size: u64::from_le_bytes(raw[120..128].try_into().unwrap_or_default()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Builds a version 3 compound file holding the given top-level streams.
+    /// Streams of 4096 bytes or more are stored in regular sectors, smaller ones in the
+    /// mini stream, so both paths can be exercised.
+    pub(crate) fn compound_file(streams: &[(&str, &[u8])]) -> Vec<u8> {
+        const SECTOR: usize = 512;
+        let mut sectors: Vec<Vec<u8>> = Vec::new();
+        let mut fat: Vec<u32> = Vec::new();
+        let push_chain = |data: &[u8], sectors: &mut Vec<Vec<u8>>, fat: &mut Vec<u32>| {
+            let start = sectors.len() as u32;
+            let chunks: Vec<&[u8]> = data.chunks(SECTOR).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let mut sector = chunk.to_vec();
+                sector.resize(SECTOR, 0);
+                sectors.push(sector);
+                fat.push(if i + 1 == chunks.len() {
+                    END_OF_CHAIN
+                } else {
+                    start + i as u32 + 1
+                });
+            }
+            if chunks.is_empty() {
+                END_OF_CHAIN
+            } else {
+                start
+            }
+        };
+
+        let mut mini_stream = Vec::new();
+        let mut minifat: Vec<u32> = Vec::new();
+        let mut locations = Vec::new();
+        for (_, data) in streams {
+            if data.len() >= 4096 {
+                locations.push(push_chain(data, &mut sectors, &mut fat));
+            } else {
+                let start = (mini_stream.len() / 64) as u32;
+                let count = data.len().div_ceil(64);
+                for i in 0..count {
+                    minifat.push(if i + 1 == count {
+                        END_OF_CHAIN
+                    } else {
+                        start + i as u32 + 1
+                    });
+                }
+                mini_stream.extend_from_slice(data);
+                mini_stream.resize(mini_stream.len().div_ceil(64) * 64, 0);
+                locations.push(start);
+            }
+        }
+        let mini_start = push_chain(&mini_stream, &mut sectors, &mut fat);
+        let minifat_bytes: Vec<u8> = minifat.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let minifat_start = push_chain(&minifat_bytes, &mut sectors, &mut fat);
+
+        let entry = |name: &str, kind: u8, child: u32, right: u32, start: u32, size: u64| {
+            let mut raw = vec![0u8; DIRECTORY_ENTRY_SIZE];
+            let units: Vec<u16> = name.encode_utf16().chain([0]).collect();
+            for (i, unit) in units.iter().enumerate() {
+                raw[i * 2..i * 2 + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            raw[64..66].copy_from_slice(&((units.len() * 2) as u16).to_le_bytes());
+            raw[66] = kind;
+            raw[68..72].copy_from_slice(&NO_STREAM.to_le_bytes());
+            raw[72..76].copy_from_slice(&right.to_le_bytes());
+            raw[76..80].copy_from_slice(&child.to_le_bytes());
+            raw[116..120].copy_from_slice(&start.to_le_bytes());
+            raw[120..128].copy_from_slice(&size.to_le_bytes());
+            raw
+        };
+        // Streams hang off the root as a chain of right siblings
+        let mut directory = entry(
+            "Root Entry",
+            5,
+            if streams.is_empty() { NO_STREAM } else { 1 },
+            NO_STREAM,
+            mini_start,
+            mini_stream.len() as u64,
+        );
+        for (i, ((name, data), start)) in streams.iter().zip(&locations).enumerate() {
+            let right = if i + 1 < streams.len() {
+                i as u32 + 2
+            } else {
+                NO_STREAM
+            };
+            directory.extend(entry(
+                name,
+                ENTRY_STREAM,
+                NO_STREAM,
+                right,
+                *start,
+                data.len() as u64,
+            ));
+        }
+        let directory_start = push_chain(&directory, &mut sectors, &mut fat);
+
+        // One FAT sector is enough for the small files built in tests
+        let fat_sector = sectors.len() as u32;
+        fat.push(0xFFFF_FFFD);
+        let mut fat_bytes: Vec<u8> = fat.iter().flat_map(|v| v.to_le_bytes()).collect();
+        fat_bytes.resize(SECTOR, 0xFF);
+        sectors.push(fat_bytes);
+
+        let mut header = vec![0u8; HEADER_SIZE];
+        header[..8].copy_from_slice(&CFB_SIGNATURE);
+        header[0x1A..0x1C].copy_from_slice(&3u16.to_le_bytes());
+        header[0x1C..0x1E].copy_from_slice(&0xFFFEu16.to_le_bytes());
+        header[0x1E..0x20].copy_from_slice(&9u16.to_le_bytes());
+        header[0x20..0x22].copy_from_slice(&6u16.to_le_bytes());
+        header[0x2C..0x30].copy_from_slice(&1u32.to_le_bytes());
+        header[0x30..0x34].copy_from_slice(&directory_start.to_le_bytes());
+        header[0x38..0x3C].copy_from_slice(&4096u32.to_le_bytes());
+        header[0x3C..0x40].copy_from_slice(&minifat_start.to_le_bytes());
+        header[0x40..0x44].copy_from_slice(&1u32.to_le_bytes());
+        header[0x44..0x48].copy_from_slice(&END_OF_CHAIN.to_le_bytes());
+        for i in 0..HEADER_DIFAT_ENTRIES {
+            let value = if i == 0 { fat_sector } else { NO_STREAM };
+            header[0x4C + i * 4..0x50 + i * 4].copy_from_slice(&value.to_le_bytes());
+        }
+        header.extend(sectors.concat());
+        header
+    }
+
+    #[test]
+    fn test_read_streams() {
+        let big: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let file = compound_file(&[("WordDocument", &big), ("1Table", b"small table")]);
+        let cfb = CompoundFile::parse(&file).unwrap();
+
+        assert_eq!(cfb.read_stream("worddocument").unwrap(), big);
+        assert_eq!(cfb.read_stream("1Table").unwrap(), b"small table");
+        assert_eq!(cfb.read_stream("Data"), None);
+    }
+
+    #[test]
+    fn test_difat_cycle_stops() {
+        // A DIFAT sector that names itself as the next one, with a claimed chain of
+        // u32::MAX sectors and as many FAT sectors
+        let mut file = compound_file(&[("WordDocument", b"text")]);
+        file[0x2C..0x30].copy_from_slice(&u32::MAX.to_le_bytes());
+        file[0x44..0x48].copy_from_slice(&0u32.to_le_bytes());
+        file[0x48..0x4C].copy_from_slice(&u32::MAX.to_le_bytes());
+        let last = HEADER_SIZE + 512 - 4;
+        file[last..last + 4].copy_from_slice(&0u32.to_le_bytes());
+        let _ = CompoundFile::parse(&file);
+    }
+
+    #[test]
+    fn test_chain_cycle_stops() {
+        // A directory sector whose FAT entry points back to itself
+        let mut file = compound_file(&[("WordDocument", b"text")]);
+        let field = |file: &[u8], at| le_u32(file, at).unwrap() as usize;
+        let directory = field(&file, 0x30);
+        let fat = HEADER_SIZE * (field(&file, 0x4C) + 1);
+        file[fat + directory * 4..fat + directory * 4 + 4]
+            .copy_from_slice(&(directory as u32).to_le_bytes());
+
+        let cfb = CompoundFile::parse(&file).unwrap();
+        assert_eq!(cfb.entries.len(), 512 / DIRECTORY_ENTRY_SIZE);
+        assert_eq!(cfb.read_stream("WordDocument").unwrap(), b"text");
+    }
+
+    #[test]
+    fn test_rejects_non_cfb() {
+        assert!(CompoundFile::parse(b"PK\x03\x04").is_err());
+    }
+}
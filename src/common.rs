@@ -1,21 +1,119 @@
 //! Common utilities shared between document processors
 
+use std::cell::Cell;
 use std::collections::HashSet;
 use std::fs;
 use std::io::{self, Write};
 use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-/// Returns the set of supported image file extensions
-pub fn get_supported_extensions() -> HashSet<&'static str> {
-    HashSet::from([
-        "jpg", "jpeg", "png", "gif", "bmp", "tiff", "tif", "svg", "wmf", "emf", "webp", "ico",
-    ])
+use clap::ValueEnum;
+use sha2::{Digest as _, Sha256};
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+use crate::output::ConflictPolicy;
+
+/// Whether per-document progress messages on stdout are suppressed
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Suppresses (or restores) the progress messages processors print to stdout.
+/// Warnings and errors still go to stderr.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+thread_local! {
+    /// Whether this thread is running an extraction that must print nothing at all
+    static SILENT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether progress messages are suppressed
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed) || SILENT.get()
+}
+
+/// Runs `f` with the progress messages of this thread suppressed, whatever `set_quiet`
+/// says for the rest of the program
+pub(crate) fn silently<T>(f: impl FnOnce() -> T) -> T {
+    /// Restores the previous state, also when `f` panics
+    struct Restore(bool);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SILENT.set(self.0);
+        }
+    }
+    let _restore = Restore(SILENT.replace(true));
+    f()
+}
+
+/// Image formats built into the extension and MIME tables
+const BUILT_IN_EXTENSIONS: [&str; 13] = [
+    "jpg", "jpeg", "png", "gif", "bmp", "tiff", "tif", "svg", "wmf", "emf", "webp", "ico", "jp2",
+];
+
+/// An image format the built-in tables do not know, such as AVIF or JPEG XL
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtraFormat {
+    /// Lowercase extension without the dot (`avif`)
+    pub extension: String,
+    /// Lowercase MIME type (`image/avif`)
+    pub mime: String,
 }
 
-/// Normalizes a format string to actual file extensions
-pub fn normalize_format(fmt: &str) -> Vec<&'static str> {
+/// Parses an `--extra-format` value, `EXT=MIME` such as `avif=image/avif`
+pub fn parse_extra_format(value: &str) -> Result<ExtraFormat, String> {
+    let (extension, mime) = value
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' is not of the form EXT=MIME", value))?;
+    let extension = extension.trim().trim_start_matches('.').to_lowercase();
+    let mime = mime.trim().to_lowercase();
+    if extension.is_empty() || !extension.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("'{}' is not a valid extension", extension));
+    }
+    if !mime.starts_with("image/") || mime.len() == "image/".len() {
+        return Err(format!("'{}' is not an image MIME type", mime));
+    }
+    Ok(ExtraFormat { extension, mime })
+}
+
+/// The extra formats of a run that the built-in tables do not already cover; an extra
+/// format with a built-in extension is ignored
+fn new_formats(extra: &[ExtraFormat]) -> impl Iterator<Item = &ExtraFormat> {
+    extra
+        .iter()
+        .filter(|format| !BUILT_IN_EXTENSIONS.contains(&format.extension.as_str()))
+}
+
+/// The extra format among `extra` with the given extension, as its canonical lowercase form
+pub fn extra_format_extension<'a>(extra: &'a [ExtraFormat], extension: &str) -> Option<&'a str> {
+    new_formats(extra)
+        .find(|format| format.extension.eq_ignore_ascii_case(extension))
+        .map(|format| format.extension.as_str())
+}
+
+/// The extension of the extra format among `extra` with the given MIME type
+pub fn extra_format_for_mime<'a>(extra: &'a [ExtraFormat], mime: &str) -> Option<&'a str> {
+    new_formats(extra)
+        .find(|format| format.mime.eq_ignore_ascii_case(mime))
+        .map(|format| format.extension.as_str())
+}
+
+/// Returns the set of supported image file extensions, including the `extra` formats
+pub fn get_supported_extensions(extra: &[ExtraFormat]) -> HashSet<String> {
+    BUILT_IN_EXTENSIONS
+        .iter()
+        .map(|extension| extension.to_string())
+        .chain(new_formats(extra).map(|format| format.extension.clone()))
+        .collect()
+}
+
+/// Normalizes a format string to actual file extensions, which may be one of the `extra`
+/// formats
+pub fn normalize_format(fmt: &str, extra: &[ExtraFormat]) -> Vec<String> {
     let fmt_lower = fmt.trim().to_lowercase();
-    match fmt_lower.as_str() {
+    let extensions = match fmt_lower.as_str() {
         "jpg" | "jpeg" => vec!["jpg", "jpeg"],
         "png" => vec!["png"],
         "gif" => vec!["gif"],
@@ -26,13 +124,171 @@ pub fn normalize_format(fmt: &str) -> Vec<&'static str> {
         "emf" => vec!["emf"],
         "webp" => vec!["webp"],
         "ico" => vec!["ico"],
+        "jp2" | "jpx" => vec!["jp2"],
+        other if extra_format_extension(extra, other).is_some() => {
+            extra_format_extension(extra, other).into_iter().collect()
+        }
         _ => {
             eprintln!("Warning: Unrecognized format '{}' ignored", fmt.trim());
             vec![]
         }
+    };
+    extensions.into_iter().map(str::to_string).collect()
+}
+
+/// Parses a human-readable byte size such as `512`, `10k`, `2M`, `4G` or `1.5GiB`.
+/// Suffixes are binary multiples (1k = 1024 bytes).
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let trimmed = value.trim();
+    let split_at = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, suffix) = trimmed.split_at(split_at);
+
+    let multiplier: u64 = match suffix.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => return Err(format!("'{}' has an unknown size suffix", value)),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("'{}' is not a valid size", value))?;
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parses a duration such as `250ms`, `2s`, `1.5s` or `1m`. A bare number is milliseconds.
+pub fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    let trimmed = value.trim();
+    let split_at = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split_at);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("'{}' is not a valid duration", value))?;
+
+    let seconds = match unit.trim() {
+        "" | "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => return Err(format!("'{}' has an unknown duration unit", value)),
+    };
+    std::time::Duration::try_from_secs_f64(seconds)
+        .map_err(|_| format!("'{}' is not a valid duration", value))
+}
+
+/// Asks the OS to schedule this process's CPU and disk I/O at background priority.
+/// On Linux this selects the idle I/O scheduling class; elsewhere only the CPU
+/// niceness is lowered. Unsupported platforms return an error.
+pub fn lower_process_priority() -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        // SAFETY: setpriority only reads its integer arguments
+        let rc = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) };
+        if rc != 0 {
+            anyhow::bail!(
+                "Failed to lower CPU priority: {}",
+                io::Error::last_os_error()
+            );
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+            const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+            const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+            // SAFETY: ioprio_set takes plain integers and affects only this process
+            let rc = unsafe {
+                libc::syscall(
+                    libc::SYS_ioprio_set,
+                    IOPRIO_WHO_PROCESS,
+                    0,
+                    IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+                )
+            };
+            if rc != 0 {
+                anyhow::bail!(
+                    "Failed to lower I/O priority: {}",
+                    io::Error::last_os_error()
+                );
+            }
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        anyhow::bail!("Lowering process priority is not supported on this platform")
+    }
+}
+
+/// Free space in bytes, available to this user, on the filesystem that holds `path`.
+/// A path that does not exist yet is looked up through its nearest existing ancestor.
+/// Returns None where the platform is not supported or the lookup fails.
+pub fn available_space(path: &Path) -> Option<u64> {
+    let existing = path
+        .ancestors()
+        .map(|dir| match dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir,
+        })
+        .find(|dir| dir.exists())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let path = std::ffi::CString::new(existing.as_os_str().as_bytes()).ok()?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        // SAFETY: `path` is NUL-terminated and `stat` is a valid buffer for the result
+        if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+            return None;
+        }
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = existing;
+        None
     }
 }
 
+/// Serializes password prompts from parallel workers
+static PROMPT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Asks for a password on the terminal, without echoing it where the platform allows
+pub fn prompt_password(prompt: &str) -> anyhow::Result<String> {
+    let _guard = PROMPT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+
+    #[cfg(unix)]
+    // SAFETY: tcgetattr/tcsetattr only read and write the termios struct passed to them
+    let saved = unsafe {
+        let mut term: libc::termios = std::mem::zeroed();
+        (libc::tcgetattr(libc::STDIN_FILENO, &mut term) == 0).then(|| {
+            let saved = term;
+            term.c_lflag &= !libc::ECHO;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term);
+            saved
+        })
+    };
+
+    let mut line = String::new();
+    let read = io::stdin().read_line(&mut line);
+
+    #[cfg(unix)]
+    if let Some(saved) = saved {
+        // SAFETY: restores the settings read above
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved) };
+        eprintln!();
+    }
+
+    read?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
 /// Validates that an archive entry path is safe (no path traversal attacks)
 ///
 /// Returns `true` if the path is safe, `false` if it contains potentially malicious patterns.
@@ -65,6 +321,158 @@ pub fn is_safe_archive_path(name: &str) -> bool {
     true
 }
 
+/// Decodes `%XX` escapes in a URI reference
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && let Some(value) = std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(value);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// A piece of a template parsed by [`parse_placeholders`]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Segment<T> {
+    Literal(String),
+    Placeholder(T),
+}
+
+/// Splits `template` into literal text and `{name}` placeholders, `{{` and `}}` standing
+/// for literal braces. `resolve` turns each name into its value, None meaning the name is
+/// unknown.
+pub(crate) fn parse_placeholders<T>(
+    template: &str,
+    mut resolve: impl FnMut(&str) -> Option<T>,
+) -> Result<Vec<Segment<T>>, String> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("unterminated placeholder '{{{}'", name)),
+                    }
+                }
+                let Some(value) = resolve(&name) else {
+                    return Err(format!("unknown placeholder '{{{}}}'", name));
+                };
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Placeholder(value));
+            }
+            '}' => return Err("unmatched '}' (write '}}' for a literal brace)".to_string()),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+/// A SHA-256 digest, as used for deduplication and integrity checks
+pub type Digest = [u8; 32];
+
+/// Computes the SHA-256 digest of a byte slice
+pub fn sha256(data: &[u8]) -> Digest {
+    Sha256::digest(data).into()
+}
+
+/// Formats a digest as lowercase hex
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes standard base64, ignoring whitespace. Returns None on malformed input.
+pub fn base64_decode(text: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    let mut padding = 0;
+    for &byte in text {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding += 1;
+                continue;
+            }
+            b if b.is_ascii_whitespace() => continue,
+            _ => return None,
+        };
+        // Data after padding is malformed
+        if padding > 0 {
+            return None;
+        }
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Resolves a relative reference found in the archive member at `base` to an archive path.
+/// External URLs and data URIs resolve to None.
+pub fn resolve_reference(base: &str, reference: &str) -> Option<String> {
+    let reference = reference.trim();
+    let reference = reference.split(['#', '?']).next().unwrap_or_default();
+    if reference.is_empty() || reference.contains("://") || reference.starts_with("data:") {
+        return None;
+    }
+
+    let decoded = percent_decode(reference);
+    let mut segments: Vec<&str> = if decoded.starts_with('/') {
+        Vec::new()
+    } else {
+        base.rsplit_once('/')
+            .map(|(dir, _)| dir.split('/').collect())
+            .unwrap_or_default()
+    };
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            s => segments.push(s),
+        }
+    }
+    Some(segments.join("/"))
+}
+
 /// Sanitizes a string to be safe for use as a filename
 /// Replaces invalid characters with underscores
 pub fn sanitize_filename(name: &str) -> String {
@@ -79,6 +487,157 @@ pub fn sanitize_filename(name: &str) -> String {
         .to_string()
 }
 
+/// Longest file or directory name written, in bytes. Most filesystems allow 255; the rest
+/// is left for the `_N` counter that resolves name clashes.
+pub const MAX_NAME_BYTES: usize = 240;
+
+/// Longest extension kept whole when a name is shortened
+const MAX_EXTENSION_BYTES: usize = 16;
+
+/// Device names Windows reserves in every directory, with or without an extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Makes each `/`-separated component of a relative output path safe on every platform,
+/// so output written on Linux can be copied to Windows as it is: names are shortened to
+/// [`MAX_NAME_BYTES`], trailing dots and spaces (which Windows drops) are removed and
+/// reserved device names such as `CON` or `aux.png` get a `_` appended to their stem.
+pub fn safe_relative_path(path: &str) -> String {
+    path.split('/')
+        .map(safe_name)
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Applies [`safe_relative_path`] to a single file or directory name
+fn safe_name(name: &str) -> String {
+    let mut name = truncate_name(name.trim_end_matches(['.', ' ']), MAX_NAME_BYTES);
+    let stem_len = name.find('.').unwrap_or(name.len());
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| name[..stem_len].eq_ignore_ascii_case(reserved))
+    {
+        name.insert(stem_len, '_');
+    }
+    name
+}
+
+/// Shortens `name` to at most `max_bytes`, keeping its extension. The stem is cut at a
+/// word boundary when there is one close to the limit, so "A Very Long Title.jpg" loses
+/// whole words rather than ending mid-word.
+fn truncate_name(name: &str, max_bytes: usize) -> String {
+    if name.len() <= max_bytes {
+        return name.to_string();
+    }
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= MAX_EXTENSION_BYTES + 1 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let mut end = max_bytes.saturating_sub(extension.len()).min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut = &stem[..end];
+    // Only back up to a word boundary if that loses less than a tenth of the name
+    let cut = match cut.rfind([' ', '-', '_', ',']) {
+        Some(boundary) if boundary > 0 && end - boundary <= max_bytes / 10 => &cut[..boundary],
+        _ => cut,
+    };
+    let cut = cut.trim_end_matches([' ', '.', '-', '_', ',']);
+    format!("{}{}", cut, extension)
+}
+
+/// Unicode normalization form applied to output names
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum NameForm {
+    /// Composed characters (`é` as one code point), as Windows and most Linux tools write them
+    Nfc,
+    /// Decomposed characters (`e` followed by a combining accent), as macOS HFS+ stores them
+    Nfd,
+}
+
+/// Normalizes `name` to the given Unicode form, so the same title never produces two
+/// names that look identical but compare differently
+pub fn normalize_name(name: &str, form: NameForm) -> String {
+    match form {
+        NameForm::Nfc => name.nfc().collect(),
+        NameForm::Nfd => name.nfd().collect(),
+    }
+}
+
+/// Transliterates `name` to printable ASCII for `--ascii-names`. Accents are dropped
+/// (`Brontë` becomes `Bronte`), compatibility characters are decomposed (`ﬁ`, full-width
+/// digits), typographic quotes and dashes become `'` and `-`, and letters without a
+/// decomposition are spelled out (`ß` as `ss`, `Æ` as `AE`). Characters with no Latin
+/// equivalent, such as CJK ideographs, are replaced, each run by a single `_`.
+pub fn ascii_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut replaced = false;
+    for c in name.nfkd().filter(|c| !is_combining_mark(*c)) {
+        let text = match c {
+            c if c.is_ascii() && !c.is_ascii_control() => {
+                out.push(c);
+                replaced = false;
+                continue;
+            }
+            'ß' => "ss",
+            'æ' => "ae",
+            'Æ' => "AE",
+            'œ' => "oe",
+            'Œ' => "OE",
+            'ø' => "o",
+            'Ø' => "O",
+            'ł' => "l",
+            'Ł' => "L",
+            'đ' | 'ð' => "d",
+            'Đ' | 'Ð' => "D",
+            'þ' => "th",
+            'Þ' => "Th",
+            'ı' => "i",
+            '‘' | '’' | '‚' | '‛' | '“' | '”' | '„' | '«' | '»' => "'",
+            '‐' | '‑' | '‒' | '–' | '—' | '―' | '−' => "-",
+            _ => {
+                if !replaced {
+                    out.push('_');
+                    replaced = true;
+                }
+                continue;
+            }
+        };
+        out.push_str(text);
+        replaced = false;
+    }
+    out
+}
+
+/// Turns an absolute path into a Windows extended-length path (`\\?\C:\...` or
+/// `\\?\UNC\server\share\...`), which is not limited to 260 characters. Paths that
+/// already have the prefix are returned unchanged. Only meaningful on Windows, where the
+/// path must be absolute and use backslashes.
+pub fn extended_length_path(path: &str) -> String {
+    if path.starts_with(r"\\?\") {
+        path.to_string()
+    } else if let Some(share) = path.strip_prefix(r"\\") {
+        format!(r"\\?\UNC\{}", share)
+    } else {
+        format!(r"\\?\{}", path)
+    }
+}
+
+/// Returns `dir` as an extended-length path on Windows, so paths below it may exceed
+/// MAX_PATH. Elsewhere, and if the directory cannot be made absolute, it is returned
+/// as it is.
+pub fn long_path_dir(dir: &Path) -> std::path::PathBuf {
+    #[cfg(windows)]
+    if let Ok(absolute) = std::path::absolute(dir) {
+        return std::path::PathBuf::from(extended_length_path(&absolute.to_string_lossy()));
+    }
+    dir.to_path_buf()
+}
+
 /// Represents an image file found within an archive, pending extraction.
 #[derive(Debug, Clone)]
 pub struct ImageToExtract {
@@ -88,64 +647,342 @@ pub struct ImageToExtract {
     pub extension: String,
 }
 
-/// Generates a unique output path, appending a counter if the file already exists
-pub fn get_unique_output_path(
-    output_base_dir: &Path,
+/// Resolves the predefined XML entities and numeric character references
+pub fn unescape_xml(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';').filter(|&end| end <= 10) else {
+            out.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let entity = &rest[1..end];
+        let resolved = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+        match resolved {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Descriptive metadata of a document, as far as its format records it.
+/// Series, publisher, language and ISBN only come from ebooks.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DocumentMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub series: Option<String>,
+    /// Position in the series as recorded, e.g. `4`, `4.0` or `4.5`
+    pub series_index: Option<String>,
+    pub publisher: Option<String>,
+    /// Language tag, e.g. `en-US`
+    pub language: Option<String>,
+    /// ISBN as digits (and a final `X`), see [`normalize_isbn`]
+    pub isbn: Option<String>,
+}
+
+/// Filter on document metadata (`--filter-title`, `--filter-author`, ...).
+/// Each set criterion is a case-insensitive substring match, except that a language
+/// matches its regional variants (`en` matches `en-GB`) and ISBNs are compared without
+/// hyphens. Documents that don't record the field don't match.
+#[derive(Debug, Default, Clone)]
+pub struct DocumentFilter {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub series: Option<String>,
+    pub publisher: Option<String>,
+    pub language: Option<String>,
+    pub isbn: Option<String>,
+}
+
+impl DocumentFilter {
+    /// Returns true if no filter criteria are set
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.author.is_none()
+            && self.subject.is_none()
+            && self.series.is_none()
+            && self.publisher.is_none()
+            && self.language.is_none()
+            && self.isbn.is_none()
+    }
+
+    /// Whether a document with `metadata` passes the filter
+    pub fn matches(&self, metadata: &DocumentMetadata) -> bool {
+        let field_matches = |filter: &Option<String>, value: &Option<String>| {
+            filter.as_ref().is_none_or(|f| {
+                value
+                    .as_ref()
+                    .is_some_and(|v| v.to_lowercase().contains(&f.to_lowercase()))
+            })
+        };
+        let language_matches = self.language.as_ref().is_none_or(|f| {
+            metadata.language.as_ref().is_some_and(|v| {
+                let (v, f) = (v.to_lowercase(), f.to_lowercase());
+                v == f || v.starts_with(&format!("{}-", f))
+            })
+        });
+        let isbn_matches = self.isbn.as_ref().is_none_or(|f| {
+            let f = normalize_isbn(f);
+            metadata
+                .isbn
+                .as_ref()
+                .is_some_and(|v| !f.is_empty() && normalize_isbn(v).contains(&f))
+        });
+        field_matches(&self.title, &metadata.title)
+            && field_matches(&self.author, &metadata.author)
+            && field_matches(&self.subject, &metadata.subject)
+            && field_matches(&self.series, &metadata.series)
+            && field_matches(&self.publisher, &metadata.publisher)
+            && language_matches
+            && isbn_matches
+    }
+}
+
+/// Reduces an ISBN to its digits and check character, dropping hyphens, spaces and
+/// prefixes like `urn:isbn:`
+pub fn normalize_isbn(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == 'x' || *c == 'X')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Reads the Dublin Core title, creator and subject of an OOXML `docProps/core.xml` or
+/// ODF `meta.xml`. ODF's `meta:initial-creator` stands in for a missing creator.
+pub fn dublin_core_metadata(xml: &str) -> DocumentMetadata {
+    let element = |name: &str| {
+        let start = xml.find(&format!("<{}", name))?;
+        let open_end = start + xml[start..].find('>')?;
+        if xml[..open_end].ends_with('/') {
+            return None;
+        }
+        let close = open_end + xml[open_end..].find(&format!("</{}>", name))?;
+        Some(unescape_xml(xml[open_end + 1..close].trim())).filter(|text| !text.is_empty())
+    };
+    DocumentMetadata {
+        title: element("dc:title"),
+        author: element("dc:creator").or_else(|| element("meta:initial-creator")),
+        subject: element("dc:subject"),
+        ..Default::default()
+    }
+}
+
+/// Lowercase extension of an archive entry or resource path, or "" if it has none
+pub fn declared_extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Formats a position in a series for file names, with the whole part zero-padded to
+/// `width` digits: `4.0` becomes `04` and `4.5` becomes `04.5` for a width of 2.
+/// Positions that are not numbers are kept as they are. None if `index` is blank.
+pub fn format_series_index(index: &str, width: usize) -> Option<String> {
+    let index = index.trim();
+    if index.is_empty() {
+        return None;
+    }
+    let (whole, fraction) = index.split_once('.').unwrap_or((index, ""));
+    let fraction = fraction.trim_end_matches('0');
+    match whole.parse::<u64>() {
+        Ok(whole) if fraction.bytes().all(|b| b.is_ascii_digit()) => {
+            let mut formatted = format!("{:0width$}", whole, width = width);
+            if !fraction.is_empty() {
+                formatted = format!("{}.{}", formatted, fraction);
+            }
+            Some(formatted)
+        }
+        _ => Some(index.to_string()),
+    }
+}
+
+/// How images are numbered in output names
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Numbering {
+    /// Zero-pad numbers to at least this many digits, so names sort naturally
+    pub pad_width: usize,
+    /// Number of the first image of a document
+    pub start_index: usize,
+}
+
+impl Default for Numbering {
+    fn default() -> Self {
+        Self {
+            pad_width: 0,
+            start_index: 1,
+        }
+    }
+}
+
+impl Numbering {
+    /// Number of the image at 0-based `seq_index`, before padding
+    pub fn number(&self, seq_index: usize) -> usize {
+        self.start_index + seq_index
+    }
+
+    /// Padded number of the image at 0-based `seq_index`
+    pub fn format(&self, seq_index: usize) -> String {
+        format!("{:0width$}", self.number(seq_index), width = self.pad_width)
+    }
+}
+
+/// Builds the output filename for an image: `{base}_{n}.{ext}`, or `{base}.{ext}` if it is the only one
+pub fn numbered_filename(
     base_name: &str,
     seq_index: usize,
     total_images: usize,
     extension: &str,
-) -> anyhow::Result<std::path::PathBuf> {
-    let output_filename = if total_images > 1 {
-        format!("{}_{}.{}", base_name, seq_index + 1, extension)
+    numbering: Numbering,
+) -> String {
+    if total_images > 1 {
+        format!(
+            "{}_{}.{}",
+            base_name,
+            numbering.format(seq_index),
+            extension
+        )
     } else {
         format!("{}.{}", base_name, extension)
-    };
+    }
+}
 
-    let mut output_path = output_base_dir.join(output_filename);
+/// Returns `filename` if it is free, otherwise the first `{stem}_{n}.{ext}` variant
+/// for which `is_taken` returns false
+pub fn next_available_name(
+    filename: &str,
+    is_taken: impl Fn(&str) -> bool,
+) -> anyhow::Result<String> {
+    if !is_taken(filename) {
+        return Ok(filename.to_string());
+    }
+
+    // Keep any `dir/` prefix; only the file name gets the counter
+    let (dir_prefix, filename) = match filename.rfind('/') {
+        Some(slash) => filename.split_at(slash + 1),
+        None => ("", filename),
+    };
+    let path = Path::new(filename);
+    let base_stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let base_ext = path
+        .extension()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
 
     // Counter-based approach to avoid infinite loops and produce cleaner filenames
-    if output_path.exists() {
-        let base_stem = output_path
-            .file_stem()
-            .map(|s| s.to_string_lossy().to_string())
-            .unwrap_or_default();
-        let base_ext = output_path
-            .extension()
-            .map(|s| s.to_string_lossy().to_string())
-            .unwrap_or_default();
-
-        let mut counter = 0u32;
-        const MAX_ATTEMPTS: u32 = 1000;
-
-        while output_path.exists() {
-            counter += 1;
-            if counter > MAX_ATTEMPTS {
-                anyhow::bail!(
-                    "Could not find unique filename after {} attempts for {}",
-                    MAX_ATTEMPTS,
-                    base_stem
-                );
+    const MAX_ATTEMPTS: u32 = 1000;
+    for counter in 1..=MAX_ATTEMPTS {
+        let candidate = if base_ext.is_empty() {
+            format!("{}{}_{}", dir_prefix, base_stem, counter)
+        } else {
+            format!("{}{}_{}.{}", dir_prefix, base_stem, counter, base_ext)
+        };
+        if !is_taken(&candidate) {
+            return Ok(candidate);
+        }
+    }
+
+    anyhow::bail!(
+        "Could not find unique filename after {} attempts for {}",
+        MAX_ATTEMPTS,
+        base_stem
+    )
+}
+
+/// Generates the output path for `output_filename` (which may contain `/`-separated
+/// subdirectories). Paths `is_claimed` reports as reserved by this run always get a
+/// counter appended; a file left by an earlier run is handled by `on_conflict`
+/// (renamed around, reused for overwriting or skipping, or an error).
+pub fn get_unique_output_path(
+    output_base_dir: &Path,
+    output_filename: &str,
+    on_conflict: ConflictPolicy,
+    is_claimed: impl Fn(&Path) -> bool,
+) -> anyhow::Result<std::path::PathBuf> {
+    let filename = match on_conflict {
+        ConflictPolicy::Rename => next_available_name(output_filename, |name| {
+            let path = output_base_dir.join(name);
+            path.exists() || is_claimed(&path)
+        })?,
+        ConflictPolicy::Overwrite | ConflictPolicy::Skip => {
+            next_available_name(output_filename, |name| {
+                is_claimed(&output_base_dir.join(name))
+            })?
+        }
+        ConflictPolicy::Error => {
+            let filename = next_available_name(output_filename, |name| {
+                is_claimed(&output_base_dir.join(name))
+            })?;
+            let path = output_base_dir.join(&filename);
+            if path.exists() {
+                anyhow::bail!("Output file already exists: {}", path.display());
             }
-            let new_filename = if base_ext.is_empty() {
-                format!("{}_{}", base_stem, counter)
-            } else {
-                format!("{}_{}.{}", base_stem, counter, base_ext)
-            };
-            output_path.set_file_name(new_filename);
+            filename
         }
+    };
+    Ok(output_base_dir.join(filename))
+}
+
+/// Creates (or truncates) an output file.
+/// On Unix, `mode` sets the permission bits of a newly created file (subject to the umask).
+fn create_output_file(output_path: &Path, mode: Option<u32>) -> anyhow::Result<fs::File> {
+    use anyhow::Context;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
     }
+    #[cfg(not(unix))]
+    let _ = mode;
 
-    Ok(output_path)
+    options
+        .open(output_path)
+        .with_context(|| format!("Failed to create output file: {}", output_path.display()))
 }
 
-/// Writes image data to a file
-pub fn write_image_to_file(output_path: &Path, data: &[u8]) -> anyhow::Result<()> {
+/// Writes image data to a file.
+/// On Unix, `mode` sets the permission bits of a newly created file (subject to the umask).
+pub fn write_image_to_file(
+    output_path: &Path,
+    data: &[u8],
+    mode: Option<u32>,
+) -> anyhow::Result<()> {
     use anyhow::Context;
 
-    let outfile = fs::File::create(output_path)
-        .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
-    let mut outfile = io::BufWriter::new(outfile);
+    let mut outfile = io::BufWriter::new(create_output_file(output_path, mode)?);
 
     outfile
         .write_all(data)
@@ -158,9 +995,64 @@ pub fn write_image_to_file(output_path: &Path, data: &[u8]) -> anyhow::Result<()
     Ok(())
 }
 
+/// Streams image data from `reader` into a file without buffering it in memory.
+/// Returns the number of bytes written.
+pub fn write_reader_to_file(
+    output_path: &Path,
+    reader: &mut dyn io::Read,
+    mode: Option<u32>,
+) -> anyhow::Result<u64> {
+    use anyhow::Context;
+
+    let mut outfile = io::BufWriter::new(create_output_file(output_path, mode)?);
+
+    let written = io::copy(reader, &mut outfile)
+        .with_context(|| format!("Failed to write image data to {}", output_path.display()))?;
+
+    outfile
+        .flush()
+        .with_context(|| format!("Failed to flush data to {}", output_path.display()))?;
+
+    Ok(written)
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use std::io::{Cursor, Write};
+    use std::path::PathBuf;
+
+    /// A fresh, empty temporary directory for the test called `name`
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "word-image-extractor-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A zip archive holding `entries`, e.g. a minimal OOXML package
+    pub(crate) fn zip_bytes(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            writer
+                .start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_silently_quiets_only_while_running() {
+        let outer = is_quiet();
+        assert!(silently(is_quiet));
+        assert!(silently(|| silently(is_quiet)));
+        assert_eq!(is_quiet(), outer);
+    }
 
     #[test]
     fn test_sanitize_filename() {
@@ -173,23 +1065,105 @@ mod tests {
         assert_eq!(sanitize_filename("  Trimmed  "), "Trimmed");
     }
 
+    #[test]
+    fn test_safe_relative_path() {
+        assert_eq!(
+            safe_relative_path("Author/Title_1.jpg"),
+            "Author/Title_1.jpg"
+        );
+        assert_eq!(safe_relative_path("con.png"), "con_.png");
+        assert_eq!(safe_relative_path("AUX/LPT1"), "AUX_/LPT1_");
+        assert_eq!(safe_relative_path("Console.png"), "Console.png");
+        assert_eq!(safe_relative_path("Vol. 2. /x..png"), "Vol. 2/x..png");
+        assert_eq!(safe_relative_path("../a"), "a");
+
+        let title = "word ".repeat(60);
+        let name = safe_relative_path(&format!("{}1.jpeg", title));
+        assert!(name.len() <= MAX_NAME_BYTES);
+        assert!(name.starts_with("word word") && name.ends_with("word.jpeg"));
+
+        // Multi-byte characters are never split
+        let name = safe_relative_path(&format!("{}.png", "é".repeat(200)));
+        assert_eq!(name, format!("{}.png", "é".repeat(118)));
+    }
+
+    #[test]
+    fn test_unicode_names() {
+        let composed = "Bront\u{eb}";
+        let decomposed = "Bronte\u{308}";
+        assert_eq!(normalize_name(decomposed, NameForm::Nfc), composed);
+        assert_eq!(normalize_name(composed, NameForm::Nfd), decomposed);
+
+        assert_eq!(ascii_name(composed), "Bronte");
+        assert_eq!(ascii_name(decomposed), "Bronte");
+        assert_eq!(ascii_name("Straße – „Ærø“"), "Strasse - 'AEro'");
+        assert_eq!(ascii_name("ﬁle１"), "file1");
+        assert_eq!(ascii_name("村上春樹 - ノルウェイの森.jpg"), "_ - _.jpg");
+    }
+
+    #[test]
+    fn test_extended_length_path() {
+        assert_eq!(
+            extended_length_path(r"C:\Users\me\images"),
+            r"\\?\C:\Users\me\images"
+        );
+        assert_eq!(
+            extended_length_path(r"\\nas\share\images"),
+            r"\\?\UNC\nas\share\images"
+        );
+        assert_eq!(extended_length_path(r"\\?\D:\x"), r"\\?\D:\x");
+        #[cfg(not(windows))]
+        assert_eq!(long_path_dir(Path::new("out")), Path::new("out"));
+    }
+
     #[test]
     fn test_normalize_format() {
-        assert_eq!(normalize_format("jpg"), vec!["jpg", "jpeg"]);
-        assert_eq!(normalize_format("JPEG"), vec!["jpg", "jpeg"]);
-        assert_eq!(normalize_format("png"), vec!["png"]);
-        assert_eq!(normalize_format("unknown").len(), 0);
+        assert_eq!(normalize_format("jpg", &[]), vec!["jpg", "jpeg"]);
+        assert_eq!(normalize_format("JPEG", &[]), vec!["jpg", "jpeg"]);
+        assert_eq!(normalize_format("png", &[]), vec!["png"]);
+        assert_eq!(normalize_format("unknown", &[]).len(), 0);
     }
 
     #[test]
     fn test_get_supported_extensions() {
-        let exts = get_supported_extensions();
+        let exts = get_supported_extensions(&[]);
         assert!(exts.contains("jpg"));
         assert!(exts.contains("png"));
         assert!(exts.contains("gif"));
         assert!(!exts.contains("pdf"));
     }
 
+    #[test]
+    fn test_extra_formats() {
+        assert!(parse_extra_format("avif").is_err());
+        assert!(parse_extra_format("a.vif=image/avif").is_err());
+        assert!(parse_extra_format("avif=video/avif").is_err());
+        // Built-in formats are not taken over
+        let extra = [parse_extra_format("png=image/x-png").unwrap()];
+        assert_eq!(extra_format_for_mime(&extra, "image/x-png"), None);
+        assert_eq!(get_supported_extensions(&extra).len(), 13);
+
+        let extra = [parse_extra_format(".JXL=Image/JXL").unwrap()];
+        assert!(get_supported_extensions(&extra).contains("jxl"));
+        assert!(!get_supported_extensions(&[]).contains("jxl"));
+        assert_eq!(normalize_format(" jxl ", &extra), vec!["jxl"]);
+        assert!(normalize_format("jxl", &[]).is_empty());
+        assert_eq!(extra_format_for_mime(&extra, "image/jxl"), Some("jxl"));
+        assert_eq!(
+            crate::epub::mime_to_extension("image/jxl", &extra).as_deref(),
+            Some("jxl")
+        );
+        assert_eq!(crate::epub::mime_to_extension("image/jxl", &[]), None);
+        assert_eq!(
+            crate::sniff::format_mismatch("jxl", b"\xff\x0a", &extra),
+            None
+        );
+        assert_eq!(
+            crate::sniff::format_mismatch("jxl", b"\xff\x0a", &[]),
+            Some("unknown")
+        );
+    }
+
     #[test]
     fn test_is_safe_archive_path_valid() {
         assert!(is_safe_archive_path("word/media/image1.png"));
@@ -226,4 +1200,195 @@ mod tests {
         assert!(!is_safe_archive_path("file.txt::$DATA"));
         assert!(!is_safe_archive_path("file::stream"));
     }
+
+    #[test]
+    fn test_numbered_filename() {
+        let numbering = Numbering::default();
+        assert_eq!(numbered_filename("doc", 0, 1, "png", numbering), "doc.png");
+        assert_eq!(
+            numbered_filename("doc", 2, 5, "gif", numbering),
+            "doc_3.gif"
+        );
+        let numbering = Numbering {
+            pad_width: 3,
+            start_index: 0,
+        };
+        assert_eq!(
+            numbered_filename("doc", 9, 12, "gif", numbering),
+            "doc_009.gif"
+        );
+        assert_eq!(numbered_filename("doc", 0, 1, "png", numbering), "doc.png");
+    }
+
+    #[test]
+    fn test_format_series_index() {
+        assert_eq!(format_series_index("4", 2).as_deref(), Some("04"));
+        assert_eq!(format_series_index(" 4.0 ", 2).as_deref(), Some("04"));
+        assert_eq!(format_series_index("4.50", 2).as_deref(), Some("04.5"));
+        assert_eq!(format_series_index("12", 0).as_deref(), Some("12"));
+        assert_eq!(format_series_index("IV", 2).as_deref(), Some("IV"));
+        assert_eq!(format_series_index("  ", 2), None);
+    }
+
+    #[test]
+    fn test_next_available_name() {
+        let taken = ["doc.png", "doc_1.png"];
+        assert_eq!(
+            next_available_name("doc.png", |n| taken.contains(&n)).unwrap(),
+            "doc_2.png"
+        );
+        assert_eq!(
+            next_available_name("other.png", |n| taken.contains(&n)).unwrap(),
+            "other.png"
+        );
+        assert_eq!(
+            next_available_name("a.b/doc.png", |n| n == "a.b/doc.png").unwrap(),
+            "a.b/doc_1.png"
+        );
+    }
+
+    #[test]
+    fn test_parse_placeholders() {
+        let resolve = |name: &str| (name == "n").then_some(7);
+        assert_eq!(
+            parse_placeholders("{{a}} {n}x{n}", resolve),
+            Ok(vec![
+                Segment::Literal("{a} ".to_string()),
+                Segment::Placeholder(7),
+                Segment::Literal("x".to_string()),
+                Segment::Placeholder(7),
+            ])
+        );
+        assert_eq!(parse_placeholders("", resolve), Ok(Vec::new()));
+        assert_eq!(
+            parse_placeholders("{m}", resolve),
+            Err("unknown placeholder '{m}'".to_string())
+        );
+        assert_eq!(
+            parse_placeholders("{n", resolve),
+            Err("unterminated placeholder '{n'".to_string())
+        );
+        assert!(parse_placeholders("n}", resolve).is_err());
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            to_hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("10k"), Ok(10 * 1024));
+        assert_eq!(parse_size("2M"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_size("4G"), Ok(4 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1.5GiB"), Ok(1536 * 1024 * 1024));
+        assert!(parse_size("12 parsecs").is_err());
+        assert!(parse_size("k").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        use std::time::Duration;
+        assert_eq!(parse_duration("250"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("5h").is_err());
+        // Too long for a Duration, rather than a panic
+        assert!(parse_duration(&format!("{}s", "9".repeat(400))).is_err());
+        assert!(parse_duration("99999999999999999999999m").is_err());
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("My%20Font.otf"), "My Font.otf");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+
+    #[test]
+    fn test_base64_decode() {
+        assert_eq!(base64_decode(b"aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(base64_decode(b"aGVs\n bG8h").unwrap(), b"hello!");
+        assert_eq!(base64_decode(b"").unwrap(), b"");
+        assert!(base64_decode(b"aGV*bG8=").is_none());
+        assert!(base64_decode(b"aG=Vs").is_none());
+    }
+
+    #[test]
+    fn test_resolve_reference() {
+        assert_eq!(
+            resolve_reference("OEBPS/Text/ch1.xhtml", "../Images/a%20b.png#frag"),
+            Some("OEBPS/Images/a b.png".to_string())
+        );
+        assert_eq!(
+            resolve_reference("OEBPS/ch1.xhtml", "./img.jpg"),
+            Some("OEBPS/img.jpg".to_string())
+        );
+        assert_eq!(
+            resolve_reference("ch1.xhtml", "/OEBPS/img.jpg"),
+            Some("OEBPS/img.jpg".to_string())
+        );
+        assert_eq!(
+            resolve_reference("OEBPS/ch1.xhtml", "https://x/y.png"),
+            None
+        );
+        assert_eq!(resolve_reference("ch1.xhtml", "../../escape.png"), None);
+    }
+
+    #[test]
+    fn test_document_filter() {
+        let metadata = dublin_core_metadata(
+            r#"<cp:coreProperties><dc:title>Quarterly &amp; Annual Report</dc:title>
+<dc:subject/><dc:creator>Jane Doe</dc:creator></cp:coreProperties>"#,
+        );
+        assert_eq!(
+            metadata,
+            DocumentMetadata {
+                title: Some("Quarterly & Annual Report".to_string()),
+                author: Some("Jane Doe".to_string()),
+                subject: None,
+                ..Default::default()
+            }
+        );
+
+        let filter = |title: Option<&str>, subject: Option<&str>| DocumentFilter {
+            title: title.map(str::to_string),
+            subject: subject.map(str::to_string),
+            ..Default::default()
+        };
+        assert!(filter(None, None).matches(&metadata));
+        assert!(filter(Some("annual"), None).matches(&metadata));
+        assert!(!filter(Some("budget"), None).matches(&metadata));
+        // A document without a subject doesn't match a subject filter
+        assert!(!filter(None, Some("finance")).matches(&metadata));
+
+        let odf = dublin_core_metadata("<meta:initial-creator>Ann</meta:initial-creator>");
+        assert_eq!(odf.author.as_deref(), Some("Ann"));
+
+        let book = DocumentMetadata {
+            language: Some("en-GB".to_string()),
+            isbn: Some("9780385121675".to_string()),
+            ..Default::default()
+        };
+        let language = |language: &str| DocumentFilter {
+            language: Some(language.to_string()),
+            ..Default::default()
+        };
+        assert!(language("EN").matches(&book));
+        assert!(language("en-gb").matches(&book));
+        assert!(!language("e").matches(&book));
+        let isbn = |isbn: &str| DocumentFilter {
+            isbn: Some(isbn.to_string()),
+            ..Default::default()
+        };
+        assert!(isbn("978-0-385-12167-5").matches(&book));
+        assert!(!isbn("0-385-12167-X").matches(&book));
+        assert!(!isbn("-").matches(&book));
+        assert_eq!(normalize_isbn("urn:isbn:0-8044-2957-x"), "080442957X");
+    }
 }
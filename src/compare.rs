@@ -0,0 +1,462 @@
+//! Differences between the images embedded in two versions of a document
+
+use anyhow::{Context, Result};
+use epub::doc::EpubDoc;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use zip::ZipArchive;
+
+use crate::common::{Digest, get_supported_extensions, is_safe_archive_path, sha256};
+use crate::doc;
+use crate::extract::{DocumentType, SUPPORTED_DOCUMENTS, get_document_type};
+use crate::fb2;
+use crate::html;
+use crate::json::Json;
+use crate::mobi;
+use crate::odf;
+use crate::pdf;
+use crate::phash::{SIMILAR_MAX_DISTANCE, hamming_distance, perceptual_hash};
+use crate::rtf;
+
+/// An image embedded in a document
+struct Image {
+    /// Archive path of the image
+    name: String,
+    digest: Digest,
+    /// Perceptual hash, only computed when requested
+    perceptual: Option<u64>,
+}
+
+/// How an image differs between the two versions
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// Same name and content
+    Unchanged {
+        name: String,
+    },
+    /// Same name, different content. `distance` is the perceptual hash distance when
+    /// both versions could be decoded.
+    Changed {
+        name: String,
+        distance: Option<u32>,
+    },
+    /// Identical content under a new name
+    Renamed {
+        old: String,
+        new: String,
+    },
+    /// Different name and content that looks the same (perceptual comparison only)
+    Similar {
+        old: String,
+        new: String,
+        distance: u32,
+    },
+    Added {
+        name: String,
+    },
+    Removed {
+        name: String,
+    },
+}
+
+impl Change {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Change::Unchanged { .. } => "unchanged",
+            Change::Changed { .. } => "changed",
+            Change::Renamed { .. } => "renamed",
+            Change::Similar { .. } => "similar",
+            Change::Added { .. } => "added",
+            Change::Removed { .. } => "removed",
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        let mut fields = vec![("change", Json::from(self.kind()))];
+        match self {
+            Change::Unchanged { name } | Change::Added { name } | Change::Removed { name } => {
+                fields.push(("name", Json::from(name.as_str())));
+            }
+            Change::Changed { name, distance } => {
+                fields.push(("name", Json::from(name.as_str())));
+                fields.push(("distance", Json::from(*distance)));
+            }
+            Change::Renamed { old, new } => {
+                fields.push(("old", Json::from(old.as_str())));
+                fields.push(("new", Json::from(new.as_str())));
+            }
+            Change::Similar { old, new, distance } => {
+                fields.push(("old", Json::from(old.as_str())));
+                fields.push(("new", Json::from(new.as_str())));
+                fields.push(("distance", Json::from(*distance)));
+            }
+        }
+        Json::object(fields)
+    }
+}
+
+/// Result of comparing two documents
+#[derive(Debug)]
+pub struct Comparison {
+    pub changes: Vec<Change>,
+}
+
+impl Comparison {
+    /// Whether anything other than unchanged images was found
+    pub fn has_differences(&self) -> bool {
+        self.changes
+            .iter()
+            .any(|c| !matches!(c, Change::Unchanged { .. }))
+    }
+
+    fn count(&self, kind: &str) -> usize {
+        self.changes.iter().filter(|c| c.kind() == kind).count()
+    }
+
+    /// Prints the differences, followed by a summary line
+    pub fn print_text(&self, old_path: &Path, new_path: &Path) {
+        println!("Comparing {} -> {}", old_path.display(), new_path.display());
+        for change in &self.changes {
+            match change {
+                Change::Unchanged { .. } => {}
+                Change::Changed {
+                    name,
+                    distance: Some(distance),
+                } => println!("  changed   {} (perceptual distance {})", name, distance),
+                Change::Changed { name, .. } => println!("  changed   {}", name),
+                Change::Renamed { old, new } => println!("  renamed   {} -> {}", old, new),
+                Change::Similar { old, new, distance } => println!(
+                    "  similar   {} -> {} (perceptual distance {})",
+                    old, new, distance
+                ),
+                Change::Added { name } => println!("  added     {}", name),
+                Change::Removed { name } => println!("  removed   {}", name),
+            }
+        }
+        println!(
+            "{} unchanged, {} changed, {} renamed, {} similar, {} added, {} removed.",
+            self.count("unchanged"),
+            self.count("changed"),
+            self.count("renamed"),
+            self.count("similar"),
+            self.count("added"),
+            self.count("removed")
+        );
+    }
+
+    pub fn to_json(&self, old_path: &Path, new_path: &Path) -> Json {
+        let summary = [
+            "unchanged",
+            "changed",
+            "renamed",
+            "similar",
+            "added",
+            "removed",
+        ]
+        .map(|kind| (kind, Json::from(self.count(kind))));
+        Json::object([
+            ("old", Json::from(old_path.display().to_string())),
+            ("new", Json::from(new_path.display().to_string())),
+            ("summary", Json::object(summary)),
+            (
+                "changes",
+                Json::Array(self.changes.iter().map(Change::to_json).collect()),
+            ),
+        ])
+    }
+}
+
+/// Compares the images embedded in two documents.
+/// With `perceptual`, changed images report how different they look, and added/removed
+/// pairs that look the same are reported as `similar`.
+pub fn compare_documents(old_path: &Path, new_path: &Path, perceptual: bool) -> Result<Comparison> {
+    let old = collect_images(old_path, perceptual)?;
+    let new = collect_images(new_path, perceptual)?;
+    Ok(compare_images(old, new))
+}
+
+fn compare_images(old: Vec<Image>, new: Vec<Image>) -> Comparison {
+    let mut changes = Vec::new();
+    let mut new_by_name: HashMap<String, Image> =
+        new.into_iter().map(|i| (i.name.clone(), i)).collect();
+
+    let mut removed = Vec::new();
+    for image in old {
+        match new_by_name.remove(&image.name) {
+            Some(other) if other.digest == image.digest => {
+                changes.push(Change::Unchanged { name: image.name })
+            }
+            Some(other) => {
+                let distance = image
+                    .perceptual
+                    .zip(other.perceptual)
+                    .map(|(a, b)| hamming_distance(a, b));
+                changes.push(Change::Changed {
+                    name: image.name,
+                    distance,
+                });
+            }
+            None => removed.push(image),
+        }
+    }
+    let mut added: Vec<Image> = new_by_name.into_values().collect();
+    added.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // Pair up removed and added images: identical content first, then look-alikes
+    let mut unmatched = Vec::new();
+    for image in removed {
+        if let Some(pos) = added.iter().position(|a| a.digest == image.digest) {
+            let other = added.remove(pos);
+            changes.push(Change::Renamed {
+                old: image.name,
+                new: other.name,
+            });
+        } else {
+            unmatched.push(image);
+        }
+    }
+    for image in unmatched {
+        let similar = image.perceptual.and_then(|hash| {
+            added
+                .iter()
+                .enumerate()
+                .filter_map(|(pos, a)| Some((pos, hamming_distance(hash, a.perceptual?))))
+                .filter(|(_, distance)| *distance <= SIMILAR_MAX_DISTANCE)
+                .min_by_key(|(_, distance)| *distance)
+        });
+        match similar {
+            Some((pos, distance)) => {
+                let other = added.remove(pos);
+                changes.push(Change::Similar {
+                    old: image.name,
+                    new: other.name,
+                    distance,
+                });
+            }
+            None => changes.push(Change::Removed { name: image.name }),
+        }
+    }
+    changes.extend(
+        added
+            .into_iter()
+            .map(|image| Change::Added { name: image.name }),
+    );
+
+    Comparison { changes }
+}
+
+/// Reads every image embedded in a document
+fn collect_images(path: &Path, perceptual: bool) -> Result<Vec<Image>> {
+    let image = |name: String, data: Vec<u8>| Image {
+        name,
+        digest: sha256(&data),
+        perceptual: if perceptual {
+            perceptual_hash(&data)
+        } else {
+            None
+        },
+    };
+
+    let mut images = Vec::new();
+    match get_document_type(path) {
+        Some(
+            document_type @ (DocumentType::Docx
+            | DocumentType::Pptx
+            | DocumentType::Xlsx
+            | DocumentType::Odf),
+        ) => {
+            let file = fs::File::open(path)
+                .with_context(|| format!("Failed to open input file: {}", path.display()))?;
+            let mut archive = ZipArchive::new(file)
+                .with_context(|| format!("Failed to read zip archive: {}", path.display()))?;
+            let allowed = get_supported_extensions(&[]);
+            for i in 0..archive.len() {
+                let mut file = archive.by_index(i)?;
+                let name = file.name().to_string();
+                let is_image = Path::new(&name)
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| allowed.contains(e.to_lowercase().as_str()))
+                    && (document_type != DocumentType::Odf || odf::is_picture(&name));
+                if !is_safe_archive_path(&name) || !is_image {
+                    continue;
+                }
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)
+                    .with_context(|| format!("Failed to read {} from {}", name, path.display()))?;
+                images.push(image(name, data));
+            }
+        }
+        Some(DocumentType::Epub) => {
+            let mut doc = EpubDoc::new(path)
+                .map_err(|e| anyhow::anyhow!("Failed to open EPUB file: {}", e))?;
+            let mut resources: Vec<(String, String)> = doc
+                .resources
+                .iter()
+                .filter(|(_, item)| item.mime.starts_with("image/"))
+                .map(|(id, item)| (id.clone(), item.path.to_string_lossy().replace('\\', "/")))
+                .collect();
+            resources.sort_by(|a, b| a.1.cmp(&b.1));
+            for (id, name) in resources {
+                let (data, _mime) = doc
+                    .get_resource(&id)
+                    .with_context(|| format!("Failed to read {} from {}", name, path.display()))?;
+                images.push(image(name, data));
+            }
+        }
+        Some(DocumentType::Doc) => {
+            let data = fs::read(path)
+                .with_context(|| format!("Failed to open input file: {}", path.display()))?;
+            for (index, (extension, data)) in
+                doc::collect_images(&data, path)?.into_iter().enumerate()
+            {
+                images.push(image(format!("image{}.{}", index + 1, extension), data));
+            }
+        }
+        Some(DocumentType::Mobi) => {
+            let data = fs::read(path)
+                .with_context(|| format!("Failed to open input file: {}", path.display()))?;
+            let book =
+                mobi::parse(&data).with_context(|| format!("Failed to read {}", path.display()))?;
+            for (index, book_image) in book.images.iter().enumerate() {
+                let name = format!("image{}.{}", index + 1, book_image.extension);
+                images.push(image(name, book_image.data.to_vec()));
+            }
+        }
+        Some(DocumentType::Fb2) => {
+            let data = fs::read(path)
+                .with_context(|| format!("Failed to open input file: {}", path.display()))?;
+            let book = fb2::parse(&data, &[])
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            for book_image in book.images {
+                images.push(image(book_image.id, book_image.data));
+            }
+        }
+        Some(DocumentType::Rtf) => {
+            let data = fs::read(path)
+                .with_context(|| format!("Failed to open input file: {}", path.display()))?;
+            for (index, (extension, data)) in
+                rtf::collect_images(&data, path)?.into_iter().enumerate()
+            {
+                images.push(image(format!("image{}.{}", index + 1, extension), data));
+            }
+        }
+        Some(DocumentType::Html) => {
+            let data = fs::read(path)
+                .with_context(|| format!("Failed to open input file: {}", path.display()))?;
+            for (index, (extension, data)) in
+                html::collect_images(&data, &[]).into_iter().enumerate()
+            {
+                images.push(image(format!("image{}.{}", index + 1, extension), data));
+            }
+        }
+        Some(DocumentType::Pdf) => {
+            let file = fs::File::open(path)
+                .with_context(|| format!("Failed to open input file: {}", path.display()))?;
+            let doc = pdf::load(file, path)?;
+            // Object numbers change when a PDF is rewritten, so images are named by position
+            let mut per_page: HashMap<u32, usize> = HashMap::new();
+            for page_image in pdf::page_images(&doc) {
+                let Ok(stream) = doc
+                    .get_object(page_image.id)
+                    .and_then(lopdf::Object::as_stream)
+                else {
+                    continue;
+                };
+                let Some(extension) = pdf::image_extension(stream) else {
+                    continue;
+                };
+                let Some(data) = pdf::image_data(&doc, stream)? else {
+                    continue;
+                };
+                let index = per_page.entry(page_image.page).or_default();
+                *index += 1;
+                let name = format!("page{}/image{}.{}", page_image.page, index, extension);
+                images.push(image(name, data));
+            }
+        }
+        None => anyhow::bail!(
+            "Unsupported file type: {}. Supported types: {}",
+            path.display(),
+            SUPPORTED_DOCUMENTS
+        ),
+    }
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Image {
+        fn new(name: &str, data: &[u8], perceptual: Option<u64>) -> Self {
+            Image {
+                name: name.to_string(),
+                digest: sha256(data),
+                perceptual,
+            }
+        }
+    }
+
+    #[test]
+    fn test_compare_images() {
+        let old = vec![
+            Image::new("same.png", b"same", None),
+            Image::new("edited.png", b"v1", Some(0b1111)),
+            Image::new("moved.png", b"moved", None),
+            Image::new("retouched.png", b"r1", Some(0)),
+            Image::new("gone.png", b"gone", Some(u64::MAX)),
+        ];
+        let new = vec![
+            Image::new("same.png", b"same", None),
+            Image::new("edited.png", b"v2", Some(0b0111)),
+            Image::new("moved-here.png", b"moved", None),
+            Image::new("retouched-2.png", b"r2", Some(0b1)),
+            Image::new("new.png", b"new", Some(0xFFFF_0000)),
+        ];
+
+        let comparison = compare_images(old, new);
+        assert!(comparison.has_differences());
+        assert_eq!(
+            comparison.changes,
+            vec![
+                Change::Unchanged {
+                    name: "same.png".into()
+                },
+                Change::Changed {
+                    name: "edited.png".into(),
+                    distance: Some(1)
+                },
+                Change::Renamed {
+                    old: "moved.png".into(),
+                    new: "moved-here.png".into()
+                },
+                Change::Similar {
+                    old: "retouched.png".into(),
+                    new: "retouched-2.png".into(),
+                    distance: 1
+                },
+                Change::Removed {
+                    name: "gone.png".into()
+                },
+                Change::Added {
+                    name: "new.png".into()
+                },
+            ]
+        );
+        let json = comparison
+            .to_json(Path::new("a.docx"), Path::new("b.docx"))
+            .to_string();
+        assert!(json.contains(
+            r#""summary":{"unchanged":1,"changed":1,"renamed":1,"similar":1,"added":1,"removed":1}"#
+        ));
+    }
+
+    #[test]
+    fn test_identical_documents() {
+        let images = || vec![Image::new("a.png", b"a", None)];
+        assert!(!compare_images(images(), images()).has_differences());
+    }
+}
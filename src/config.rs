@@ -0,0 +1,373 @@
+//! Configuration file with default command line options
+//!
+//! `word-gif-extractor.toml` holds flat `key = value` pairs in a small TOML subset:
+//! strings, booleans, integers and arrays of strings, with `#` comments. Keys may be
+//! written with dashes or underscores (`on-conflict` or `on_conflict`).
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::common::{ExtraFormat, parse_extra_format};
+use crate::extract::CoverFallback;
+use crate::glob::Glob;
+use crate::messages::{Lang, SummaryTemplate, parse_summary_template};
+use crate::naming::{NameTemplate, parse_name_template};
+use crate::output::{ConflictPolicy, FormatRoutes, GroupBy, parse_routes};
+
+/// Name of the configuration file discovered in the working directory
+pub const CONFIG_FILE_NAME: &str = "word-gif-extractor.toml";
+
+/// Option defaults read from a configuration file. Unset keys are `None`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub output: Option<PathBuf>,
+    pub formats: Option<Vec<String>>,
+    pub recursive: Option<bool>,
+    pub exclude: Option<Vec<Glob>>,
+    pub extra_formats: Option<Vec<ExtraFormat>>,
+    pub on_conflict: Option<ConflictPolicy>,
+    pub name_template: Option<NameTemplate>,
+    pub group_by: Option<GroupBy>,
+    pub route: Option<FormatRoutes>,
+    pub cover_only: Option<bool>,
+    pub cover_fallback: Option<CoverFallback>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub series: Option<String>,
+    pub publisher: Option<String>,
+    pub language: Option<String>,
+    pub isbn: Option<String>,
+    pub recurse_embedded: Option<bool>,
+    pub doc_thumbnail: Option<bool>,
+    pub treat_as_zip: Option<bool>,
+    pub skip_macro_enabled: Option<bool>,
+    pub jobs: Option<usize>,
+    pub lang: Option<Lang>,
+    pub summary_template: Option<SummaryTemplate>,
+}
+
+/// A parsed value
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Bool(bool),
+    Int(i64),
+    Array(Vec<String>),
+}
+
+impl Config {
+    /// Loads `path`, or `word-gif-extractor.toml` in the working directory if no path is
+    /// given. A missing default file is not an error.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => (PathBuf::from(CONFIG_FILE_NAME), false),
+        };
+        if !required && !path.is_file() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid config file: {}", path.display()))
+    }
+
+    /// Parses configuration text
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut config = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .with_context(|| format!("line {}: expected `key = value`", number + 1))?;
+            let key = key.trim().replace('-', "_");
+            let value = parse_value(value.trim())
+                .with_context(|| format!("line {}: invalid value for `{}`", number + 1, key))?;
+            config
+                .set(&key, value)
+                .with_context(|| format!("line {}", number + 1))?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: Value) -> Result<()> {
+        match key {
+            "output" => self.output = Some(PathBuf::from(expect_string(key, value)?)),
+            "formats" => {
+                self.formats = Some(match value {
+                    Value::Array(items) => items,
+                    // Also accept the command line's comma-separated form
+                    Value::String(list) => list.split(',').map(|f| f.trim().to_string()).collect(),
+                    _ => anyhow::bail!("`formats` must be a list of strings"),
+                })
+            }
+            "recursive" => self.recursive = Some(expect_bool(key, value)?),
+            "exclude" => {
+                let patterns = match value {
+                    Value::Array(items) => items,
+                    Value::String(pattern) => vec![pattern],
+                    _ => anyhow::bail!("`exclude` must be a list of strings"),
+                };
+                self.exclude = Some(
+                    patterns
+                        .iter()
+                        .map(|pattern| pattern.parse())
+                        .collect::<Result<_, String>>()
+                        .map_err(anyhow::Error::msg)?,
+                );
+            }
+            "extra_formats" => {
+                let formats = match value {
+                    Value::Array(items) => items,
+                    Value::String(format) => vec![format],
+                    _ => anyhow::bail!("`extra_formats` must be a list of strings"),
+                };
+                self.extra_formats = Some(
+                    formats
+                        .iter()
+                        .map(|format| parse_extra_format(format))
+                        .collect::<Result<_, String>>()
+                        .map_err(anyhow::Error::msg)?,
+                );
+            }
+            "on_conflict" => self.on_conflict = Some(expect_enum(key, value)?),
+            "group_by" => self.group_by = Some(expect_enum(key, value)?),
+            "route" => {
+                let routes = expect_string(key, value)?;
+                self.route = Some(parse_routes(&routes).map_err(anyhow::Error::msg)?);
+            }
+            "name_template" => {
+                let template = expect_string(key, value)?;
+                self.name_template =
+                    Some(parse_name_template(&template).map_err(anyhow::Error::msg)?);
+            }
+            "cover_only" => self.cover_only = Some(expect_bool(key, value)?),
+            // `true` is the plain `--cover-fallback`
+            "cover_fallback" => {
+                self.cover_fallback = match value {
+                    Value::Bool(true) => Some(CoverFallback::All),
+                    Value::Bool(false) => None,
+                    value => Some(expect_enum(key, value)?),
+                }
+            }
+            "title" => self.title = Some(expect_string(key, value)?),
+            "author" => self.author = Some(expect_string(key, value)?),
+            "subject" => self.subject = Some(expect_string(key, value)?),
+            "series" => self.series = Some(expect_string(key, value)?),
+            "publisher" => self.publisher = Some(expect_string(key, value)?),
+            "language" => self.language = Some(expect_string(key, value)?),
+            "isbn" => self.isbn = Some(expect_string(key, value)?),
+            "recurse_embedded" => self.recurse_embedded = Some(expect_bool(key, value)?),
+            "doc_thumbnail" => self.doc_thumbnail = Some(expect_bool(key, value)?),
+            "treat_as_zip" => self.treat_as_zip = Some(expect_bool(key, value)?),
+            "skip_macro_enabled" => self.skip_macro_enabled = Some(expect_bool(key, value)?),
+            "jobs" => match value {
+                Value::Int(n) if n >= 0 => self.jobs = Some(n as usize),
+                _ => anyhow::bail!("`jobs` must be a non-negative integer"),
+            },
+            "lang" => self.lang = Some(expect_enum(key, value)?),
+            "summary_template" => {
+                let template = expect_string(key, value)?;
+                self.summary_template =
+                    Some(parse_summary_template(&template).map_err(anyhow::Error::msg)?);
+            }
+            _ => anyhow::bail!("unknown option `{}`", key),
+        }
+        Ok(())
+    }
+}
+
+/// Removes a `#` comment that is not inside a quoted string
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+fn parse_value(text: &str) -> Result<Value> {
+    match text {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+    if let Some(inner) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        let mut items = Vec::new();
+        let mut rest = inner.trim();
+        while !rest.is_empty() {
+            let (item, after) = parse_string(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            rest = match rest.strip_prefix(',') {
+                Some(after_comma) => after_comma.trim_start(),
+                None if rest.is_empty() => rest,
+                None => anyhow::bail!("expected `,` between list items"),
+            };
+        }
+        return Ok(Value::Array(items));
+    }
+    if text.starts_with(['"', '\'']) {
+        let (string, rest) = parse_string(text)?;
+        if !rest.trim().is_empty() {
+            anyhow::bail!("unexpected text after string");
+        }
+        return Ok(Value::String(string));
+    }
+    text.replace('_', "")
+        .parse()
+        .map(Value::Int)
+        .map_err(|_| anyhow::anyhow!("expected a string, boolean, integer or list"))
+}
+
+/// Parses a basic (`"..."`) or literal (`'...'`) string at the start of `text`.
+/// Returns the string and the text after its closing quote.
+fn parse_string(text: &str) -> Result<(String, &str)> {
+    if let Some(literal) = text.strip_prefix('\'') {
+        let end = literal.find('\'').context("unterminated string")?;
+        return Ok((literal[..end].to_string(), &literal[end + 1..]));
+    }
+    let basic = text.strip_prefix('"').context("expected a quoted string")?;
+    let mut out = String::new();
+    let mut chars = basic.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((out, &basic[i + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                other => anyhow::bail!("unsupported escape `\\{}`", other.unwrap_or(' ')),
+            },
+            c => out.push(c),
+        }
+    }
+    anyhow::bail!("unterminated string")
+}
+
+fn expect_string(key: &str, value: Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => anyhow::bail!("`{}` must be a string", key),
+    }
+}
+
+fn expect_bool(key: &str, value: Value) -> Result<bool> {
+    match value {
+        Value::Bool(b) => Ok(b),
+        _ => anyhow::bail!("`{}` must be true or false", key),
+    }
+}
+
+fn expect_enum<T: ValueEnum>(key: &str, value: Value) -> Result<T> {
+    let name = expect_string(key, value)?;
+    T::from_str(&name, true).map_err(|_| anyhow::anyhow!("`{}` cannot be `{}`", key, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(
+            r#"
+# Defaults for the weekly ebook run
+output = "C:\\Images"   # trailing comment
+formats = ["png", 'jpg']
+recursive = true
+on-conflict = "skip"
+route = "svg=vectors/"
+title = "Dune # Messiah"
+jobs = 4
+lang = "de"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                output: Some(PathBuf::from("C:\\Images")),
+                formats: Some(vec!["png".to_string(), "jpg".to_string()]),
+                recursive: Some(true),
+                on_conflict: Some(ConflictPolicy::Skip),
+                route: Some(parse_routes("svg=vectors").unwrap()),
+                title: Some("Dune # Messiah".to_string()),
+                jobs: Some(4),
+                lang: Some(Lang::De),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            Config::parse("cover-fallback = true")
+                .unwrap()
+                .cover_fallback,
+            Some(CoverFallback::All)
+        );
+        assert_eq!(
+            Config::parse("cover-fallback = \"largest\"")
+                .unwrap()
+                .cover_fallback,
+            Some(CoverFallback::Largest)
+        );
+        assert_eq!(
+            Config::parse("formats = \"gif, png\"").unwrap().formats,
+            Some(vec!["gif".to_string(), "png".to_string()])
+        );
+        assert_eq!(
+            Config::parse("exclude = [\".git\", \"**/backup/**\"]")
+                .unwrap()
+                .exclude,
+            Some(vec![
+                ".git".parse().unwrap(),
+                "**/backup/**".parse().unwrap()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_extra_formats() {
+        let config =
+            Config::parse("extra-formats = [\"avif=image/avif\", \".JXL = image/jxl\"]").unwrap();
+        assert_eq!(
+            config.extra_formats,
+            Some(vec![
+                ExtraFormat {
+                    extension: "avif".to_string(),
+                    mime: "image/avif".to_string(),
+                },
+                ExtraFormat {
+                    extension: "jxl".to_string(),
+                    mime: "image/jxl".to_string(),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_config_errors() {
+        assert!(Config::parse("recursive = \"yes\"").is_err());
+        assert!(Config::parse("summary-template = \"{pages}\"").is_err());
+        assert!(Config::parse("on_conflict = \"replace\"").is_err());
+        assert!(Config::parse("formats = [\"png\" \"jpg\"]").is_err());
+        assert!(Config::parse("exclude = true").is_err());
+        assert!(Config::parse("extra_formats = [\"avif\"]").is_err());
+        let err = Config::parse("\nrecursiv = true").unwrap_err();
+        assert_eq!(format!("{:#}", err), "line 2: unknown option `recursiv`");
+    }
+}
@@ -0,0 +1,852 @@
+//! Re-encoding extracted images: conversion to a single output format (`--convert-to`)
+//! with color management (`--color-profile`), thumbnails (`--thumbnails`), splitting
+//! animated GIFs into frames (`--split-gif-frames`) and re-encoding them as smaller GIFs
+//! (`--optimize-gif`) or in another animated format (`--animated-to`)
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use image::codecs::gif::GifDecoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{
+    AnimationDecoder, DynamicImage, ImageBuffer, ImageDecoder, ImageEncoder, ImageFormat,
+    ImageReader, Limits, RgbImage, RgbaImage,
+};
+use moxcms::{DataColorSpace, Layout, TransformOptions};
+use std::collections::HashSet;
+use std::io::Cursor;
+use zune_core::bytestream::ZCursor;
+use zune_core::colorspace::ColorSpace as ZuneColorSpace;
+use zune_core::options::DecoderOptions;
+
+use crate::sniff::{canonical_extension, sniff_image_format};
+
+/// Format images are converted to
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ConvertFormat {
+    Png,
+    #[value(alias = "jpeg")]
+    Jpg,
+    /// Lossless WebP
+    Webp,
+}
+
+/// Raster formats that can be decoded for conversion. Vector formats (SVG, EMF, WMF)
+/// and JPEG 2000 are always written as they are.
+const DECODABLE: &[&str] = &["png", "jpg", "gif", "bmp", "tiff", "webp", "ico"];
+
+/// The largest width or height of an image that is decoded. Larger images, which a few
+/// header bytes can claim, cannot be converted and are left as they are.
+const MAX_DIMENSION: u32 = 32_768;
+
+/// The most memory decoding an image may take, for all frames of an animation together
+const MAX_DECODED_BYTES: u64 = 512 * 1024 * 1024;
+
+/// The limits every decoder is given
+pub(crate) fn decode_limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODED_BYTES);
+    limits
+}
+
+impl ConvertFormat {
+    /// File extension of converted images
+    pub fn extension(self) -> &'static str {
+        match self {
+            ConvertFormat::Png => "png",
+            ConvertFormat::Jpg => "jpg",
+            ConvertFormat::Webp => "webp",
+        }
+    }
+
+    /// Whether an image with `extension` is converted to this format
+    pub fn converts(self, extension: &str) -> bool {
+        canonical_extension(extension) != self.extension() && is_decodable(extension)
+    }
+
+    /// Decodes `data` and encodes it in this format, handling its colors as `color` says.
+    /// Transparency is flattened for JPEG.
+    pub fn convert(self, data: &[u8], color: ColorProfile) -> Result<Vec<u8>> {
+        let decoded = Decoded::new(data, color)?;
+        self.encode(decoded)
+    }
+
+    /// Decodes `data` and encodes a copy scaled down to fit within `max_edge` pixels in
+    /// this format. Images that already fit keep their size.
+    pub fn thumbnail(self, data: &[u8], max_edge: u32, color: ColorProfile) -> Result<Vec<u8>> {
+        let mut decoded = Decoded::new(data, color)?;
+        let image = decoded.image;
+        decoded.image = if image.width() > max_edge || image.height() > max_edge {
+            image.thumbnail(max_edge, max_edge)
+        } else {
+            image
+        };
+        self.encode(decoded)
+    }
+
+    fn encode(self, decoded: Decoded) -> Result<Vec<u8>> {
+        let Decoded { image, profile } = decoded;
+        let image = match self {
+            ConvertFormat::Png => image,
+            ConvertFormat::Jpg => DynamicImage::ImageRgb8(image.to_rgb8()),
+            ConvertFormat::Webp => DynamicImage::ImageRgba8(image.to_rgba8()),
+        };
+        // A gray profile only describes gray pixels; colors that became RGB are
+        // converted instead, as are those of profiles that are neither RGB nor gray. A
+        // profile that cannot be applied is dropped.
+        let gray = !image.color().has_color();
+        let (image, profile) = match profile {
+            Some((icc, parsed))
+                if parsed.color_space == DataColorSpace::Rgb
+                    || (parsed.color_space == DataColorSpace::Gray && gray) =>
+            {
+                (image, Some(icc))
+            }
+            Some((_, parsed)) => match to_srgb(&image, &parsed) {
+                Ok(converted) => (converted, None),
+                Err(_) => (image, None),
+            },
+            None => (image, None),
+        };
+
+        let mut out = Vec::new();
+        let written = match self {
+            ConvertFormat::Png => write_with_profile(&image, PngEncoder::new(&mut out), profile),
+            ConvertFormat::Jpg => write_with_profile(&image, JpegEncoder::new(&mut out), profile),
+            ConvertFormat::Webp => {
+                write_with_profile(&image, WebPEncoder::new_lossless(&mut out), profile)
+            }
+        };
+        written.with_context(|| format!("Failed to encode image as {}", self.extension()))?;
+        Ok(out)
+    }
+}
+
+/// What happens to the colors of images that are re-encoded (`--color-profile`)
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum ColorProfile {
+    /// Keep the colors as they are and embed the image's ICC profile in the converted
+    /// image, so viewers show it the same way
+    #[default]
+    Keep,
+    /// Convert the colors to sRGB and embed no profile, for viewers and tools that
+    /// ignore profiles
+    Srgb,
+}
+
+/// A decoded image with the ICC profile its colors are in, `None` meaning sRGB
+struct Decoded {
+    image: DynamicImage,
+    profile: Option<(Vec<u8>, moxcms::ColorProfile)>,
+}
+
+impl Decoded {
+    /// Decodes `data`. CMYK JPEGs are always converted to sRGB, since none of the output
+    /// formats holds CMYK; with [`ColorProfile::Srgb`] so are images in other profiles.
+    /// A profile that cannot be read is ignored, as viewers would.
+    fn new(data: &[u8], color: ColorProfile) -> Result<Self> {
+        if let Some(image) = decode_cmyk_jpeg(data)? {
+            return Ok(Self {
+                image,
+                profile: None,
+            });
+        }
+        let mut reader = ImageReader::new(Cursor::new(data))
+            .with_guessed_format()
+            .context("Failed to decode image")?;
+        reader.limits(decode_limits());
+        let mut decoder = reader.into_decoder().context("Failed to decode image")?;
+        anyhow::ensure!(
+            decoder.total_bytes() <= MAX_DECODED_BYTES,
+            "Image is too large to decode"
+        );
+        let icc = decoder.icc_profile().ok().flatten();
+        let image = DynamicImage::from_decoder(decoder).context("Failed to decode image")?;
+        let profile = icc.and_then(|icc| {
+            let parsed = moxcms::ColorProfile::new_from_slice(&icc).ok()?;
+            Some((icc, parsed))
+        });
+        match (profile, color) {
+            (Some((_, parsed)), ColorProfile::Srgb) => Ok(Self {
+                image: to_srgb(&image, &parsed).context("Failed to convert colors to sRGB")?,
+                profile: None,
+            }),
+            (profile, _) => Ok(Self { image, profile }),
+        }
+    }
+}
+
+/// Writes `image` with `encoder`, embedding the ICC `profile` if there is one
+fn write_with_profile(
+    image: &DynamicImage,
+    mut encoder: impl ImageEncoder,
+    profile: Option<Vec<u8>>,
+) -> Result<()> {
+    if let Some(profile) = profile {
+        encoder
+            .set_icc_profile(profile)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+    }
+    image.write_with_encoder(encoder)?;
+    Ok(())
+}
+
+/// Converts the colors of `image`, described by the RGB or gray `profile`, to sRGB.
+/// Gray images stay gray only if their profile is gray too.
+fn to_srgb(image: &DynamicImage, profile: &moxcms::ColorProfile) -> Result<DynamicImage> {
+    let gray = match profile.color_space {
+        DataColorSpace::Rgb => false,
+        DataColorSpace::Gray => true,
+        other => anyhow::bail!("{:?} color profiles are not supported", other),
+    };
+    let srgb = moxcms::ColorProfile::new_srgb();
+    let options = TransformOptions::default();
+    let alpha = image.color().has_alpha();
+    let (src_layout, dst_layout) = match (gray, alpha) {
+        (true, false) => (Layout::Gray, Layout::Rgb),
+        (true, true) => (Layout::GrayAlpha, Layout::Rgba),
+        (false, false) => (Layout::Rgb, Layout::Rgb),
+        (false, true) => (Layout::Rgba, Layout::Rgba),
+    };
+    let (width, height) = (image.width(), image.height());
+    let pixels = width as usize * height as usize;
+    // 16-bit images keep their precision, everything else is converted as 8-bit
+    let wide = image.color().bytes_per_pixel() / image.color().channel_count() == 2;
+    if wide {
+        let source = match (gray, alpha) {
+            (true, false) => image.to_luma16().into_raw(),
+            (true, true) => image.to_luma_alpha16().into_raw(),
+            (false, false) => image.to_rgb16().into_raw(),
+            (false, true) => image.to_rgba16().into_raw(),
+        };
+        let mut converted = vec![0u16; pixels * dst_layout.channels()];
+        profile
+            .create_transform_16bit(src_layout, &srgb, dst_layout, options)?
+            .transform(&source, &mut converted)?;
+        let image = match alpha {
+            true => ImageBuffer::from_raw(width, height, converted).map(DynamicImage::ImageRgba16),
+            false => ImageBuffer::from_raw(width, height, converted).map(DynamicImage::ImageRgb16),
+        };
+        return image.context("Converted image has the wrong size");
+    }
+    let source = match (gray, alpha) {
+        (true, false) => image.to_luma8().into_raw(),
+        (true, true) => image.to_luma_alpha8().into_raw(),
+        (false, false) => image.to_rgb8().into_raw(),
+        (false, true) => image.to_rgba8().into_raw(),
+    };
+    let mut converted = vec![0u8; pixels * dst_layout.channels()];
+    profile
+        .create_transform_8bit(src_layout, &srgb, dst_layout, options)?
+        .transform(&source, &mut converted)?;
+    let image = match alpha {
+        true => ImageBuffer::from_raw(width, height, converted).map(DynamicImage::ImageRgba8),
+        false => ImageBuffer::from_raw(width, height, converted).map(DynamicImage::ImageRgb8),
+    };
+    image.context("Converted image has the wrong size")
+}
+
+/// Decodes a CMYK JPEG to sRGB through its embedded CMYK profile. `None` for other
+/// images and for CMYK JPEGs without such a profile, which the `image` crate turns into
+/// RGB with a plain formula instead.
+fn decode_cmyk_jpeg(data: &[u8]) -> Result<Option<DynamicImage>> {
+    if sniff_image_format(data) != Some("jpg") {
+        return Ok(None);
+    }
+    let options = DecoderOptions::default()
+        .set_strict_mode(false)
+        .set_max_width(MAX_DIMENSION as usize)
+        .set_max_height(MAX_DIMENSION as usize);
+    let mut decoder = zune_jpeg::JpegDecoder::new_with_options(ZCursor::new(data), options);
+    // Errors are left for the regular decoder to report
+    if decoder.decode_headers().is_err() {
+        return Ok(None);
+    }
+    let colorspace = match decoder.input_colorspace() {
+        Some(colorspace @ (ZuneColorSpace::CMYK | ZuneColorSpace::YCCK)) => colorspace,
+        _ => return Ok(None),
+    };
+    let Some(profile) = decoder
+        .icc_profile()
+        .and_then(|icc| moxcms::ColorProfile::new_from_slice(&icc).ok())
+        .filter(|profile| profile.color_space == DataColorSpace::Cmyk)
+    else {
+        return Ok(None);
+    };
+    let (width, height) = decoder.dimensions().context("JPEG has no dimensions")?;
+
+    // The four channels as they are stored, without the formula applied
+    decoder.set_options(options.jpeg_set_out_colorspace(colorspace));
+    let mut cmyk = decoder.decode().context("Failed to decode image")?;
+    if colorspace == ZuneColorSpace::YCCK {
+        ycck_to_cmyk(&mut cmyk);
+    }
+    // Adobe applications store CMYK inverted, with 255 meaning no ink
+    if has_adobe_marker(data) {
+        cmyk.iter_mut().for_each(|value| *value = 255 - *value);
+    }
+
+    let mut rgb = vec![0u8; width * height * 3];
+    profile
+        .create_transform_8bit(
+            Layout::Rgba,
+            &moxcms::ColorProfile::new_srgb(),
+            Layout::Rgb,
+            TransformOptions::default(),
+        )?
+        .transform(&cmyk, &mut rgb)
+        .context("Failed to convert CMYK colors to sRGB")?;
+    let image = RgbImage::from_raw(width as u32, height as u32, rgb)
+        .context("Converted image has the wrong size")?;
+    Ok(Some(DynamicImage::ImageRgb8(image)))
+}
+
+/// Turns YCCK samples (YCbCr of the inverted CMY, then K) into CMYK samples as stored
+fn ycck_to_cmyk(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let (y, cb, cr) = (
+            f32::from(pixel[0]),
+            f32::from(pixel[1]) - 128.0,
+            f32::from(pixel[2]) - 128.0,
+        );
+        let rgb = [
+            y + 1.402 * cr,
+            y - 0.344_136 * cb - 0.714_136 * cr,
+            y + 1.772 * cb,
+        ];
+        for (value, channel) in pixel.iter_mut().zip(rgb) {
+            *value = 255 - channel.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Whether a JPEG has an Adobe `APP14` segment, as written by the Adobe applications
+/// that store CMYK inverted
+fn has_adobe_marker(data: &[u8]) -> bool {
+    let mut pos = 2;
+    while let (Some(&0xFF), Some(&marker)) = (data.get(pos), data.get(pos + 1)) {
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let Some(length) = data.get(pos + 2..pos + 4) else {
+            break;
+        };
+        let length = u16::from_be_bytes([length[0], length[1]]) as usize;
+        if marker == 0xEE && data.get(pos + 4..pos + 9) == Some(b"Adobe") {
+            return true;
+        }
+        pos += 2 + length;
+    }
+    false
+}
+
+/// Whether images with `extension` can be decoded
+pub fn is_decodable(extension: &str) -> bool {
+    DECODABLE.contains(&canonical_extension(extension).as_str())
+}
+
+/// One frame of an animated GIF
+#[derive(Debug)]
+pub struct GifFrame {
+    /// The full canvas after drawing the frame, as PNG
+    pub png: Vec<u8>,
+    /// How long the frame is shown
+    pub delay_ms: u32,
+}
+
+/// Animated format GIFs are converted to
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum AnimatedFormat {
+    /// Lossless animated WebP
+    Webp,
+    /// Animated PNG
+    Apng,
+}
+
+impl AnimatedFormat {
+    /// File extension of converted GIFs
+    pub fn extension(self) -> &'static str {
+        match self {
+            AnimatedFormat::Webp => "webp",
+            AnimatedFormat::Apng => "png",
+        }
+    }
+
+    /// Decodes a GIF and encodes all of its frames, with their delays and the number of
+    /// times it plays, in this format. A GIF with a single frame becomes a still image.
+    pub fn convert(self, data: &[u8]) -> Result<Vec<u8>> {
+        let animation = Animation::decode(data)?;
+        match self {
+            AnimatedFormat::Webp => animation.encode_webp(),
+            AnimatedFormat::Apng => animation.encode_apng(),
+        }
+        .with_context(|| format!("Failed to encode animation as {}", self.extension()))
+    }
+}
+
+/// A decoded GIF: every frame composited onto the full canvas
+struct Animation {
+    width: u32,
+    height: u32,
+    frames: Vec<(RgbaImage, u32)>,
+    /// Times the animation plays, 0 for forever
+    plays: u32,
+}
+
+impl Animation {
+    fn decode(data: &[u8]) -> Result<Self> {
+        // The loop count is only known once the frames were read past it
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut reader = options
+            .read_info(Cursor::new(data))
+            .context("Failed to decode GIF")?;
+        while reader
+            .read_next_frame()
+            .context("Failed to decode GIF")?
+            .is_some()
+        {}
+        // A GIF without a loop count plays once, one with a count repeats that often
+        let plays = match reader.repeat() {
+            gif::Repeat::Infinite => 0,
+            gif::Repeat::Finite(repeats) => u32::from(repeats) + 1,
+        };
+
+        let mut decoder = GifDecoder::new(Cursor::new(data)).context("Failed to decode GIF")?;
+        decoder
+            .set_limits(decode_limits())
+            .context("Failed to decode GIF")?;
+        let mut frames = Vec::new();
+        for frame in decoder.into_frames() {
+            let frame = frame.context("Failed to decode GIF frame")?;
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            frames.push((frame.into_buffer(), numer / denom.max(1)));
+        }
+        let Some((first, _)) = frames.first() else {
+            anyhow::bail!("GIF has no frames");
+        };
+        Ok(Self {
+            width: first.width(),
+            height: first.height(),
+            frames,
+            plays,
+        })
+    }
+
+    fn encode_apng(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        if self.frames.len() > 1 {
+            encoder.set_animated(self.frames.len() as u32, self.plays)?;
+        }
+        let mut writer = encoder.write_header()?;
+        for (canvas, delay_ms) in &self.frames {
+            if self.frames.len() > 1 {
+                writer.set_frame_delay(u16::try_from(*delay_ms).unwrap_or(u16::MAX), 1000)?;
+            }
+            writer.write_image_data(canvas.as_raw())?;
+        }
+        writer.finish()?;
+        Ok(out)
+    }
+
+    /// Lossless WebP, with each frame a full canvas in an `ANMF` chunk of the extended format
+    fn encode_webp(&self) -> Result<Vec<u8>> {
+        let still = |canvas: &RgbaImage| -> Result<Vec<u8>> {
+            let mut out = Vec::new();
+            image_webp::WebPEncoder::new(&mut out).encode(
+                canvas.as_raw(),
+                canvas.width(),
+                canvas.height(),
+                image_webp::ColorType::Rgba8,
+            )?;
+            Ok(out)
+        };
+        if self.frames.len() == 1 {
+            return still(&self.frames[0].0);
+        }
+
+        let mut body = b"WEBP".to_vec();
+        let mut header = vec![ANIMATION_FLAG | ALPHA_FLAG, 0, 0, 0];
+        header.extend_from_slice(&u24(self.width - 1));
+        header.extend_from_slice(&u24(self.height - 1));
+        push_chunk(&mut body, b"VP8X", &header);
+        let mut animation = vec![0, 0, 0, 0];
+        animation.extend_from_slice(&u16::try_from(self.plays).unwrap_or(0).to_le_bytes());
+        push_chunk(&mut body, b"ANIM", &animation);
+        for (canvas, delay_ms) in &self.frames {
+            // The still image is a RIFF header followed by its single VP8L chunk
+            let encoded = still(canvas)?;
+            let bitstream = encoded.get(12..).filter(|chunk| chunk.starts_with(b"VP8L"));
+            let bitstream = bitstream.context("Unexpected WebP frame layout")?;
+            let mut frame = vec![0; 6];
+            frame.extend_from_slice(&u24(self.width - 1));
+            frame.extend_from_slice(&u24(self.height - 1));
+            frame.extend_from_slice(&u24((*delay_ms).min(0xFF_FFFF)));
+            // Replaces the canvas instead of blending over it
+            frame.push(NO_BLEND_FLAG);
+            frame.extend_from_slice(bitstream);
+            push_chunk(&mut body, b"ANMF", &frame);
+        }
+
+        let mut out = b"RIFF".to_vec();
+        out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        out.extend_from_slice(&body);
+        Ok(out)
+    }
+
+    /// Encodes as a GIF in which each frame only covers what changed since the one before,
+    /// leaving unchanged pixels inside that area transparent. `None` if the frames cannot be
+    /// stored that way without loss: more than 256 colors in one frame, or pixels that turn
+    /// transparent, which drawing over the previous frame cannot show.
+    fn encode_gif(&self) -> Result<Option<Vec<u8>>> {
+        let (Ok(width), Ok(height)) = (u16::try_from(self.width), u16::try_from(self.height))
+        else {
+            return Ok(None);
+        };
+        let mut out = Vec::new();
+        let mut encoder = gif::Encoder::new(&mut out, width, height, &[])?;
+        encoder.set_repeat(match self.plays {
+            0 => gif::Repeat::Infinite,
+            plays => gif::Repeat::Finite(u16::try_from(plays - 1).unwrap_or(u16::MAX)),
+        })?;
+
+        let mut frames: Vec<gif::Frame> = Vec::new();
+        let mut previous: Option<&RgbaImage> = None;
+        for (canvas, delay_ms) in &self.frames {
+            let delay = u16::try_from(delay_ms / 10).unwrap_or(u16::MAX);
+            let changed = |x: u32, y: u32| {
+                previous.is_none_or(|previous| previous.get_pixel(x, y) != canvas.get_pixel(x, y))
+            };
+            let mut area: Option<(u32, u32, u32, u32)> = None;
+            for (x, y, pixel) in canvas.enumerate_pixels() {
+                if !changed(x, y) {
+                    continue;
+                }
+                if previous.is_some() && pixel[3] == 0 {
+                    return Ok(None);
+                }
+                area = Some(match area {
+                    Some((left, top, right, bottom)) => {
+                        (left.min(x), top.min(y), right.max(x), bottom.max(y))
+                    }
+                    None => (x, y, x, y),
+                });
+            }
+            let Some((left, top, right, bottom)) = area else {
+                // Nothing changed: show the previous frame for longer
+                if let Some(last) = frames.last_mut() {
+                    last.delay = last.delay.saturating_add(delay);
+                }
+                continue;
+            };
+
+            let mut pixels = Vec::new();
+            for y in top..=bottom {
+                for x in left..=right {
+                    match changed(x, y) {
+                        true => pixels.extend_from_slice(&canvas.get_pixel(x, y).0),
+                        false => pixels.extend_from_slice(&[0, 0, 0, 0]),
+                    }
+                }
+            }
+            let colors: HashSet<[u8; 4]> = pixels
+                .chunks_exact(4)
+                .map(|p| {
+                    if p[3] == 0 {
+                        [0; 4]
+                    } else {
+                        [p[0], p[1], p[2], p[3]]
+                    }
+                })
+                .collect();
+            if colors.len() > 256 {
+                return Ok(None);
+            }
+            let (frame_width, frame_height) =
+                ((right - left + 1) as u16, (bottom - top + 1) as u16);
+            let mut frame = gif::Frame::from_rgba_speed(frame_width, frame_height, &mut pixels, 10);
+            frame.left = left as u16;
+            frame.top = top as u16;
+            frame.delay = delay;
+            frame.dispose = gif::DisposalMethod::Keep;
+            frames.push(frame);
+            previous = Some(canvas);
+        }
+        for frame in &frames {
+            encoder.write_frame(frame)?;
+        }
+        encoder.into_inner()?;
+        Ok(Some(out))
+    }
+}
+
+/// `VP8X` flag: the file is animated
+const ANIMATION_FLAG: u8 = 0x02;
+/// `VP8X` flag: frames may have transparent pixels
+const ALPHA_FLAG: u8 = 0x10;
+/// `ANMF` flag: the frame replaces the canvas area instead of blending over it
+const NO_BLEND_FLAG: u8 = 0x02;
+
+/// Low 24 bits of `value`, little-endian, as WebP stores sizes and durations
+fn u24(value: u32) -> [u8; 3] {
+    let [a, b, c, _] = value.to_le_bytes();
+    [a, b, c]
+}
+
+/// Appends a RIFF chunk, padded to an even length
+fn push_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Re-encodes a GIF so each frame only stores the pixels that changed. Returns the new
+/// GIF only if it is smaller and shows exactly the same frames.
+pub fn optimize_gif(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let optimized = Animation::decode(data)?.encode_gif()?;
+    Ok(optimized.filter(|optimized| optimized.len() < data.len()))
+}
+
+/// Decodes every frame of a GIF, composited onto the full canvas
+pub fn gif_frames(data: &[u8]) -> Result<Vec<GifFrame>> {
+    let mut decoder = GifDecoder::new(Cursor::new(data)).context("Failed to decode GIF")?;
+    decoder
+        .set_limits(decode_limits())
+        .context("Failed to decode GIF")?;
+    let mut frames = Vec::new();
+    for frame in decoder.into_frames() {
+        let frame = frame.context("Failed to decode GIF frame")?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(frame.into_buffer())
+            .write_to(&mut png, ImageFormat::Png)
+            .context("Failed to encode GIF frame")?;
+        frames.push(GifFrame {
+            png: png.into_inner(),
+            delay_ms: numer / denom.max(1),
+        });
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sniff::sniff_image_format;
+
+    #[test]
+    fn test_convert_formats() {
+        // 2x1 GIF: black and white pixels
+        let mut gif = b"GIF89a\x02\x00\x01\x00\x80\x00\x00".to_vec();
+        gif.extend_from_slice(&[0, 0, 0, 255, 255, 255]);
+        gif.extend_from_slice(b"\x2C\0\0\0\0\x02\x00\x01\x00\x00");
+        gif.extend_from_slice(&[0x02, 0x02, 0x44, 0x0A, 0x00, 0x3B]);
+
+        for format in [ConvertFormat::Png, ConvertFormat::Jpg, ConvertFormat::Webp] {
+            let converted = format.convert(&gif, ColorProfile::Keep).unwrap();
+            assert_eq!(sniff_image_format(&converted), Some(format.extension()));
+        }
+        assert!(
+            ConvertFormat::Png
+                .convert(b"GIF89a garbage", ColorProfile::Keep)
+                .is_err()
+        );
+
+        // The same pixels on a canvas 40000 pixels wide, beyond what is decoded
+        let mut wide = gif.clone();
+        wide[6..8].copy_from_slice(&40000u16.to_le_bytes());
+        assert!(
+            ConvertFormat::Png
+                .convert(&wide, ColorProfile::Keep)
+                .is_err()
+        );
+        assert!(gif_frames(&wide).is_err());
+        assert!(gif_frames(&gif).is_ok());
+
+        assert!(ConvertFormat::Jpg.converts("png"));
+        assert!(!ConvertFormat::Jpg.converts("jpeg"));
+        assert!(!ConvertFormat::Png.converts("svg"));
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(400, 100)
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        let thumbnail = ConvertFormat::Jpg
+            .thumbnail(png.get_ref(), 100, ColorProfile::Keep)
+            .unwrap();
+        let image = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((image.width(), image.height()), (100, 25));
+
+        let small = ConvertFormat::Png
+            .thumbnail(png.get_ref(), 1000, ColorProfile::Keep)
+            .unwrap();
+        assert_eq!(image::load_from_memory(&small).unwrap().width(), 400);
+    }
+
+    #[test]
+    fn test_color_profiles() {
+        // A green in Adobe RGB, whose wider gamut needs less red for the same color
+        let adobe_rgb = moxcms::ColorProfile::new_adobe_rgb().encode().unwrap();
+        let mut png = Vec::new();
+        let mut encoder = PngEncoder::new(&mut png);
+        encoder.set_icc_profile(adobe_rgb.clone()).unwrap();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, image::Rgb([120, 200, 120])))
+            .write_with_encoder(encoder)
+            .unwrap();
+        let profile_and_pixel = |data: &[u8]| {
+            let mut decoder = ImageReader::new(Cursor::new(data))
+                .with_guessed_format()
+                .unwrap()
+                .into_decoder()
+                .unwrap();
+            let profile = decoder.icc_profile().unwrap();
+            let image = DynamicImage::from_decoder(decoder).unwrap();
+            (profile, image.to_rgb8().get_pixel(0, 0).0)
+        };
+
+        for format in [ConvertFormat::Jpg, ConvertFormat::Webp] {
+            let kept = format.convert(&png, ColorProfile::Keep).unwrap();
+            let (profile, pixel) = profile_and_pixel(&kept);
+            assert_eq!(profile.as_ref(), Some(&adobe_rgb));
+            assert!(pixel[0].abs_diff(120) <= 3);
+
+            // In sRGB values the color has much less red, with no profile left to apply
+            let srgb = format.convert(&png, ColorProfile::Srgb).unwrap();
+            let (profile, pixel) = profile_and_pixel(&srgb);
+            assert_eq!(profile, None);
+            assert!(pixel[0] < 80);
+        }
+
+        // A gray profile cannot describe an image that becomes RGB
+        let gray = moxcms::ColorProfile::new_gray_with_gamma(1.0)
+            .encode()
+            .unwrap();
+        let mut png = Vec::new();
+        let mut encoder = PngEncoder::new(&mut png);
+        encoder.set_icc_profile(gray.clone()).unwrap();
+        DynamicImage::ImageLuma8(image::GrayImage::from_pixel(1, 1, image::Luma([64])))
+            .write_with_encoder(encoder)
+            .unwrap();
+        let kept = ConvertFormat::Png
+            .convert(&png, ColorProfile::Keep)
+            .unwrap();
+        assert_eq!(profile_and_pixel(&kept).0, Some(gray));
+        let jpeg = ConvertFormat::Jpg
+            .convert(&png, ColorProfile::Keep)
+            .unwrap();
+        let (profile, pixel) = profile_and_pixel(&jpeg);
+        assert_eq!(profile, None);
+        // Linear gray 64 is much lighter in sRGB
+        assert!(pixel[0] > 120);
+    }
+
+    #[test]
+    fn test_ycck_and_adobe_marker() {
+        // Gray YCC of the inverted CMY is no ink, K passes through
+        let mut pixels = [255, 128, 128, 40];
+        ycck_to_cmyk(&mut pixels);
+        assert_eq!(pixels, [0, 0, 0, 40]);
+
+        let mut jpeg = b"\xFF\xD8\xFF\xE0\x00\x04\x00\x00".to_vec();
+        assert!(!has_adobe_marker(&jpeg));
+        jpeg.extend_from_slice(b"\xFF\xEE\x00\x0EAdobe\x00\x64\x00\x00\x00\x00\x02");
+        jpeg.extend_from_slice(b"\xFF\xDA");
+        assert!(has_adobe_marker(&jpeg));
+    }
+
+    /// 1x1 GIF with two frames shown for 100 ms and 250 ms
+    fn two_frame_gif() -> Vec<u8> {
+        let mut gif = b"GIF89a\x01\x00\x01\x00\x80\x00\x00".to_vec();
+        gif.extend_from_slice(&[0, 0, 0, 255, 255, 255]);
+        for (delay, index) in [(10u8, 0u8), (25, 1)] {
+            gif.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00, delay, 0x00, 0x00, 0x00]);
+            gif.extend_from_slice(b"\x2C\0\0\0\0\x01\x00\x01\x00\x00");
+            // LZW (min code size 2): clear, pixel, end
+            let code = 0x04 | (index << 3) | (0x05 << 6);
+            gif.extend_from_slice(&[0x02, 0x02, code, 0x01, 0x00]);
+        }
+        gif.push(0x3B);
+        gif
+    }
+
+    #[test]
+    fn test_gif_frames() {
+        let frames = gif_frames(&two_frame_gif()).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].delay_ms, 100);
+        assert_eq!(frames[1].delay_ms, 250);
+        assert_eq!(sniff_image_format(&frames[1].png), Some("png"));
+    }
+
+    #[test]
+    fn test_animated_formats() {
+        let gif = two_frame_gif();
+        let delays = |frames: Vec<image::Frame>| -> Vec<u32> {
+            frames
+                .iter()
+                .map(|frame| {
+                    let (numer, denom) = frame.delay().numer_denom_ms();
+                    numer / denom
+                })
+                .collect()
+        };
+
+        let apng = AnimatedFormat::Apng.convert(&gif).unwrap();
+        let decoder = image::codecs::png::PngDecoder::new(Cursor::new(&apng)).unwrap();
+        let frames = decoder.apng().unwrap().into_frames();
+        assert_eq!(delays(frames.collect_frames().unwrap()), [100, 250]);
+
+        let webp = AnimatedFormat::Webp.convert(&gif).unwrap();
+        assert_eq!(sniff_image_format(&webp), Some("webp"));
+        let decoder = image::codecs::webp::WebPDecoder::new(Cursor::new(&webp)).unwrap();
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(frames[1].buffer().get_pixel(0, 0).0, [255, 255, 255, 255]);
+        assert_eq!(delays(frames), [100, 250]);
+
+        assert!(AnimatedFormat::Webp.convert(b"GIF89a garbage").is_err());
+    }
+
+    #[test]
+    fn test_optimize_gif() {
+        // 64x64 frames that differ in a single pixel, each stored in full
+        let mut gif = Vec::new();
+        let mut encoder = gif::Encoder::new(&mut gif, 64, 64, &[]).unwrap();
+        encoder.set_repeat(gif::Repeat::Infinite).unwrap();
+        for frame in 0..4u8 {
+            let mut pixels: Vec<u8> = (0..64 * 64)
+                .flat_map(|i| [(i % 7 * 30) as u8, (i % 5 * 50) as u8, 0, 255])
+                .collect();
+            pixels[..4].copy_from_slice(&[frame * 60, 0, 255, 255]);
+            let mut frame = gif::Frame::from_rgba(64, 64, &mut pixels);
+            frame.delay = 5;
+            encoder.write_frame(&frame).unwrap();
+        }
+        encoder.into_inner().unwrap();
+
+        let optimized = optimize_gif(&gif).unwrap().unwrap();
+        assert!(optimized.len() < gif.len());
+        let original = Animation::decode(&gif).unwrap();
+        let decoded = Animation::decode(&optimized).unwrap();
+        assert_eq!(decoded.plays, 0);
+        assert_eq!(decoded.frames, original.frames);
+
+        // Already as small as it gets
+        assert!(optimize_gif(&two_frame_gif()).unwrap().is_none());
+    }
+}
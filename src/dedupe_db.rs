@@ -0,0 +1,112 @@
+//! Persistent record of extracted image content (`--dedupe-db`)
+//!
+//! A plain text file with one `sha256<TAB>output path` line per unique image ever written.
+//! Loading it before a run makes images extracted by earlier runs count as duplicates, so
+//! re-running over a growing library only writes new images.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::common::{Digest, to_hex};
+
+/// Reads the database at `path`. A missing file is an empty database.
+pub fn load(path: &Path) -> Result<HashMap<Digest, PathBuf>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to read dedupe database: {}", path.display()));
+        }
+    };
+
+    let mut known = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (hex, output) = line.split_once('\t').unwrap_or((line, ""));
+        let digest = from_hex(hex.trim()).with_context(|| {
+            format!(
+                "Invalid hash on line {} of dedupe database {}",
+                number + 1,
+                path.display()
+            )
+        })?;
+        known.entry(digest).or_insert_with(|| PathBuf::from(output));
+    }
+    Ok(known)
+}
+
+/// Appends the entries of `written` that are not in `known` to the database at `path`
+pub fn append_new(
+    path: &Path,
+    known: &HashMap<Digest, PathBuf>,
+    written: &HashMap<Digest, PathBuf>,
+) -> Result<()> {
+    let mut new: Vec<_> = written
+        .iter()
+        .filter(|(digest, _)| !known.contains_key(*digest))
+        .collect();
+    if new.is_empty() {
+        return Ok(());
+    }
+    new.sort_by(|a, b| a.1.cmp(b.1));
+
+    let mut lines = String::new();
+    for (digest, output) in new {
+        lines.push_str(&format!("{}\t{}\n", to_hex(digest), output.display()));
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(lines.as_bytes()))
+        .with_context(|| format!("Failed to update dedupe database: {}", path.display()))
+}
+
+fn from_hex(hex: &str) -> Option<Digest> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut digest = [0u8; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::sha256;
+    use crate::common::tests::temp_dir;
+
+    #[test]
+    fn test_dedupe_db_round_trip() {
+        let dir = temp_dir("dedupe-db");
+        let path = dir.join("hashes.db");
+        assert!(load(&path).unwrap().is_empty());
+
+        let written = HashMap::from([
+            (sha256(b"a"), PathBuf::from("out/a.png")),
+            (sha256(b"b"), PathBuf::from("out/b.png")),
+        ]);
+        append_new(&path, &HashMap::new(), &written).unwrap();
+        let known = load(&path).unwrap();
+        assert_eq!(known, written);
+
+        // Only content not seen before is appended
+        let mut written = known.clone();
+        written.insert(sha256(b"c"), PathBuf::from("out/c.png"));
+        append_new(&path, &known, &written).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+
+        fs::write(&path, "not a hash\tout/x.png\n").unwrap();
+        assert!(load(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
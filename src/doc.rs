@@ -0,0 +1,426 @@
+//! Legacy Word (.doc) file processing module
+//!
+//! Binary Word files are OLE compound files. Pictures are stored as OfficeArt BLIP
+//! records: in the `Data` stream for inline pictures, and in the `WordDocument` or
+//! table stream for floating shapes. Rather than following every reference from the
+//! document text, those streams are scanned for BLIP records.
+
+use anyhow::{Context, Result};
+use flate2::read::ZlibDecoder;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use crate::cfb::CompoundFile;
+use crate::common::{DocumentMetadata, sha256};
+use crate::extract::ExtractOptions;
+use crate::metafile::packed_dib_file;
+use crate::output::OutputSink;
+use crate::sniff::sniff_image_format;
+
+/// Streams that can hold BLIP records
+const PICTURE_STREAMS: [&str; 4] = ["Data", "WordDocument", "1Table", "0Table"];
+
+/// `fEncrypted` flag in the FIB flags word at offset 0x0A of the `WordDocument` stream
+const FIB_ENCRYPTED: u16 = 0x0100;
+
+/// Property set stream holding the document's title, subject and author
+const SUMMARY_INFORMATION: &str = "\u{5}SummaryInformation";
+/// Property ids in the summary information property set
+const PID_CODEPAGE: u32 = 1;
+const PID_TITLE: u32 = 2;
+const PID_SUBJECT: u32 = 3;
+const PID_AUTHOR: u32 = 4;
+/// Property types of narrow (code page) and UTF-16 strings
+const VT_LPSTR: u32 = 0x1E;
+const VT_LPWSTR: u32 = 0x1F;
+
+/// Largest decompressed metafile accepted, guarding against decompression bombs
+const MAX_METAFILE_BYTES: u64 = 256 << 20;
+
+/// BLIP record types: (recType, record instances, extension, is metafile).
+/// An odd instance means the record carries a second 16-byte UID.
+const BLIP_TYPES: [(u16, &[u16], &str, bool); 6] = [
+    (0xF01A, &[0x3D4, 0x3D5], "emf", true),
+    (0xF01B, &[0x216, 0x217], "wmf", true),
+    (0xF01D, &[0x46A, 0x46B, 0x6E2, 0x6E3], "jpg", false),
+    (0xF01E, &[0x6E0, 0x6E1], "png", false),
+    (0xF01F, &[0x7A8, 0x7A9], "bmp", false),
+    (0xF029, &[0x6E4, 0x6E5], "tiff", false),
+];
+
+/// A decoded picture: `(extension, data)`
+type Picture = (&'static str, Vec<u8>);
+
+/// Size of the OfficeArtMetafileHeader preceding metafile data
+const METAFILE_HEADER_SIZE: usize = 34;
+/// `compression` value for deflate-compressed metafiles
+const COMPRESSION_DEFLATE: u8 = 0x00;
+
+fn le_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Processes a single .doc file, extracting images matching the allowed extensions.
+/// Returns the number of images extracted.
+pub fn process_file(
+    input_path: &Path,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let doc_name = input_path
+        .file_stem()
+        .context("Invalid filename")?
+        .to_string_lossy()
+        .to_string();
+
+    let file = fs::File::open(input_path)
+        .with_context(|| format!("Failed to open input file: {}", input_path.display()))?;
+
+    process_reader(file, input_path, &doc_name, sink, options)
+}
+
+/// Processes a .doc file from any reader.
+/// `input_path` is only used for messages; `doc_name` is the output base name.
+pub fn process_reader<R: Read>(
+    mut reader: R,
+    input_path: &Path,
+    doc_name: &str,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let mut data = Vec::new();
+    reader
+        .read_to_end(&mut data)
+        .with_context(|| format!("Failed to read {}", input_path.display()))?;
+
+    // Silently skip documents that don't match the metadata filter
+    if !options.document_filter.is_empty() {
+        let metadata = CompoundFile::parse(&data)
+            .ok()
+            .and_then(|cfb| cfb.read_stream(SUMMARY_INFORMATION))
+            .map(|stream| summary_metadata(&stream))
+            .unwrap_or_default();
+        if !options.document_filter.matches(&metadata) {
+            return Ok(0);
+        }
+    }
+
+    let images: Vec<(&str, Vec<u8>)> = collect_images(&data, input_path)?
+        .into_iter()
+        .filter(|(extension, _)| options.allowed_extensions.contains(*extension))
+        .collect();
+
+    let total_images = images.len();
+    if total_images > 0 {
+        sink.create_base_dir()?;
+
+        status!(
+            "Found {} image files in {}.",
+            total_images,
+            input_path.display()
+        );
+    }
+
+    for (seq_index, (extension, data)) in images.iter().enumerate() {
+        let output_path = sink.output_path(doc_name, seq_index, total_images, extension)?;
+
+        status!("Extracting to: {}", output_path.display());
+
+        sink.write_image(&output_path, data)?;
+    }
+
+    Ok(total_images)
+}
+
+/// Reads the title, author and subject from a `SummaryInformation` property set
+fn summary_metadata(stream: &[u8]) -> DocumentMetadata {
+    // The first section's offset follows the 28-byte header and its 16-byte FMTID
+    let Some(section) = le_u32(stream, 44).map(|offset| offset as usize) else {
+        return DocumentMetadata::default();
+    };
+    let count = le_u32(stream, section + 4).unwrap_or(0) as usize;
+    let property = |wanted: u32| {
+        (0..count.min(1024)).find_map(|i| {
+            let entry = section + 8 + i * 8;
+            (le_u32(stream, entry)? == wanted).then(|| le_u32(stream, entry + 4))?
+        })
+    };
+    let codepage = property(PID_CODEPAGE)
+        .and_then(|offset| le_u16(stream, section + offset as usize + 4))
+        .unwrap_or(1252);
+    let string = |pid: u32| {
+        let at = section + property(pid)? as usize;
+        let len = le_u32(stream, at + 4)? as usize;
+        let text = match le_u32(stream, at)? {
+            VT_LPWSTR => utf16_le(stream.get(at + 8..at + 8 + len.checked_mul(2)?)?),
+            VT_LPSTR => {
+                let bytes = stream.get(at + 8..at + 8 + len)?;
+                match codepage {
+                    1200 => utf16_le(bytes),
+                    65001 => String::from_utf8_lossy(bytes).into_owned(),
+                    _ => bytes.iter().map(|&b| b as char).collect(),
+                }
+            }
+            _ => return None,
+        };
+        let text = text.trim_end_matches('\0').trim();
+        Some(text.to_string()).filter(|t| !t.is_empty())
+    };
+    DocumentMetadata {
+        title: string(PID_TITLE),
+        author: string(PID_AUTHOR),
+        subject: string(PID_SUBJECT),
+        ..Default::default()
+    }
+}
+
+fn utf16_le(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Reads every picture in a binary Word file as `(extension, data)`, in stream order.
+/// Pictures stored more than once are returned once.
+pub fn collect_images(data: &[u8], input_path: &Path) -> Result<Vec<Picture>> {
+    let cfb = CompoundFile::parse(data)
+        .with_context(|| format!("Failed to read {}", input_path.display()))?;
+    let word_document = cfb
+        .read_stream("WordDocument")
+        .with_context(|| format!("{} is not a Word document", input_path.display()))?;
+    if le_u16(&word_document, 0x0A).is_some_and(|flags| flags & FIB_ENCRYPTED != 0) {
+        anyhow::bail!("{} is password-protected", input_path.display());
+    }
+
+    let mut seen = HashSet::new();
+    let mut images = Vec::new();
+    for name in PICTURE_STREAMS {
+        let stream = if name == "WordDocument" {
+            Some(word_document.clone())
+        } else {
+            cfb.read_stream(name)
+        };
+        for image in stream.iter().flat_map(|s| scan_blips(s)) {
+            if seen.insert(sha256(&image.1)) {
+                images.push(image);
+            }
+        }
+    }
+    Ok(images)
+}
+
+/// Finds BLIP records in a stream, returning their images as `(extension, data)`
+fn scan_blips(stream: &[u8]) -> Vec<Picture> {
+    let mut images = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= stream.len() {
+        match parse_blip(stream, pos) {
+            Some((image, end)) => {
+                images.extend(image);
+                pos = end;
+            }
+            None => pos += 1,
+        }
+    }
+    images
+}
+
+/// Parses a BLIP record at `pos`. Returns its image (if it decodes) and where the
+/// record ends, or `None` if no BLIP record starts there.
+fn parse_blip(stream: &[u8], pos: usize) -> Option<(Option<Picture>, usize)> {
+    let ver_inst = le_u16(stream, pos)?;
+    let rec_type = le_u16(stream, pos + 2)?;
+    let (_, instances, extension, metafile) =
+        BLIP_TYPES.iter().find(|(kind, ..)| *kind == rec_type)?;
+    let inst = ver_inst >> 4;
+    if ver_inst & 0xF != 0 || !instances.contains(&inst) {
+        return None;
+    }
+    let len = le_u32(stream, pos + 4)? as usize;
+    let end = (pos + 8).checked_add(len)?;
+    let body = stream.get(pos + 8..end)?;
+    let uid_len = if inst & 1 == 1 { 32 } else { 16 };
+
+    let image = if *metafile {
+        let header = body.get(uid_len..uid_len + METAFILE_HEADER_SIZE)?;
+        let size = le_u32(header, 0)? as u64;
+        let saved = le_u32(header, 28)? as usize;
+        let compressed = body.get(uid_len + METAFILE_HEADER_SIZE..)?;
+        let compressed = compressed.get(..saved).unwrap_or(compressed);
+        let data = if header[32] == COMPRESSION_DEFLATE {
+            let mut data = Vec::new();
+            ZlibDecoder::new(compressed)
+                .take(size.min(MAX_METAFILE_BYTES))
+                .read_to_end(&mut data)
+                .ok()?;
+            data
+        } else {
+            compressed.to_vec()
+        };
+        (sniff_image_format(&data) == Some(extension)).then_some((*extension, data))
+    } else {
+        // Bitmaps follow the UID(s) and a one-byte tag
+        let data = body.get(uid_len + 1..)?;
+        if *extension == "bmp" {
+            packed_dib_file(data)
+        } else {
+            (sniff_image_format(data) == Some(extension)).then(|| (*extension, data.to_vec()))
+        }
+    };
+    Some((image, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfb::tests::compound_file;
+    use crate::common::tests::temp_dir;
+    use crate::output::OutputOptions;
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    fn record(rec_type: u16, inst: u16, body: &[u8]) -> Vec<u8> {
+        let mut record = Vec::new();
+        record.extend_from_slice(&(inst << 4).to_le_bytes());
+        record.extend_from_slice(&rec_type.to_le_bytes());
+        record.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        record.extend_from_slice(body);
+        record
+    }
+
+    fn jpeg_blip(jpeg: &[u8]) -> Vec<u8> {
+        let mut body = vec![0x11; 16];
+        body.push(0xFF);
+        body.extend_from_slice(jpeg);
+        record(0xF01D, 0x46A, &body)
+    }
+
+    fn emf_blip(emf: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(emf).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut body = vec![0x22; 32];
+        let mut header = [0u8; METAFILE_HEADER_SIZE];
+        header[0..4].copy_from_slice(&(emf.len() as u32).to_le_bytes());
+        header[28..32].copy_from_slice(&(compressed.len() as u32).to_le_bytes());
+        header[32] = COMPRESSION_DEFLATE;
+        header[33] = 0xFE;
+        body.extend_from_slice(&header);
+        body.extend_from_slice(&compressed);
+        record(0xF01A, 0x3D5, &body)
+    }
+
+    fn word_document(flags: u16) -> Vec<u8> {
+        let mut fib = vec![0u8; 64];
+        fib[0..2].copy_from_slice(&0xA5ECu16.to_le_bytes());
+        fib[0x0A..0x0C].copy_from_slice(&flags.to_le_bytes());
+        fib
+    }
+
+    #[test]
+    fn test_extract_doc_pictures() {
+        let dir = temp_dir("doc");
+
+        let jpeg = b"\xFF\xD8\xFF\xE0legacy jpeg".to_vec();
+        let mut emf = vec![0u8; 64];
+        emf[0] = 1;
+        emf[40..44].copy_from_slice(b" EMF");
+
+        let mut data_stream = b"PICF header bytes".to_vec();
+        data_stream.extend(jpeg_blip(&jpeg));
+        data_stream.extend(emf_blip(&emf));
+        // The same picture referenced again from the drawing group is not duplicated
+        let mut table = b"table".to_vec();
+        table.extend(jpeg_blip(&jpeg));
+        let file = compound_file(&[
+            ("WordDocument", &word_document(0)),
+            ("1Table", &table),
+            ("Data", &data_stream),
+        ]);
+
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count = process_reader(
+            file.as_slice(),
+            Path::new("old.doc"),
+            "old",
+            &mut sink,
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(std::fs::read(dir.join("old_1.jpg")).unwrap(), jpeg);
+        assert_eq!(std::fs::read(dir.join("old_2.emf")).unwrap(), emf);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_doc_is_reported() {
+        let file = compound_file(&[("WordDocument", &word_document(FIB_ENCRYPTED))]);
+        let err = collect_images(&file, Path::new("secret.doc")).unwrap_err();
+        assert!(err.to_string().contains("password-protected"));
+    }
+
+    #[test]
+    fn test_summary_metadata() {
+        // Header with one section at offset 48
+        let mut stream = vec![0u8; 48];
+        stream[44..48].copy_from_slice(&48u32.to_le_bytes());
+        let properties: [(u32, Vec<u8>); 3] = [
+            (
+                PID_CODEPAGE,
+                [2u32.to_le_bytes(), 1252u32.to_le_bytes()].concat(),
+            ),
+            (
+                PID_TITLE,
+                [
+                    &VT_LPSTR.to_le_bytes()[..],
+                    &8u32.to_le_bytes(),
+                    b"Minut\xe9s\0",
+                ]
+                .concat(),
+            ),
+            (
+                PID_AUTHOR,
+                [
+                    &VT_LPWSTR.to_le_bytes()[..],
+                    &3u32.to_le_bytes(),
+                    b"L\0i\0\0\0",
+                ]
+                .concat(),
+            ),
+        ];
+        let mut section = Vec::new();
+        section.extend_from_slice(&0u32.to_le_bytes());
+        section.extend_from_slice(&(properties.len() as u32).to_le_bytes());
+        let mut offset = 8 + properties.len() * 8;
+        let mut values = Vec::new();
+        for (pid, value) in &properties {
+            section.extend_from_slice(&pid.to_le_bytes());
+            section.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += value.len();
+            values.extend_from_slice(value);
+        }
+        stream.extend(section);
+        stream.extend(values);
+
+        let metadata = summary_metadata(&stream);
+        assert_eq!(metadata.title.as_deref(), Some("Minutés"));
+        assert_eq!(metadata.author.as_deref(), Some("Li"));
+        assert_eq!(metadata.subject, None);
+    }
+}
@@ -1,22 +1,48 @@
-//! DOCX file processing module
+//! OOXML (.docx, .pptx, .xlsx) file processing module
 
 use anyhow::{Context, Result};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::Read;
+use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::path::Path;
 use zip::ZipArchive;
 
+use crate::cfb::CFB_SIGNATURE;
 use crate::common::{
-    ImageToExtract, get_unique_output_path, is_safe_archive_path, write_image_to_file,
+    ImageToExtract, declared_extension, dublin_core_metadata, get_supported_extensions,
+    is_safe_archive_path, prompt_password,
 };
+use crate::docx_layout::{
+    EmbeddedObject, Placement, anchor_notes, appearance_key, note_kind, parse_objects,
+    parse_placements, parse_relationships, rels_path, section,
+};
+use crate::epub;
+use crate::extract::{self, CoverFallback, ExtractOptions, nested_base_name};
+use crate::json::Json;
+use crate::messages::{self, Message};
+use crate::metafile::embedded_bitmap;
+use crate::office_crypto::{decrypt_package, is_encrypted_package};
+use crate::output::{ImageDetails, OutputSink};
+use crate::raster::size_rank;
+use crate::sniff::{detect_extension, format_mismatch, read_head};
+use crate::timings::{self, Phase};
+
+/// Archive path prefix of the OOXML package thumbnail (`docProps/thumbnail.jpeg`, `.emf`, `.wmf`)
+const THUMBNAIL_PREFIX: &str = "docProps/thumbnail.";
+/// Main part of a Word document. Images of packages that have one are numbered in reading
+/// order, and `--referenced-only` and `--sections` apply to them.
+const MAIN_DOCUMENT: &str = "word/document.xml";
+/// Core properties part holding the document's title, author and subject
+const CORE_PROPERTIES: &str = "docProps/core.xml";
+/// Archive folder of embedded OLE objects and packages in a Word document
+const EMBEDDINGS_PREFIX: &str = "word/embeddings/";
 
 /// Processes a single .docx file, extracting images matching the allowed extensions.
 /// Returns the number of images extracted.
 pub fn process_file(
     input_path: &Path,
-    output_base_dir: &Path,
-    allowed_extensions: &HashSet<&str>,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
 ) -> Result<usize> {
     let doc_name = input_path
         .file_stem()
@@ -26,66 +52,591 @@ pub fn process_file(
 
     let file = fs::File::open(input_path)
         .with_context(|| format!("Failed to open input file: {}", input_path.display()))?;
-    let mut archive = ZipArchive::new(file)
+
+    process_reader(file, input_path, &doc_name, 0, sink, options)
+}
+
+/// Processes a .docx package from any seekable reader.
+/// `input_path` is only used for messages; `doc_name` is the output base name and
+/// `depth` is the embedding depth (0 for a top-level document).
+pub fn process_reader<R: Read + Seek>(
+    mut reader: R,
+    input_path: &Path,
+    doc_name: &str,
+    depth: usize,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    // Password-protected packages are zips wrapped in an encrypted OLE container
+    let mut magic = [0u8; 8];
+    let is_compound_file = reader.read_exact(&mut magic).is_ok() && magic == CFB_SIGNATURE;
+    reader.seek(SeekFrom::Start(0))?;
+    if is_compound_file {
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to read {}", input_path.display()))?;
+        let package = decrypt(&data, input_path, options)?;
+        return process_reader(
+            Cursor::new(package),
+            input_path,
+            doc_name,
+            depth,
+            sink,
+            options,
+        );
+    }
+
+    let opening = timings::start();
+    let mut archive = ZipArchive::new(reader)
         .with_context(|| format!("Failed to read zip archive: {}", input_path.display()))?;
+    options.limits.check_archive(&mut archive, input_path)?;
+    sink.record_time(Phase::Open, opening);
+    let scanning = timings::start();
+
+    // Silently skip documents that don't match the metadata filter
+    if !options.document_filter.is_empty() {
+        let metadata = read_entry_string(&mut archive, CORE_PROPERTIES)
+            .map(|xml| dublin_core_metadata(&xml))
+            .unwrap_or_default();
+        if !options.document_filter.matches(&metadata) {
+            return Ok(0);
+        }
+    }
 
     let mut images: Vec<ImageToExtract> = Vec::new();
+    let mut embedded_documents: Vec<usize> = Vec::new();
+    let mut thumbnail: Option<usize> = None;
+    let supported = get_supported_extensions(&options.extra_formats);
 
     for i in 0..archive.len() {
-        let file = archive.by_index(i)?;
-        let name = file.name();
+        let listed = archive.name_for_index(i).unwrap_or_default().to_string();
+        let mut file = match archive.by_index(i) {
+            Ok(file) => file,
+            Err(e) => {
+                extract::skip_damaged(e.into(), &listed, sink, options)?;
+                continue;
+            }
+        };
+        let name = file.name().to_string();
+        let name = name.as_str();
 
         // Defense-in-depth: skip entries with path traversal patterns
         if !is_safe_archive_path(name) {
             continue;
         }
 
-        // Check if file has an extension and if it's in our allowed list
+        // The package thumbnail is written under its own name rather than numbered with the body
+        if (options.doc_thumbnail || options.cover_only) && name.starts_with(THUMBNAIL_PREFIX) {
+            thumbnail = Some(i);
+            continue;
+        }
+
+        if options.recurse_embedded && extract::is_embedded_document(name, options) {
+            embedded_documents.push(i);
+            continue;
+        }
+
+        // Check if file has an image extension and if its format is in our allowed list
         if let Some(ext) = Path::new(name).extension().and_then(|e| e.to_str()) {
             let ext_lower = ext.to_lowercase();
-            if allowed_extensions.contains(ext_lower.as_str()) {
+            if !supported.contains(ext_lower.as_str()) {
+                continue;
+            }
+            let extension = if options.trust_extension {
+                ext_lower
+            } else {
+                let head = match read_head(&mut file).context("Failed to read image from archive") {
+                    Ok(head) => head,
+                    Err(e) => {
+                        extract::skip_damaged(e, &listed, sink, options)?;
+                        continue;
+                    }
+                };
+                detect_extension(&ext_lower, &head, &options.extra_formats)
+            };
+            if options.allowed_extensions.contains(extension.as_str()) {
                 images.push(ImageToExtract {
                     index: i,
-                    extension: ext_lower,
+                    extension,
                 });
             }
         }
     }
 
-    if images.is_empty() {
-        return Ok(0);
+    let is_word = archive.index_for_name(MAIN_DOCUMENT).is_some();
+    let wants_objects = is_word && options.embedded_objects;
+    let (placements, objects) =
+        if (is_word || sink.records_manifest()) && (!images.is_empty() || wants_objects) {
+            collect_layout(&mut archive)
+        } else {
+            (HashMap::new(), Vec::new())
+        };
+    let objects = if wants_objects {
+        embedded_objects(&archive, objects, options.embedded_object_files)
+    } else {
+        Vec::new()
+    };
+
+    // Object previews are written next to their objects rather than numbered with the body
+    if !objects.is_empty() {
+        images.retain(|image| {
+            let name = archive.name_for_index(image.index).unwrap_or_default();
+            !objects
+                .iter()
+                .any(|object| object.preview.as_deref() == Some(name))
+        });
+    }
+
+    // Number Word images in reading order rather than archive order
+    if is_word {
+        images.sort_by_cached_key(|image| {
+            let name = archive.name_for_index(image.index).unwrap_or_default();
+            appearance_key(placements.get(name), MAIN_DOCUMENT)
+        });
+    }
+
+    // Media left behind by edits is not drawn anywhere in the document
+    if options.referenced_only && is_word {
+        let before = images.len();
+        images.retain(|image| {
+            archive
+                .name_for_index(image.index)
+                .is_some_and(|name| placements.contains_key(name))
+        });
+        if images.len() < before {
+            status!(
+                "{}",
+                messages::text(
+                    Message::SkippingUnreferenced,
+                    &[
+                        ("count", &(before - images.len())),
+                        ("path", &input_path.display())
+                    ]
+                )
+            );
+        }
+    }
+
+    // Forensic mode: keep only entries whose content disagrees with their extension
+    if options.only_mismatched {
+        let mut mismatched = Vec::new();
+        for image in images {
+            let file = archive.by_index(image.index)?;
+            let entry_name = file.name().to_string();
+            let data = options
+                .limits
+                .read_entry(file, &entry_name)
+                .context("Failed to read image from archive")?;
+            let declared = declared_extension(&entry_name);
+            if let Some(actual) = format_mismatch(&declared, &data, &options.extra_formats) {
+                status!(
+                    "Format mismatch: {} is declared as {} but contains {}",
+                    entry_name,
+                    declared,
+                    actual
+                );
+                mismatched.push(image);
+            }
+        }
+        images = mismatched;
     }
+    sink.record_time(Phase::Scan, scanning);
 
-    // create_dir_all is idempotent - succeeds if directory exists
-    fs::create_dir_all(output_base_dir).context("Failed to create output directory")?;
+    if options.cover_only {
+        if let Some(count) = extract_cover(
+            &mut archive,
+            thumbnail,
+            &images,
+            input_path,
+            doc_name,
+            sink,
+            options,
+        )? {
+            return Ok(count);
+        }
+        if options.cover_fallback != Some(CoverFallback::All) {
+            status!("No cover image found in {}", input_path.display());
+            return Ok(0);
+        }
+        status!(
+            "No cover image found in {}, falling back to extracting all images.",
+            input_path.display()
+        );
+        thumbnail = None;
+    }
 
     let total_images = images.len();
-    println!(
-        "Found {} image files in {}.",
-        total_images,
-        input_path.display()
-    );
+    if total_images > 0 {
+        sink.create_base_dir()?;
+
+        status!(
+            "Found {} image files in {}.",
+            total_images,
+            input_path.display()
+        );
+    }
 
     for (seq_index, image) in images.iter().enumerate() {
         let mut file = archive.by_index(image.index)?;
+        let entry_name = file.name().to_string();
+        // Salvaging reads the entry whole first, so a damaged one is skipped before its
+        // output file is created
+        let data = match options.salvage {
+            true => match options.limits.read_entry(&mut file, &entry_name) {
+                Ok(data) => Some(data),
+                Err(e) => {
+                    extract::skip_damaged(e, &entry_name, sink, options)?;
+                    continue;
+                }
+            },
+            false => None,
+        };
 
-        let output_path = get_unique_output_path(
-            output_base_dir,
-            &doc_name,
+        let placed = placements.get(&entry_name);
+        let details = ImageDetails {
+            original: Some(&entry_name),
+            section: (options.sections && is_word).then(|| section(placed, MAIN_DOCUMENT)),
+            alt_text: placed
+                .and_then(|list| list.iter().find_map(|p| p.alt_text.as_deref()))
+                .filter(|_| sink.names_from_alt()),
+            suffix: (options.tag_notes && is_word)
+                .then(|| note_kind(section(placed, MAIN_DOCUMENT)))
+                .flatten(),
+        };
+        let output_path = sink.detailed_output_path(
+            doc_name,
             seq_index,
             total_images,
             &image.extension,
+            details,
         )?;
 
-        println!("Extracting to: {}", output_path.display());
+        status!("Extracting to: {}", output_path.display());
+        sink.set_entry_time(&output_path, file.last_modified());
 
-        // Read archive entry into memory and use shared write function
-        let mut data = Vec::new();
-        file.read_to_end(&mut data)
-            .context("Failed to read image from archive")?;
+        match data {
+            Some(data) => sink.write_image(&output_path, &data)?,
+            None => {
+                // Stream the entry so very large images never have to fit in memory
+                let size = file.size();
+                let mut reader = options.limits.limit(file, &entry_name);
+                sink.write_image_from_reader(&output_path, &mut reader, size)
+                    .with_context(|| format!("Failed to extract {}", entry_name))?;
+            }
+        }
+        if sink.records_manifest() {
+            let layout = placed
+                .map(|list| list.iter().map(Placement::to_json).collect())
+                .unwrap_or_default();
+            sink.annotate("layout", Json::Array(layout));
+            if is_word {
+                sink.annotate("section", Json::from(section(placed, MAIN_DOCUMENT)));
+            }
+            let placed = placed.map(Vec::as_slice).unwrap_or_default();
+            if let Some(alt_text) = placed.iter().find_map(|p| p.alt_text.clone()) {
+                sink.annotate("alt_text", Json::from(alt_text));
+            }
+            if let Some(caption) = placed.iter().find_map(|p| p.caption.clone()) {
+                sink.annotate("caption", Json::from(caption));
+            }
+        }
+    }
+
+    let mut extracted = total_images;
+    if !objects.is_empty() {
+        sink.create_base_dir()?;
+        extracted += extract_objects(&mut archive, &objects, doc_name, sink, options)?;
+    }
+    if let Some(index) = thumbnail {
+        extract_thumbnail(&mut archive, index, doc_name, sink, options)?;
+        extracted += 1;
+    }
+
+    for index in embedded_documents {
+        let reading = timings::start();
+        let file = archive.by_index(index)?;
+        let entry_name = file.name().to_string();
+        let data = options
+            .limits
+            .read_entry(file, &entry_name)
+            .context("Failed to read embedded document from archive")?;
+        sink.record_time(Phase::Read, reading);
+
+        let virtual_path = input_path.join(&entry_name);
+        let base_name = nested_base_name(doc_name, &entry_name);
+        match extract::process_embedded(data, &virtual_path, &base_name, depth + 1, sink, options) {
+            Ok(count) => extracted += count,
+            Err(e) => sink.warn(&format!("Skipping {}: {}", virtual_path.display(), e)),
+        }
+    }
+
+    Ok(extracted)
+}
+
+/// Decrypts a password-protected package with `--password`, or a password asked for on
+/// the terminal
+fn decrypt(data: &[u8], input_path: &Path, options: &ExtractOptions) -> Result<Vec<u8>> {
+    if !is_encrypted_package(data) {
+        anyhow::bail!(
+            "{} is an OLE compound file, not an Office Open XML package",
+            input_path.display()
+        );
+    }
+    let password = match &options.password {
+        Some(password) => password.clone(),
+        None if options.prompt_password => {
+            prompt_password(&format!("Password for {}: ", input_path.display()))?
+        }
+        None => anyhow::bail!(
+            "{} is password-protected; pass --password to decrypt it",
+            input_path.display()
+        ),
+    };
+    status!("Decrypting {}", input_path.display());
+    decrypt_package(data, &password)
+        .with_context(|| format!("Failed to decrypt {}", input_path.display()))
+}
+
+/// With `--cover-only`, writes the package thumbnail or else the first image (in reading
+/// order for Word documents), or the largest with `--cover-fallback=largest`, named after
+/// the document alone like ebook covers. Returns None if the package has no images.
+fn extract_cover<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    thumbnail: Option<usize>,
+    images: &[ImageToExtract],
+    input_path: &Path,
+    doc_name: &str,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<Option<usize>> {
+    let (extension, data, source) = if let Some(index) = thumbnail {
+        let (extension, data) = read_thumbnail(archive, index, options)?;
+        (extension, data, "thumbnail")
+    } else if options.cover_fallback == Some(CoverFallback::Largest) {
+        let mut largest: Option<(String, Vec<u8>)> = None;
+        for image in images {
+            let data = read_image(archive, image.index, options)?;
+            if largest
+                .as_ref()
+                .is_none_or(|(_, best)| size_rank(&data) > size_rank(best))
+            {
+                largest = Some((image.extension.clone(), data));
+            }
+        }
+        let Some((extension, data)) = largest else {
+            return Ok(None);
+        };
+        (extension, data, "largest")
+    } else if let Some(image) = images.first() {
+        let data = read_image(archive, image.index, options)?;
+        (image.extension.clone(), data, "first-image")
+    } else {
+        return Ok(None);
+    };
+
+    let written = epub::write_cover(&extension, &data, doc_name, input_path, sink, options)?;
+    if written > 0 {
+        sink.annotate("cover_source", Json::from(source));
+    }
+    Ok(Some(written))
+}
+
+/// Reads a whole archive entry into memory
+fn read_image<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    index: usize,
+    options: &ExtractOptions,
+) -> Result<Vec<u8>> {
+    let file = archive.by_index(index)?;
+    let entry_name = file.name().to_string();
+    options
+        .limits
+        .read_entry(file, &entry_name)
+        .context("Failed to read image from archive")
+}
+
+/// Reads the package thumbnail, returning its extension and data.
+/// With `rasterize_thumbnail`, EMF/WMF thumbnails are replaced by the bitmap they draw.
+fn read_thumbnail<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    index: usize,
+    options: &ExtractOptions,
+) -> Result<(String, Vec<u8>)> {
+    let file = archive.by_index(index)?;
+    let entry_name = file.name().to_string();
+    let mut data = options
+        .limits
+        .read_entry(file, &entry_name)
+        .context("Failed to read package thumbnail from archive")?;
+    let mut extension = Path::new(&entry_name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_else(|| "bin".to_string());
+
+    if options.rasterize_thumbnail && matches!(extension.as_str(), "emf" | "wmf") {
+        match embedded_bitmap(&data) {
+            Some((bitmap_extension, bitmap)) => {
+                extension = bitmap_extension.to_string();
+                data = bitmap;
+            }
+            None => status!(
+                "Package thumbnail {} contains no bitmap; keeping the metafile.",
+                entry_name
+            ),
+        }
+    }
+    Ok((extension, data))
+}
+
+/// Writes the package thumbnail as `{doc}_thumbnail.{ext}`
+fn extract_thumbnail<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    index: usize,
+    doc_name: &str,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<()> {
+    let (extension, data) = read_thumbnail(archive, index, options)?;
+    sink.create_base_dir()?;
+    let output_path = sink.output_path(&format!("{}_thumbnail", doc_name), 0, 1, &extension)?;
+    status!("Extracting package thumbnail to: {}", output_path.display());
+    sink.write_image(&output_path, &data)
+}
 
-        write_image_to_file(&output_path, &data)?;
+/// Objects to extract with `--embedded-objects`, in reading order. With `files`, embedded
+/// files that are not drawn as an object (such as the workbooks behind charts) are added
+/// at the end.
+fn embedded_objects<R: Read + Seek>(
+    archive: &ZipArchive<R>,
+    mut objects: Vec<EmbeddedObject>,
+    files: bool,
+) -> Vec<EmbeddedObject> {
+    objects.sort_by(|a, b| {
+        (a.part != MAIN_DOCUMENT, &a.part, a.offset).cmp(&(
+            b.part != MAIN_DOCUMENT,
+            &b.part,
+            b.offset,
+        ))
+    });
+    let mut seen = HashSet::new();
+    objects.retain(|object| seen.insert(object.object.clone()));
+
+    if files {
+        let mut others: Vec<&str> = archive
+            .file_names()
+            .filter(|name| name.starts_with(EMBEDDINGS_PREFIX) && !name.ends_with('/'))
+            .filter(|name| is_safe_archive_path(name) && !seen.contains(*name))
+            .collect();
+        others.sort();
+        objects.extend(others.into_iter().map(|name| EmbeddedObject {
+            part: String::new(),
+            offset: 0,
+            object: name.to_string(),
+            preview: None,
+            prog_id: None,
+        }));
     }
+    objects
+}
+
+/// Writes each object's preview as `{doc}_object_{n}_preview.{ext}` and, with
+/// `embedded_object_files`, the object itself as `{doc}_object_{n}.{ext}`.
+/// Returns the number of files written.
+fn extract_objects<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    objects: &[EmbeddedObject],
+    doc_name: &str,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let mut written = 0;
+    for (index, object) in objects.iter().enumerate() {
+        let base_name = format!("{}_object_{}", doc_name, index + 1);
+
+        if let Some(preview) = &object.preview
+            && let Some(data) = read_entry_bytes(archive, preview, options)
+        {
+            let declared = declared_extension(preview);
+            let extension = if options.trust_extension {
+                declared
+            } else {
+                detect_extension(&declared, &data, &options.extra_formats)
+            };
+            let output_path =
+                sink.output_path(&format!("{}_preview", base_name), 0, 1, &extension)?;
+            status!("Extracting object preview to: {}", output_path.display());
+            sink.write_image(&output_path, &data)?;
+            sink.annotate("object", Json::from(object.object.as_str()));
+            sink.annotate("prog_id", Json::from(object.prog_id.clone()));
+            written += 1;
+        }
+
+        if options.embedded_object_files
+            && let Some(data) = read_entry_bytes(archive, &object.object, options)
+        {
+            let extension = Path::new(&object.object)
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_else(|| "bin".to_string());
+            let output_path = sink.output_path(&base_name, 0, 1, &extension)?;
+            status!("Extracting embedded object to: {}", output_path.display());
+            sink.write_image(&output_path, &data)?;
+            sink.annotate("prog_id", Json::from(object.prog_id.clone()));
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+/// Maps each media entry to where it is drawn in the main document, headers,
+/// footers and notes, and lists the embedded objects in those parts.
+/// Parts that can't be read are skipped.
+fn collect_layout<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+) -> (HashMap<String, Vec<Placement>>, Vec<EmbeddedObject>) {
+    let mut parts: Vec<String> = archive
+        .file_names()
+        .filter(|name| {
+            name.strip_prefix("word/")
+                .is_some_and(|rest| !rest.contains('/') && rest.ends_with(".xml"))
+        })
+        .map(str::to_string)
+        .collect();
+    parts.sort();
+
+    let main_xml = read_entry_string(archive, MAIN_DOCUMENT).unwrap_or_default();
+    let mut placements = HashMap::new();
+    let mut objects = Vec::new();
+    for part in parts {
+        let (Some(rels_xml), Some(part_xml)) = (
+            read_entry_string(archive, &rels_path(&part)),
+            read_entry_string(archive, &part),
+        ) else {
+            continue;
+        };
+        let relationships = parse_relationships(&part, &rels_xml);
+        parse_placements(&part, &part_xml, &relationships, &mut placements);
+        anchor_notes(&part, &part_xml, &main_xml, &mut placements);
+        objects.extend(parse_objects(&part, &part_xml, &relationships));
+    }
+    (placements, objects)
+}
+
+/// Reads a named archive entry within the resource limits, if present
+fn read_entry_bytes<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+    options: &ExtractOptions,
+) -> Option<Vec<u8>> {
+    let file = archive.by_name(name).ok()?;
+    options.limits.read_entry(file, name).ok()
+}
 
-    Ok(total_images)
+/// Reads a named archive entry as text, if present
+fn read_entry_string<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Option<String> {
+    let mut file = archive.by_name(name).ok()?;
+    let mut text = String::new();
+    file.read_to_string(&mut text).ok()?;
+    Some(text)
 }
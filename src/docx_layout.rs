@@ -0,0 +1,521 @@
+//! Placement of images within DOCX parts (inline vs anchored, wrapping, size)
+//!
+//! Drawings in `word/*.xml` reference media through the part's relationships file;
+//! this maps each media entry back to every place it is drawn. Embedded OLE objects
+//! (workbooks, Visio drawings, ...) are found the same way, together with their previews.
+
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use crate::common::{resolve_reference, unescape_xml};
+use crate::json::Json;
+
+static RELATIONSHIP_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<(?:\w+:)?Relationship\b[^>]*>").unwrap());
+static ATTRIBUTE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"([\w:]+)\s*=\s*["']([^"']*)["']"#).unwrap());
+/// Start of a paragraph (but not `<w:pPr>` and friends)
+static PARAGRAPH_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<w:p[\s>/]").unwrap());
+static DRAWING_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<w:drawing\b.*?</w:drawing>|<w:pict\b.*?</w:pict>").unwrap());
+static PLACEMENT_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<wp:(inline|anchor)\b([^>]*)>").unwrap());
+static EXTENT_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<wp:extent\b([^>]*)/?>").unwrap());
+static WRAP_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<wp:wrap(\w+)\b").unwrap());
+static BLIP_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<a:blip\b[^>]*\br:embed\s*=\s*["']([^"']+)["']"#).unwrap());
+static DOC_PR_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<wp:docPr\b([^>]*)>").unwrap());
+static VML_SHAPE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<v:shape\b([^>]*)>").unwrap());
+static TEXT_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<w:t(?:\s[^>]*)?>(.*?)</w:t>").unwrap());
+/// Marks of a caption paragraph: the built-in Caption style or a SEQ numbering field
+static CAPTION_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"<w:pStyle\s+w:val\s*=\s*["']Caption["']|<w:instrText\b[^>]*>\s*SEQ\s|w:instr\s*=\s*["']\s*SEQ\s"#)
+        .unwrap()
+});
+static OBJECT_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<w:object\b.*?</w:object>").unwrap());
+static OLE_OBJECT_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<o:OLEObject\b([^>]*)>").unwrap());
+static VML_IMAGE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<v:imagedata\b[^>]*\br:id\s*=\s*["']([^"']+)["']"#).unwrap());
+/// Start of a footnote, endnote or comment in its part (not `w:footnotePr` and friends)
+static NOTE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<w:(footnote|endnote|comment)\s([^>]*)>").unwrap());
+/// Reference to a footnote, endnote or comment from the text
+static NOTE_REFERENCE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<w:(footnote|endnote|comment)Reference\s([^>]*)>").unwrap());
+
+/// How a drawing is positioned relative to the text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    /// Part the drawing appears in, e.g. `word/document.xml`
+    pub part: String,
+    /// Zero-based index of the containing paragraph within the part
+    pub paragraph: usize,
+    /// Byte offset of the drawing within the part, for ordering drawings
+    pub offset: usize,
+    /// `inline`, `anchor` or `vml` (legacy `w:pict`)
+    pub kind: &'static str,
+    /// Text wrapping: `inline`, `square`, `tight`, `through`, `top-and-bottom`,
+    /// `behind-text` or `in-front-of-text`
+    pub wrap: Option<String>,
+    /// Displayed size in EMUs (914400 per inch)
+    pub width_emu: Option<u64>,
+    pub height_emu: Option<u64>,
+    /// Alternative text (`descr` of `wp:docPr`, `alt` of a VML shape)
+    pub alt_text: Option<String>,
+    /// Text of the caption paragraph right after (or else right before) the drawing
+    pub caption: Option<String>,
+    /// For a drawing in a footnote, endnote or comment, the byte offset in the main
+    /// document of the reference to that note
+    pub anchor: Option<usize>,
+}
+
+impl Placement {
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("part", Json::from(self.part.as_str())),
+            ("paragraph", Json::from(self.paragraph)),
+            ("placement", Json::from(self.kind)),
+            ("wrap", Json::from(self.wrap.clone())),
+            ("width_emu", Json::from(self.width_emu)),
+            ("height_emu", Json::from(self.height_emu)),
+        ])
+    }
+}
+
+/// An embedded OLE object and the image Word shows in its place
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddedObject {
+    /// Part the object appears in, e.g. `word/document.xml`
+    pub part: String,
+    /// Byte offset of the object within the part, for ordering objects
+    pub offset: usize,
+    /// Archive path of the object, e.g. `word/embeddings/oleObject1.bin`
+    pub object: String,
+    /// Archive path of the preview image, usually an EMF
+    pub preview: Option<String>,
+    /// Program that created the object, e.g. `Excel.Sheet.12` or `Visio.Drawing.15`
+    pub prog_id: Option<String>,
+}
+
+pub(crate) fn attributes(tag: &str) -> HashMap<&str, &str> {
+    ATTRIBUTE_RE
+        .captures_iter(tag)
+        .map(|c| {
+            let (_, [key, value]) = c.extract();
+            (key, value)
+        })
+        .collect()
+}
+
+/// Returns the `_rels` path for a part: `word/document.xml` -> `word/_rels/document.xml.rels`
+pub fn rels_path(part: &str) -> String {
+    match part.rsplit_once('/') {
+        Some((dir, name)) => format!("{}/_rels/{}.rels", dir, name),
+        None => format!("_rels/{}.rels", part),
+    }
+}
+
+/// Parses a relationships file into relationship id -> archive path of the target.
+/// External targets (hyperlinks, linked images) are left out.
+pub fn parse_relationships(part: &str, rels_xml: &str) -> HashMap<String, String> {
+    RELATIONSHIP_RE
+        .find_iter(rels_xml)
+        .filter_map(|tag| {
+            let attrs = attributes(tag.as_str());
+            if attrs.get("TargetMode") == Some(&"External") {
+                return None;
+            }
+            let target = resolve_reference(part, attrs.get("Target")?)?;
+            Some((attrs.get("Id")?.to_string(), target))
+        })
+        .collect()
+}
+
+/// Finds every drawing in a part and maps the media archive path to its placements
+pub fn parse_placements(
+    part: &str,
+    part_xml: &str,
+    relationships: &HashMap<String, String>,
+    placements: &mut HashMap<String, Vec<Placement>>,
+) {
+    let paragraph_starts: Vec<usize> = PARAGRAPH_RE
+        .find_iter(part_xml)
+        .map(|m| m.start())
+        .collect();
+
+    for drawing in DRAWING_RE.find_iter(part_xml) {
+        let block = drawing.as_str();
+        let paragraph = paragraph_starts
+            .partition_point(|&start| start < drawing.start())
+            .saturating_sub(1);
+
+        let (kind, wrap, extent) = match PLACEMENT_RE.captures(block) {
+            Some(c) if &c[1] == "inline" => ("inline", Some("inline".to_string()), true),
+            Some(c) => {
+                let behind = attributes(&c[2]).get("behindDoc") == Some(&"1");
+                let wrap = WRAP_RE.captures(block).map(|w| match &w[1] {
+                    "None" if behind => "behind-text".to_string(),
+                    "None" => "in-front-of-text".to_string(),
+                    "TopAndBottom" => "top-and-bottom".to_string(),
+                    other => other.to_lowercase(),
+                });
+                ("anchor", wrap, true)
+            }
+            None => ("vml", None, false),
+        };
+
+        let (width_emu, height_emu) = match EXTENT_RE.captures(block).filter(|_| extent) {
+            Some(c) => {
+                let attrs = attributes(c.get(1).map_or("", |m| m.as_str()));
+                (
+                    attrs.get("cx").and_then(|v| v.parse().ok()),
+                    attrs.get("cy").and_then(|v| v.parse().ok()),
+                )
+            }
+            None => (None, None),
+        };
+
+        let alt_text = DOC_PR_RE
+            .captures(block)
+            .map(|c| attributes(&c[1]).get("descr").map(|v| v.to_string()))
+            .unwrap_or_else(|| {
+                VML_SHAPE_RE
+                    .captures(block)
+                    .and_then(|c| attributes(&c[1]).get("alt").map(|v| v.to_string()))
+            })
+            .map(|text| unescape_xml(&text))
+            .filter(|text| !text.trim().is_empty());
+        let caption = [paragraph + 1, paragraph.wrapping_sub(1)]
+            .into_iter()
+            .find_map(|index| caption_text(part_xml, &paragraph_starts, index));
+
+        let ids = BLIP_RE
+            .captures_iter(block)
+            .chain(VML_IMAGE_RE.captures_iter(block));
+        for id in ids {
+            let Some(target) = relationships.get(&id[1]) else {
+                continue;
+            };
+            placements
+                .entry(target.clone())
+                .or_default()
+                .push(Placement {
+                    part: part.to_string(),
+                    paragraph,
+                    offset: drawing.start(),
+                    kind,
+                    wrap: wrap.clone(),
+                    width_emu,
+                    height_emu,
+                    alt_text: alt_text.clone(),
+                    caption: caption.clone(),
+                    anchor: None,
+                });
+        }
+    }
+}
+
+/// Text of paragraph `index` if it is a caption
+fn caption_text(part_xml: &str, paragraph_starts: &[usize], index: usize) -> Option<String> {
+    let start = *paragraph_starts.get(index)?;
+    let end = paragraph_starts
+        .get(index + 1)
+        .copied()
+        .unwrap_or(part_xml.len());
+    let paragraph = &part_xml[start..end];
+    if !CAPTION_RE.is_match(paragraph) {
+        return None;
+    }
+    let text: String = TEXT_RE
+        .captures_iter(paragraph)
+        .map(|c| unescape_xml(&c[1]))
+        .collect();
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+/// Sort key placing media in reading order: drawings in the main document first, by their
+/// position, with drawings in footnotes, endnotes and comments where the note is
+/// referenced, then those in other parts (headers, footers, unreferenced notes). Media
+/// that is not drawn anywhere sorts last.
+pub fn appearance_key(
+    placements: Option<&Vec<Placement>>,
+    main_part: &str,
+) -> (bool, bool, String, usize) {
+    placements
+        .and_then(|list| {
+            list.iter()
+                .map(|p| match p.anchor {
+                    Some(anchor) => (false, false, main_part.to_string(), anchor),
+                    None => (false, p.part != main_part, p.part.clone(), p.offset),
+                })
+                .min()
+        })
+        .unwrap_or((true, true, String::new(), 0))
+}
+
+/// Points the drawings of a footnotes, endnotes or comments part at the reference to
+/// their note in the main document, so they are numbered where the note is referenced
+/// rather than after the whole body. Other parts are left alone.
+pub fn anchor_notes(
+    part: &str,
+    part_xml: &str,
+    main_xml: &str,
+    placements: &mut HashMap<String, Vec<Placement>>,
+) {
+    let Some(kind) = note_kind(section_of_part(part, "")) else {
+        return;
+    };
+    let references: HashMap<String, usize> = NOTE_REFERENCE_RE
+        .captures_iter(main_xml)
+        .filter(|c| &c[1] == kind)
+        .filter_map(|c| {
+            let id = attributes(c.get(2)?.as_str()).get("w:id")?.to_string();
+            Some((id, c.get(0)?.start()))
+        })
+        .collect();
+    let notes: Vec<(usize, String)> = NOTE_RE
+        .captures_iter(part_xml)
+        .filter(|c| &c[1] == kind)
+        .filter_map(|c| {
+            let id = attributes(c.get(2)?.as_str()).get("w:id")?.to_string();
+            Some((c.get(0)?.start(), id))
+        })
+        .collect();
+    for placement in placements.values_mut().flatten() {
+        if placement.part != part {
+            continue;
+        }
+        let index = notes.partition_point(|(start, _)| *start < placement.offset);
+        placement.anchor = index
+            .checked_sub(1)
+            .and_then(|i| references.get(&notes[i].1))
+            .copied();
+    }
+}
+
+/// Singular name of a notes section (`footnotes` -> `footnote`), which `--tag-notes`
+/// appends to image names; None for the other sections
+pub fn note_kind(section: &str) -> Option<&'static str> {
+    match section {
+        "footnotes" => Some("footnote"),
+        "endnotes" => Some("endnote"),
+        "comments" => Some("comment"),
+        _ => None,
+    }
+}
+
+/// Finds every embedded OLE object (`w:object`) in a part
+pub fn parse_objects(
+    part: &str,
+    part_xml: &str,
+    relationships: &HashMap<String, String>,
+) -> Vec<EmbeddedObject> {
+    OBJECT_RE
+        .find_iter(part_xml)
+        .filter_map(|block| {
+            let ole = OLE_OBJECT_RE.captures(block.as_str())?;
+            let attrs = attributes(ole.get(1)?.as_str());
+            let object = relationships.get(*attrs.get("r:id")?)?.clone();
+            let preview = VML_IMAGE_RE
+                .captures(block.as_str())
+                .and_then(|c| relationships.get(&c[1]).cloned());
+            Some(EmbeddedObject {
+                part: part.to_string(),
+                offset: block.start(),
+                object,
+                preview,
+                prog_id: attrs.get("ProgID").map(|id| id.to_string()),
+            })
+        })
+        .collect()
+}
+
+/// Sections of a Word document, in the order an image drawn in several of them is
+/// assigned to one
+const SECTIONS: &[&str] = &[
+    "body",
+    "headers",
+    "footers",
+    "footnotes",
+    "endnotes",
+    "comments",
+    "other",
+];
+
+/// Section a part belongs to (`word/header2.xml` is in `headers`)
+fn section_of_part(part: &str, main_part: &str) -> &'static str {
+    let name = part.rsplit('/').next().unwrap_or(part);
+    if part == main_part {
+        "body"
+    } else if name.starts_with("header") {
+        "headers"
+    } else if name.starts_with("footer") {
+        "footers"
+    } else if name.starts_with("footnotes") {
+        "footnotes"
+    } else if name.starts_with("endnotes") {
+        "endnotes"
+    } else if name.starts_with("comments") {
+        "comments"
+    } else {
+        "other"
+    }
+}
+
+/// Section an image is used in (`--sections`): the first of body, headers, footers,
+/// footnotes, endnotes and comments it is drawn in, or `unreferenced`
+pub fn section(placements: Option<&Vec<Placement>>, main_part: &str) -> &'static str {
+    placements
+        .and_then(|list| {
+            list.iter()
+                .map(|p| section_of_part(&p.part, main_part))
+                .min_by_key(|section| SECTIONS.iter().position(|s| s == section))
+        })
+        .unwrap_or("unreferenced")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RELS: &str = r#"<?xml version="1.0"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+  <Relationship Id="rId4" Type=".../image" Target="media/image1.png"/>
+  <Relationship Id="rId5" Type=".../image" Target="media/image2.jpeg"/>
+  <Relationship Id="rId6" Type=".../hyperlink" Target="https://example.com" TargetMode="External"/>
+</Relationships>"#;
+
+    const DOCUMENT: &str = r#"<w:document><w:body>
+<w:p><w:pPr/><w:r><w:t>Intro</w:t></w:r></w:p>
+<w:p><w:r><w:drawing><wp:inline distT="0"><wp:extent cx="914400" cy="457200"/>
+  <wp:docPr id="1" name="Picture 1" descr="Sales by region &amp; year"/>
+  <a:graphic><a:blip r:embed="rId4"/></a:graphic></wp:inline></w:drawing></w:r></w:p>
+<w:p><w:pPr><w:pStyle w:val="Caption"/></w:pPr><w:r><w:t xml:space="preserve">Figure </w:t></w:r>
+  <w:fldSimple w:instr=" SEQ Figure \* ARABIC "><w:r><w:t>1</w:t></w:r></w:fldSimple><w:r><w:t>: Sales</w:t></w:r></w:p>
+<w:p><w:r><w:drawing><wp:anchor behindDoc="1" simplePos="0"><wp:extent cx="100" cy="200"/>
+  <wp:wrapNone/><a:graphic><a:blip r:embed="rId5"/></a:graphic></wp:anchor></w:drawing></w:r></w:p>
+<w:p><w:r><w:drawing><wp:anchor behindDoc="0"><wp:extent cx="1" cy="2"/><wp:wrapSquare wrapText="bothSides"/>
+  <a:blip r:embed="rId4"/></wp:anchor></w:drawing></w:r></w:p>
+</w:body></w:document>"#;
+
+    #[test]
+    fn test_rels_path() {
+        assert_eq!(
+            rels_path("word/document.xml"),
+            "word/_rels/document.xml.rels"
+        );
+        assert_eq!(rels_path("word/header1.xml"), "word/_rels/header1.xml.rels");
+    }
+
+    #[test]
+    fn test_parse_relationships() {
+        let rels = parse_relationships("word/document.xml", RELS);
+        assert_eq!(rels.len(), 2);
+        assert_eq!(rels["rId4"], "word/media/image1.png");
+    }
+
+    #[test]
+    fn test_parse_placements() {
+        let rels = parse_relationships("word/document.xml", RELS);
+        let mut placements = HashMap::new();
+        parse_placements("word/document.xml", DOCUMENT, &rels, &mut placements);
+
+        let first = &placements["word/media/image1.png"];
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[0].paragraph, 1);
+        assert_eq!(first[0].kind, "inline");
+        assert_eq!(first[0].wrap.as_deref(), Some("inline"));
+        assert_eq!(first[0].width_emu, Some(914400));
+        assert_eq!(first[0].height_emu, Some(457200));
+        assert_eq!(first[0].alt_text.as_deref(), Some("Sales by region & year"));
+        assert_eq!(first[0].caption.as_deref(), Some("Figure 1: Sales"));
+        assert_eq!(first[1].paragraph, 4);
+        assert_eq!(first[1].caption, None);
+        assert_eq!(first[1].wrap.as_deref(), Some("square"));
+
+        assert!(
+            appearance_key(
+                Some(&placements["word/media/image1.png"]),
+                "word/document.xml"
+            ) < appearance_key(
+                Some(&placements["word/media/image2.jpeg"]),
+                "word/document.xml"
+            )
+        );
+        assert!(
+            appearance_key(None, "word/document.xml")
+                > appearance_key(Some(first), "word/document.xml")
+        );
+
+        assert_eq!(section(Some(first), "word/document.xml"), "body");
+        assert_eq!(section(None, "word/document.xml"), "unreferenced");
+
+        let second = &placements["word/media/image2.jpeg"];
+        assert_eq!(second[0].kind, "anchor");
+        assert_eq!(second[0].paragraph, 3);
+        assert_eq!(second[0].alt_text, None);
+        assert_eq!(second[0].wrap.as_deref(), Some("behind-text"));
+    }
+
+    #[test]
+    fn test_sections() {
+        let mut placements = HashMap::new();
+        parse_placements(
+            "word/footer1.xml",
+            DOCUMENT,
+            &parse_relationships("word/footer1.xml", RELS),
+            &mut placements,
+        );
+        parse_placements(
+            "word/header2.xml",
+            DOCUMENT,
+            &parse_relationships("word/header2.xml", RELS),
+            &mut placements,
+        );
+        let logo = &placements["word/media/image1.png"];
+        assert_eq!(section(Some(logo), "word/document.xml"), "headers");
+
+        assert_eq!(
+            section_of_part("word/footnotes.xml", "word/document.xml"),
+            "footnotes"
+        );
+        assert_eq!(
+            section_of_part("word/commentsExtended.xml", "word/document.xml"),
+            "comments"
+        );
+        assert_eq!(
+            section_of_part("word/glossary.xml", "word/document.xml"),
+            "other"
+        );
+    }
+
+    #[test]
+    fn test_parse_objects() {
+        let rels = parse_relationships(
+            "word/document.xml",
+            r#"<Relationships>
+  <Relationship Id="rId7" Target="media/image3.emf"/>
+  <Relationship Id="rId8" Target="embeddings/Microsoft_Excel_Worksheet.xlsx"/>
+</Relationships>"#,
+        );
+        let xml = r##"<w:body><w:p><w:r><w:object w:dxaOrig="4320" w:dyaOrig="2880">
+  <v:shape id="_x0000_i1025" type="#_x0000_t75"><v:imagedata r:id="rId7" o:title=""/></v:shape>
+  <o:OLEObject Type="Embed" ProgID="Excel.Sheet.12" ShapeID="_x0000_i1025" r:id="rId8"/>
+</w:object></w:r></w:p></w:body>"##;
+
+        let objects = parse_objects("word/document.xml", xml, &rels);
+        assert_eq!(objects.len(), 1);
+        assert_eq!(
+            objects[0].object,
+            "word/embeddings/Microsoft_Excel_Worksheet.xlsx"
+        );
+        assert_eq!(objects[0].preview.as_deref(), Some("word/media/image3.emf"));
+        assert_eq!(objects[0].prog_id.as_deref(), Some("Excel.Sheet.12"));
+    }
+}
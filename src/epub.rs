@@ -2,79 +2,677 @@
 
 use anyhow::{Context, Result};
 use epub::doc::EpubDoc;
-use std::collections::HashSet;
+use regex::Regex;
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use zip::{ZipArchive, ZipWriter};
 
 use crate::common::{
-    get_unique_output_path, is_safe_archive_path, sanitize_filename, write_image_to_file,
+    DocumentMetadata, ExtraFormat, extra_format_for_mime, format_series_index,
+    is_safe_archive_path, normalize_isbn, resolve_reference, sanitize_filename, unescape_xml,
 };
+use crate::extract::{self, CoverFallback, DrmProtected, ExtractOptions, nested_base_name};
+use crate::html;
+use crate::json::Json;
+use crate::messages::{self, Message};
+use crate::obfuscation::{ObfuscatedResources, obfuscation_for, parse_encryption_xml};
+use crate::output::{ImageDetails, OutputSink};
+use crate::phash::{SIMILAR_MAX_DISTANCE, perceptual_hash};
+use crate::raster::size_rank;
+use crate::sniff::{detect_extension, format_mismatch};
+use crate::timings::{self, Phase};
 
-/// Filter criteria for EPUB files
-#[derive(Debug, Default)]
-pub struct EpubFilter {
-    pub title: Option<String>,
-    pub author: Option<String>,
+/// Formats a filename based on EPUB metadata (author and title, or series and title)
+/// Falls back to the provided fallback name if metadata is missing
+pub fn format_epub_base_name(
+    author: Option<&str>,
+    title: Option<&str>,
+    series: Option<&str>,
+    series_index: Option<&str>,
+    fallback: &str,
+) -> String {
+    let author = author.map(|s| s.trim()).filter(|s| !s.is_empty());
+    let title = title.map(|s| s.trim()).filter(|s| !s.is_empty());
+    let series = series.map(|s| s.trim()).filter(|s| !s.is_empty());
+    // "Discworld 04", which sorts the books of a series in reading order
+    let series =
+        series.map(
+            |series| match series_index.and_then(|index| format_series_index(index, 2)) {
+                Some(index) => format!("{} {}", series, index),
+                None => series.to_string(),
+            },
+        );
+
+    let raw_name = match (series, author, title) {
+        (Some(s), _, Some(t)) => format!("{} - {}", s, t),
+        (Some(s), _, None) => s,
+        (None, Some(a), Some(t)) => format!("{} - {}", a, t),
+        (None, None, Some(t)) => t.to_string(),
+        (None, Some(a), None) => a.to_string(),
+        (None, None, None) => fallback.to_string(),
+    };
+
+    sanitize_filename(&raw_name)
+}
+
+/// Which renditions of a multiple-rendition EPUB to process
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum RenditionChoice {
+    /// The default (first) rendition, as every reading system does
+    #[default]
+    Default,
+    /// The rendition at this 1-based position in `container.xml`
+    Index(usize),
+    /// The rendition whose label or layout matches (case-insensitive)
+    Label(String),
+    /// Every rendition; output of all but the first is labelled with the rendition
+    All,
 }
 
-impl EpubFilter {
-    /// Returns true if no filter criteria are set
-    pub fn is_empty(&self) -> bool {
-        self.title.is_none() && self.author.is_none()
+/// Parses a `--rendition` value: `all`, a 1-based number, or a rendition label
+pub fn parse_rendition(value: &str) -> Result<RenditionChoice, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err("expected 'all', a number or a rendition label".to_string());
+    }
+    if value.eq_ignore_ascii_case("all") {
+        return Ok(RenditionChoice::All);
+    }
+    match value.parse::<usize>() {
+        Ok(0) => Err("renditions are numbered from 1".to_string()),
+        Ok(n) => Ok(RenditionChoice::Index(n)),
+        Err(_) => Ok(RenditionChoice::Label(value.to_string())),
     }
 }
 
-/// Checks if EPUB metadata matches the filter (case-insensitive substring match)
-/// Returns (matches, title, author) - title/author are returned for skip messaging
-fn matches_filter(title: Option<&str>, author: Option<&str>, filter: &EpubFilter) -> bool {
-    let title_matches = filter
-        .title
-        .as_ref()
-        .is_none_or(|f| title.is_some_and(|t| t.to_lowercase().contains(&f.to_lowercase())));
+/// A `<rootfile>` declared in `META-INF/container.xml`
+#[derive(Debug, Clone, PartialEq)]
+struct Rendition {
+    /// Archive path of the package document
+    path: String,
+    /// `rendition:label`, falling back to `rendition:layout`
+    label: Option<String>,
+}
 
-    let author_matches = filter
-        .author
-        .as_ref()
-        .is_none_or(|f| author.is_some_and(|a| a.to_lowercase().contains(&f.to_lowercase())));
+impl Rendition {
+    /// Label used in output names; unlabelled renditions are numbered
+    fn output_label(&self, index: usize) -> String {
+        match &self.label {
+            Some(label) => sanitize_filename(label),
+            None => format!("rendition{}", index + 1),
+        }
+    }
 
-    title_matches && author_matches
+    fn matches(&self, label: &str) -> bool {
+        self.label
+            .as_deref()
+            .is_some_and(|l| l.eq_ignore_ascii_case(label))
+    }
 }
 
-/// Formats a filename based on EPUB metadata (author and title)
-/// Falls back to the provided fallback name if metadata is missing
-fn format_epub_base_name(author: Option<&str>, title: Option<&str>, fallback: &str) -> String {
-    let author = author.map(|s| s.trim()).filter(|s| !s.is_empty());
-    let title = title.map(|s| s.trim()).filter(|s| !s.is_empty());
+/// Location of the OCF container document
+const CONTAINER_XML_PATH: &str = "META-INF/container.xml";
 
-    let raw_name = match (author, title) {
-        (Some(a), Some(t)) => format!("{} - {}", a, t),
-        (None, Some(t)) => t.to_string(),
-        (Some(a), None) => a.to_string(),
-        (None, None) => fallback.to_string(),
-    };
+static ROOTFILE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<(?:\w+:)?rootfile\b[^>]*>").unwrap());
+static XML_ATTRIBUTE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"([\w:-]+)\s*=\s*["']([^"']*)["']"#).unwrap());
 
-    sanitize_filename(&raw_name)
+/// Lists the package documents declared in `container.xml`, in order
+fn parse_rootfiles(container_xml: &str) -> Vec<Rendition> {
+    ROOTFILE_RE
+        .find_iter(container_xml)
+        .filter_map(|tag| {
+            let attrs: HashMap<&str, &str> = XML_ATTRIBUTE_RE
+                .captures_iter(tag.as_str())
+                .map(|c| {
+                    let (_, [key, value]) = c.extract();
+                    (key, value)
+                })
+                .collect();
+            let label = attrs
+                .get("rendition:label")
+                .or_else(|| attrs.get("rendition:layout"))
+                .map(|l| l.trim().to_string())
+                .filter(|l| !l.is_empty());
+            Some(Rendition {
+                path: attrs.get("full-path")?.to_string(),
+                label,
+            })
+        })
+        .collect()
+}
+
+/// Reads the renditions declared by an EPUB archive, after checking it against the
+/// resource limits since the EPUB parser reads whole entries into memory
+fn read_renditions<R: Read + Seek>(
+    reader: R,
+    input_path: &Path,
+    options: &ExtractOptions,
+) -> Result<Vec<Rendition>> {
+    let mut archive = ZipArchive::new(reader).context("Failed to read zip archive")?;
+    options.limits.check_archive(&mut archive, input_path)?;
+    let mut container_xml = String::new();
+    match archive.by_name(CONTAINER_XML_PATH) {
+        Ok(mut file) => {
+            file.read_to_string(&mut container_xml)
+                .context("Failed to read container.xml")?;
+        }
+        // Let the EPUB parser report the missing container
+        Err(_) => return Ok(Vec::new()),
+    }
+    Ok(parse_rootfiles(&container_xml))
+}
+
+/// Picks the renditions to process, as indices into `renditions`
+fn select_renditions(
+    renditions: &[Rendition],
+    choice: &RenditionChoice,
+    input_path: &Path,
+) -> Result<Vec<usize>> {
+    match choice {
+        RenditionChoice::Default => {
+            if renditions.len() > 1 {
+                status!(
+                    "{} declares {} renditions; extracting the first (see --rendition).",
+                    input_path.display(),
+                    renditions.len()
+                );
+            }
+            Ok(vec![0])
+        }
+        RenditionChoice::All => Ok((0..renditions.len().max(1)).collect()),
+        RenditionChoice::Index(n) if *n <= renditions.len().max(1) => Ok(vec![n - 1]),
+        RenditionChoice::Index(n) => anyhow::bail!(
+            "Rendition {} requested but {} declares {}",
+            n,
+            input_path.display(),
+            renditions.len()
+        ),
+        RenditionChoice::Label(label) => match renditions.iter().position(|r| r.matches(label)) {
+            Some(index) => Ok(vec![index]),
+            None => anyhow::bail!(
+                "No rendition labelled '{}' in {}",
+                label,
+                input_path.display()
+            ),
+        },
+    }
+}
+
+/// Copies an EPUB into memory with `container.xml` rewritten so that `rootfile` is the
+/// only (and therefore default) rendition. Other entries are copied without recompressing.
+fn with_default_rootfile<R: Read + Seek>(reader: R, rootfile: &str) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(reader).context("Failed to read zip archive")?;
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let escaped = rootfile
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;");
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        if entry.name() != CONTAINER_XML_PATH {
+            writer.raw_copy_file(entry)?;
+            continue;
+        }
+        drop(entry);
+        writer.start_file(CONTAINER_XML_PATH, zip::write::SimpleFileOptions::default())?;
+        write!(
+            writer,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\
+             <rootfiles><rootfile full-path=\"{}\" media-type=\"application/oebps-package+xml\"/>\
+             </rootfiles></container>\n",
+            escaped
+        )?;
+    }
+    Ok(writer.finish()?.into_inner())
 }
 
 /// Struct to hold image data extracted from EPUB
 struct EpubImage {
     id: String,
+    path: String,
+    /// Extension the image is extracted with
     extension: String,
+    /// Extension from the resource path or MIME type
+    declared: String,
+    /// Set for an image embedded as a data URI rather than stored as a resource; `id` and
+    /// `path` are then those of the first document embedding it
+    inline: Option<InlineImage>,
+}
+
+/// An image embedded in content documents or stylesheets as a base64 `data:` URI
+struct InlineImage {
+    data: Vec<u8>,
+    /// Documents embedding the image with the number of times each does
+    referenced_by: Vec<(String, usize)>,
+}
+
+/// Per-book data shared by the image extraction functions
+#[derive(Default)]
+struct BookContext {
+    obfuscated: ObfuscatedResources,
+    /// Image path -> content documents referencing it with reference counts.
+    /// Only built when a manifest is being recorded, with `--referenced-only` or
+    /// with `--by-chapter`.
+    references: HashMap<String, Vec<(String, usize)>>,
+    /// Image path -> position and directory of its chapter. Only built with `--by-chapter`.
+    chapters: HashMap<String, (usize, String)>,
+    /// Image path -> first non-empty `alt` text of an `<img>` showing it. Only built
+    /// with `--name-from-alt`.
+    alt_texts: HashMap<String, String>,
+}
+
+impl BookContext {
+    /// Adds the `referenced_by` manifest field for the image just written
+    fn annotate_references(&self, sink: &mut OutputSink, image_path: &str) {
+        if !sink.records_manifest() {
+            return;
+        }
+        let referenced_by = self
+            .references
+            .get(image_path)
+            .map(|refs| {
+                refs.iter()
+                    .map(|(document, count)| {
+                        Json::object([
+                            ("document", Json::from(document.as_str())),
+                            ("count", Json::from(*count)),
+                        ])
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        sink.annotate("referenced_by", Json::Array(referenced_by));
+    }
+
+    /// Adds the `referenced_by` manifest field for the data URI image just written
+    fn annotate_inline(&self, sink: &mut OutputSink, image: &InlineImage) {
+        if !sink.records_manifest() {
+            return;
+        }
+        let referenced_by = image
+            .referenced_by
+            .iter()
+            .map(|(document, count)| {
+                Json::object([
+                    ("document", Json::from(document.as_str())),
+                    ("count", Json::from(*count)),
+                ])
+            })
+            .collect();
+        sink.annotate("referenced_by", Json::Array(referenced_by));
+    }
+}
+
+/// Attribute references to other resources in XHTML and SVG
+static ATTRIBUTE_REF_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)\b(?:src|href|xlink:href|poster)\s*=\s*["']([^"']+)["']"#).unwrap()
+});
+/// `url(...)` references in CSS and inline styles
+static CSS_URL_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"url\(\s*["']?([^"')]+?)["']?\s*\)"#).unwrap());
+/// `@import "other.css"`, the CSS import written without `url()`
+static CSS_IMPORT_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"@import\s+["']([^"']+)["']"#).unwrap());
+
+/// Counts how often each resource is referenced by each content document (XHTML, SVG, CSS)
+fn build_reference_index<R: Read + Seek>(
+    doc: &mut EpubDoc<R>,
+) -> HashMap<String, Vec<(String, usize)>> {
+    let mut content: Vec<(String, String)> = doc
+        .resources
+        .iter()
+        .filter(|(_, item)| {
+            matches!(
+                item.mime.as_str(),
+                "application/xhtml+xml" | "text/html" | "image/svg+xml" | "text/css"
+            )
+        })
+        .map(|(id, item)| (id.clone(), item.path.to_string_lossy().replace('\\', "/")))
+        .collect();
+    content.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let mut index: HashMap<String, Vec<(String, usize)>> = HashMap::new();
+    for (id, path) in content {
+        let Some((data, _mime)) = doc.get_resource(&id) else {
+            continue;
+        };
+        let text = String::from_utf8_lossy(&data);
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let references = ATTRIBUTE_REF_RE
+            .captures_iter(&text)
+            .chain(CSS_URL_RE.captures_iter(&text))
+            .chain(CSS_IMPORT_RE.captures_iter(&text));
+        for captures in references {
+            if let Some(target) = resolve_reference(&path, &captures[1]) {
+                *counts.entry(target).or_default() += 1;
+            }
+        }
+        for (target, count) in counts {
+            index.entry(target).or_default().push((path.clone(), count));
+        }
+    }
+    index
+}
+
+/// The pages (XHTML and SVG documents) using `resource`, directly or through stylesheets:
+/// an image that is only a `background-image` in `style.css` is used by every page linking
+/// `style.css`, or a stylesheet importing it. Stylesheets no page uses add nothing.
+fn using_pages<'a>(
+    resource: &str,
+    references: &'a HashMap<String, Vec<(String, usize)>>,
+) -> Vec<&'a str> {
+    let mut pages = Vec::new();
+    let mut visited: Vec<&str> = Vec::new();
+    let mut pending: Vec<&str> = references
+        .get(resource)
+        .into_iter()
+        .flatten()
+        .map(|(document, _)| document.as_str())
+        .collect();
+    while let Some(document) = pending.pop() {
+        if visited.contains(&document) {
+            continue;
+        }
+        visited.push(document);
+        if !document.to_lowercase().ends_with(".css") {
+            pages.push(document);
+            continue;
+        }
+        if let Some(referrers) = references.get(document) {
+            pending.extend(referrers.iter().map(|(referrer, _)| referrer.as_str()));
+        }
+    }
+    pages
+}
+
+/// `<img>` tags in XHTML content documents
+static IMG_TAG_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<img\b[^>]*>").unwrap());
+/// Attributes of an HTML tag; alt text may contain the other kind of quote
+static HTML_ATTRIBUTE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"([\w:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+
+/// Maps each image to the first non-empty `alt` text of an `<img>` tag showing it,
+/// going through the content documents in path order
+fn build_alt_text_index<R: Read + Seek>(doc: &mut EpubDoc<R>) -> HashMap<String, String> {
+    let mut content: Vec<(String, String)> = doc
+        .resources
+        .iter()
+        .filter(|(_, item)| matches!(item.mime.as_str(), "application/xhtml+xml" | "text/html"))
+        .map(|(id, item)| (id.clone(), item.path.to_string_lossy().replace('\\', "/")))
+        .collect();
+    content.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let mut index = HashMap::new();
+    for (id, path) in content {
+        let Some((data, _mime)) = doc.get_resource(&id) else {
+            continue;
+        };
+        let text = String::from_utf8_lossy(&data);
+        for tag in IMG_TAG_RE.find_iter(&text) {
+            let mut src = None;
+            let mut alt = None;
+            for captures in HTML_ATTRIBUTE_RE.captures_iter(tag.as_str()) {
+                let value = captures
+                    .get(2)
+                    .or(captures.get(3))
+                    .map_or("", |m| m.as_str());
+                match captures[1].to_ascii_lowercase().as_str() {
+                    "src" => src = Some(value),
+                    "alt" => alt = Some(value),
+                    _ => {}
+                }
+            }
+            let (Some(src), Some(alt)) = (src, alt) else {
+                continue;
+            };
+            let alt = unescape_xml(alt).trim().to_string();
+            if let Some(target) = resolve_reference(&path, src).filter(|_| !alt.is_empty()) {
+                index.entry(target).or_insert(alt);
+            }
+        }
+    }
+    index
+}
+
+/// Decodes the `data:image/...` URIs of the content documents and stylesheets, which
+/// the manifest does not list, going through them in path order. An image embedded
+/// several times, on every page or by several documents, is returned once, with the id
+/// and path of the first document embedding it.
+fn data_uri_images<R: Read + Seek>(doc: &mut EpubDoc<R>, extra: &[ExtraFormat]) -> Vec<EpubImage> {
+    let mut content: Vec<(String, String)> = doc
+        .resources
+        .iter()
+        .filter(|(_, item)| {
+            matches!(
+                item.mime.as_str(),
+                "application/xhtml+xml" | "text/html" | "text/css"
+            )
+        })
+        .map(|(id, item)| (id.clone(), item.path.to_string_lossy().replace('\\', "/")))
+        .collect();
+    content.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let mut images: Vec<EpubImage> = Vec::new();
+    for (id, path) in content {
+        let Some((data, _mime)) = doc.get_resource(&id) else {
+            continue;
+        };
+        for (extension, data) in html::data_uri_images(&data, extra) {
+            let existing = images
+                .iter_mut()
+                .filter_map(|image| image.inline.as_mut())
+                .find(|image| image.data == data);
+            if let Some(image) = existing {
+                match image.referenced_by.last_mut() {
+                    Some((document, count)) if *document == path => *count += 1,
+                    _ => image.referenced_by.push((path.clone(), 1)),
+                }
+                continue;
+            }
+            images.push(EpubImage {
+                id: id.clone(),
+                path: path.clone(),
+                declared: extension.clone(),
+                extension,
+                inline: Some(InlineImage {
+                    data,
+                    referenced_by: vec![(path.clone(), 1)],
+                }),
+            });
+        }
+    }
+    images
+}
+
+/// Chapter directory for images only referenced outside the spine (e.g. from a stylesheet
+/// no page uses)
+const OTHER_CHAPTER: &str = "other";
+/// Chapter directory for images nothing references
+const UNREFERENCED_CHAPTER: &str = "unreferenced";
+
+static NAV_TOC_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)<nav\b[^>]*epub:type\s*=\s*["'][^"']*\btoc\b[^"']*["'][^>]*>(.*?)</nav>"#)
+        .unwrap()
+});
+static NAV_LINK_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)<a\b[^>]*\bhref\s*=\s*["']([^"']+)["'][^>]*>(.*?)</a>"#).unwrap()
+});
+static TAG_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+/// Table of contents entries as (document path, title) in reading order, from the NCX or
+/// else the EPUB 3 navigation document
+fn toc_entries<R: Read + Seek>(doc: &mut EpubDoc<R>) -> Vec<(String, String)> {
+    fn flatten(points: &[epub::doc::NavPoint], entries: &mut Vec<(String, String)>) {
+        for point in points {
+            let content = point.content.to_string_lossy().replace('\\', "/");
+            if let Some(path) = resolve_reference("", &content) {
+                entries.push((path, point.label.trim().to_string()));
+            }
+            flatten(&point.children, entries);
+        }
+    }
+    let mut entries = Vec::new();
+    flatten(&doc.toc, &mut entries);
+    if !entries.is_empty() {
+        return entries;
+    }
+
+    let Some(nav_id) = doc.get_nav_id() else {
+        return entries;
+    };
+    let Some(nav_path) = doc
+        .resources
+        .get(&nav_id)
+        .map(|item| item.path.to_string_lossy().replace('\\', "/"))
+    else {
+        return entries;
+    };
+    let Some((xhtml, _)) = doc.get_resource_str(&nav_id) else {
+        return entries;
+    };
+    let Some(toc) = NAV_TOC_RE.captures(&xhtml) else {
+        return entries;
+    };
+    for link in NAV_LINK_RE.captures_iter(&toc[1]) {
+        let label = unescape_xml(&TAG_RE.replace_all(&link[2], ""));
+        let label = label.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some(path) = resolve_reference(&nav_path, &link[1]) {
+            entries.push((path, label));
+        }
+    }
+    entries
+}
+
+/// Assigns each referenced image to the first chapter, in reading order, of a spine document
+/// that references it. Chapters are named `{nn} - {title}` after the table of contents;
+/// spine documents it doesn't list belong to the chapter before them, or are named after
+/// their file if they come first.
+fn image_chapters<R: Read + Seek>(
+    doc: &mut EpubDoc<R>,
+    references: &HashMap<String, Vec<(String, usize)>>,
+) -> HashMap<String, (usize, String)> {
+    let toc = toc_entries(doc);
+    let spine: Vec<String> = doc
+        .spine
+        .iter()
+        .filter_map(|item| doc.resources.get(&item.idref))
+        .map(|item| item.path.to_string_lossy().replace('\\', "/"))
+        .collect();
+
+    // Spine document -> chapter number and directory
+    let mut documents: HashMap<&str, (usize, String)> = HashMap::new();
+    let mut current: Option<(usize, String)> = None;
+    let mut count = 0;
+    for path in &spine {
+        let title = toc
+            .iter()
+            .find(|(target, title)| target == path && !title.is_empty())
+            .map(|(_, title)| title.clone());
+        let title = match (title, &current) {
+            (Some(title), _) => title,
+            (None, Some(chapter)) => {
+                documents.insert(path, chapter.clone());
+                continue;
+            }
+            (None, None) => Path::new(path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+        count += 1;
+        let chapter = (
+            count,
+            sanitize_filename(&format!("{:02} - {}", count, title)),
+        );
+        documents.insert(path, chapter.clone());
+        current = Some(chapter);
+    }
+
+    doc.resources
+        .values()
+        .filter(|item| item.mime.starts_with("image/"))
+        .map(|item| {
+            let path = item.path.to_string_lossy().to_string();
+            let path_key = path.replace('\\', "/");
+            let chapter = if references.contains_key(&path_key) {
+                using_pages(&path_key, references)
+                    .into_iter()
+                    .filter_map(|document| documents.get(document))
+                    .min_by_key(|(n, _)| *n)
+                    .cloned()
+                    .unwrap_or((usize::MAX - 1, OTHER_CHAPTER.to_string()))
+            } else {
+                (usize::MAX, UNREFERENCED_CHAPTER.to_string())
+            };
+            (path, chapter)
+        })
+        .collect()
+}
+
+/// Location of the OCF encryption manifest inside the container
+const ENCRYPTION_XML_PATH: &str = "META-INF/encryption.xml";
+/// Adobe ADEPT license, present in books sold with Adobe DRM
+const RIGHTS_XML_PATH: &str = "META-INF/rights.xml";
+/// Apple FairPlay license
+const SINF_XML_PATH: &str = "META-INF/sinf.xml";
+
+/// Identifies the DRM scheme locking a book, if any. Font obfuscation listed in
+/// `encryption.xml` is not DRM; any other encryption algorithm is.
+fn drm_scheme<R: Read + Seek>(doc: &mut EpubDoc<R>) -> Option<&'static str> {
+    if doc.get_resource_by_path(RIGHTS_XML_PATH).is_some() {
+        return Some("Adobe ADEPT");
+    }
+    if doc.get_resource_by_path(SINF_XML_PATH).is_some() {
+        return Some("Apple FairPlay");
+    }
+    let xml = doc.get_resource_by_path(ENCRYPTION_XML_PATH)?;
+    parse_encryption_xml(&String::from_utf8_lossy(&xml))
+        .values()
+        .any(|algorithm| obfuscation_for(algorithm).is_none())
+        .then_some("encrypted resources")
+}
+
+/// Reads the book's obfuscated resources from `META-INF/encryption.xml`, if present.
+/// The IDPF key comes from the unique identifier; the Adobe key from the first
+/// `urn:uuid:` identifier, falling back to the unique identifier.
+fn load_obfuscated_resources<R: Read + Seek>(doc: &mut EpubDoc<R>) -> ObfuscatedResources {
+    let Some(xml) = doc.get_resource_by_path(ENCRYPTION_XML_PATH) else {
+        return ObfuscatedResources::default();
+    };
+
+    let unique_identifier = doc.unique_identifier.clone();
+    let uuid_identifier = doc
+        .metadata
+        .iter()
+        .filter(|m| m.property == "identifier")
+        .map(|m| m.value.trim())
+        .find(|v| v.starts_with("urn:uuid:"))
+        .map(str::to_string)
+        .or_else(|| unique_identifier.clone());
+
+    ObfuscatedResources::from_encryption_xml(
+        &String::from_utf8_lossy(&xml),
+        unique_identifier.as_deref(),
+        uuid_identifier.as_deref(),
+    )
 }
 
 /// Processes a single .epub file, extracting images matching the allowed extensions.
 /// Uses author and title metadata for naming, falling back to filename.
 /// If cover_only is true, only extracts the cover image.
-/// If cover_fallback is true and cover_only is true but no cover is found, extracts all images.
+/// If cover_fallback is set and cover_only is true but no cover is found, extracts all images.
 /// If a filter is provided, only processes files matching the filter criteria.
 /// Returns the number of images extracted.
 pub fn process_file(
     input_path: &Path,
-    output_base_dir: &Path,
-    allowed_extensions: &HashSet<&str>,
-    cover_only: bool,
-    cover_fallback: bool,
-    filter: &EpubFilter,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
 ) -> Result<usize> {
     let fallback_name = input_path
         .file_stem()
@@ -82,62 +680,329 @@ pub fn process_file(
         .to_string_lossy()
         .to_string();
 
-    let mut doc =
-        EpubDoc::new(input_path).map_err(|e| anyhow::anyhow!("Failed to open EPUB file: {}", e))?;
+    let open = || {
+        fs::File::open(input_path)
+            .with_context(|| format!("Failed to open input file: {}", input_path.display()))
+    };
+    let opening = timings::start();
+    let renditions = read_renditions(open()?, input_path, options)?;
+    let selected = select_renditions(&renditions, &options.rendition, input_path)?;
+    sink.record_time(Phase::Open, opening);
+
+    let mut extracted = 0;
+    for index in selected {
+        if index == 0 {
+            let opening = timings::start();
+            let doc = EpubDoc::new(input_path)
+                .map_err(|e| anyhow::anyhow!("Failed to open EPUB file: {}", e))?;
+            sink.record_time(Phase::Open, opening);
+            extracted += process_doc(doc, input_path, &fallback_name, None, 0, sink, options)?;
+        } else {
+            let rendition = &renditions[index];
+            let data = with_default_rootfile(open()?, &rendition.path)?;
+            extracted += process_rendition(
+                data,
+                rendition,
+                index,
+                input_path,
+                &fallback_name,
+                0,
+                sink,
+                options,
+            )?;
+        }
+    }
+    Ok(extracted)
+}
+
+/// Processes an EPUB from any seekable reader.
+/// `input_path` is only used for messages; `fallback_name` is used when the book
+/// has no usable metadata and `depth` is the embedding depth.
+pub fn process_reader<R: Read + Seek>(
+    mut reader: R,
+    input_path: &Path,
+    fallback_name: &str,
+    depth: usize,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let opening = timings::start();
+    let renditions = read_renditions(&mut reader, input_path, options)?;
+    let selected = select_renditions(&renditions, &options.rendition, input_path)?;
+    sink.record_time(Phase::Open, opening);
 
-    // Extract metadata - mdata() returns Option<MetadataItem> with .value field
-    let title = doc.mdata("title").map(|m| m.value.clone());
-    let author = doc.mdata("creator").map(|m| m.value.clone()); // 'creator' is the Dublin Core element for author
+    // Copy out the other renditions before the reader is handed to the EPUB parser
+    let mut others = Vec::new();
+    for &index in selected.iter().filter(|&&i| i > 0) {
+        reader.seek(SeekFrom::Start(0))?;
+        let data = with_default_rootfile(&mut reader, &renditions[index].path)?;
+        others.push((index, data));
+    }
+
+    let mut extracted = 0;
+    if selected.contains(&0) {
+        reader.seek(SeekFrom::Start(0))?;
+        let opening = timings::start();
+        let doc = EpubDoc::from_reader(reader)
+            .map_err(|e| anyhow::anyhow!("Failed to open EPUB file: {}", e))?;
+        sink.record_time(Phase::Open, opening);
+        extracted += process_doc(doc, input_path, fallback_name, None, depth, sink, options)?;
+    }
+    for (index, data) in others {
+        extracted += process_rendition(
+            data,
+            &renditions[index],
+            index,
+            input_path,
+            fallback_name,
+            depth,
+            sink,
+            options,
+        )?;
+    }
+    Ok(extracted)
+}
+
+/// Processes a non-default rendition from an archive rewritten by [`with_default_rootfile`]
+#[allow(clippy::too_many_arguments)]
+fn process_rendition(
+    data: Vec<u8>,
+    rendition: &Rendition,
+    index: usize,
+    input_path: &Path,
+    fallback_name: &str,
+    depth: usize,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let label = rendition.output_label(index);
+    status!(
+        "Processing rendition {} ({}) of {}",
+        index + 1,
+        label,
+        input_path.display()
+    );
+    let doc = EpubDoc::from_reader(Cursor::new(data))
+        .map_err(|e| anyhow::anyhow!("Failed to open EPUB rendition {}: {}", rendition.path, e))?;
+    process_doc(
+        doc,
+        input_path,
+        fallback_name,
+        Some(&label),
+        depth,
+        sink,
+        options,
+    )
+}
+
+/// Reads the book's OPF metadata. The series comes from calibre's `calibre:series` or an
+/// EPUB 3 `belongs-to-collection`; the ISBN from an identifier with an ISBN scheme or
+/// `urn:isbn:` prefix.
+fn book_metadata<R: Read + Seek>(doc: &EpubDoc<R>) -> DocumentMetadata {
+    let value = |property: &str| {
+        doc.mdata(property)
+            .map(|m| m.value.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let collection = doc
+        .metadata
+        .iter()
+        .filter(|m| m.property == "belongs-to-collection")
+        .find(|m| {
+            m.refinement("collection-type")
+                .is_none_or(|r| r.value.trim() == "series")
+        });
+    let (series, series_index) = match value("calibre:series") {
+        Some(series) => (Some(series), value("calibre:series_index")),
+        None => (
+            collection.map(|m| m.value.trim().to_string()),
+            collection
+                .and_then(|m| m.refinement("group-position"))
+                .map(|r| r.value.trim().to_string())
+                .filter(|v| !v.is_empty()),
+        ),
+    };
+    let isbn = doc
+        .metadata
+        .iter()
+        .filter(|m| m.property == "identifier")
+        .find(|m| {
+            let value = m.value.trim().to_lowercase();
+            let scheme = m
+                .refinement("scheme")
+                .or_else(|| m.refinement("identifier-type"))
+                .map(|r| r.value.to_lowercase());
+            value.starts_with("urn:isbn:")
+                || value.starts_with("isbn:")
+                // ONIX code 15 is ISBN-13
+                || matches!(scheme.as_deref(), Some("isbn" | "15"))
+        })
+        .map(|m| normalize_isbn(&m.value))
+        .filter(|isbn| !isbn.is_empty());
+    DocumentMetadata {
+        title: value("title"),
+        // 'creator' is the Dublin Core element for author
+        author: value("creator"),
+        subject: value("subject"),
+        series,
+        series_index,
+        publisher: value("publisher"),
+        language: value("language"),
+        isbn,
+    }
+}
 
-    // Check filter if any criteria are set - silently skip non-matching files
-    if !filter.is_empty() && !matches_filter(title.as_deref(), author.as_deref(), filter) {
+/// Shared EPUB processing once the document is open.
+/// `rendition` labels the output of a non-default rendition.
+fn process_doc<R: Read + Seek>(
+    mut doc: EpubDoc<R>,
+    input_path: &Path,
+    fallback_name: &str,
+    rendition: Option<&str>,
+    depth: usize,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    // Silently skip books that don't match the metadata filter
+    let scanning = timings::start();
+    let metadata = book_metadata(&doc);
+    if !options.document_filter.matches(&metadata) {
         return Ok(0);
     }
+    let DocumentMetadata {
+        title,
+        author,
+        series,
+        series_index,
+        ..
+    } = metadata;
 
-    let base_name = format_epub_base_name(author.as_deref(), title.as_deref(), &fallback_name);
+    if let Some(scheme) = drm_scheme(&mut doc) {
+        return Err(DrmProtected { scheme }.into());
+    }
+
+    let mut base_name = format_epub_base_name(
+        author.as_deref(),
+        title.as_deref(),
+        series.as_deref(),
+        series_index.as_deref(),
+        fallback_name,
+    );
+    if let Some(label) = rendition {
+        base_name = format!("{}__{}", base_name, label);
+    }
 
     // Print metadata info
     if let Some(ref t) = title {
-        println!("EPUB Title: {}", t);
+        status!("EPUB Title: {}", t);
+        sink.record_metadata("title", t);
     }
     if let Some(ref a) = author {
-        println!("EPUB Author: {}", a);
+        status!("EPUB Author: {}", a);
+        sink.record_metadata("author", a);
+    }
+    if let Some(ref s) = series {
+        status!("EPUB Series: {}", s);
+        sink.record_metadata("series", s);
+    }
+    if let Some(ref i) = series_index {
+        sink.record_metadata("series_index", i);
     }
 
-    if cover_only {
-        return extract_cover_only(
-            &mut doc,
-            output_base_dir,
-            &base_name,
-            allowed_extensions,
-            input_path,
-            cover_fallback,
-        );
+    let mut book = BookContext {
+        obfuscated: load_obfuscated_resources(&mut doc),
+        references: if sink.records_manifest() || options.referenced_only || options.by_chapter {
+            build_reference_index(&mut doc)
+        } else {
+            HashMap::new()
+        },
+        chapters: HashMap::new(),
+        alt_texts: if sink.names_from_alt() {
+            build_alt_text_index(&mut doc)
+        } else {
+            HashMap::new()
+        },
+    };
+    if options.by_chapter {
+        book.chapters = image_chapters(&mut doc, &book.references);
     }
+    sink.record_time(Phase::Scan, scanning);
 
-    extract_all_images(
-        &mut doc,
-        output_base_dir,
-        &base_name,
-        allowed_extensions,
-        input_path,
-    )
+    let mut extracted = if options.cover_only {
+        extract_cover_only(&mut doc, sink, &base_name, &book, input_path, options)?
+    } else {
+        extract_all_images(&mut doc, sink, &base_name, &book, input_path, options)?
+    };
+
+    if options.extract_fonts {
+        extracted += extract_fonts(&mut doc, sink, &base_name, &book)?;
+    }
+
+    if options.recurse_embedded {
+        extracted +=
+            extract_embedded_documents(&mut doc, input_path, &base_name, depth, sink, options);
+    }
+
+    Ok(extracted)
+}
+
+/// Recurses into supported documents bundled as EPUB resources.
+/// Failures are reported as warnings so they don't hide the book's own images.
+fn extract_embedded_documents<R: Read + Seek>(
+    doc: &mut EpubDoc<R>,
+    input_path: &Path,
+    base_name: &str,
+    depth: usize,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> usize {
+    let mut embedded: Vec<(String, PathBuf)> = doc
+        .resources
+        .iter()
+        .filter(|(_, item)| {
+            let path = item.path.to_string_lossy();
+            is_safe_archive_path(&path) && extract::is_embedded_document(&path, options)
+        })
+        .map(|(id, item)| (id.clone(), item.path.clone()))
+        .collect();
+    embedded.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let mut extracted = 0;
+    for (id, path) in embedded {
+        let virtual_path = input_path.join(&path);
+        let Some((data, _mime)) = doc.get_resource(&id) else {
+            sink.warn(&format!(
+                "Skipping {}: resource could not be read",
+                virtual_path.display()
+            ));
+            continue;
+        };
+        let nested_name = nested_base_name(base_name, &path.to_string_lossy());
+        match extract::process_embedded(data, &virtual_path, &nested_name, depth + 1, sink, options)
+        {
+            Ok(count) => extracted += count,
+            Err(e) => sink.warn(&format!("Skipping {}: {}", virtual_path.display(), e)),
+        }
+    }
+    extracted
 }
 
 /// Extracts all images from an EPUB file
-fn extract_all_images(
-    doc: &mut EpubDoc<std::io::BufReader<std::fs::File>>,
-    output_base_dir: &Path,
+fn extract_all_images<R: Read + Seek>(
+    doc: &mut EpubDoc<R>,
+    sink: &mut OutputSink,
     base_name: &str,
-    allowed_extensions: &HashSet<&str>,
+    book: &BookContext,
     input_path: &Path,
+    options: &ExtractOptions,
 ) -> Result<usize> {
     // Collect images from resources
     // resources is HashMap<String, ResourceItem> where ResourceItem has path and mime fields
+    let scanning = timings::start();
     let mut images: Vec<EpubImage> = Vec::new();
 
     // Clone the resource keys and extract info to avoid borrow issues
-    let resources: Vec<(String, String)> = doc
+    let resources: Vec<(String, String, String)> = doc
         .resources
         .iter()
         .filter_map(|(id, item)| {
@@ -158,122 +1023,586 @@ fn extract_all_images(
                 .extension()
                 .and_then(|e| e.to_str())
                 .map(|s| s.to_lowercase())
-                .or_else(|| mime_to_extension(&item.mime));
+                .or_else(|| mime_to_extension(&item.mime, &options.extra_formats));
 
-            ext.map(|e| (id.clone(), e))
+            ext.map(|e| (id.clone(), path_str.to_string(), e))
         })
-        .collect::<Vec<(String, String)>>();
+        .collect::<Vec<(String, String, String)>>();
+
+    for (id, path, declared) in resources {
+        let extension = if options.trust_extension {
+            declared.clone()
+        } else {
+            match doc.get_resource(&id) {
+                Some((mut data, _)) => {
+                    book.obfuscated.apply(&path, &mut data);
+                    detect_extension(&declared, &data, &options.extra_formats)
+                }
+                None => declared.clone(),
+            }
+        };
 
-    for (id, extension) in resources {
-        // Check if this extension is in our allowed list
-        if allowed_extensions.contains(extension.as_str()) {
-            images.push(EpubImage { id, extension });
+        // Check if this format is in our allowed list
+        if options.allowed_extensions.contains(extension.as_str()) {
+            images.push(EpubImage {
+                id,
+                path,
+                extension,
+                declared,
+                inline: None,
+            });
         }
     }
+    images.extend(
+        data_uri_images(doc, &options.extra_formats)
+            .into_iter()
+            .filter(|image| {
+                options
+                    .allowed_extensions
+                    .contains(image.extension.as_str())
+            }),
+    );
+
+    // Publisher boilerplate and orphaned assets are not used by any page or stylesheet
+    if options.referenced_only {
+        let before = images.len();
+        images.retain(|image| {
+            image.inline.is_some() || !using_pages(&image.path, &book.references).is_empty()
+        });
+        if images.len() < before {
+            status!(
+                "{}",
+                messages::text(
+                    Message::SkippingUnreferenced,
+                    &[
+                        ("count", &(before - images.len())),
+                        ("path", &input_path.display())
+                    ]
+                )
+            );
+        }
+    }
+
+    // Number images chapter by chapter; data URI images have no chapter and come last
+    if options.by_chapter {
+        images.sort_by(|a, b| {
+            let chapter = |image: &EpubImage| book.chapters.get(&image.path).map(|(n, _)| *n);
+            (a.inline.is_some(), chapter(a))
+                .cmp(&(b.inline.is_some(), chapter(b)))
+                .then_with(|| a.path.cmp(&b.path))
+        });
+    }
+
+    // Forensic mode: keep only resources whose content disagrees with their declared type
+    if options.only_mismatched {
+        let mut mismatched = Vec::new();
+        // Data URI images are typed by their content, so they cannot be mismatched
+        for image in images.into_iter().filter(|image| image.inline.is_none()) {
+            let Some((mut data, mime)) = doc.get_resource(&image.id) else {
+                continue;
+            };
+            book.obfuscated.apply(&image.path, &mut data);
+            if let Some(actual) =
+                epub_format_mismatch(&image.declared, &mime, &data, &options.extra_formats)
+            {
+                status!(
+                    "Format mismatch: {} is declared as {} ({}) but contains {}",
+                    image.path,
+                    image.declared,
+                    mime,
+                    actual
+                );
+                mismatched.push(image);
+            }
+        }
+        images = mismatched;
+    }
+    sink.record_time(Phase::Scan, scanning);
 
     if images.is_empty() {
         return Ok(0);
     }
 
-    // create_dir_all is idempotent - succeeds if directory exists
-    fs::create_dir_all(output_base_dir).context("Failed to create output directory")?;
+    sink.create_base_dir()?;
 
     let total_images = images.len();
 
-    println!(
+    status!(
         "Found {} image files in {}.",
         total_images,
         input_path.display()
     );
 
     for (seq_index, image) in images.iter().enumerate() {
+        if let Some(inline) = &image.inline {
+            // Data URI images have no name or chapter of their own and are numbered
+            let output_path =
+                sink.output_path(base_name, seq_index, total_images, &image.extension)?;
+            status!("Extracting to: {}", output_path.display());
+            sink.write_image(&output_path, &inline.data)?;
+            book.annotate_inline(sink, inline);
+            continue;
+        }
+
         // Get the image data - get_resource returns Option<(Vec<u8>, String)>
-        let (data, _mime) = doc
+        let reading = timings::start();
+        let (mut data, _mime) = doc
             .get_resource(&image.id)
             .ok_or_else(|| anyhow::anyhow!("Failed to get resource '{}'", image.id))?;
+        book.obfuscated.apply(&image.path, &mut data);
+        sink.record_time(Phase::Read, reading);
 
-        let output_path = get_unique_output_path(
-            output_base_dir,
+        let details = ImageDetails {
+            original: Some(&image.path),
+            section: book
+                .chapters
+                .get(&image.path)
+                .map(|(_, chapter)| chapter.as_str()),
+            alt_text: book.alt_texts.get(&image.path).map(String::as_str),
+            suffix: None,
+        };
+        let output_path = sink.detailed_output_path(
             base_name,
             seq_index,
             total_images,
             &image.extension,
+            details,
         )?;
 
-        println!("Extracting to: {}", output_path.display());
+        status!("Extracting to: {}", output_path.display());
 
-        write_image_to_file(&output_path, &data)?;
+        sink.write_image(&output_path, &data)?;
+        book.annotate_references(sink, &image.path);
     }
 
     Ok(total_images)
 }
 
+/// Subdirectory of the output that fonts are written to
+const FONTS_DIR: &str = "fonts";
+
+/// Returns the file extension for a font resource, or None if it isn't a font
+fn font_extension(path: &Path, mime: &str) -> Option<String> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .filter(|e| matches!(e.as_str(), "ttf" | "otf" | "woff" | "woff2"));
+    extension.or_else(|| {
+        let extension = match mime {
+            "font/ttf" | "application/x-font-ttf" | "application/x-font-truetype" => "ttf",
+            "font/otf" | "application/x-font-otf" | "application/vnd.ms-opentype" => "otf",
+            "font/woff" | "application/font-woff" => "woff",
+            "font/woff2" => "woff2",
+            _ => return None,
+        };
+        Some(extension.to_string())
+    })
+}
+
+/// Writes the book's embedded fonts to `fonts/`, undoing IDPF or Adobe obfuscation.
+/// Returns the number of fonts written.
+fn extract_fonts<R: Read + Seek>(
+    doc: &mut EpubDoc<R>,
+    sink: &mut OutputSink,
+    base_name: &str,
+    book: &BookContext,
+) -> Result<usize> {
+    let mut fonts: Vec<(String, String, String)> = doc
+        .resources
+        .iter()
+        .filter(|(_, item)| is_safe_archive_path(&item.path.to_string_lossy()))
+        .filter_map(|(id, item)| {
+            let extension = font_extension(&item.path, &item.mime)?;
+            Some((
+                id.clone(),
+                item.path.to_string_lossy().to_string(),
+                extension,
+            ))
+        })
+        .collect();
+    if fonts.is_empty() {
+        return Ok(0);
+    }
+    fonts.sort_by(|a, b| a.1.cmp(&b.1));
+
+    sink.create_base_dir()?;
+    let total = fonts.len();
+    for (seq_index, (id, path, extension)) in fonts.iter().enumerate() {
+        let (mut data, _mime) = doc
+            .get_resource(id)
+            .ok_or_else(|| anyhow::anyhow!("Failed to get resource '{}'", id))?;
+        let deobfuscated = book.obfuscated.apply(path, &mut data);
+
+        let output_path =
+            sink.section_output_path(FONTS_DIR, base_name, seq_index, total, extension, path)?;
+        status!("Extracting font to: {}", output_path.display());
+        sink.write_image(&output_path, &data)?;
+        sink.annotate("font", Json::from(path.as_str()));
+        if deobfuscated {
+            sink.annotate("deobfuscated", Json::from(true));
+        }
+    }
+    Ok(total)
+}
+
 /// Extracts only the cover image from an EPUB file
-/// If cover_fallback is true and no cover is found, extracts all images instead
-fn extract_cover_only(
-    doc: &mut EpubDoc<std::io::BufReader<std::fs::File>>,
-    output_base_dir: &Path,
+/// If cover_fallback is `All` and no cover is found, extracts all images instead
+fn extract_cover_only<R: Read + Seek>(
+    doc: &mut EpubDoc<R>,
+    sink: &mut OutputSink,
     base_name: &str,
-    allowed_extensions: &HashSet<&str>,
+    book: &BookContext,
     input_path: &Path,
-    cover_fallback: bool,
+    options: &ExtractOptions,
 ) -> Result<usize> {
-    // Try to get the cover image using the epub crate's get_cover method
-    let cover = doc.get_cover();
+    let cover = find_cover(doc, book).and_then(|(id, source)| {
+        let path = doc.resources.get(&id)?.path.to_string_lossy().to_string();
+        let (mut data, mime) = doc.get_resource(&id)?;
+        book.obfuscated.apply(&path, &mut data);
+        Some((path, data, mime, source))
+    });
 
     match cover {
-        Some((data, mime)) => {
-            // Determine the extension from the MIME type
-            let extension = mime_to_extension(&mime).unwrap_or_else(|| "jpg".to_string());
+        Some((cover_path, data, mime, source)) => {
+            if source != CoverSource::Declared {
+                status!(
+                    "No cover declared in {}, using {}",
+                    input_path.display(),
+                    source.description()
+                );
+            }
+
+            // Determine the extension from the MIME type, unless the content says otherwise
+            let declared = mime_to_extension(&mime, &options.extra_formats)
+                .unwrap_or_else(|| "jpg".to_string());
+            let extension = if options.trust_extension {
+                declared.clone()
+            } else {
+                detect_extension(&declared, &data, &options.extra_formats)
+            };
 
             // Check if this extension is in our allowed list
-            if !allowed_extensions.contains(extension.as_str()) {
-                println!(
+            if !options.allowed_extensions.contains(extension.as_str()) {
+                status!(
                     "Cover image format '{}' not in allowed formats, skipping.",
                     extension
                 );
                 return Ok(0);
             }
 
-            // create_dir_all is idempotent - succeeds if directory exists
-            fs::create_dir_all(output_base_dir).context("Failed to create output directory")?;
+            if options.only_mismatched {
+                match epub_format_mismatch(&declared, &mime, &data, &options.extra_formats) {
+                    Some(actual) => status!(
+                        "Format mismatch: cover of {} is declared as {} but contains {}",
+                        input_path.display(),
+                        mime,
+                        actual
+                    ),
+                    None => return Ok(0),
+                }
+            }
+
+            let Some(perceptual) = screen_similar_cover(&data, input_path, sink, options) else {
+                return Ok(0);
+            };
+
+            sink.create_base_dir()?;
 
             // Use just the base name (author/title) for cover-only mode
-            let output_path = get_unique_output_path(output_base_dir, base_name, 0, 1, &extension)?;
+            let output_path = sink.output_path(base_name, 0, 1, &extension)?;
 
-            println!(
+            status!(
                 "Extracting cover from {} to: {}",
                 input_path.display(),
                 output_path.display()
             );
 
-            write_image_to_file(&output_path, &data)?;
+            sink.write_image(&output_path, &data)?;
+            book.annotate_references(sink, &cover_path);
+            sink.annotate("cover_source", Json::from(source.name()));
+            if let Some(hash) = perceptual {
+                sink.remember_perceptual_hash(hash, &output_path);
+            }
 
             Ok(1)
         }
+        // The heuristics only come up empty for books without images, so there is no
+        // largest image to fall back to either
         None => {
-            if cover_fallback {
-                println!(
+            if options.cover_fallback == Some(CoverFallback::All) {
+                status!(
                     "No cover image found in {}, falling back to extracting all images.",
                     input_path.display()
                 );
-                extract_all_images(
-                    doc,
-                    output_base_dir,
-                    base_name,
-                    allowed_extensions,
-                    input_path,
-                )
+                extract_all_images(doc, sink, base_name, book, input_path, options)
             } else {
-                println!("No cover image found in {}", input_path.display());
+                status!("No cover image found in {}", input_path.display());
                 Ok(0)
             }
         }
     }
 }
 
-/// Converts a MIME type to a file extension
-fn mime_to_extension(mime: &str) -> Option<String> {
+/// How the cover of a book was identified, from most to least reliable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CoverSource {
+    /// The `cover-image` manifest property (EPUB 3) or `<meta name="cover">` (EPUB 2)
+    Declared,
+    /// `<meta name="cover">` in an EPUB 3 book, or one naming the image by path
+    MetaCover,
+    /// An image resource named `cover.*`
+    FileName,
+    /// The only image on the first page of the spine
+    FirstPage,
+    /// The image with the most pixels
+    Largest,
+}
+
+impl CoverSource {
+    /// Value of the `cover_source` manifest field
+    fn name(self) -> &'static str {
+        match self {
+            CoverSource::Declared => "declared",
+            CoverSource::MetaCover => "meta-cover",
+            CoverSource::FileName => "file-name",
+            CoverSource::FirstPage => "first-page",
+            CoverSource::Largest => "largest",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            CoverSource::Declared => "the declared cover",
+            CoverSource::MetaCover => "the <meta name=\"cover\"> item",
+            CoverSource::FileName => "the image named cover",
+            CoverSource::FirstPage => "the image on the first page",
+            CoverSource::Largest => "the largest image",
+        }
+    }
+}
+
+/// Finds the resource id of the book's cover image. Many EPUB 2 books don't declare
+/// their cover in a way `get_cover_id` recognizes, so this falls back to
+/// `<meta name="cover">`, an image named `cover.*`, the sole image on the first page
+/// and finally the largest image.
+fn find_cover<R: Read + Seek>(
+    doc: &mut EpubDoc<R>,
+    book: &BookContext,
+) -> Option<(String, CoverSource)> {
+    let is_image = |doc: &EpubDoc<R>, id: &str| {
+        doc.resources
+            .get(id)
+            .is_some_and(|item| item.mime.starts_with("image/"))
+    };
+
+    if let Some(id) = doc.get_cover_id()
+        && is_image(doc, &id)
+    {
+        return Some((id, CoverSource::Declared));
+    }
+
+    // Some books put the item's path rather than its id in the meta content
+    if let Some(value) = doc.mdata("cover").map(|m| m.value.trim().to_string()) {
+        let id = if is_image(doc, &value) {
+            Some(value)
+        } else {
+            doc.resources
+                .iter()
+                .filter(|(_, item)| item.mime.starts_with("image/"))
+                .find(|(_, item)| {
+                    let path = item.path.to_string_lossy().replace('\\', "/");
+                    !value.is_empty() && (path == value || path.ends_with(&format!("/{}", value)))
+                })
+                .map(|(id, _)| id.clone())
+        };
+        if let Some(id) = id {
+            return Some((id, CoverSource::MetaCover));
+        }
+    }
+
+    let mut images: Vec<(String, String)> = doc
+        .resources
+        .iter()
+        .filter(|(_, item)| item.mime.starts_with("image/"))
+        .map(|(id, item)| (id.clone(), item.path.to_string_lossy().replace('\\', "/")))
+        .collect();
+    images.sort_by(|a, b| a.1.cmp(&b.1));
+
+    if let Some((id, _)) = images.iter().find(|(_, path)| {
+        Path::new(path)
+            .file_stem()
+            .is_some_and(|stem| stem.eq_ignore_ascii_case("cover"))
+    }) {
+        return Some((id.clone(), CoverSource::FileName));
+    }
+
+    if let Some(id) = first_page_image(doc, &images) {
+        return Some((id, CoverSource::FirstPage));
+    }
+
+    let mut largest: Option<((u64, usize), String)> = None;
+    for (id, path) in images {
+        let Some((mut data, _)) = doc.get_resource(&id) else {
+            continue;
+        };
+        book.obfuscated.apply(&path, &mut data);
+        let rank = size_rank(&data);
+        if largest.as_ref().is_none_or(|(best, _)| rank > *best) {
+            largest = Some((rank, id));
+        }
+    }
+    largest.map(|(_, id)| (id, CoverSource::Largest))
+}
+
+/// Returns the id of the only image referenced by the first spine item, if it references
+/// exactly one. `images` holds the id and archive path of every image resource.
+fn first_page_image<R: Read + Seek>(
+    doc: &mut EpubDoc<R>,
+    images: &[(String, String)],
+) -> Option<String> {
+    let first = doc.spine.first()?.idref.clone();
+    let page = doc
+        .resources
+        .get(&first)?
+        .path
+        .to_string_lossy()
+        .replace('\\', "/");
+    let (data, _) = doc.get_resource(&first)?;
+    let text = String::from_utf8_lossy(&data);
+
+    let mut found: Option<&str> = None;
+    for captures in ATTRIBUTE_REF_RE.captures_iter(&text) {
+        let Some(target) = resolve_reference(&page, &captures[1]) else {
+            continue;
+        };
+        let Some((id, _)) = images.iter().find(|(_, path)| *path == target) else {
+            continue;
+        };
+        match found {
+            Some(previous) if previous != id => return None,
+            _ => found = Some(id),
+        }
+    }
+    found.map(str::to_string)
+}
+
+/// Writes the cover of a book whose container is not an EPUB (MOBI, FB2), named after
+/// the book alone as in cover-only mode. Returns the number of images written.
+pub fn write_cover(
+    extension: &str,
+    data: &[u8],
+    base_name: &str,
+    input_path: &Path,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    if !options.allowed_extensions.contains(extension) {
+        status!(
+            "Cover image format '{}' not in allowed formats, skipping.",
+            extension
+        );
+        return Ok(0);
+    }
+    let Some(perceptual) = screen_similar_cover(data, input_path, sink, options) else {
+        return Ok(0);
+    };
+
+    sink.create_base_dir()?;
+    let output_path = sink.output_path(base_name, 0, 1, extension)?;
+
+    status!(
+        "Extracting cover from {} to: {}",
+        input_path.display(),
+        output_path.display()
+    );
+
+    sink.write_image(&output_path, data)?;
+    if let Some(hash) = perceptual {
+        sink.remember_perceptual_hash(hash, &output_path);
+    }
+    Ok(1)
+}
+
+/// With `--cover-fallback=largest`, writes the image with the most pixels as the cover of a
+/// book that has none. `images` holds each image's extension and data; formats that are not
+/// allowed are passed over. Returns the number of images written.
+pub fn write_largest_cover<'a>(
+    images: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    base_name: &str,
+    input_path: &Path,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let largest = images
+        .into_iter()
+        .filter(|(extension, _)| options.allowed_extensions.contains(*extension))
+        .max_by_key(|(_, data)| size_rank(data));
+    let Some((extension, data)) = largest else {
+        status!("No cover image found in {}", input_path.display());
+        return Ok(0);
+    };
+    status!(
+        "No cover image found in {}, using the largest image.",
+        input_path.display()
+    );
+    let written = write_cover(extension, data, base_name, input_path, sink, options)?;
+    if written > 0 {
+        sink.annotate("cover_source", Json::from(CoverSource::Largest.name()));
+    }
+    Ok(written)
+}
+
+/// With `--skip-similar-covers`, checks a cover against those already extracted this run,
+/// since bundles often reuse one cover. Returns `None` (after recording the skip) for a
+/// near-duplicate, otherwise the perceptual hash to remember once the cover is written.
+fn screen_similar_cover(
+    data: &[u8],
+    input_path: &Path,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Option<Option<u64>> {
+    let perceptual = if options.skip_similar_covers {
+        perceptual_hash(data)
+    } else {
+        None
+    };
+    if let Some(hash) = perceptual
+        && let Some((similar, distance)) = sink.find_similar(hash, SIMILAR_MAX_DISTANCE)
+    {
+        let similar = similar.display().to_string();
+        status!(
+            "Skipping cover of {}: near-duplicate of {}",
+            input_path.display(),
+            similar
+        );
+        sink.record_skipped(
+            data,
+            "near-duplicate cover",
+            vec![
+                ("similar_to".to_string(), Json::from(similar)),
+                ("distance".to_string(), Json::from(distance)),
+            ],
+        );
+        return None;
+    }
+    Some(perceptual)
+}
+
+/// Checks a resource's content against both its extension and its declared MIME type.
+/// Returns the sniffed format if it disagrees with either.
+fn epub_format_mismatch(
+    extension: &str,
+    mime: &str,
+    data: &[u8],
+    extra: &[ExtraFormat],
+) -> Option<&'static str> {
+    format_mismatch(extension, data, extra).or_else(|| {
+        mime_to_extension(mime, extra).and_then(|declared| format_mismatch(&declared, data, extra))
+    })
+}
+
+/// Converts a MIME type to a file extension, which may be one of the `extra` formats
+pub fn mime_to_extension(mime: &str, extra: &[ExtraFormat]) -> Option<String> {
     match mime {
         "image/jpeg" => Some("jpg".to_string()),
         "image/png" => Some("png".to_string()),
@@ -285,55 +1614,615 @@ fn mime_to_extension(mime: &str) -> Option<String> {
         "image/x-icon" | "image/vnd.microsoft.icon" => Some("ico".to_string()),
         "image/x-emf" | "image/emf" => Some("emf".to_string()),
         "image/x-wmf" | "image/wmf" => Some("wmf".to_string()),
-        _ => None,
+        _ => extra_format_for_mime(extra, mime).map(str::to_string),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::tests::temp_dir;
 
     #[test]
     fn test_format_epub_base_name_both() {
-        let result = format_epub_base_name(Some("Stephen King"), Some("The Shining"), "fallback");
+        let result = format_epub_base_name(
+            Some("Stephen King"),
+            Some("The Shining"),
+            None,
+            None,
+            "fallback",
+        );
         assert_eq!(result, "Stephen King - The Shining");
     }
 
     #[test]
     fn test_format_epub_base_name_title_only() {
-        let result = format_epub_base_name(None, Some("The Shining"), "fallback");
+        let result = format_epub_base_name(None, Some("The Shining"), None, None, "fallback");
         assert_eq!(result, "The Shining");
     }
 
     #[test]
     fn test_format_epub_base_name_author_only() {
-        let result = format_epub_base_name(Some("Stephen King"), None, "fallback");
+        let result = format_epub_base_name(Some("Stephen King"), None, None, None, "fallback");
         assert_eq!(result, "Stephen King");
     }
 
     #[test]
     fn test_format_epub_base_name_neither() {
-        let result = format_epub_base_name(None, None, "fallback");
+        let result = format_epub_base_name(None, None, None, None, "fallback");
         assert_eq!(result, "fallback");
     }
 
     #[test]
     fn test_format_epub_base_name_empty_strings() {
-        let result = format_epub_base_name(Some("  "), Some(""), "fallback");
+        let result = format_epub_base_name(Some("  "), Some(""), None, None, "fallback");
         assert_eq!(result, "fallback");
     }
 
     #[test]
     fn test_format_epub_base_name_sanitizes() {
-        let result = format_epub_base_name(Some("Author/Name"), Some("Title:Subtitle"), "fallback");
+        let result = format_epub_base_name(
+            Some("Author/Name"),
+            Some("Title:Subtitle"),
+            None,
+            None,
+            "fallback",
+        );
         assert_eq!(result, "Author_Name - Title_Subtitle");
     }
 
+    #[test]
+    fn test_format_epub_base_name_series() {
+        let name = |index| {
+            format_epub_base_name(
+                Some("Terry Pratchett"),
+                Some("Mort"),
+                Some("Discworld"),
+                index,
+                "fallback",
+            )
+        };
+        assert_eq!(name(Some("4.0")), "Discworld 04 - Mort");
+        assert_eq!(name(Some("12.5")), "Discworld 12.5 - Mort");
+        assert_eq!(name(None), "Discworld - Mort");
+        let result = format_epub_base_name(None, None, Some("Discworld"), Some("4"), "fallback");
+        assert_eq!(result, "Discworld 04");
+    }
+
+    #[test]
+    fn test_reference_patterns() {
+        let html = r#"<img src="a.png"/><image xlink:href='b.svg'/><div style="background: url( 'c.jpg' )"/>"#;
+        let found: Vec<&str> = ATTRIBUTE_REF_RE
+            .captures_iter(html)
+            .chain(CSS_URL_RE.captures_iter(html))
+            .map(|c| c.get(1).unwrap().as_str())
+            .collect();
+        assert_eq!(found, vec!["a.png", "b.svg", "c.jpg"]);
+    }
+
+    #[test]
+    fn test_epub_format_mismatch() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0";
+        assert_eq!(epub_format_mismatch("png", "image/png", png, &[]), None);
+        assert_eq!(
+            epub_format_mismatch("jpg", "image/png", png, &[]),
+            Some("png")
+        );
+        assert_eq!(
+            epub_format_mismatch("png", "image/jpeg", png, &[]),
+            Some("png")
+        );
+    }
+
+    #[test]
+    fn test_parse_rendition() {
+        assert_eq!(parse_rendition("all"), Ok(RenditionChoice::All));
+        assert_eq!(parse_rendition("2"), Ok(RenditionChoice::Index(2)));
+        assert_eq!(
+            parse_rendition("Fixed"),
+            Ok(RenditionChoice::Label("Fixed".to_string()))
+        );
+        assert!(parse_rendition("0").is_err());
+    }
+
+    const CONTAINER: &str = r#"<container xmlns:rendition="http://www.idpf.org/2013/rendition"><rootfiles>
+  <rootfile full-path="OEBPS/reflow.opf" media-type="application/oebps-package+xml"/>
+  <rootfile full-path="OEBPS/fixed.opf" media-type="application/oebps-package+xml"
+    rendition:layout="pre-paginated"/>
+</rootfiles></container>"#;
+
+    fn package(image: &str) -> String {
+        format!(
+            r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="id">book</dc:identifier><dc:title>Book</dc:title>
+  </metadata>
+  <manifest>
+    <item id="page" href="page.xhtml" media-type="application/xhtml+xml"/>
+    <item id="img" href="{}" media-type="image/png"/>
+  </manifest>
+  <spine><itemref idref="page"/></spine>
+</package>"#,
+            image
+        )
+    }
+
+    #[test]
+    fn test_parse_rootfiles() {
+        let renditions = parse_rootfiles(CONTAINER);
+        assert_eq!(renditions.len(), 2);
+        assert_eq!(renditions[0].path, "OEBPS/reflow.opf");
+        assert_eq!(renditions[0].output_label(0), "rendition1");
+        assert_eq!(renditions[1].output_label(1), "pre-paginated");
+        assert!(renditions[1].matches("Pre-Paginated"));
+    }
+
+    #[test]
+    fn test_extract_all_renditions() {
+        use crate::output::OutputOptions;
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let reflow = package("a.png");
+        let fixed = package("b.png");
+        let entries: [(&str, &[u8]); 6] = [
+            ("mimetype", b"application/epub+zip"),
+            (CONTAINER_XML_PATH, CONTAINER.as_bytes()),
+            ("OEBPS/reflow.opf", reflow.as_bytes()),
+            ("OEBPS/fixed.opf", fixed.as_bytes()),
+            ("OEBPS/a.png", b"\x89PNG a"),
+            ("OEBPS/b.png", b"\x89PNG b"),
+        ];
+        for (name, data) in entries {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let epub = writer.finish().unwrap();
+
+        let dir = temp_dir("renditions");
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            rendition: RenditionChoice::All,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count =
+            process_reader(epub, Path::new("book.epub"), "book", 0, &mut sink, &options).unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(fs::read(dir.join("Book.png")).unwrap(), b"\x89PNG a");
+        assert_eq!(
+            fs::read(dir.join("Book__pre-paginated.png")).unwrap(),
+            b"\x89PNG b"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Builds an EPUB 3 book with the given extra metadata, image items and first page
+    fn book_with_images(meta: &str, images: &[(&str, Vec<u8>)], page: &str) -> Cursor<Vec<u8>> {
+        let items: String = images
+            .iter()
+            .enumerate()
+            .map(|(i, (href, _))| {
+                let mime = if href.ends_with(".ttf") {
+                    "font/ttf"
+                } else {
+                    "image/png"
+                };
+                format!(
+                    r#"<item id="img{}" href="{}" media-type="{}"/>"#,
+                    i, href, mime
+                )
+            })
+            .collect();
+        let opf = format!(
+            r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="id">book</dc:identifier><dc:title>Book</dc:title>{}
+  </metadata>
+  <manifest>
+    <item id="page" href="text/page.xhtml" media-type="application/xhtml+xml"/>{}
+  </manifest>
+  <spine><itemref idref="page"/></spine>
+</package>"#,
+            meta, items
+        );
+        let container = CONTAINER.replace(
+            r#"<rootfile full-path="OEBPS/fixed.opf" media-type="application/oebps-package+xml"
+    rendition:layout="pre-paginated"/>"#,
+            "",
+        );
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let mut entries: Vec<(String, &[u8])> = vec![
+            ("mimetype".to_string(), b"application/epub+zip"),
+            (CONTAINER_XML_PATH.to_string(), container.as_bytes()),
+            ("OEBPS/reflow.opf".to_string(), opf.as_bytes()),
+            ("OEBPS/text/page.xhtml".to_string(), page.as_bytes()),
+        ];
+        for (href, data) in images {
+            entries.push((format!("OEBPS/{}", href), data));
+        }
+        for (name, data) in entries {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn cover_book(meta: &str, images: &[(&str, Vec<u8>)], page: &str) -> EpubDoc<Cursor<Vec<u8>>> {
+        EpubDoc::from_reader(book_with_images(meta, images, page)).unwrap()
+    }
+
+    /// A PNG header declaring the given size
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        data.extend(width.to_be_bytes());
+        data.extend(height.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_find_cover() {
+        let book = BookContext::default();
+        let cover_of = |mut doc: EpubDoc<Cursor<Vec<u8>>>| {
+            find_cover(&mut doc, &book).map(|(id, source)| (id, source.name()))
+        };
+        let two_images = r#"<img src="../images/a.png"/><img src="../images/b.png"/>"#;
+
+        let doc = cover_book(
+            r#"<meta name="cover" content="images/b.png"/>"#,
+            &[("images/a.png", png(1, 1)), ("images/b.png", png(1, 1))],
+            two_images,
+        );
+        assert_eq!(cover_of(doc), Some(("img1".into(), "meta-cover")));
+
+        let doc = cover_book(
+            "",
+            &[("images/a.png", png(1, 1)), ("images/Cover.png", png(1, 1))],
+            two_images,
+        );
+        assert_eq!(cover_of(doc), Some(("img1".into(), "file-name")));
+
+        let doc = cover_book(
+            "",
+            &[("images/a.png", png(1, 1)), ("images/b.png", png(1, 1))],
+            r#"<svg><image xlink:href="../images/b.png"/></svg><img src="../images/b.png"/>"#,
+        );
+        assert_eq!(cover_of(doc), Some(("img1".into(), "first-page")));
+
+        let doc = cover_book(
+            "",
+            &[
+                ("images/a.png", png(600, 800)),
+                ("images/b.png", png(800, 100)),
+            ],
+            two_images,
+        );
+        assert_eq!(cover_of(doc), Some(("img0".into(), "largest")));
+
+        assert_eq!(cover_of(cover_book("", &[], "")), None);
+    }
+
+    #[test]
+    fn test_book_metadata() {
+        let doc = cover_book(
+            r#"<dc:publisher>Doubleday</dc:publisher><dc:language>en-US</dc:language>
+<dc:identifier>urn:isbn:978-0-385-12167-5</dc:identifier>
+<meta name="calibre:series" content="The Shining"/>"#,
+            &[],
+            "",
+        );
+        let metadata = book_metadata(&doc);
+        assert_eq!(metadata.title.as_deref(), Some("Book"));
+        assert_eq!(metadata.series.as_deref(), Some("The Shining"));
+        assert_eq!(metadata.publisher.as_deref(), Some("Doubleday"));
+        assert_eq!(metadata.language.as_deref(), Some("en-US"));
+        assert_eq!(metadata.isbn.as_deref(), Some("9780385121675"));
+
+        let doc = cover_book(
+            r##"<meta property="belongs-to-collection" id="c1">Dune Chronicles</meta>
+<meta refines="#c1" property="collection-type">series</meta>"##,
+            &[],
+            "",
+        );
+        let metadata = book_metadata(&doc);
+        assert_eq!(metadata.series.as_deref(), Some("Dune Chronicles"));
+        // The book's only identifier is not an ISBN
+        assert_eq!(metadata.isbn, None);
+    }
+
+    /// Adds an entry to a built book
+    fn with_entry(book: Cursor<Vec<u8>>, name: &str, data: &str) -> Cursor<Vec<u8>> {
+        let mut writer = ZipWriter::new_append(book).unwrap();
+        writer
+            .start_file(name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(data.as_bytes()).unwrap();
+        let mut book = writer.finish().unwrap();
+        book.set_position(0);
+        book
+    }
+
+    #[test]
+    fn test_drm_protected() {
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            ..Default::default()
+        };
+        let dir = temp_dir("epub-drm");
+        let mut sink = OutputSink::new(dir.clone(), crate::output::OutputOptions::default());
+        let mut process =
+            |book| process_reader(book, Path::new("book.epub"), "book", 0, &mut sink, &options);
+        let images = [("images/a.png", png(1, 1))];
+        let encryption = |algorithm: &str| {
+            format!(
+                r#"<encryption><EncryptedData><EncryptionMethod Algorithm="{}"/>
+<CipherData><CipherReference URI="OEBPS/images/a.png"/></CipherData></EncryptedData></encryption>"#,
+                algorithm
+            )
+        };
+
+        let adept = with_entry(
+            book_with_images("", &images, ""),
+            RIGHTS_XML_PATH,
+            "<rights/>",
+        );
+        let error = process(adept).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DrmProtected>(),
+            Some(&DrmProtected {
+                scheme: "Adobe ADEPT"
+            })
+        );
+
+        let aes = encryption("http://www.w3.org/2001/04/xmlenc#aes128-cbc");
+        let encrypted = with_entry(book_with_images("", &images, ""), ENCRYPTION_XML_PATH, &aes);
+        assert_eq!(
+            process(encrypted).unwrap_err().to_string(),
+            "DRM-protected (encrypted resources)"
+        );
+
+        // Font obfuscation is not DRM
+        let fonts = encryption(crate::obfuscation::IDPF_ALGORITHM);
+        let obfuscated = with_entry(
+            book_with_images("", &images, ""),
+            ENCRYPTION_XML_PATH,
+            &fonts,
+        );
+        assert!(process(obfuscated).is_ok());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_extract_fonts() {
+        use crate::obfuscation::{IDPF_ALGORITHM, Obfuscation, deobfuscate, idpf_key};
+
+        let font = b"\0\x01\0\0 TrueType outlines".repeat(64);
+        let mut obfuscated = font.clone();
+        deobfuscate(&mut obfuscated, Obfuscation::Idpf, &idpf_key("book"));
+        let epub = book_with_images(
+            "",
+            &[("images/a.png", png(1, 1)), ("fonts/Serif.ttf", obfuscated)],
+            "",
+        );
+        let epub = with_entry(
+            epub,
+            ENCRYPTION_XML_PATH,
+            &format!(
+                r#"<encryption><EncryptedData><EncryptionMethod Algorithm="{}"/>
+<CipherData><CipherReference URI="OEBPS/fonts/Serif.ttf"/></CipherData></EncryptedData></encryption>"#,
+                IDPF_ALGORITHM
+            ),
+        );
+
+        let dir = temp_dir("epub-fonts");
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            extract_fonts: true,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), crate::output::OutputOptions::default());
+        let count =
+            process_reader(epub, Path::new("book.epub"), "book", 0, &mut sink, &options).unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(fs::read(dir.join("Book.png")).unwrap(), png(1, 1));
+        assert_eq!(fs::read(dir.join("fonts/Book.ttf")).unwrap(), font);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_image_chapters() {
+        let opf = r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="id">book</dc:identifier><dc:title>Book</dc:title>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+    <item id="cover" href="cover.xhtml" media-type="application/xhtml+xml"/>
+    <item id="ch1" href="ch1.xhtml" media-type="application/xhtml+xml"/>
+    <item id="ch1b" href="ch1b.xhtml" media-type="application/xhtml+xml"/>
+    <item id="ch2" href="ch2.xhtml" media-type="application/xhtml+xml"/>
+    <item id="a" href="a.png" media-type="image/png"/>
+    <item id="b" href="b.png" media-type="image/png"/>
+    <item id="c" href="c.png" media-type="image/png"/>
+    <item id="d" href="d.png" media-type="image/png"/>
+    <item id="e" href="e.png" media-type="image/png"/>
+    <item id="f" href="f.png" media-type="image/png"/>
+    <item id="style" href="css/style.css" media-type="text/css"/>
+    <item id="base" href="css/base.css" media-type="text/css"/>
+    <item id="unused" href="css/unused.css" media-type="text/css"/>
+  </manifest>
+  <spine><itemref idref="cover"/><itemref idref="ch1"/><itemref idref="ch1b"/><itemref idref="ch2"/></spine>
+</package>"#;
+        let nav = r#"<html><body><nav epub:type="toc"><ol>
+<li><a href="ch1.xhtml#start">Chapter <em>One</em></a></li>
+<li><a href="ch2.xhtml">Chapter: Two</a></li></ol></nav></body></html>"#;
+        let container = r#"<container><rootfiles>
+<rootfile full-path="OEBPS/book.opf" media-type="application/oebps-package+xml"/>
+</rootfiles></container>"#;
+        let entries: [(&str, &[u8]); 12] = [
+            ("mimetype", b"application/epub+zip"),
+            (CONTAINER_XML_PATH, container.as_bytes()),
+            ("OEBPS/book.opf", opf.as_bytes()),
+            ("OEBPS/nav.xhtml", nav.as_bytes()),
+            ("OEBPS/cover.xhtml", b"<img src=\"a.png\"/>"),
+            ("OEBPS/ch1.xhtml", b"<img src=\"b.png\"/>"),
+            (
+                "OEBPS/ch1b.xhtml",
+                b"<img src=\"a.png\"/><img src=\"c.png\"/>",
+            ),
+            (
+                "OEBPS/ch2.xhtml",
+                b"<link rel=\"stylesheet\" href=\"css/style.css\"/><img src=\"c.png\"/>",
+            ),
+            ("OEBPS/css/style.css", b"@import \"base.css\";"),
+            (
+                "OEBPS/css/base.css",
+                b"h1 { background-image: url(../e.png) }",
+            ),
+            ("OEBPS/css/unused.css", b"p { background: url('../f.png') }"),
+            ("OEBPS/a.png", b"\x89PNG a"),
+        ];
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let mut doc = EpubDoc::from_reader(writer.finish().unwrap()).unwrap();
+
+        let references = build_reference_index(&mut doc);
+        let chapters = image_chapters(&mut doc, &references);
+        let chapter = |path: &str| chapters.get(path).map(|(_, dir)| dir.as_str());
+        assert_eq!(chapter("OEBPS/a.png"), Some("01 - cover"));
+        assert_eq!(chapter("OEBPS/b.png"), Some("02 - Chapter One"));
+        // ch1b.xhtml is not in the table of contents, so it belongs to chapter one
+        assert_eq!(chapter("OEBPS/c.png"), Some("02 - Chapter One"));
+        assert_eq!(chapter("OEBPS/d.png"), Some("unreferenced"));
+        // Stylesheet backgrounds belong to the pages linking the stylesheet
+        assert_eq!(chapter("OEBPS/e.png"), Some("03 - Chapter_ Two"));
+        assert_eq!(using_pages("OEBPS/e.png", &references), ["OEBPS/ch2.xhtml"]);
+        assert_eq!(chapter("OEBPS/f.png"), Some("other"));
+        assert!(using_pages("OEBPS/f.png", &references).is_empty());
+    }
+
+    #[test]
+    fn test_alt_text_index() {
+        let opf = r#"<package version="3.0"><metadata/><manifest>
+<item id="ch1" href="text/ch1.xhtml" media-type="application/xhtml+xml"/>
+<item id="ch2" href="text/ch2.xhtml" media-type="application/xhtml+xml"/>
+<item id="a" href="images/a.png" media-type="image/png"/>
+</manifest><spine><itemref idref="ch1"/><itemref idref="ch2"/></spine></package>"#;
+        let container = r#"<container><rootfiles>
+<rootfile full-path="OEBPS/book.opf" media-type="application/oebps-package+xml"/>
+</rootfiles></container>"#;
+        let entries: [(&str, &[u8]); 5] = [
+            (CONTAINER_XML_PATH, container.as_bytes()),
+            ("OEBPS/book.opf", opf.as_bytes()),
+            (
+                "OEBPS/text/ch1.xhtml",
+                b"<img alt=\"\" src=\"../images/a.png\"/><IMG SRC='../images/b.png' ALT='Bob&apos;s \"map\"'>",
+            ),
+            (
+                "OEBPS/text/ch2.xhtml",
+                b"<img src=\"../images/a.png\" alt=\"Figure 1 &amp; 2\"/><img src=\"../images/b.png\" alt=\"Later\"/>",
+            ),
+            ("OEBPS/images/a.png", b"\x89PNG a"),
+        ];
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let mut doc = EpubDoc::from_reader(writer.finish().unwrap()).unwrap();
+
+        let alt_texts = build_alt_text_index(&mut doc);
+        assert_eq!(alt_texts["OEBPS/images/a.png"], "Figure 1 & 2");
+        assert_eq!(alt_texts["OEBPS/images/b.png"], "Bob's \"map\"");
+    }
+
+    #[test]
+    fn test_referenced_only() {
+        use crate::output::OutputOptions;
+
+        let epub = book_with_images(
+            "",
+            &[
+                ("images/a.png", png(1, 1)),
+                ("images/logo.png", png(2, 2)),
+                ("images/b.png", png(3, 3)),
+            ],
+            r#"<img src="../images/a.png"/><p style="background: url('../images/b.png')"/>"#,
+        );
+        let dir = temp_dir("epub-referenced");
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            referenced_only: true,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count =
+            process_reader(epub, Path::new("book.epub"), "book", 0, &mut sink, &options).unwrap();
+
+        assert_eq!(count, 2);
+        let mut written = vec![
+            fs::read(dir.join("Book_1.png")).unwrap(),
+            fs::read(dir.join("Book_2.png")).unwrap(),
+        ];
+        written.sort();
+        assert_eq!(written, [png(1, 1), png(3, 3)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_data_uri_images() {
+        use crate::output::OutputOptions;
+
+        let epub = book_with_images(
+            "",
+            &[("images/a.png", png(1, 1))],
+            r#"<img src="../images/a.png"/><img src="data:image/png;base64,iVBORw0KGgpmYjIgcG5n"/>
+<p style="background: url(data:image/gif;base64,R0lGODlhAQABAA==)"/>
+<img src="data:image/png;base64,iVBORw0KGgpmYjIgcG5n"/><img src="data:image/png;base64,!!!"/>"#,
+        );
+        let dir = temp_dir("epub-data-uri");
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count =
+            process_reader(epub, Path::new("book.epub"), "book", 0, &mut sink, &options).unwrap();
+
+        // The resource comes first, then each distinct data URI image once
+        assert_eq!(count, 3);
+        assert_eq!(fs::read(dir.join("Book_1.png")).unwrap(), png(1, 1));
+        assert_eq!(
+            fs::read(dir.join("Book_2.png")).unwrap(),
+            b"\x89PNG\r\n\x1a\nfb2 png"
+        );
+        assert!(dir.join("Book_3.gif").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_mime_to_extension() {
-        assert_eq!(mime_to_extension("image/jpeg"), Some("jpg".to_string()));
-        assert_eq!(mime_to_extension("image/png"), Some("png".to_string()));
-        assert_eq!(mime_to_extension("image/gif"), Some("gif".to_string()));
-        assert_eq!(mime_to_extension("image/unknown"), None);
+        assert_eq!(
+            mime_to_extension("image/jpeg", &[]),
+            Some("jpg".to_string())
+        );
+        assert_eq!(mime_to_extension("image/png", &[]), Some("png".to_string()));
+        assert_eq!(mime_to_extension("image/gif", &[]), Some("gif".to_string()));
+        assert_eq!(mime_to_extension("image/unknown", &[]), None);
     }
 }
@@ -0,0 +1,1286 @@
+//! Document type detection and dispatch to the per-format processors
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::common::{DocumentFilter, ExtraFormat};
+use crate::docx;
+use crate::epub::RenditionChoice;
+use crate::glob::Glob;
+use crate::limits::ResourceLimits;
+use crate::messages::{self, Message};
+use crate::output::OutputSink;
+use crate::registry::Registry;
+
+/// Maximum nesting depth when recursing into embedded documents.
+/// Guards against archives that (directly or indirectly) contain themselves.
+pub const MAX_EMBED_DEPTH: usize = 4;
+
+/// Document extensions listed in "unsupported file type" messages
+pub const SUPPORTED_DOCUMENTS: &str = ".docx, .docm, .dotx, .dotm, .doc, .pptx, .pptm, .xlsx, .xlsm, .odt, .odp, .ods, .epub, .mobi, .azw3, .fb2, .rtf, .html, .mht, .pdf";
+
+/// Supported document types
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DocumentType {
+    /// Word documents and templates, with or without macros (`.docx`, `.docm`, `.dotx`, `.dotm`)
+    Docx,
+    /// Legacy binary Word documents
+    Doc,
+    /// PowerPoint decks, templates and shows share the OOXML package layout and processor
+    /// with `Docx`
+    Pptx,
+    /// Excel workbooks and templates, with or without macros (`.xlsx`, `.xlsm`, `.xltx`, `.xltm`)
+    Xlsx,
+    /// OpenDocument text, presentations and spreadsheets (`.odt`, `.odp`, `.ods`)
+    Odf,
+    Epub,
+    /// Kindle books (`.mobi`, `.azw3`)
+    Mobi,
+    /// FictionBook ebooks (`.fb2`)
+    Fb2,
+    /// Rich Text Format documents (`.rtf`)
+    Rtf,
+    /// Saved web pages (`.html`, `.htm`, `.mht`, `.mhtml`)
+    Html,
+    Pdf,
+}
+
+impl DocumentType {
+    /// Short lowercase name used in machine-readable output
+    pub fn name(self) -> &'static str {
+        match self {
+            DocumentType::Docx => "docx",
+            DocumentType::Doc => "doc",
+            DocumentType::Pptx => "pptx",
+            DocumentType::Xlsx => "xlsx",
+            DocumentType::Odf => "odf",
+            DocumentType::Epub => "epub",
+            DocumentType::Mobi => "mobi",
+            DocumentType::Fb2 => "fb2",
+            DocumentType::Rtf => "rtf",
+            DocumentType::Html => "html",
+            DocumentType::Pdf => "pdf",
+        }
+    }
+}
+
+/// What `--cover-only` extracts from a document without a cover
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum CoverFallback {
+    /// Every image, as without `--cover-only`
+    All,
+    /// Only the image with the most pixels, the presumed cover
+    Largest,
+}
+
+/// Error for a document whose content is locked by DRM. The extractor reports such
+/// documents as skipped rather than failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrmProtected {
+    /// DRM scheme, e.g. "Adobe ADEPT"
+    pub scheme: &'static str,
+}
+
+impl std::fmt::Display for DrmProtected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DRM-protected ({})", self.scheme)
+    }
+}
+
+impl std::error::Error for DrmProtected {}
+
+/// Options shared by every document processor
+#[derive(Debug, Default)]
+pub struct ExtractOptions {
+    /// Lowercase image extensions to extract
+    pub allowed_extensions: HashSet<String>,
+    /// Image formats the built-in tables do not know, extracted and recognised by their
+    /// MIME type like the built-in ones (`--extra-format`)
+    pub extra_formats: Vec<ExtraFormat>,
+    /// Extract only the cover image (EPUB, MOBI and FB2), or the package thumbnail or first
+    /// image of OOXML documents
+    pub cover_only: bool,
+    /// What to extract with `cover_only` when a document has no cover; None extracts nothing
+    pub cover_fallback: Option<CoverFallback>,
+    /// Filter on document title, author and subject; documents that don't match are skipped
+    pub document_filter: DocumentFilter,
+    /// Also extract images from supported documents and zip archives embedded in a document
+    pub recurse_embedded: bool,
+    /// Deepest nesting of embedded documents followed with `recurse_embedded`;
+    /// None is [`MAX_EMBED_DEPTH`]
+    pub max_embed_depth: Option<usize>,
+    /// Only extract images whose content doesn't match their declared format
+    pub only_mismatched: bool,
+    /// Skip covers perceptually identical to one already extracted this run
+    pub skip_similar_covers: bool,
+    /// Renditions to process in EPUBs declaring more than one
+    pub rendition: RenditionChoice,
+    /// Extract the OOXML package thumbnail (`docProps/thumbnail.*`)
+    pub doc_thumbnail: bool,
+    /// Write the bitmap inside EMF/WMF package thumbnails instead of the metafile
+    pub rasterize_thumbnail: bool,
+    /// Scan zip files with unrecognised extensions for image entries, like a .docx
+    pub treat_as_zip: bool,
+    /// Skip macro-enabled OOXML documents (`.docm`, `.xlsm`, `.pptm`, ...)
+    pub skip_macro_enabled: bool,
+    /// Only extract media that the document draws (DOCX) or that a page or stylesheet
+    /// references (EPUB)
+    pub referenced_only: bool,
+    /// Write images into a subdirectory for the part of the document they are used in:
+    /// `body`, `headers`, `footers`, `footnotes`, `endnotes`, `comments` (DOCX)
+    pub sections: bool,
+    /// Append `_footnote`, `_endnote` or `_comment` to the names of images only used in
+    /// notes or comments (DOCX)
+    pub tag_notes: bool,
+    /// Write images into a subdirectory for the chapter they first appear in, named from
+    /// the table of contents (EPUB)
+    pub by_chapter: bool,
+    /// Extract the preview image of each embedded OLE object (Excel sheets, Visio drawings,
+    /// ...) as `{doc}_object_{n}_preview.{ext}` (DOCX)
+    pub embedded_objects: bool,
+    /// With `embedded_objects`, also write the object files themselves as
+    /// `{doc}_object_{n}.{ext}`
+    pub embedded_object_files: bool,
+    /// Also write embedded fonts, de-obfuscated, under `fonts/` (EPUB)
+    pub extract_fonts: bool,
+    /// Take image formats from entry extensions and declared MIME types rather than
+    /// sniffing the content (DOCX, ODF and EPUB; other formats are always sniffed)
+    pub trust_extension: bool,
+    /// Password for encrypted Office packages
+    pub password: Option<String>,
+    /// Ask on the terminal for the password of each encrypted Office package when no
+    /// `password` is given
+    pub prompt_password: bool,
+    /// Limits on entry counts and sizes that protect against zip bombs (DOCX, ODF and EPUB)
+    pub limits: ResourceLimits,
+    /// Skip archive entries that cannot be read (bad headers, CRC or decompression errors)
+    /// instead of failing the document, listing them in the report (DOCX and ODF)
+    pub salvage: bool,
+    /// Extractors for each document type; the built-in ones unless more are registered
+    pub extractors: Registry,
+}
+
+/// Determines the document type based on file extension
+pub fn get_document_type(path: &Path) -> Option<DocumentType> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .and_then(|ext| match ext.as_str() {
+            "docx" | "docm" | "dotx" | "dotm" => Some(DocumentType::Docx),
+            "doc" => Some(DocumentType::Doc),
+            "pptx" | "pptm" | "potx" | "potm" | "ppsx" | "ppsm" => Some(DocumentType::Pptx),
+            "xlsx" | "xlsm" | "xltx" | "xltm" => Some(DocumentType::Xlsx),
+            "odt" | "odp" | "ods" => Some(DocumentType::Odf),
+            "epub" => Some(DocumentType::Epub),
+            "mobi" | "azw3" => Some(DocumentType::Mobi),
+            "fb2" => Some(DocumentType::Fb2),
+            "rtf" => Some(DocumentType::Rtf),
+            "html" | "htm" | "mht" | "mhtml" => Some(DocumentType::Html),
+            "pdf" => Some(DocumentType::Pdf),
+            _ => None,
+        })
+}
+
+/// Checks if a path is a supported document type
+pub fn is_supported_document(path: &Path) -> bool {
+    get_document_type(path).is_some()
+}
+
+/// Checks if a path is a macro-enabled OOXML document or template
+pub fn is_macro_enabled(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .is_some_and(|ext| {
+            matches!(
+                ext.as_str(),
+                "docm" | "dotm" | "xlsm" | "xltm" | "pptm" | "potm" | "ppsm"
+            )
+        })
+}
+
+/// Checks whether a file starts with a zip local file header, whatever its extension
+pub fn is_zip_file(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| magic == *b"PK\x03\x04")
+}
+
+/// Whether an entry of a document, judged by its name, is a document or zip archive to
+/// recurse into with `recurse_embedded`
+pub fn is_embedded_document(name: &str, options: &ExtractOptions) -> bool {
+    let path = Path::new(name);
+    options.extractors.handles(path)
+        || path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// How directories given as input are searched for documents
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// Descend into subdirectories
+    pub recursive: bool,
+    /// Collect zip files with other extensions too
+    pub treat_as_zip: bool,
+    /// Files and directories to leave out; excluded directories are not descended into
+    pub exclude: Vec<Glob>,
+    /// With `recursive`, how many levels to search: 1 is the directory itself
+    pub max_depth: Option<usize>,
+    /// With `recursive`, descend into symlinked directories
+    pub follow_symlinks: bool,
+    /// With `recursive`, do not cross into other file systems (mount points)
+    pub same_filesystem: bool,
+    /// Extractors whose documents are collected; the built-in ones by default
+    pub extractors: Registry,
+}
+
+impl ScanOptions {
+    /// Whether `path`, found while scanning `dir`, matches an exclude pattern
+    fn is_excluded(&self, dir: &Path, path: &Path, is_dir: bool) -> bool {
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        self.exclude
+            .iter()
+            .any(|pattern| pattern.matches(relative, is_dir))
+    }
+}
+
+/// Collects supported documents from a directory, optionally descending into subdirectories.
+pub fn collect_documents(dir: &Path, options: &ScanOptions, documents: &mut Vec<PathBuf>) {
+    let is_candidate = |path: &Path| {
+        options.extractors.handles(path) || (options.treat_as_zip && is_zip_file(path))
+    };
+    if options.recursive {
+        let mut walker = WalkDir::new(dir)
+            .follow_links(options.follow_symlinks)
+            .same_file_system(options.same_filesystem);
+        if let Some(max_depth) = options.max_depth {
+            walker = walker.max_depth(max_depth);
+        }
+        let walker = walker.into_iter().filter_entry(|entry| {
+            entry.depth() == 0
+                || !options.is_excluded(dir, entry.path(), entry.file_type().is_dir())
+        });
+        for entry in walker {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    eprintln!("Warning: Could not access path: {}", e);
+                    continue;
+                }
+            };
+            let path = entry.path();
+            if path.is_file() && is_candidate(path) {
+                documents.push(path.to_path_buf());
+            }
+        }
+    } else {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Warning: Could not read directory {}: {}", dir.display(), e);
+                return;
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    eprintln!("Warning: Could not access entry: {}", e);
+                    continue;
+                }
+            };
+            let path = entry.path();
+            if path.is_file() && is_candidate(&path) && !options.is_excluded(dir, &path, false) {
+                documents.push(path);
+            }
+        }
+    }
+}
+
+/// Parses a list of document paths, one per line or NUL-terminated as written by
+/// `find -print0`. NUL separators are used if the data contains any. Blank lines are
+/// skipped, as are the carriage returns of Windows line endings.
+pub fn parse_file_list(data: &[u8]) -> Vec<PathBuf> {
+    let separator = if data.contains(&0) { 0 } else { b'\n' };
+    data.split(|&b| b == separator)
+        .map(|entry| match separator {
+            b'\n' => entry.strip_suffix(b"\r").unwrap_or(entry),
+            _ => entry,
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(path_from_bytes)
+        .collect()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Removes documents listed more than once, keeping the first occurrence. Paths are
+/// compared after resolving them, so `./a.docx` and `dir/../a.docx` are the same document,
+/// as are files found through two overlapping input directories.
+/// Returns the number of documents removed.
+pub fn remove_duplicate_documents(documents: &mut Vec<PathBuf>) -> usize {
+    let before = documents.len();
+    let mut seen = HashSet::new();
+    documents.retain(|path| seen.insert(fs::canonicalize(path).unwrap_or_else(|_| path.clone())));
+    before - documents.len()
+}
+
+/// Handles an archive entry of the current document that could not be read: with
+/// `salvage` it is recorded as damaged and the document carries on without it, otherwise
+/// the error fails the document
+pub(crate) fn skip_damaged(
+    error: anyhow::Error,
+    entry: &str,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<()> {
+    if !options.salvage {
+        return Err(error);
+    }
+    sink.record_damaged(entry, &error);
+    Ok(())
+}
+
+/// Processes a single file based on its type
+pub fn process_file(
+    input_path: &Path,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    if options.skip_macro_enabled && is_macro_enabled(input_path) {
+        status!(
+            "{}",
+            messages::text(
+                Message::SkippingMacroEnabled,
+                &[("path", &input_path.display())]
+            )
+        );
+        return Ok(0);
+    }
+    match options.extractors.find(input_path) {
+        Some(extractor) => extractor.extract(input_path, sink, options),
+        // Unknown zip containers go through the OOXML path, which takes every image entry
+        None if options.treat_as_zip && is_zip_file(input_path) => {
+            status!("Treating {} as a zip archive", input_path.display());
+            docx::process_file(input_path, sink, options)
+        }
+        None => {
+            anyhow::bail!(
+                "Unsupported file type: {}. Supported types: {}",
+                input_path.display(),
+                SUPPORTED_DOCUMENTS
+            );
+        }
+    }
+}
+
+/// Processes a document found inside another document.
+/// `virtual_path` is the parent path joined with the entry name, used for messages,
+/// and `base_name` is the output base name labelled with the nesting path.
+/// Returns the number of images extracted, including from further nested documents.
+pub fn process_embedded(
+    data: Vec<u8>,
+    virtual_path: &Path,
+    base_name: &str,
+    depth: usize,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let max_depth = options.max_embed_depth.unwrap_or(MAX_EMBED_DEPTH);
+    if depth > max_depth {
+        sink.warn(&format!(
+            "Not descending into {}: nesting deeper than {} levels",
+            virtual_path.display(),
+            max_depth
+        ));
+        return Ok(0);
+    }
+
+    if options.skip_macro_enabled && is_macro_enabled(virtual_path) {
+        status!(
+            "{}",
+            messages::text(
+                Message::SkippingMacroEnabledEmbedded,
+                &[("path", &virtual_path.display())]
+            )
+        );
+        return Ok(0);
+    }
+
+    // Zip archives bundled in a document go through the OOXML path, which takes every
+    // image entry and recurses into the documents among them
+    if !options.extractors.handles(virtual_path) && data.starts_with(b"PK\x03\x04") {
+        status!("Processing embedded zip archive {}", virtual_path.display());
+        return docx::process_reader(
+            Cursor::new(data),
+            virtual_path,
+            base_name,
+            depth,
+            sink,
+            options,
+        );
+    }
+
+    status!("Processing embedded document {}", virtual_path.display());
+    process_reader(data, virtual_path, base_name, depth, sink, options)
+}
+
+/// Processes a whole document held in memory, such as one piped to stdin.
+/// `virtual_path` stands in for its file name: its extension selects the document type
+/// and its stem is the output base name.
+pub fn process_bytes(
+    data: Vec<u8>,
+    virtual_path: &Path,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    if options.skip_macro_enabled && is_macro_enabled(virtual_path) {
+        status!(
+            "{}",
+            messages::text(
+                Message::SkippingMacroEnabled,
+                &[("path", &virtual_path.display())]
+            )
+        );
+        return Ok(0);
+    }
+    let base_name = virtual_path
+        .file_stem()
+        .context("Invalid filename")?
+        .to_string_lossy()
+        .to_string();
+    if !options.extractors.handles(virtual_path) {
+        if options.treat_as_zip && data.starts_with(b"PK\x03\x04") {
+            status!("Treating {} as a zip archive", virtual_path.display());
+            return docx::process_reader(
+                Cursor::new(data),
+                virtual_path,
+                &base_name,
+                0,
+                sink,
+                options,
+            );
+        }
+        anyhow::bail!(
+            "Unsupported file type: {}. Supported types: {}",
+            virtual_path.display(),
+            SUPPORTED_DOCUMENTS
+        );
+    }
+    process_reader(data, virtual_path, &base_name, 0, sink, options)
+}
+
+/// Hands in-memory document data to the extractor for its type
+fn process_reader(
+    data: Vec<u8>,
+    virtual_path: &Path,
+    base_name: &str,
+    depth: usize,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    match options.extractors.find(virtual_path) {
+        Some(extractor) => {
+            extractor.extract_bytes(data, virtual_path, base_name, depth, sink, options)
+        }
+        None => Ok(0),
+    }
+}
+
+/// Builds the output base name for a nested document: `{parent}__{child stem}`
+pub fn nested_base_name(parent_base: &str, entry_name: &str) -> String {
+    let child = Path::new(entry_name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    format!("{}__{}", parent_base, child)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::{temp_dir, zip_bytes};
+    use crate::output::OutputOptions;
+    use std::io::Write;
+
+    #[test]
+    fn test_recurse_into_embedded_docx() {
+        let dir = temp_dir("embedded");
+
+        let inner = zip_bytes(&[("word/media/image1.gif", b"GIF89a")]);
+        let outer = zip_bytes(&[
+            ("word/media/image1.png", b"png"),
+            ("word/embeddings/Inner.docx", &inner),
+        ]);
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            recurse_embedded: true,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+
+        let count = process_embedded(
+            outer,
+            Path::new("outer.docx"),
+            "outer",
+            0,
+            &mut sink,
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(count, 2);
+        assert!(dir.join("outer.png").exists());
+        assert!(dir.join("outer__Inner.gif").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pptx_media_is_extracted() {
+        let dir = temp_dir("pptx");
+
+        let pptx = zip_bytes(&[
+            ("ppt/slides/slide1.xml", b"<p:sld/>"),
+            ("ppt/media/image1.png", b"png"),
+            ("ppt/media/image2.gif", b"GIF89a"),
+        ]);
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count =
+            process_embedded(pptx, Path::new("deck.pptx"), "deck", 0, &mut sink, &options).unwrap();
+
+        assert_eq!(count, 2);
+        assert!(dir.join("deck_1.png").exists());
+        assert!(dir.join("deck_2.gif").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_formats_are_detected_from_content() {
+        let dir = temp_dir("sniffed");
+
+        let docx = zip_bytes(&[
+            ("word/media/image1.png", b"\xFF\xD8\xFF\xE0 really a jpeg"),
+            ("word/media/image2.png", b"\x89PNG\r\n\x1a\n"),
+        ]);
+        let mut options = ExtractOptions {
+            allowed_extensions: HashSet::from(["jpg".to_string(), "jpeg".to_string()]),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count = process_embedded(
+            docx.clone(),
+            Path::new("doc.docx"),
+            "doc",
+            0,
+            &mut sink,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+        assert!(dir.join("doc.jpg").exists());
+
+        options.trust_extension = true;
+        let count =
+            process_embedded(docx, Path::new("doc.docx"), "doc", 0, &mut sink, &options).unwrap();
+        assert_eq!(count, 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_referenced_only_skips_orphaned_media() {
+        let dir = temp_dir("referenced");
+
+        let docx = zip_bytes(&[
+            (
+                "word/document.xml",
+                b"<w:body><w:p><w:drawing><a:blip r:embed=\"rId1\"/></w:drawing></w:p></w:body>",
+            ),
+            (
+                "word/_rels/document.xml.rels",
+                b"<Relationships><Relationship Id=\"rId1\" Target=\"media/image2.gif\"/></Relationships>",
+            ),
+            ("word/media/image1.gif", b"GIF89a orphan"),
+            ("word/media/image2.gif", b"GIF89a drawn"),
+        ]);
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            referenced_only: true,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count =
+            process_embedded(docx, Path::new("doc.docx"), "doc", 0, &mut sink, &options).unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(std::fs::read(dir.join("doc.gif")).unwrap(), b"GIF89a drawn");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_docx_images_are_numbered_in_reading_order() {
+        let dir = temp_dir("order");
+
+        let docx = zip_bytes(&[
+            ("word/media/image1.gif", b"GIF89a second"),
+            ("word/media/image2.gif", b"GIF89a first"),
+            ("word/media/image3.gif", b"GIF89a unused"),
+            (
+                "word/document.xml",
+                b"<w:body><w:p><w:drawing><a:blip r:embed=\"rId2\"/></w:drawing></w:p>\
+                  <w:p><w:drawing><a:blip r:embed=\"rId1\"/></w:drawing></w:p></w:body>",
+            ),
+            (
+                "word/_rels/document.xml.rels",
+                b"<Relationships><Relationship Id=\"rId1\" Target=\"media/image1.gif\"/>\
+                  <Relationship Id=\"rId2\" Target=\"media/image2.gif\"/></Relationships>",
+            ),
+        ]);
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count =
+            process_embedded(docx, Path::new("doc.docx"), "doc", 0, &mut sink, &options).unwrap();
+
+        assert_eq!(count, 3);
+        assert_eq!(
+            std::fs::read(dir.join("doc_1.gif")).unwrap(),
+            b"GIF89a first"
+        );
+        assert_eq!(
+            std::fs::read(dir.join("doc_2.gif")).unwrap(),
+            b"GIF89a second"
+        );
+        assert_eq!(
+            std::fs::read(dir.join("doc_3.gif")).unwrap(),
+            b"GIF89a unused"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_docx_embedded_objects() {
+        let dir = temp_dir("objects");
+
+        let docx = zip_bytes(&[
+            ("word/media/image1.gif", b"GIF89a picture"),
+            ("word/media/image2.wmf", b"\xD7\xCD\xC6\x9A preview"),
+            ("word/embeddings/oleObject1.bin", b"ole object"),
+            ("word/embeddings/Chart_Data.xlsx", b"chart data"),
+            (
+                "word/document.xml",
+                b"<w:body><w:p><w:drawing><a:blip r:embed=\"rId1\"/></w:drawing></w:p>\
+                  <w:p><w:object><v:shape><v:imagedata r:id=\"rId2\"/></v:shape>\
+                  <o:OLEObject ProgID=\"Visio.Drawing.15\" r:id=\"rId3\"/></w:object></w:p></w:body>",
+            ),
+            (
+                "word/_rels/document.xml.rels",
+                b"<Relationships><Relationship Id=\"rId1\" Target=\"media/image1.gif\"/>\
+                  <Relationship Id=\"rId2\" Target=\"media/image2.wmf\"/>\
+                  <Relationship Id=\"rId3\" Target=\"embeddings/oleObject1.bin\"/></Relationships>",
+            ),
+        ]);
+        let options = ExtractOptions {
+            allowed_extensions: HashSet::from(["gif".to_string()]),
+            embedded_objects: true,
+            embedded_object_files: true,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count =
+            process_embedded(docx, Path::new("doc.docx"), "doc", 0, &mut sink, &options).unwrap();
+
+        assert_eq!(count, 4);
+        assert_eq!(
+            std::fs::read(dir.join("doc.gif")).unwrap(),
+            b"GIF89a picture"
+        );
+        assert_eq!(
+            std::fs::read(dir.join("doc_object_1_preview.wmf")).unwrap(),
+            b"\xD7\xCD\xC6\x9A preview"
+        );
+        assert_eq!(
+            std::fs::read(dir.join("doc_object_1.bin")).unwrap(),
+            b"ole object"
+        );
+        assert_eq!(
+            std::fs::read(dir.join("doc_object_2.xlsx")).unwrap(),
+            b"chart data"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_docx() {
+        let dir = temp_dir("encrypted");
+
+        let docx = zip_bytes(&[("word/media/image1.gif", b"GIF89a secret")]);
+        let encrypted = crate::office_crypto::tests::encrypted_package(&docx, "hunter2");
+        let mut options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let err = process_embedded(
+            encrypted.clone(),
+            Path::new("doc.docx"),
+            "doc",
+            0,
+            &mut sink,
+            &options,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("password-protected"));
+
+        options.password = Some("hunter2".to_string());
+        let count = process_embedded(
+            encrypted,
+            Path::new("doc.docx"),
+            "doc",
+            0,
+            &mut sink,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            std::fs::read(dir.join("doc.gif")).unwrap(),
+            b"GIF89a secret"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_docx_sections() {
+        let dir = temp_dir("sections");
+
+        let docx = zip_bytes(&[
+            ("word/media/image1.gif", b"GIF89a logo"),
+            ("word/media/image2.gif", b"GIF89a chart"),
+            (
+                "word/document.xml",
+                b"<w:body><w:p><w:drawing><a:blip r:embed=\"rId1\"/></w:drawing></w:p></w:body>",
+            ),
+            (
+                "word/_rels/document.xml.rels",
+                b"<Relationships><Relationship Id=\"rId1\" Target=\"media/image2.gif\"/></Relationships>",
+            ),
+            (
+                "word/header1.xml",
+                b"<w:hdr><w:p><w:drawing><a:blip r:embed=\"rId1\"/></w:drawing></w:p></w:hdr>",
+            ),
+            (
+                "word/_rels/header1.xml.rels",
+                b"<Relationships><Relationship Id=\"rId1\" Target=\"media/image1.gif\"/></Relationships>",
+            ),
+        ]);
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            sections: true,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        process_embedded(docx, Path::new("doc.docx"), "doc", 0, &mut sink, &options).unwrap();
+
+        assert_eq!(
+            std::fs::read(dir.join("body/doc_1.gif")).unwrap(),
+            b"GIF89a chart"
+        );
+        assert_eq!(
+            std::fs::read(dir.join("headers/doc_2.gif")).unwrap(),
+            b"GIF89a logo"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_docx_footnote_images() {
+        let dir = temp_dir("footnotes");
+
+        let docx = zip_bytes(&[
+            ("word/media/image1.gif", b"GIF89a note"),
+            ("word/media/image2.gif", b"GIF89a chart"),
+            ("word/media/image3.gif", b"GIF89a map"),
+            (
+                "word/document.xml",
+                b"<w:body><w:p><w:drawing><a:blip r:embed=\"rId1\"/></w:drawing>\
+<w:r><w:footnoteReference w:id=\"2\"/></w:r></w:p>\
+<w:p><w:drawing><a:blip r:embed=\"rId2\"/></w:drawing></w:p></w:body>",
+            ),
+            (
+                "word/_rels/document.xml.rels",
+                b"<Relationships><Relationship Id=\"rId1\" Target=\"media/image2.gif\"/>\
+<Relationship Id=\"rId2\" Target=\"media/image3.gif\"/></Relationships>",
+            ),
+            (
+                "word/footnotes.xml",
+                b"<w:footnotes><w:footnote w:type=\"separator\" w:id=\"-1\"/>\
+<w:footnote w:id=\"2\"><w:p><w:drawing><a:blip r:embed=\"rId1\"/></w:drawing></w:p></w:footnote>\
+</w:footnotes>",
+            ),
+            (
+                "word/_rels/footnotes.xml.rels",
+                b"<Relationships><Relationship Id=\"rId1\" Target=\"media/image1.gif\"/></Relationships>",
+            ),
+        ]);
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            tag_notes: true,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        process_embedded(docx, Path::new("doc.docx"), "doc", 0, &mut sink, &options).unwrap();
+
+        // The footnote's image is numbered where the footnote is referenced
+        assert_eq!(
+            std::fs::read(dir.join("doc_1.gif")).unwrap(),
+            b"GIF89a chart"
+        );
+        assert_eq!(
+            std::fs::read(dir.join("doc_2_footnote.gif")).unwrap(),
+            b"GIF89a note"
+        );
+        assert_eq!(std::fs::read(dir.join("doc_3.gif")).unwrap(), b"GIF89a map");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_salvage_skips_damaged_entries() {
+        let dir = temp_dir("salvage");
+
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        for (name, data) in [
+            ("word/media/image1.gif", &b"GIF89a chart"[..]),
+            ("word/media/image2.gif", b"GIF89a map"),
+        ] {
+            writer.start_file(name, stored).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let mut docx = writer.finish().unwrap().into_inner();
+        // Damage the first image so it no longer matches its CRC
+        let at = docx.windows(5).position(|w| w == b"chart").unwrap();
+        docx[at] = b'C';
+
+        let mut options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        sink.begin_document(Path::new("doc.docx"));
+        let failed = process_embedded(
+            docx.clone(),
+            Path::new("doc.docx"),
+            "doc",
+            0,
+            &mut sink,
+            &options,
+        );
+        assert!(failed.is_err());
+
+        options.salvage = true;
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        sink.begin_document(Path::new("doc.docx"));
+        let count =
+            process_embedded(docx, Path::new("doc.docx"), "doc", 0, &mut sink, &options).unwrap();
+        assert_eq!(count, 1);
+        // The damaged entry is left out before numbering, so the one image keeps a bare name
+        assert_eq!(std::fs::read(dir.join("doc.gif")).unwrap(), b"GIF89a map");
+        let damaged = sink.take_damaged();
+        assert_eq!(damaged.len(), 1);
+        assert_eq!(damaged[0].0, "word/media/image1.gif");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_treat_as_zip() {
+        let dir = temp_dir("zip");
+
+        let container = dir.join("scans.cbz");
+        std::fs::write(&container, zip_bytes(&[("pages/001.png", b"png")])).unwrap();
+        let not_zip = dir.join("notes.txt");
+        std::fs::write(&not_zip, b"plain text").unwrap();
+        assert!(is_zip_file(&container));
+        assert!(!is_zip_file(&not_zip));
+
+        let mut options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            ..Default::default()
+        };
+        let out = dir.join("out");
+        let mut sink = OutputSink::new(out.clone(), OutputOptions::default());
+        assert!(process_file(&container, &mut sink, &options).is_err());
+
+        options.treat_as_zip = true;
+        assert_eq!(process_file(&container, &mut sink, &options).unwrap(), 1);
+        assert!(out.join("scans.png").exists());
+        assert!(process_file(&not_zip, &mut sink, &options).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_collect_documents_exclude() {
+        let dir = temp_dir("exclude");
+        for name in [
+            "report.docx",
+            "report draft.docx",
+            "old/backup/report.docx",
+            "old/notes.epub",
+            ".git/objects/book.epub",
+        ] {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+
+        let collect = |recursive: bool| {
+            let options = ScanOptions {
+                recursive,
+                exclude: ["*draft*", "**/backup/**", ".git"]
+                    .iter()
+                    .map(|pattern| pattern.parse().unwrap())
+                    .collect(),
+                ..Default::default()
+            };
+            let mut documents = Vec::new();
+            collect_documents(&dir, &options, &mut documents);
+            let mut names: Vec<_> = documents
+                .iter()
+                .map(|path| path.strip_prefix(&dir).unwrap().to_path_buf())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            collect(true),
+            [
+                PathBuf::from("old/notes.epub"),
+                PathBuf::from("report.docx")
+            ]
+        );
+        assert_eq!(collect(false), [PathBuf::from("report.docx")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_collect_documents_max_depth() {
+        let dir = temp_dir("depth");
+        for name in ["a.docx", "one/b.docx", "one/two/c.docx"] {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+
+        let count = |max_depth: Option<usize>| {
+            let options = ScanOptions {
+                recursive: true,
+                max_depth,
+                ..Default::default()
+            };
+            let mut documents = Vec::new();
+            collect_documents(&dir, &options, &mut documents);
+            documents.len()
+        };
+        assert_eq!(count(Some(1)), 1);
+        assert_eq!(count(Some(2)), 2);
+        assert_eq!(count(None), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_file_list() {
+        assert_eq!(
+            parse_file_list(b"a.docx\r\n\nbooks/b c.epub\n"),
+            [PathBuf::from("a.docx"), PathBuf::from("books/b c.epub")]
+        );
+        assert_eq!(
+            parse_file_list(b"one\nline.docx\0b.epub\0"),
+            [PathBuf::from("one\nline.docx"), PathBuf::from("b.epub")]
+        );
+        assert!(parse_file_list(b"").is_empty());
+    }
+
+    #[test]
+    fn test_remove_duplicate_documents() {
+        let dir = temp_dir("duplicates");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.docx"), b"").unwrap();
+        std::fs::write(dir.join("sub/b.docx"), b"").unwrap();
+
+        let mut documents = vec![
+            dir.join("a.docx"),
+            dir.join("sub/b.docx"),
+            dir.join("sub/../a.docx"),
+            dir.join("sub/b.docx"),
+        ];
+        assert_eq!(remove_duplicate_documents(&mut documents), 2);
+        assert_eq!(documents, [dir.join("a.docx"), dir.join("sub/b.docx")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_doc_thumbnail_is_named_separately() {
+        let dir = temp_dir("thumbnail");
+
+        let docx = zip_bytes(&[
+            ("word/document.xml", b"<w:document/>"),
+            ("docProps/thumbnail.jpeg", b"\xFF\xD8\xFF\xE0"),
+        ]);
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            doc_thumbnail: true,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+
+        let count =
+            process_embedded(docx, Path::new("doc.docx"), "doc", 0, &mut sink, &options).unwrap();
+
+        assert_eq!(count, 1);
+        assert!(dir.join("doc_thumbnail.jpeg").exists());
+        assert!(!dir.join("doc.jpeg").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_docx_cover_only() {
+        let dir = temp_dir("cover");
+
+        let body: [(&str, &[u8]); 4] = [
+            (
+                "word/document.xml",
+                b"<w:body><w:p><w:drawing><a:blip r:embed=\"rId1\"/></w:drawing></w:p></w:body>",
+            ),
+            (
+                "word/_rels/document.xml.rels",
+                b"<Relationships><Relationship Id=\"rId1\" Target=\"media/image2.gif\"/></Relationships>",
+            ),
+            ("word/media/image1.gif", b"GIF89a orphan"),
+            ("word/media/image2.gif", b"GIF89a first"),
+        ];
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            cover_only: true,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+
+        // Without a thumbnail, the first image in reading order is the cover
+        let docx = zip_bytes(&body);
+        let count =
+            process_embedded(docx, Path::new("a.docx"), "a", 0, &mut sink, &options).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(std::fs::read(dir.join("a.gif")).unwrap(), b"GIF89a first");
+
+        let mut entries = body.to_vec();
+        entries.push(("docProps/thumbnail.jpeg", b"\xFF\xD8\xFF\xE0"));
+        let docx = zip_bytes(&entries);
+        let count =
+            process_embedded(docx, Path::new("b.docx"), "b", 0, &mut sink, &options).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            std::fs::read(dir.join("b.jpeg")).unwrap(),
+            b"\xFF\xD8\xFF\xE0"
+        );
+        assert!(!dir.join("b.gif").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_zip64_archive_with_many_entries() {
+        let dir = temp_dir("zip64");
+
+        // More entries than a classic end-of-central-directory record can count,
+        // with the image itself written using ZIP64 extra fields
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        for i in 0..70_000 {
+            writer
+                .start_file(format!("word/glossary/part{}.xml", i), stored)
+                .unwrap();
+        }
+        writer
+            .start_file("word/media/image1.gif", stored.large_file(true))
+            .unwrap();
+        writer.write_all(b"GIF89a zip64").unwrap();
+        let docx = writer.finish().unwrap().into_inner();
+
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count =
+            process_embedded(docx, Path::new("big.docx"), "big", 0, &mut sink, &options).unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(std::fs::read(dir.join("big.gif")).unwrap(), b"GIF89a zip64");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_embedding_depth_is_limited() {
+        let mut options = ExtractOptions::default();
+        let mut sink = OutputSink::new(std::env::temp_dir(), OutputOptions::default());
+        let mut embedded = |depth, options: &ExtractOptions| {
+            process_embedded(
+                b"not even a zip".to_vec(),
+                Path::new("deep.docx"),
+                "deep",
+                depth,
+                &mut sink,
+                options,
+            )
+        };
+        assert_eq!(embedded(MAX_EMBED_DEPTH + 1, &options).unwrap(), 0);
+        // Within the limit the (broken) document is opened
+        assert!(embedded(MAX_EMBED_DEPTH, &options).is_err());
+        options.max_embed_depth = Some(1);
+        assert_eq!(embedded(2, &options).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_embedded_zip_archive() {
+        let dir = temp_dir("embedded-zip");
+        let zip = |entries: &[(&str, &[u8])]| {
+            let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+            for (name, data) in entries {
+                writer
+                    .start_file(*name, zip::write::SimpleFileOptions::default())
+                    .unwrap();
+                writer.write_all(data).unwrap();
+            }
+            writer.finish().unwrap().into_inner()
+        };
+        let inner = zip(&[("word/media/image1.gif", b"GIF89a inner")]);
+        let bundle = zip(&[("scan.png", b"\x89PNG scan"), ("extra/report.docx", &inner)]);
+
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            recurse_embedded: true,
+            ..Default::default()
+        };
+        assert!(is_embedded_document("OEBPS/extras.ZIP", &options));
+        assert!(is_embedded_document("word/embeddings/a.xlsx", &options));
+        assert!(!is_embedded_document("word/media/a.png", &options));
+
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count = process_embedded(
+            bundle,
+            Path::new("book.epub/extras.zip"),
+            "book__extras",
+            1,
+            &mut sink,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(
+            std::fs::read(dir.join("book__extras.png")).unwrap(),
+            b"\x89PNG scan"
+        );
+        assert_eq!(
+            std::fs::read(dir.join("book__extras__report.gif")).unwrap(),
+            b"GIF89a inner"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_get_document_type() {
+        assert_eq!(
+            get_document_type(Path::new("a/Report.DOCX")),
+            Some(DocumentType::Docx)
+        );
+        assert_eq!(
+            get_document_type(Path::new("book.epub")),
+            Some(DocumentType::Epub)
+        );
+        assert_eq!(
+            get_document_type(Path::new("memo.DOC")),
+            Some(DocumentType::Doc)
+        );
+        assert_eq!(
+            get_document_type(Path::new("deck.pptx")),
+            Some(DocumentType::Pptx)
+        );
+        assert_eq!(
+            get_document_type(Path::new("letter.dotm")),
+            Some(DocumentType::Docx)
+        );
+        assert_eq!(
+            get_document_type(Path::new("talk.ppsx")),
+            Some(DocumentType::Pptx)
+        );
+        assert_eq!(
+            get_document_type(Path::new("budget.XLSM")),
+            Some(DocumentType::Xlsx)
+        );
+        assert_eq!(
+            get_document_type(Path::new("slides.odp")),
+            Some(DocumentType::Odf)
+        );
+        assert_eq!(
+            get_document_type(Path::new("novel.azw3")),
+            Some(DocumentType::Mobi)
+        );
+        assert_eq!(
+            get_document_type(Path::new("story.fb2")),
+            Some(DocumentType::Fb2)
+        );
+        assert_eq!(
+            get_document_type(Path::new("letter.RTF")),
+            Some(DocumentType::Rtf)
+        );
+        assert_eq!(
+            get_document_type(Path::new("page.mhtml")),
+            Some(DocumentType::Html)
+        );
+        assert_eq!(
+            get_document_type(Path::new("scan.Pdf")),
+            Some(DocumentType::Pdf)
+        );
+        assert_eq!(get_document_type(Path::new("notes.txt")), None);
+    }
+
+    #[test]
+    fn test_is_macro_enabled() {
+        assert!(is_macro_enabled(Path::new("macros.DOCM")));
+        assert!(is_macro_enabled(Path::new("budget.xlsm")));
+        assert!(is_macro_enabled(Path::new("show.ppsm")));
+        assert!(!is_macro_enabled(Path::new("letter.dotx")));
+        assert!(!is_macro_enabled(Path::new("report.docx")));
+    }
+
+    #[test]
+    fn test_nested_base_name() {
+        assert_eq!(
+            nested_base_name("report", "word/embeddings/Microsoft_Word_Document1.docx"),
+            "report__Microsoft_Word_Document1"
+        );
+    }
+}
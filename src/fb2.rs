@@ -0,0 +1,496 @@
+//! FictionBook (.fb2) file processing module
+//!
+//! FB2 books are a single XML document: metadata lives in `<description><title-info>`,
+//! and every image is a base64 `<binary>` element at the end of the file, referenced
+//! from the text by `#id`.
+
+use anyhow::{Context, Result};
+use regex::bytes::Regex;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use crate::common::{
+    DocumentMetadata, ExtraFormat, base64_decode, get_supported_extensions, normalize_isbn,
+    unescape_xml,
+};
+use crate::epub::{format_epub_base_name, mime_to_extension, write_cover, write_largest_cover};
+use crate::extract::{CoverFallback, ExtractOptions};
+use crate::output::OutputSink;
+use crate::sniff::sniff_image_format;
+
+static BINARY_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<(?:\w+:)?binary\b([^>]*)>(.*?)</(?:\w+:)?binary>").unwrap());
+static ATTR_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"([\w:-]+)\s*=\s*["']([^"']*)["']"#).unwrap());
+static TITLE_INFO_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<(?:\w+:)?title-info\b.*?</(?:\w+:)?title-info>").unwrap());
+static AUTHOR_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<(?:\w+:)?author\b.*?</(?:\w+:)?author>").unwrap());
+static GENRE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<(?:\w+:)?genre\b[^>]*>(.*?)</(?:\w+:)?genre>").unwrap());
+static SEQUENCE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<(?:\w+:)?sequence\b([^>]*)>").unwrap());
+static PUBLISH_INFO_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?s)<(?:\w+:)?publish-info\b.*?</(?:\w+:)?publish-info>").unwrap()
+});
+static COVERPAGE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<(?:\w+:)?coverpage\b.*?</(?:\w+:)?coverpage>").unwrap());
+static HREF_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r##"(?:\w+:)?href\s*=\s*["']#([^"']+)["']"##).unwrap());
+static ENCODING_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"^\s*<\?xml[^>]*\bencoding\s*=\s*["']([^"']+)["']"#).unwrap());
+static TAG_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+/// Windows-1251 code points for bytes 0x80-0xBF; 0xC0-0xFF map to U+0410-U+044F
+const CP1251_HIGH: [u16; 64] = [
+    0x0402, 0x0403, 0x201A, 0x0453, 0x201E, 0x2026, 0x2020, 0x2021, 0x20AC, 0x2030, 0x0409, 0x2039,
+    0x040A, 0x040C, 0x040B, 0x040F, 0x0452, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014,
+    0xFFFD, 0x2122, 0x0459, 0x203A, 0x045A, 0x045C, 0x045B, 0x045F, 0x00A0, 0x040E, 0x045E, 0x0408,
+    0x00A4, 0x0490, 0x00A6, 0x00A7, 0x0401, 0x00A9, 0x0404, 0x00AB, 0x00AC, 0x00AD, 0x00AE, 0x0407,
+    0x00B0, 0x00B1, 0x0406, 0x0456, 0x0491, 0x00B5, 0x00B6, 0x00B7, 0x0451, 0x2116, 0x0454, 0x00BB,
+    0x0458, 0x0405, 0x0455, 0x0457,
+];
+
+/// A decoded `<binary>` image
+pub struct BookImage {
+    pub id: String,
+    pub extension: String,
+    pub data: Vec<u8>,
+}
+
+/// Metadata and images of a FictionBook
+pub struct Book {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub series: Option<String>,
+    /// `number` of the first `<sequence>`
+    pub series_index: Option<String>,
+    pub publisher: Option<String>,
+    pub language: Option<String>,
+    pub isbn: Option<String>,
+    /// Images in document order
+    pub images: Vec<BookImage>,
+    /// Position of the `<coverpage>` image in `images`
+    pub cover: Option<usize>,
+    /// Ids of binaries whose base64 content could not be decoded
+    pub corrupt: Vec<String>,
+}
+
+/// Parses an FB2 document. Binaries whose content is not a known image format are kept if
+/// their declared type is one of the `extra` formats.
+pub fn parse(data: &[u8], extra: &[ExtraFormat]) -> Result<Book> {
+    if !data.windows(12).any(|w| w == b"<FictionBook") {
+        anyhow::bail!("Not a FictionBook document");
+    }
+    let cp1251 = ENCODING_RE
+        .captures(data)
+        .is_some_and(|c| c[1].eq_ignore_ascii_case(b"windows-1251"));
+    let decode = |bytes: &[u8]| decode_text(bytes, cp1251);
+
+    let title_info = TITLE_INFO_RE.find(data).map(|m| m.as_bytes());
+    let element = |name: &str, within: &[u8]| {
+        Regex::new(&format!(
+            r"(?s)<(?:\w+:)?{0}\b[^>]*>(.*?)</(?:\w+:)?{0}>",
+            name
+        ))
+        .ok()?
+        .captures(within)
+        .map(|c| decode(&c[1]))
+        .filter(|t| !t.is_empty())
+    };
+    let title = title_info.and_then(|info| element("book-title", info));
+    let author = title_info
+        .and_then(|info| AUTHOR_RE.find(info))
+        .and_then(|block| {
+            let block = block.as_bytes();
+            let names: Vec<String> = ["first-name", "middle-name", "last-name"]
+                .iter()
+                .filter_map(|name| element(name, block))
+                .collect();
+            if names.is_empty() {
+                element("nickname", block)
+            } else {
+                Some(names.join(" "))
+            }
+        });
+    // Genres stand in for the subject
+    let genres: Vec<String> = title_info
+        .map(|info| {
+            GENRE_RE
+                .captures_iter(info)
+                .map(|c| decode(&c[1]))
+                .filter(|g| !g.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    let subject = Some(genres.join(", ")).filter(|s| !s.is_empty());
+    // The name and number of the first `<sequence>`
+    let sequence = title_info.and_then(|info| SEQUENCE_RE.captures(info));
+    let sequence_attr = |name: &[u8]| {
+        sequence
+            .as_ref()
+            .and_then(|c| {
+                ATTR_RE
+                    .captures_iter(&c[1])
+                    .find(|a| &a[1] == name)
+                    .map(|a| decode(&a[2]))
+            })
+            .filter(|value| !value.is_empty())
+    };
+    let series = sequence_attr(b"name");
+    let series_index = series.as_ref().and_then(|_| sequence_attr(b"number"));
+    let language = title_info.and_then(|info| element("lang", info));
+    let publish_info = PUBLISH_INFO_RE.find(data).map(|m| m.as_bytes());
+    let publisher = publish_info.and_then(|info| element("publisher", info));
+    let isbn = publish_info
+        .and_then(|info| element("isbn", info))
+        .map(|isbn| normalize_isbn(&isbn))
+        .filter(|isbn| !isbn.is_empty());
+    let cover_id = title_info
+        .and_then(|info| COVERPAGE_RE.find(info))
+        .and_then(|coverpage| HREF_RE.captures(coverpage.as_bytes()))
+        .map(|c| decode(&c[1]));
+
+    let mut book = Book {
+        title,
+        author,
+        subject,
+        series,
+        series_index,
+        publisher,
+        language,
+        isbn,
+        images: Vec::new(),
+        cover: None,
+        corrupt: Vec::new(),
+    };
+    for binary in BINARY_RE.captures_iter(data) {
+        let attributes: Vec<(String, String)> = ATTR_RE
+            .captures_iter(&binary[1])
+            .map(|c| (decode(&c[1]), decode(&c[2])))
+            .collect();
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let id = attribute("id").unwrap_or_default().to_string();
+        let Some(image) = base64_decode(&binary[2]) else {
+            book.corrupt.push(id);
+            continue;
+        };
+        // Trust the content over the declared type, which is often wrong or missing
+        let Some(extension) = sniff_image_format(&image).map(str::to_string).or_else(|| {
+            let declared = mime_to_extension(attribute("content-type").unwrap_or_default(), extra)?;
+            get_supported_extensions(extra).take(&declared)
+        }) else {
+            continue;
+        };
+        if cover_id.as_deref() == Some(id.as_str()) {
+            book.cover = Some(book.images.len());
+        }
+        book.images.push(BookImage {
+            id,
+            extension,
+            data: image,
+        });
+    }
+    Ok(book)
+}
+
+/// Decodes XML character data: strips markup, resolves entities and collapses whitespace
+fn decode_text(bytes: &[u8], cp1251: bool) -> String {
+    let bytes = TAG_RE.replace_all(bytes, &b""[..]);
+    let text = match std::str::from_utf8(&bytes) {
+        Ok(text) if !cp1251 => text.to_string(),
+        _ => bytes
+            .iter()
+            .map(|&b| match b {
+                0x80..=0xBF if cp1251 => {
+                    char::from_u32(CP1251_HIGH[(b - 0x80) as usize] as u32).unwrap_or('\u{FFFD}')
+                }
+                0xC0..=0xFF if cp1251 => char::from_u32(0x0410 + (b - 0xC0) as u32).unwrap(),
+                b => b as char,
+            })
+            .collect(),
+    };
+    unescape_xml(&text)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Processes a single .fb2 file, extracting images matching the allowed extensions.
+/// Returns the number of images extracted.
+pub fn process_file(
+    input_path: &Path,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let fallback_name = input_path
+        .file_stem()
+        .context("Invalid filename")?
+        .to_string_lossy()
+        .to_string();
+
+    let file = fs::File::open(input_path)
+        .with_context(|| format!("Failed to open input file: {}", input_path.display()))?;
+
+    process_reader(file, input_path, &fallback_name, sink, options)
+}
+
+/// Processes a FictionBook from any reader.
+/// `input_path` is only used for messages; `fallback_name` is the output base name used
+/// when the book has no author or title.
+pub fn process_reader<R: Read>(
+    mut reader: R,
+    input_path: &Path,
+    fallback_name: &str,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let mut data = Vec::new();
+    reader
+        .read_to_end(&mut data)
+        .with_context(|| format!("Failed to read {}", input_path.display()))?;
+    let book = parse(&data, &options.extra_formats)
+        .with_context(|| format!("Failed to read {}", input_path.display()))?;
+
+    // Same metadata filter as EPUBs - silently skip non-matching books
+    let metadata = DocumentMetadata {
+        title: book.title.clone(),
+        author: book.author.clone(),
+        subject: book.subject.clone(),
+        series: book.series.clone(),
+        series_index: book.series_index.clone(),
+        publisher: book.publisher.clone(),
+        language: book.language.clone(),
+        isbn: book.isbn.clone(),
+    };
+    if !options.document_filter.matches(&metadata) {
+        return Ok(0);
+    }
+
+    let base_name = format_epub_base_name(
+        book.author.as_deref(),
+        book.title.as_deref(),
+        book.series.as_deref(),
+        book.series_index.as_deref(),
+        fallback_name,
+    );
+    if let Some(ref t) = book.title {
+        status!("FB2 Title: {}", t);
+        sink.record_metadata("title", t);
+    }
+    if let Some(ref a) = book.author {
+        status!("FB2 Author: {}", a);
+        sink.record_metadata("author", a);
+    }
+    if let Some(ref s) = book.series {
+        status!("FB2 Series: {}", s);
+        sink.record_metadata("series", s);
+    }
+    if let Some(ref i) = book.series_index {
+        sink.record_metadata("series_index", i);
+    }
+    for id in &book.corrupt {
+        sink.warn(&format!(
+            "Skipping binary '{}' in {}: invalid base64 data",
+            id,
+            input_path.display()
+        ));
+    }
+
+    if options.cover_only {
+        match book.cover.map(|i| &book.images[i]) {
+            Some(cover) => {
+                return write_cover(
+                    &cover.extension,
+                    &cover.data,
+                    &base_name,
+                    input_path,
+                    sink,
+                    options,
+                );
+            }
+            None if options.cover_fallback == Some(CoverFallback::All) => status!(
+                "No cover image found in {}, falling back to extracting all images.",
+                input_path.display()
+            ),
+            None if options.cover_fallback == Some(CoverFallback::Largest) => {
+                let images = book
+                    .images
+                    .iter()
+                    .map(|image| (image.extension.as_str(), image.data.as_slice()));
+                return write_largest_cover(images, &base_name, input_path, sink, options);
+            }
+            None => {
+                status!("No cover image found in {}", input_path.display());
+                return Ok(0);
+            }
+        }
+    }
+
+    let images: Vec<&BookImage> = book
+        .images
+        .iter()
+        .filter(|image| options.allowed_extensions.contains(&image.extension))
+        .collect();
+    let total_images = images.len();
+    if total_images > 0 {
+        sink.create_base_dir()?;
+
+        status!(
+            "Found {} image files in {}.",
+            total_images,
+            input_path.display()
+        );
+    }
+
+    for (seq_index, image) in images.iter().enumerate() {
+        let output_path = sink.original_output_path(
+            &base_name,
+            seq_index,
+            total_images,
+            &image.extension,
+            &image.id,
+        )?;
+
+        status!("Extracting to: {}", output_path.display());
+
+        sink.write_image(&output_path, &image.data)?;
+    }
+
+    Ok(total_images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::temp_dir;
+    use crate::output::OutputOptions;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\nfb2 png";
+
+    fn fb2(binaries: &str) -> String {
+        format!(
+            r##"<?xml version="1.0" encoding="utf-8"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0" xmlns:l="http://www.w3.org/1999/xlink">
+  <description>
+    <title-info>
+      <genre>sf_social</genre><genre>adventure</genre>
+      <author><first-name>Arkady</first-name><last-name>Strugatsky</last-name></author>
+      <book-title>Roadside &amp; Picnic</book-title>
+      <coverpage><image l:href="#cover.jpg"/></coverpage>
+      <lang>ru</lang><sequence name="Noon Universe" number="3"/>
+    </title-info>
+    <publish-info><publisher>Macmillan</publisher><isbn>0-02-615170-7</isbn></publish-info>
+  </description>
+  <body><section><p>Text</p><image l:href="#map.png"/></section></body>
+  {}
+</FictionBook>"##,
+            binaries
+        )
+    }
+
+    #[test]
+    fn test_parse_fb2() {
+        let book = parse(
+            fb2(
+                r#"<binary id="cover.jpg" content-type="image/jpeg">/9j/4AAQ</binary>
+  <binary id="map.png" content-type="image/png">iVBORw0KGgpmYjIgcG5n</binary>
+  <binary id="bad.png" content-type="image/png">!!!</binary>"#,
+            )
+            .as_bytes(),
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(book.title.as_deref(), Some("Roadside & Picnic"));
+        assert_eq!(book.author.as_deref(), Some("Arkady Strugatsky"));
+        assert_eq!(book.subject.as_deref(), Some("sf_social, adventure"));
+        assert_eq!(book.series.as_deref(), Some("Noon Universe"));
+        assert_eq!(book.series_index.as_deref(), Some("3"));
+        assert_eq!(book.publisher.as_deref(), Some("Macmillan"));
+        assert_eq!(book.language.as_deref(), Some("ru"));
+        assert_eq!(book.isbn.as_deref(), Some("0026151707"));
+        assert_eq!(book.images.len(), 2);
+        assert_eq!(book.cover, Some(0));
+        assert_eq!(book.images[0].extension, "jpg");
+        assert_eq!(book.images[1].id, "map.png");
+        assert_eq!(book.images[1].data, PNG);
+        assert_eq!(book.corrupt, vec!["bad.png".to_string()]);
+    }
+
+    #[test]
+    fn test_decode_cp1251_title() {
+        assert_eq!(decode_text(b"\xcf\xe8\xea\xed\xe8\xea", true), "Пикник");
+        assert_eq!(decode_text(b"  A <i>b</i>\n &#233;  ", false), "A b é");
+    }
+
+    #[test]
+    fn test_extract_fb2_images() {
+        let dir = temp_dir("fb2");
+
+        let data = fb2(
+            r#"<binary id="cover.jpg" content-type="image/jpeg">/9j/4AAQ</binary>
+  <binary id="map.png" content-type="image/png">iVBORw0KGgpmYjIgcG5n</binary>"#,
+        );
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count = process_reader(
+            data.as_bytes(),
+            Path::new("book.fb2"),
+            "book",
+            &mut sink,
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(
+            std::fs::read(dir.join("Noon Universe 03 - Roadside & Picnic_2.png")).unwrap(),
+            PNG
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cover_fallback_largest() {
+        let dir = temp_dir("fb2-largest");
+
+        // No cover.jpg binary, so the declared cover is missing; the 40x30 image wins
+        // over the 1x1 one despite coming second
+        let data = fb2(
+            r#"<binary id="small.png" content-type="image/png">iVBORw0KGgoAAAANSUhEUgAAAAEAAAAB</binary>
+  <binary id="map.png" content-type="image/png">iVBORw0KGgoAAAANSUhEUgAAACgAAAAe</binary>"#,
+        );
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            cover_only: true,
+            cover_fallback: Some(CoverFallback::Largest),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count = process_reader(
+            data.as_bytes(),
+            Path::new("book.fb2"),
+            "book",
+            &mut sink,
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(count, 1);
+        let cover = std::fs::read(dir.join("Noon Universe 03 - Roadside & Picnic.png")).unwrap();
+        assert_eq!(&cover[16..24], &[0, 0, 0, 40, 0, 0, 0, 30]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,343 @@
+//! Image filters applied by the output sink before anything is written
+//!
+//! Filters run on every image from every document type, so processors do not need to
+//! know about them. Rejected images are recorded in the manifest as skipped.
+
+use clap::ValueEnum;
+use std::path::Path;
+
+use crate::glob::Glob;
+use crate::raster::image_dimensions;
+
+/// Shape an image must have
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Orientation {
+    /// Wider than tall
+    Landscape,
+    /// Taller than wide
+    Portrait,
+    /// Exactly as wide as tall
+    Square,
+}
+
+impl Orientation {
+    fn of(width: u32, height: u32) -> Self {
+        match width.cmp(&height) {
+            std::cmp::Ordering::Greater => Orientation::Landscape,
+            std::cmp::Ordering::Less => Orientation::Portrait,
+            std::cmp::Ordering::Equal => Orientation::Square,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Orientation::Landscape => "landscape",
+            Orientation::Portrait => "portrait",
+            Orientation::Square => "square",
+        }
+    }
+}
+
+/// Limits an image must satisfy to be written. Unset limits always pass.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImageFilter {
+    /// Smallest file size in bytes
+    pub min_size: Option<u64>,
+    /// Largest file size in bytes
+    pub max_size: Option<u64>,
+    pub min_width: Option<u32>,
+    pub min_height: Option<u32>,
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub orientation: Option<Orientation>,
+    /// Smallest width to height ratio
+    pub min_aspect: Option<f64>,
+    /// Largest width to height ratio
+    pub max_aspect: Option<f64>,
+}
+
+impl ImageFilter {
+    /// Whether any limit is set
+    pub fn is_active(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// Whether the limits can only be checked against the image content,
+    /// rather than just its size
+    pub fn needs_content(&self) -> bool {
+        self.min_width.is_some()
+            || self.min_height.is_some()
+            || self.max_width.is_some()
+            || self.max_height.is_some()
+            || self.orientation.is_some()
+            || self.min_aspect.is_some()
+            || self.max_aspect.is_some()
+    }
+
+    /// Returns why an image of `size` bytes is rejected, or None if it passes
+    pub fn rejects_size(&self, size: u64) -> Option<String> {
+        match (self.min_size, self.max_size) {
+            (Some(min), _) if size < min => Some(format!("size {} is below {} bytes", size, min)),
+            (_, Some(max)) if size > max => Some(format!("size {} is above {} bytes", size, max)),
+            _ => None,
+        }
+    }
+
+    /// Returns why `data` is rejected, or None if it passes. Images whose dimensions
+    /// cannot be read from their header (SVG, EMF, ...) pass the dimension limits.
+    pub fn rejects(&self, data: &[u8]) -> Option<String> {
+        if let Some(reason) = self.rejects_size(data.len() as u64) {
+            return Some(reason);
+        }
+        let (width, height) = image_dimensions(data)?;
+        let checks = [
+            (self.min_width, width, "width", true),
+            (self.min_height, height, "height", true),
+            (self.max_width, width, "width", false),
+            (self.max_height, height, "height", false),
+        ];
+        for (limit, value, name, is_min) in checks {
+            match limit {
+                Some(min) if is_min && value < min => {
+                    return Some(format!("{} {} is below {}", name, value, min));
+                }
+                Some(max) if !is_min && value > max => {
+                    return Some(format!("{} {} is above {}", name, value, max));
+                }
+                _ => {}
+            }
+        }
+        if let Some(wanted) = self.orientation {
+            let actual = Orientation::of(width, height);
+            if actual != wanted {
+                return Some(format!(
+                    "{}x{} is {}, not {}",
+                    width,
+                    height,
+                    actual.name(),
+                    wanted.name()
+                ));
+            }
+        }
+        if height > 0 {
+            let aspect = f64::from(width) / f64::from(height);
+            match (self.min_aspect, self.max_aspect) {
+                (Some(min), _) if aspect < min => {
+                    return Some(format!("aspect ratio {:.2} is below {}", aspect, min));
+                }
+                (_, Some(max)) if aspect > max => {
+                    return Some(format!("aspect ratio {:.2} is above {}", aspect, max));
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// Images picked by `--only`, by their 1-based position within the document (the number in
+/// `report_3.png`) or by their name inside it. An image matching any item is selected.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImageSelection {
+    /// Inclusive ranges of positions
+    positions: Vec<(usize, usize)>,
+    /// Patterns for the name inside the document, e.g. `word/media/image1*.png`
+    names: Vec<Glob>,
+}
+
+impl ImageSelection {
+    /// Whether the image at 0-based `seq_index`, named `original` inside its document if
+    /// it has a name there, is selected
+    pub fn selects(&self, seq_index: usize, original: Option<&str>) -> bool {
+        let position = seq_index + 1;
+        self.positions
+            .iter()
+            .any(|&(first, last)| (first..=last).contains(&position))
+            || original.is_some_and(|original| {
+                let original = Path::new(original);
+                self.names.iter().any(|glob| glob.matches(original, false))
+            })
+    }
+
+    /// The position this selection picks if it picks exactly one, as a single number
+    pub fn single_position(&self) -> Option<usize> {
+        match (self.positions.as_slice(), self.names.is_empty()) {
+            ([(first, last)], true) if first == last => Some(*first),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a comma-separated list of positions (`3`), ranges (`5-8`, or `10-` for the
+/// rest) and name patterns (`word/media/image1*.png`) for `--only`
+pub fn parse_selection(value: &str) -> Result<ImageSelection, String> {
+    let mut selection = ImageSelection::default();
+    for item in value.split(',').map(str::trim) {
+        if item.is_empty() {
+            return Err(format!("empty item in image selection '{}'", value));
+        }
+        let is_range = item.bytes().all(|b| b.is_ascii_digit() || b == b'-');
+        if !is_range {
+            selection.names.push(item.parse()?);
+            continue;
+        }
+        let invalid = || format!("invalid image position or range '{}'", item);
+        let (first, last) = match item.split_once('-') {
+            Some((first, "")) => (first.parse().map_err(|_| invalid())?, usize::MAX),
+            Some((first, last)) => (
+                first.parse().map_err(|_| invalid())?,
+                last.parse().map_err(|_| invalid())?,
+            ),
+            None => {
+                let position = item.parse().map_err(|_| invalid())?;
+                (position, position)
+            }
+        };
+        if first == 0 || last < first {
+            return Err(format!(
+                "invalid image range '{}': positions start at 1, ranges go upwards",
+                item
+            ));
+        }
+        selection.positions.push((first, last));
+    }
+    Ok(selection)
+}
+
+/// Parses a width to height ratio given as a number (`1.5`) or as `W:H` (`16:9`)
+pub fn parse_aspect(value: &str) -> Result<f64, String> {
+    let invalid = || {
+        format!(
+            "invalid aspect ratio '{}', expected e.g. 1.5 or 16:9",
+            value
+        )
+    };
+    let ratio = match value.split_once(':') {
+        Some((width, height)) => {
+            let width: f64 = width.trim().parse().map_err(|_| invalid())?;
+            let height: f64 = height.trim().parse().map_err(|_| invalid())?;
+            width / height
+        }
+        None => value.trim().parse().map_err(|_| invalid())?,
+    };
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    } else {
+        Err(invalid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageFormat};
+    use std::io::Cursor;
+
+    fn gif(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(width, height)
+            .write_to(&mut data, ImageFormat::Gif)
+            .unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn test_dimension_filter() {
+        // 1x1 GIF, as used for spacers and trackers
+        let pixel = &gif(1, 1);
+        let filter = ImageFilter {
+            min_width: Some(2),
+            ..Default::default()
+        };
+        assert!(filter.is_active());
+        assert_eq!(filter.rejects(pixel).as_deref(), Some("width 1 is below 2"));
+        assert_eq!(filter.rejects(b"<svg></svg>"), None);
+
+        let filter = ImageFilter {
+            max_height: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(filter.rejects(pixel), None);
+        assert!(!ImageFilter::default().is_active());
+    }
+
+    #[test]
+    fn test_orientation_and_aspect_filter() {
+        // 4x3 and 3x4 GIFs
+        let wide = &gif(4, 3);
+        let tall = &gif(3, 4);
+        let filter = ImageFilter {
+            orientation: Some(Orientation::Portrait),
+            ..Default::default()
+        };
+        assert!(filter.needs_content());
+        assert_eq!(
+            filter.rejects(wide).as_deref(),
+            Some("4x3 is landscape, not portrait")
+        );
+        assert_eq!(filter.rejects(tall), None);
+        assert_eq!(filter.rejects(b"<svg></svg>"), None);
+
+        let filter = ImageFilter {
+            min_aspect: Some(0.5),
+            max_aspect: Some(1.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.rejects(wide).as_deref(),
+            Some("aspect ratio 1.33 is above 1")
+        );
+        assert_eq!(filter.rejects(tall), None);
+    }
+
+    #[test]
+    fn test_parse_aspect() {
+        assert_eq!(parse_aspect("1.5"), Ok(1.5));
+        assert_eq!(parse_aspect("16:9"), Ok(16.0 / 9.0));
+        assert!(parse_aspect("16:0").is_err());
+        assert!(parse_aspect("wide").is_err());
+        assert!(parse_aspect("-2").is_err());
+    }
+
+    #[test]
+    fn test_size_filter() {
+        let filter = ImageFilter {
+            min_size: Some(1024),
+            max_size: Some(2048),
+            ..Default::default()
+        };
+        assert!(filter.is_active());
+        assert!(!filter.needs_content());
+        assert_eq!(
+            filter.rejects(&[0; 10]).as_deref(),
+            Some("size 10 is below 1024 bytes")
+        );
+        assert_eq!(filter.rejects(&[0; 1500]), None);
+        assert!(filter.rejects_size(4096).is_some());
+    }
+
+    #[test]
+    fn test_image_selection() {
+        let selection = parse_selection("1, 5-8,word/media/image1*.png,12-").unwrap();
+        assert!(selection.selects(0, None));
+        assert!(!selection.selects(1, Some("word/media/image2.png")));
+        assert!(selection.selects(4, None) && selection.selects(7, None));
+        assert!(!selection.selects(8, None));
+        assert!(selection.selects(8, Some("word/media/image10.png")));
+        assert!(!selection.selects(8, Some("ppt/media/image10.png")));
+        assert!(selection.selects(500, None));
+
+        let by_name = parse_selection("cover.*").unwrap();
+        assert!(by_name.selects(3, Some("OEBPS/images/cover.jpg")));
+        assert!(!by_name.selects(0, None));
+
+        assert_eq!(parse_selection("3").unwrap().single_position(), Some(3));
+        assert_eq!(parse_selection("4-4").unwrap().single_position(), Some(4));
+        assert_eq!(selection.single_position(), None);
+        assert_eq!(by_name.single_position(), None);
+
+        for invalid in ["", "1,,2", "0", "8-5", "1-2-3"] {
+            assert!(parse_selection(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Shell-style glob patterns for `--exclude`
+//!
+//! `*` matches within a path component, `**` across components, `?` a single character
+//! and `[...]` a character class (`[!...]` negated). A pattern without a `/` matches the
+//! name of any file or directory; one with a `/` matches the whole path relative to the
+//! directory being scanned.
+
+use regex::Regex;
+use std::fmt;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+/// A compiled glob pattern
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: String,
+    regex: Regex,
+    /// Whether the pattern contains a `/` and so matches relative paths, not names
+    anchored: bool,
+}
+
+impl Glob {
+    /// Whether the entry at `relative` (a path below the scanned directory) is matched.
+    /// Directories also match patterns ending in `/` or `/**`, so they can be pruned.
+    pub fn matches(&self, relative: &Path, is_dir: bool) -> bool {
+        if !self.anchored {
+            return relative
+                .file_name()
+                .is_some_and(|name| self.regex.is_match(&name.to_string_lossy()));
+        }
+        let path = slash_path(relative);
+        self.regex.is_match(&path) || (is_dir && self.regex.is_match(&format!("{}/", path)))
+    }
+}
+
+impl PartialEq for Glob {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+impl FromStr for Glob {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Self, String> {
+        let normalized = pattern.replace('\\', "/");
+        let trimmed = normalized.trim_start_matches("./");
+        if trimmed.is_empty() {
+            return Err("empty exclude pattern".to_string());
+        }
+        // A trailing `/` only says the pattern names a directory; the contents of a
+        // matched directory are skipped either way
+        let anchored = trimmed.trim_end_matches('/').contains('/');
+        let body = if anchored {
+            trimmed.trim_start_matches('/')
+        } else {
+            trimmed.trim_end_matches('/')
+        };
+
+        let mut regex = String::from(if cfg!(windows) { "(?i)^" } else { "^" });
+        let chars: Vec<char> = body.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    if chars.get(i + 2) == Some(&'/') {
+                        regex.push_str("(?:.*/)?");
+                        i += 3;
+                    } else {
+                        regex.push_str(".*");
+                        i += 2;
+                    }
+                    continue;
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                '[' => match chars[i + 1..].iter().skip(1).position(|&c| c == ']') {
+                    Some(end) => {
+                        let class: String = chars[i + 1..i + 2 + end].iter().collect();
+                        let class = class
+                            .strip_prefix('!')
+                            .map_or(class.clone(), |rest| format!("^{}", rest));
+                        regex.push('[');
+                        regex.push_str(&class.replace('\\', "\\\\").replace('[', "\\["));
+                        regex.push(']');
+                        i += end + 3;
+                        continue;
+                    }
+                    None => regex.push_str("\\["),
+                },
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+            i += 1;
+        }
+        regex.push('$');
+
+        let regex = Regex::new(&regex)
+            .map_err(|e| format!("invalid exclude pattern '{}': {}", pattern, e))?;
+        Ok(Self {
+            pattern: pattern.to_string(),
+            regex,
+            anchored,
+        })
+    }
+}
+
+/// Joins the normal components of `path` with `/`
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str, is_dir: bool) -> bool {
+        pattern
+            .parse::<Glob>()
+            .unwrap()
+            .matches(Path::new(path), is_dir)
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(matches("*draft*", "notes/my draft.docx", false));
+        assert!(matches("*draft*", "drafts", true));
+        assert!(!matches("*draft*", "drafts/report.docx", false));
+        assert!(matches(".git", "sub/.git", true));
+        assert!(matches("*.epub", "books/a.epub", false));
+        assert!(!matches("*.epub", "books/a.epub.bak", false));
+        assert!(matches("report-?.docx", "report-1.docx", false));
+        assert!(matches("report-[0-9].docx", "report-7.docx", false));
+        assert!(!matches("report-[!0-9].docx", "report-7.docx", false));
+
+        assert!(matches("**/backup/**", "backup", true));
+        assert!(matches("**/backup/**", "a/b/backup/c.docx", false));
+        assert!(!matches("**/backup/**", "a/backups/c.docx", false));
+        assert!(matches("archive/*.pdf", "archive/x.pdf", false));
+        assert!(!matches("archive/*.pdf", "old/archive/x.pdf", false));
+        assert!(matches("old/", "old", true));
+    }
+
+    #[test]
+    fn test_invalid_glob() {
+        assert!("".parse::<Glob>().is_err());
+        assert!("a[".parse::<Glob>().is_ok());
+    }
+}
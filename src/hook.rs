@@ -0,0 +1,210 @@
+//! Commands run for each written image (`--exec`)
+//!
+//! The command line is split into words once, like a shell would but without running
+//! one: words are separated by whitespace and may be quoted with `'` or `"` to keep
+//! spaces. Placeholders are then filled in per word, so a path with spaces stays a
+//! single argument and is never interpreted by a shell.
+
+use anyhow::{Context, Result};
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::common::{Segment, parse_placeholders};
+
+/// Values a command can refer to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    /// Path of the written image
+    Path,
+    /// File name of the written image
+    Name,
+    /// Path of the document the image came from
+    Doc,
+    /// Format of the written image, as its canonical extension
+    Format,
+}
+
+type Part = Segment<Field>;
+
+/// A parsed `--exec` command
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecCommand {
+    /// The words of the command line, the program first
+    words: Vec<Vec<Part>>,
+}
+
+/// Placeholder values for one written image
+#[derive(Debug, Clone, Copy)]
+pub struct ExecFields<'a> {
+    pub path: &'a Path,
+    pub doc: &'a Path,
+    pub format: &'a str,
+}
+
+/// Parses a command line. `{{` and `}}` stand for literal braces.
+/// Used as a clap value parser.
+pub fn parse_exec_command(command: &str) -> Result<ExecCommand, String> {
+    let words = split_words(command)?
+        .iter()
+        .map(|word| parse_word(word))
+        .collect::<Result<Vec<_>, _>>()?;
+    if words.is_empty() {
+        return Err("the command is empty".to_string());
+    }
+    Ok(ExecCommand { words })
+}
+
+/// Splits a command line into words at unquoted whitespace, removing the quotes
+fn split_words(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = None::<String>;
+    let mut quote = None;
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_default().push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_default().push(c),
+        }
+    }
+    if let Some(q) = quote {
+        return Err(format!("unterminated {} quote", q));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Parses the placeholders of one word
+fn parse_word(word: &str) -> Result<Vec<Part>, String> {
+    let mut parts = parse_placeholders(word, |name| match name {
+        "path" => Some(Field::Path),
+        "name" => Some(Field::Name),
+        "doc" => Some(Field::Doc),
+        "format" => Some(Field::Format),
+        _ => None,
+    })?;
+    // A quoted empty word is still an argument
+    if parts.is_empty() {
+        parts.push(Part::Literal(String::new()));
+    }
+    Ok(parts)
+}
+
+impl ExecCommand {
+    /// The command's words with the placeholders filled in for one image
+    fn render(&self, fields: &ExecFields) -> Vec<String> {
+        self.words
+            .iter()
+            .map(|word| {
+                let mut out = String::new();
+                for part in word {
+                    match part {
+                        Part::Literal(text) => out.push_str(text),
+                        Part::Placeholder(Field::Path) => {
+                            out.push_str(&fields.path.to_string_lossy())
+                        }
+                        Part::Placeholder(Field::Name) => out.push_str(
+                            &fields
+                                .path
+                                .file_name()
+                                .unwrap_or_default()
+                                .to_string_lossy(),
+                        ),
+                        Part::Placeholder(Field::Doc) => {
+                            out.push_str(&fields.doc.to_string_lossy())
+                        }
+                        Part::Placeholder(Field::Format) => out.push_str(fields.format),
+                    }
+                }
+                out
+            })
+            .collect()
+    }
+
+    /// Runs the command for one image and waits for it. Its output goes to stderr, so
+    /// it never mixes with a JSON report on stdout.
+    pub fn run(&self, fields: &ExecFields) -> Result<()> {
+        let words = self.render(fields);
+        let status = Command::new(&words[0])
+            .args(&words[1..])
+            .stdin(Stdio::null())
+            .stdout(io::stderr())
+            .status()
+            .with_context(|| format!("Failed to run {}", words[0]))?;
+        if !status.success() {
+            anyhow::bail!("{} exited with {}", words[0], status);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(command: &str) -> Vec<String> {
+        parse_exec_command(command).unwrap().render(&ExecFields {
+            path: Path::new("out/My Report_1.png"),
+            doc: Path::new("docs/My Report.docx"),
+            format: "png",
+        })
+    }
+
+    #[test]
+    fn test_placeholders_fill_whole_words() {
+        assert_eq!(
+            render("upscale --in {path} --out 'big/{name}' --tag={format}"),
+            [
+                "upscale",
+                "--in",
+                "out/My Report_1.png",
+                "--out",
+                "big/My Report_1.png",
+                "--tag=png"
+            ]
+        );
+        assert_eq!(
+            render("echo \"from {doc}\""),
+            ["echo", "from docs/My Report.docx"]
+        );
+        assert_eq!(render("echo {{}} ''"), ["echo", "{}", ""]);
+    }
+
+    #[test]
+    fn test_invalid_commands() {
+        assert!(parse_exec_command("").is_err());
+        assert!(parse_exec_command("   ").is_err());
+        assert!(parse_exec_command("echo 'open").is_err());
+        assert!(parse_exec_command("echo {page}").is_err());
+        assert!(parse_exec_command("echo {path").is_err());
+        assert!(parse_exec_command("echo path}").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_reports_failure() {
+        let fields = ExecFields {
+            path: Path::new("image.png"),
+            doc: Path::new("doc.docx"),
+            format: "png",
+        };
+        assert!(
+            parse_exec_command("true {path}")
+                .unwrap()
+                .run(&fields)
+                .is_ok()
+        );
+        assert!(parse_exec_command("false").unwrap().run(&fields).is_err());
+        assert!(
+            parse_exec_command("no-such-program-for-exec")
+                .unwrap()
+                .run(&fields)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! HTML and MHTML (saved web page) file processing module
+//!
+//! Plain HTML pages can only carry images as base64 `data:` URIs. MHTML pages are MIME
+//! `multipart/related` messages with one part per resource, so images are decoded from
+//! their parts, and HTML parts are scanned for data URIs too.
+
+use anyhow::{Context, Result};
+use regex::bytes::Regex;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use crate::common::{
+    DocumentMetadata, ExtraFormat, base64_decode, extra_format_for_mime, unescape_xml,
+};
+use crate::epub::mime_to_extension;
+use crate::extract::ExtractOptions;
+use crate::output::OutputSink;
+use crate::sniff::sniff_image_format;
+
+static DATA_URI_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)data:(image/[\w.+-]+)(?:;[\w=.-]+)*;base64,([A-Za-z0-9+/=\r\n]+)").unwrap()
+});
+static BOUNDARY_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\bboundary\s*=\s*"?([^";\r\n]+)"?"#).unwrap());
+
+static TITLE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<title\b[^>]*>(.*?)</title>").unwrap());
+static META_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)<meta\b[^>]*>").unwrap());
+static META_ATTR_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\b(name|content)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+
+/// Nesting limit for multipart entities inside multipart entities
+const MAX_MIME_DEPTH: usize = 8;
+
+/// A decoded picture: `(extension, data)`
+type Picture = (String, Vec<u8>);
+
+/// Processes a single HTML or MHTML file, extracting images matching the allowed extensions.
+/// Returns the number of images extracted.
+pub fn process_file(
+    input_path: &Path,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let doc_name = input_path
+        .file_stem()
+        .context("Invalid filename")?
+        .to_string_lossy()
+        .to_string();
+
+    let file = fs::File::open(input_path)
+        .with_context(|| format!("Failed to open input file: {}", input_path.display()))?;
+
+    process_reader(file, input_path, &doc_name, sink, options)
+}
+
+/// Processes an HTML or MHTML page from any reader.
+/// `input_path` is only used for messages; `doc_name` is the output base name.
+pub fn process_reader<R: Read>(
+    mut reader: R,
+    input_path: &Path,
+    doc_name: &str,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let mut data = Vec::new();
+    reader
+        .read_to_end(&mut data)
+        .with_context(|| format!("Failed to read {}", input_path.display()))?;
+
+    // Silently skip pages that don't match the metadata filter
+    if !options.document_filter.is_empty()
+        && !options.document_filter.matches(&page_metadata(&data))
+    {
+        return Ok(0);
+    }
+
+    let images: Vec<Picture> = collect_images(&data, &options.extra_formats)
+        .into_iter()
+        .filter(|(extension, _)| options.allowed_extensions.contains(extension))
+        .collect();
+
+    let total_images = images.len();
+    if total_images > 0 {
+        sink.create_base_dir()?;
+
+        status!(
+            "Found {} image files in {}.",
+            total_images,
+            input_path.display()
+        );
+    }
+
+    for (seq_index, (extension, data)) in images.iter().enumerate() {
+        let output_path = sink.output_path(doc_name, seq_index, total_images, extension)?;
+
+        status!("Extracting to: {}", output_path.display());
+
+        sink.write_image(&output_path, data)?;
+    }
+
+    Ok(total_images)
+}
+
+/// Reads every image in an HTML or MHTML page as `(extension, data)`, in document order.
+/// Images of the `extra` formats are recognised by their MIME type.
+pub fn collect_images(data: &[u8], extra: &[ExtraFormat]) -> Vec<Picture> {
+    let mut images = Vec::new();
+    let (headers, _) = split_entity(data);
+    if header(&headers, "content-type").is_some_and(|t| t.to_lowercase().starts_with("multipart/"))
+    {
+        mime_images(data, 0, extra, &mut images);
+    } else {
+        images.extend(data_uri_images(data, extra));
+    }
+    images
+}
+
+/// Decodes the base64 `data:image/...` URIs in a text resource
+pub fn data_uri_images(text: &[u8], extra: &[ExtraFormat]) -> Vec<Picture> {
+    DATA_URI_RE
+        .captures_iter(text)
+        .filter_map(|uri| {
+            let data = base64_decode(&uri[2])?;
+            let mime = String::from_utf8_lossy(&uri[1]).to_lowercase();
+            Some((image_extension(&mime, &data, extra)?, data))
+        })
+        .collect()
+}
+
+/// Determines an image's extension from its content. SVG is also accepted on its MIME
+/// type alone, since an SVG without an XML prolog or leading `<svg` cannot be sniffed,
+/// and so are the `extra` formats.
+fn image_extension(mime: &str, data: &[u8], extra: &[ExtraFormat]) -> Option<String> {
+    sniff_image_format(data)
+        .or_else(|| (mime_to_extension(mime, extra)? == "svg").then_some("svg"))
+        .or_else(|| extra_format_for_mime(extra, mime))
+        .map(str::to_string)
+}
+
+/// Collects the images of a MIME entity: image parts are decoded, HTML and CSS parts are
+/// scanned for data URIs, and multipart entities are split into their parts
+fn mime_images(entity: &[u8], depth: usize, extra: &[ExtraFormat], images: &mut Vec<Picture>) {
+    let (headers, body) = split_entity(entity);
+    let content_type = header(&headers, "content-type").unwrap_or("text/plain");
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+
+    if mime.starts_with("multipart/") {
+        if depth >= MAX_MIME_DEPTH {
+            return;
+        }
+        let Some(boundary) = BOUNDARY_RE.captures(content_type.as_bytes()) else {
+            return;
+        };
+        for part in split_multipart(body, &boundary[1]) {
+            mime_images(part, depth + 1, extra, images);
+        }
+        return;
+    }
+
+    let encoding = header(&headers, "content-transfer-encoding").map(str::to_lowercase);
+    let body = match encoding.as_deref() {
+        Some("base64") => match base64_decode(body) {
+            Some(decoded) => decoded,
+            None => return,
+        },
+        Some("quoted-printable") => quoted_printable_decode(body),
+        _ => body.to_vec(),
+    };
+    if mime.starts_with("image/") {
+        if let Some(extension) = image_extension(&mime, &body, extra) {
+            images.push((extension, body));
+        }
+    } else if mime.starts_with("text/") {
+        images.extend(data_uri_images(&body, extra));
+    }
+}
+
+/// Splits a MIME entity into its unfolded headers (lowercase names) and its body
+fn split_entity(entity: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    let crlf = entity.windows(4).position(|w| w == b"\r\n\r\n");
+    let lf = entity.windows(2).position(|w| w == b"\n\n");
+    let (header_end, body_start) = match (crlf, lf) {
+        (Some(c), Some(l)) if l < c => (l, l + 2),
+        (Some(c), _) => (c, c + 4),
+        (None, Some(l)) => (l, l + 2),
+        (None, None) => return (Vec::new(), entity),
+    };
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in String::from_utf8_lossy(&entity[..header_end]).lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        } else {
+            // Not a header block: the entity is all body
+            return (Vec::new(), entity);
+        }
+    }
+    (headers, &entity[body_start..])
+}
+
+/// Looks up a header value by its lowercase name
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Splits a multipart body on its boundary delimiter lines
+fn split_multipart<'a>(body: &'a [u8], boundary: &[u8]) -> Vec<&'a [u8]> {
+    let delimiter = [b"--", boundary].concat();
+    let mut parts = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut pos = 0;
+    while pos < body.len() {
+        let line_end = body[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(body.len(), |i| pos + i + 1);
+        let line = body[pos..line_end].trim_ascii_end();
+        if line.starts_with(&delimiter) {
+            if let Some(part_start) = start {
+                // The line break before the delimiter belongs to the delimiter
+                let part = &body[part_start..pos];
+                let part = part
+                    .strip_suffix(b"\r\n")
+                    .or_else(|| part.strip_suffix(b"\n"))
+                    .unwrap_or(part);
+                parts.push(part);
+            }
+            if line[delimiter.len()..].starts_with(b"--") {
+                break;
+            }
+            start = Some(line_end);
+        }
+        pos = line_end;
+    }
+    parts
+}
+
+/// Decodes a quoted-printable body, including `=` soft line breaks
+fn quoted_printable_decode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] != b'=' {
+            out.push(data[i]);
+            i += 1;
+            continue;
+        }
+        let rest = &data[i + 1..];
+        if rest.starts_with(b"\r\n") {
+            i += 3;
+        } else if rest.starts_with(b"\n") {
+            i += 2;
+        } else if let Some(value) = rest
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(value);
+            i += 3;
+        } else {
+            out.push(b'=');
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Reads the page `<title>` and the `author` and `subject` (or else `keywords`) meta tags
+fn page_metadata(data: &[u8]) -> DocumentMetadata {
+    let text = |bytes: &[u8]| {
+        let text = unescape_xml(String::from_utf8_lossy(bytes).trim());
+        Some(text.split_whitespace().collect::<Vec<_>>().join(" ")).filter(|t| !t.is_empty())
+    };
+    let meta = |wanted: &str| {
+        META_RE.find_iter(data).find_map(|tag| {
+            let mut name = None;
+            let mut content = None;
+            for c in META_ATTR_RE.captures_iter(tag.as_bytes()) {
+                let value = c.get(2).or(c.get(3)).map_or(&[][..], |m| m.as_bytes());
+                if c[1].eq_ignore_ascii_case(b"name") {
+                    name = Some(value);
+                } else {
+                    content = Some(value);
+                }
+            }
+            name.filter(|n| n.eq_ignore_ascii_case(wanted.as_bytes()))
+                .and(content)
+                .and_then(text)
+        })
+    };
+    DocumentMetadata {
+        title: TITLE_RE.captures(data).and_then(|c| text(&c[1])),
+        author: meta("author"),
+        subject: meta("subject").or_else(|| meta("keywords")),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::temp_dir;
+    use crate::output::OutputOptions;
+
+    const PNG_BASE64: &str = "iVBORw0KGgpodG1sIHBuZw==";
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\nhtml png";
+
+    #[test]
+    fn test_html_data_uris() {
+        let html = format!(
+            r#"<html><body><img src="data:image/png;base64,{}" alt="x">
+<div style="background: url(data:image/gif;charset=binary;base64,R0lGODlhAQABAA==)"></div>
+<img src="photo.jpg"><img src="data:image/png;base64,!!!"></body></html>"#,
+            PNG_BASE64
+        );
+        let images = collect_images(html.as_bytes(), &[]);
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0], ("png".to_string(), PNG.to_vec()));
+        assert_eq!(images[1].0, "gif");
+
+        // Formats the content cannot tell apart are taken from the extra formats of the run
+        let jxl = br#"<img src="data:image/jxl;base64,/wo=">"#;
+        let extra = [crate::common::parse_extra_format("jxl=image/jxl").unwrap()];
+        assert_eq!(collect_images(jxl, &extra)[0].0, "jxl");
+        assert!(collect_images(jxl, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_quoted_printable_decode() {
+        assert_eq!(
+            quoted_printable_decode(b"a=3Db=\r\nc =E2=9C=93"),
+            "a=bc \u{2713}".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_extract_mhtml_parts() {
+        let dir = temp_dir("html");
+
+        let mhtml = format!(
+            "From: <Saved by Blink>\r\n\
+             MIME-Version: 1.0\r\n\
+             Content-Type: multipart/related;\r\n\
+             \ttype=\"text/html\";\r\n\
+             \tboundary=\"----MultipartBoundary--abc\"\r\n\
+             \r\n\
+             ------MultipartBoundary--abc\r\n\
+             Content-Type: text/html\r\n\
+             Content-Transfer-Encoding: quoted-printable\r\n\
+             \r\n\
+             <img src=3D\"data:image/png;base64,{}\"><img src=3D\"cid:photo\">\r\n\
+             ------MultipartBoundary--abc\r\n\
+             Content-Type: image/jpeg\r\n\
+             Content-Transfer-Encoding: base64\r\n\
+             Content-Location: https://example.com/photo.jpg\r\n\
+             \r\n\
+             /9j/4A==\r\n\
+             ------MultipartBoundary--abc--\r\n",
+            PNG_BASE64.replace('=', "=3D")
+        );
+
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count = process_reader(
+            mhtml.as_bytes(),
+            Path::new("page.mhtml"),
+            "page",
+            &mut sink,
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(std::fs::read(dir.join("page_1.png")).unwrap(), PNG);
+        assert_eq!(
+            std::fs::read(dir.join("page_2.jpg")).unwrap(),
+            b"\xFF\xD8\xFF\xE0"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_page_metadata() {
+        let html = br#"<html><head><title>
+  Holiday &amp; Travel</title><meta name="Author" content="Sam">
+<meta content='beaches, islands' name="keywords"></head></html>"#;
+        let metadata = page_metadata(html);
+        assert_eq!(metadata.title.as_deref(), Some("Holiday & Travel"));
+        assert_eq!(metadata.author.as_deref(), Some("Sam"));
+        assert_eq!(metadata.subject.as_deref(), Some("beaches, islands"));
+    }
+}
@@ -0,0 +1,145 @@
+//! Record of documents processed by earlier runs (`--incremental`)
+//!
+//! A plain text file with one `size<TAB>modified<TAB>path` line per document, the
+//! modification time in nanoseconds since the Unix epoch. Documents whose size and
+//! modification time still match are skipped, so nightly re-scans of a large library only
+//! open what changed.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Size and modification time of a document when it was last processed
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Stamp {
+    size: u64,
+    modified: u128,
+}
+
+impl Stamp {
+    /// Reads the stamp of the file at `path`, if its metadata is available
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            size: metadata.len(),
+            modified: modified.as_nanos(),
+        })
+    }
+}
+
+/// Documents processed by earlier runs, keyed by their canonical path
+#[derive(Debug, Default)]
+pub struct State {
+    documents: HashMap<PathBuf, Stamp>,
+}
+
+impl State {
+    /// Reads the state file at `path`. A missing file is an empty state.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to read incremental state: {}", path.display())
+                });
+            }
+        };
+
+        let mut documents = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(3, '\t');
+            let stamp = match (fields.next(), fields.next(), fields.next()) {
+                (Some(size), Some(modified), Some(document)) => size
+                    .parse()
+                    .ok()
+                    .zip(modified.parse().ok())
+                    .map(|(size, modified)| (Stamp { size, modified }, document)),
+                _ => None,
+            };
+            let (stamp, document) = stamp.with_context(|| {
+                format!(
+                    "Invalid entry on line {} of incremental state {}",
+                    number + 1,
+                    path.display()
+                )
+            })?;
+            documents.insert(PathBuf::from(document), stamp);
+        }
+        Ok(Self { documents })
+    }
+
+    /// Whether `document` was processed before and has not changed since
+    pub fn is_unchanged(&self, document: &Path) -> bool {
+        self.documents
+            .get(&canonical(document))
+            .is_some_and(|stamp| Stamp::of(document).as_ref() == Some(stamp))
+    }
+
+    /// Remembers `document` as processed in its current state
+    pub fn record(&mut self, document: &Path) {
+        if let Some(stamp) = Stamp::of(document) {
+            self.documents.insert(canonical(document), stamp);
+        }
+    }
+
+    /// Writes the state to `path`, replacing the file only once it is complete
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut entries: Vec<_> = self.documents.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        let mut text = String::new();
+        for (document, stamp) in entries {
+            text.push_str(&format!(
+                "{}\t{}\t{}\n",
+                stamp.size,
+                stamp.modified,
+                document.display()
+            ));
+        }
+
+        let mut temp_name = path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+        let temp_path = PathBuf::from(temp_name);
+        fs::write(&temp_path, text)
+            .and_then(|()| fs::rename(&temp_path, path))
+            .with_context(|| format!("Failed to update incremental state: {}", path.display()))
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::temp_dir;
+
+    #[test]
+    fn test_incremental_state_round_trip() {
+        let dir = temp_dir("incremental");
+        let state_path = dir.join("state.txt");
+        let document = dir.join("a b.docx");
+        fs::write(&document, b"first").unwrap();
+
+        let mut state = State::load(&state_path).unwrap();
+        assert!(!state.is_unchanged(&document));
+        state.record(&document);
+        state.save(&state_path).unwrap();
+
+        let state = State::load(&state_path).unwrap();
+        assert!(state.is_unchanged(&document));
+        fs::write(&document, b"second version").unwrap();
+        assert!(!state.is_unchanged(&document));
+
+        fs::write(&state_path, "12\tyesterday\tx.docx\n").unwrap();
+        assert!(State::load(&state_path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
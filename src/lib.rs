@@ -0,0 +1,1310 @@
+//! Word Image Extractor - extracts images from Office, OpenDocument, ebook, RTF, HTML and
+//! PDF documents.
+//!
+//! The [`Extractor`] runs the same pipeline as the `word-image-extractor` command line tool:
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use word_image_extractor::{ExtractOptions, Extractor, OutputTarget};
+//!
+//! let extractor = Extractor::new(OutputTarget::Directory("images".into()), ExtractOptions::default())
+//!     .recursive(true);
+//! let report = extractor.extract_from_path(Path::new("documents"))?;
+//! println!("{} images from {} documents", report.images(), report.documents.len());
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Progress is printed to stdout and warnings to stderr, as in the CLI.
+//! [`common::set_quiet`] turns the progress messages off, and
+//! [`Extractor::observer`] receives the same events as calls. Documents that fail or are
+//! skipped are not printed; they are in the [`ExtractReport`] and are passed to
+//! [`ProgressObserver::document_finished`].
+
+/// Prints a progress message to stdout unless [`common::set_quiet`] is in effect
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::common::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+mod bundle;
+mod cfb;
+pub mod common;
+pub mod compare;
+pub mod config;
+pub mod convert;
+mod dedupe_db;
+mod doc;
+mod docx;
+mod docx_layout;
+pub mod epub;
+pub mod extract;
+mod fb2;
+pub mod filter;
+pub mod glob;
+pub mod hook;
+mod html;
+mod incremental;
+pub mod json;
+pub mod limits;
+pub mod manifest;
+pub mod messages;
+mod metafile;
+mod mobi;
+pub mod naming;
+mod obfuscation;
+mod odf;
+mod office_crypto;
+pub mod output;
+mod pdf;
+mod phash;
+pub mod progress;
+mod raster;
+pub mod registry;
+pub mod report;
+mod resume;
+mod rtf;
+mod sniff;
+mod strip;
+pub mod timings;
+pub mod update;
+pub mod validate;
+
+use anyhow::{Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::HashMap;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub use common::{DocumentFilter, ExtraFormat};
+pub use extract::{DocumentType, ExtractOptions};
+pub use manifest::Manifest;
+pub use output::{ExtractedImage, OutputOptions, OutputSink};
+pub use progress::ProgressObserver;
+pub use registry::{DocumentExtractor, Registry};
+pub use report::DocumentResult;
+
+/// Where an [`Extractor`] writes images
+#[derive(Debug, Clone)]
+pub enum OutputTarget {
+    /// Image files in a directory
+    Directory(PathBuf),
+    /// A zip archive (appended to if it exists)
+    Zip(PathBuf),
+    /// One zip archive per document, named after it, in a directory
+    ZipPerDocument(PathBuf),
+    /// A new tar archive, gzip-compressed if the name ends in `.gz` or `.tgz`
+    Tar(PathBuf),
+    /// A content-addressable store with a per-document tree of links
+    Cas(PathBuf),
+    /// The bytes of a single image on standard output. A run that would write a second
+    /// image fails on it.
+    Stdout,
+    /// Nothing at all: every image is processed and recorded in the manifest as if it
+    /// were written into this directory, to estimate a run. The dedupe database and the
+    /// incremental state are read but not updated.
+    DryRun(PathBuf),
+}
+
+/// Outcome of an extraction run
+#[derive(Debug)]
+pub struct ExtractReport {
+    /// One result per processed document, in processing order
+    pub documents: Vec<DocumentResult>,
+    /// Manifest of written and skipped images, if `OutputOptions::record_manifest` was set
+    pub manifest: Option<Manifest>,
+    /// Images not written because of `OutputOptions::dedupe`
+    pub duplicates: usize,
+    /// Images not written because their output already exists, identical or kept by
+    /// `OutputOptions::on_conflict`
+    pub existing: usize,
+    /// Documents not processed because they are unchanged since an earlier
+    /// [`Extractor::incremental`] run
+    pub unchanged: usize,
+    /// Documents not processed because the interrupted run being continued with
+    /// [`Extractor::resume`] finished them
+    pub resumed: usize,
+    /// Documents left for a later run by [`Extractor::limit`]
+    pub limited: usize,
+}
+
+impl ExtractReport {
+    /// Total number of images extracted
+    pub fn images(&self) -> usize {
+        self.documents.iter().map(|d| d.images).sum()
+    }
+
+    /// Number of documents at least one image was extracted from
+    pub fn documents_with_images(&self) -> usize {
+        self.documents.iter().filter(|d| d.images > 0).count()
+    }
+
+    /// Documents that failed to process
+    pub fn failures(&self) -> impl Iterator<Item = &DocumentResult> {
+        self.documents.iter().filter(|d| d.error.is_some())
+    }
+
+    /// Documents in which damaged entries were skipped, with `ExtractOptions::salvage` or
+    /// because an image could not be converted
+    pub fn damaged(&self) -> impl Iterator<Item = &DocumentResult> {
+        self.documents.iter().filter(|d| !d.damaged.is_empty())
+    }
+
+    /// Documents skipped because they are DRM-protected
+    pub fn drm_protected(&self) -> impl Iterator<Item = &DocumentResult> {
+        self.documents.iter().filter(|d| d.skipped.is_some())
+    }
+}
+
+/// Extracts images from documents with a fixed set of options
+#[derive(Debug)]
+pub struct Extractor {
+    target: OutputTarget,
+    options: ExtractOptions,
+    output_options: OutputOptions,
+    scan: extract::ScanOptions,
+    pause: Option<Duration>,
+    jobs: usize,
+    progress: bool,
+    dedupe_db: Option<PathBuf>,
+    incremental: Option<PathBuf>,
+    resume: Option<PathBuf>,
+    fail_fast: bool,
+    limit: Option<usize>,
+    mirror: Vec<PathBuf>,
+    observer: Option<progress::SharedObserver>,
+}
+
+impl Extractor {
+    /// Creates an extractor writing to `target`.
+    /// Empty `allowed_extensions` means every supported image format.
+    pub fn new(target: OutputTarget, mut options: ExtractOptions) -> Self {
+        if options.allowed_extensions.is_empty() {
+            options.allowed_extensions = common::get_supported_extensions(&options.extra_formats);
+        }
+        Self {
+            target,
+            options,
+            output_options: OutputOptions::default(),
+            scan: extract::ScanOptions::default(),
+            pause: None,
+            jobs: 1,
+            progress: false,
+            dedupe_db: None,
+            incremental: None,
+            resume: None,
+            fail_fast: false,
+            limit: None,
+            mirror: Vec::new(),
+            observer: None,
+        }
+    }
+
+    /// Registers an extractor for another document type, or one that replaces a built-in
+    /// extractor. It is asked before the extractors registered so far.
+    pub fn extractor(mut self, extractor: impl registry::DocumentExtractor + 'static) -> Self {
+        self.options.extractors.register(extractor);
+        self
+    }
+
+    /// Sets how images are written (permissions, conflicts, manifest, ...)
+    pub fn output_options(mut self, output_options: OutputOptions) -> Self {
+        self.output_options = output_options;
+        self
+    }
+
+    /// Descends into subdirectories when given a directory
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.scan.recursive = recursive;
+        self
+    }
+
+    /// Leaves out files and directories matching any of `patterns` when given a directory
+    pub fn exclude(mut self, patterns: Vec<glob::Glob>) -> Self {
+        self.scan.exclude = patterns;
+        self
+    }
+
+    /// Sets how directories are searched, including depth and symlink handling.
+    /// `treat_as_zip` and the extractors are taken from the extract options instead.
+    pub fn scan_options(mut self, scan: extract::ScanOptions) -> Self {
+        self.scan = scan;
+        self
+    }
+
+    /// Sleeps between documents to leave resources for other work.
+    /// With several jobs, each worker pauses between its own documents.
+    pub fn pause_between_documents(mut self, pause: Duration) -> Self {
+        self.pause = Some(pause);
+        self
+    }
+
+    /// Processes up to `jobs` documents at once (0 = one per CPU).
+    /// Zip output is always written by a single worker.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = if jobs == 0 {
+            thread::available_parallelism().map_or(1, |n| n.get())
+        } else {
+            jobs
+        };
+        self
+    }
+
+    /// Tells `observer` about each document, image and warning as the run goes on.
+    /// With several jobs, it is called from the worker threads.
+    pub fn observer(mut self, observer: Arc<dyn ProgressObserver>) -> Self {
+        self.observer = Some(progress::SharedObserver(observer));
+        self
+    }
+
+    /// Shows a progress bar on stderr (documents done, current file, images extracted).
+    /// It is only drawn when stdout is a terminal.
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Skips images already extracted by earlier runs, as recorded in the database file
+    /// at `path`, and adds this run's new images to it. Implies `OutputOptions::dedupe`.
+    pub fn dedupe_db(mut self, path: PathBuf) -> Self {
+        self.dedupe_db = Some(path);
+        self
+    }
+
+    /// Skips documents whose size and modification time match those recorded in the
+    /// state file at `path` by an earlier run, and records the documents this run
+    /// processes without errors
+    pub fn incremental(mut self, path: PathBuf) -> Self {
+        self.incremental = Some(path);
+        self
+    }
+
+    /// Processes at most `limit` documents of those given. Documents skipped as unchanged
+    /// by [`Extractor::incremental`] do not count, so a limited incremental run picks up
+    /// where the last one stopped.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Keeps a journal at `path` of the files written for each document and of the
+    /// documents finished, so a run that is interrupted can be started again with the
+    /// same journal: documents it finished are skipped and files written for the others
+    /// are deleted before they are processed again. A run that finishes every document
+    /// without failures deletes the journal.
+    pub fn resume(mut self, path: PathBuf) -> Self {
+        self.resume = Some(path);
+        self
+    }
+
+    /// Stops at the first document that fails instead of processing the rest.
+    /// The report then only lists the documents processed so far.
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Recreates the directory structure below `roots` in the output: the images of
+    /// `roots[i]/a/b/doc.docx` go into `a/b/` of the output (or the per-document archive
+    /// goes there). Documents below no root, and those directly in one, are not moved.
+    pub fn mirror(mut self, roots: Vec<PathBuf>) -> Self {
+        self.mirror = roots;
+        self
+    }
+
+    /// Directory of `document` relative to the deepest mirrored root containing it
+    fn mirror_dir<'a>(&self, document: &'a Path) -> Option<&'a Path> {
+        let parent = document.parent()?;
+        self.mirror
+            .iter()
+            .filter_map(|root| parent.strip_prefix(root).ok())
+            .min_by_key(|relative| relative.components().count())
+            .filter(|relative| !relative.as_os_str().is_empty())
+    }
+
+    /// Extracts images from a document, or from every supported document in a directory.
+    /// Per-document failures are recorded in the report rather than returned.
+    pub fn extract_from_path(&self, path: &Path) -> Result<ExtractReport> {
+        if !path.exists() {
+            anyhow::bail!("Input path does not exist: {}", path.display());
+        }
+        let mut documents = Vec::new();
+        if path.is_dir() {
+            let scan = extract::ScanOptions {
+                treat_as_zip: self.options.treat_as_zip,
+                extractors: self.options.extractors.clone(),
+                ..self.scan.clone()
+            };
+            extract::collect_documents(path, &scan, &mut documents);
+        } else {
+            documents.push(path.to_path_buf());
+        }
+        self.extract_documents(&documents)
+    }
+
+    /// Extracts images from each document, all into the same output. Archives of
+    /// documents (`.zip`, `.tar`, `.tar.gz`) stand for the documents inside them.
+    pub fn extract_documents(&self, documents: &[PathBuf]) -> Result<ExtractReport> {
+        let mut state = match &self.incremental {
+            Some(state_path) => Some(incremental::State::load(state_path)?),
+            None => None,
+        };
+        let mut pending: Vec<&PathBuf> = documents
+            .iter()
+            .filter(|path| !state.as_ref().is_some_and(|s| s.is_unchanged(path)))
+            .collect();
+        let unchanged = documents.len() - pending.len();
+        let mut limited = 0;
+        if let Some(limit) = self.limit
+            && pending.len() > limit
+        {
+            status!(
+                "{}",
+                messages::text(
+                    messages::Message::LimitedRun,
+                    &[("limit", &limit), ("total", &pending.len())]
+                )
+            );
+            limited = pending.len() - limit;
+            pending.truncate(limit);
+        }
+
+        let journal = match &self.resume {
+            Some(path) if !matches!(self.target, OutputTarget::DryRun(_)) => {
+                let journal = resume::Journal::open(path)?;
+                if journal.removed() > 0 {
+                    status!(
+                        "{}",
+                        messages::text(
+                            messages::Message::UnfinishedFilesRemoved,
+                            &[("count", &journal.removed())]
+                        )
+                    );
+                }
+                Some(Arc::new(journal))
+            }
+            _ => None,
+        };
+        let is_done = |path: &Path| journal.as_ref().is_some_and(|j| j.is_done(path));
+
+        // Only the names of the members are read here; each archive's documents are read
+        // when its turn comes
+        let bundles: Vec<(&Path, Result<bundle::Archive, String>)> = pending
+            .iter()
+            .filter(|path| bundle::is_document_archive(path, &self.options))
+            .map(|path| {
+                let archive = bundle::Archive::open(path, &self.options, |m| !is_done(m));
+                (path.as_path(), archive.map_err(|e| format!("{:#}", e)))
+            })
+            .collect();
+        let mut inputs = Vec::new();
+        let mut resumed = 0;
+        let mut next_bundle = bundles.iter();
+        for path in &pending {
+            if !bundle::is_document_archive(path, &self.options) {
+                if is_done(path) {
+                    resumed += 1;
+                } else {
+                    inputs.push(Input::File(path));
+                }
+                continue;
+            }
+            match next_bundle.next() {
+                Some((_, Ok(archive))) => {
+                    let members = archive.members().enumerate();
+                    inputs.extend(members.map(|(index, name)| Input::Member {
+                        archive,
+                        index,
+                        name,
+                    }));
+                    resumed += archive.len() - archive.members().count();
+                }
+                Some((_, Err(error))) => inputs.push(Input::Unreadable { path, error }),
+                None => unreachable!("every archive was listed"),
+            }
+        }
+
+        let mut report = self.extract_inputs(&inputs, journal.clone())?;
+        report.resumed = resumed;
+        report.limited = limited;
+        if let Some(journal) = &journal
+            && report.failures().next().is_none()
+        {
+            journal.finish()?;
+        }
+        let (Some(state), Some(state_path)) = (state.as_mut(), &self.incremental) else {
+            return Ok(report);
+        };
+        report.unchanged = unchanged;
+        if matches!(self.target, OutputTarget::DryRun(_)) {
+            return Ok(report);
+        }
+        // Failed documents are left out so the next run tries them again
+        for result in report.documents.iter().filter(|d| d.error.is_none()) {
+            state.record(&result.path);
+        }
+        // An archive counts as done once every document inside it was
+        for (path, archive) in &bundles {
+            let Ok(archive) = archive else { continue };
+            let done = report
+                .documents
+                .iter()
+                .filter(|d| d.path.starts_with(path) && d.error.is_none())
+                .count();
+            if done == archive.len() {
+                state.record(path);
+            }
+        }
+        state.save(state_path)?;
+        Ok(report)
+    }
+
+    /// Extracts images from a document held in memory, such as one read from stdin.
+    /// `name` stands in for its file name: its extension selects the document type
+    /// (`stdin.docx`) and its stem names the images.
+    pub fn extract_from_bytes(&self, data: &[u8], name: &Path) -> Result<ExtractReport> {
+        self.extract_inputs(&[Input::Memory { name, data }], None)
+    }
+
+    fn extract_inputs(
+        &self,
+        documents: &[Input],
+        journal: Option<Arc<resume::Journal>>,
+    ) -> Result<ExtractReport> {
+        let mut jobs = self.jobs.min(documents.len()).max(1);
+        if jobs > 1 && matches!(self.target, OutputTarget::Zip(_) | OutputTarget::Tar(_)) {
+            self.warn("--jobs has no effect with archive output; processing sequentially.");
+            jobs = 1;
+        }
+        if matches!(self.target, OutputTarget::Stdout) {
+            jobs = 1;
+        }
+
+        let mut output_options = self.output_options.clone();
+        output_options.dedupe |= self.dedupe_db.is_some();
+        // Each worker paces itself, so split the rate limit between them
+        output_options.max_write_rate = output_options
+            .max_write_rate
+            .map(|rate| (rate / jobs as u64).max(1));
+        let mut sink = match &self.target {
+            OutputTarget::Directory(dir) => OutputSink::new(dir.clone(), output_options),
+            OutputTarget::Zip(archive_path) => {
+                OutputSink::new_zip(archive_path.clone(), output_options)?
+            }
+            OutputTarget::Tar(archive_path) => {
+                let gzip = archive_path
+                    .extension()
+                    .is_some_and(|e| e.eq_ignore_ascii_case("gz") || e.eq_ignore_ascii_case("tgz"));
+                OutputSink::new_tar(archive_path.clone(), gzip, output_options)?
+            }
+            OutputTarget::Cas(store_dir) => OutputSink::new_cas(store_dir.clone(), output_options),
+            OutputTarget::Stdout => OutputSink::new_stdout(output_options),
+            OutputTarget::DryRun(dir) => OutputSink::new_dry_run(dir.clone(), output_options),
+            // Only collects the manifest; each document gets a sink of its own
+            OutputTarget::ZipPerDocument(dir) => OutputSink::new(dir.clone(), output_options),
+        };
+
+        let known = match &self.dedupe_db {
+            Some(db) => dedupe_db::load(db)?,
+            None => HashMap::new(),
+        };
+        sink.seed_written(&known);
+        if let Some(journal) = journal {
+            sink.set_journal(journal);
+        }
+        if let Some(observer) = &self.observer {
+            sink.set_observer(observer.clone());
+        }
+
+        let progress = self.progress_bar(documents.len());
+        let images = AtomicUsize::new(0);
+        let results = if jobs == 1 {
+            let mut results = Vec::new();
+            for (index, input) in documents.iter().enumerate() {
+                show_progress(progress.as_ref(), input.path(), &images);
+                // Hide the bar while the document's own output is printed
+                let result = match &progress {
+                    Some(bar) => bar.suspend(|| self.process_document(index > 0, input, &mut sink)),
+                    None => self.process_document(index > 0, input, &mut sink),
+                };
+                finish_progress(progress.as_ref(), &result, &images);
+                let failed = result.error.is_some();
+                results.push(result);
+                if failed && self.fail_fast {
+                    break;
+                }
+            }
+            results
+        } else {
+            let mut workers = (0..jobs).map(|_| sink.fork()).collect::<Result<Vec<_>>>()?;
+            let next = AtomicUsize::new(0);
+            let stop = AtomicBool::new(false);
+            let mut results: Vec<(usize, DocumentResult)> = thread::scope(|scope| {
+                let handles: Vec<_> = workers
+                    .iter_mut()
+                    .map(|worker| {
+                        let (next, stop, progress, images) = (&next, &stop, &progress, &images);
+                        scope.spawn(move || {
+                            let mut done = Vec::new();
+                            // Documents already started are finished after a fail-fast stop
+                            while !stop.load(Ordering::Relaxed) {
+                                let index = next.fetch_add(1, Ordering::Relaxed);
+                                let Some(input) = documents.get(index) else {
+                                    break;
+                                };
+                                let pause = !done.is_empty();
+                                show_progress(progress.as_ref(), input.path(), images);
+                                let result = self.process_document(pause, input, worker);
+                                finish_progress(progress.as_ref(), &result, images);
+                                if result.error.is_some() && self.fail_fast {
+                                    stop.store(true, Ordering::Relaxed);
+                                }
+                                done.push((index, result));
+                            }
+                            done
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| handle.join().expect("extraction worker panicked"))
+                    .collect()
+            });
+            for worker in workers {
+                sink.merge(worker)?;
+            }
+            results.sort_by_key(|(index, _)| *index);
+            results.into_iter().map(|(_, result)| result).collect()
+        };
+
+        if let Some(bar) = progress {
+            bar.finish_and_clear();
+        }
+
+        if let Some(db) = &self.dedupe_db
+            && !matches!(self.target, OutputTarget::DryRun(_))
+        {
+            dedupe_db::append_new(db, &known, &sink.written_content())?;
+        }
+        let duplicates = sink.duplicates_skipped();
+        let existing = sink.existing_skipped();
+        let mut manifest = sink.finish()?;
+        if jobs > 1
+            && let Some(manifest) = &mut manifest
+        {
+            // Workers finish in any order; list entries in document order like a sequential run
+            let order: HashMap<&Path, usize> = documents
+                .iter()
+                .enumerate()
+                .map(|(index, input)| (input.path(), index))
+                .collect();
+            manifest
+                .entries
+                .sort_by_key(|entry| order.get(entry.document.as_path()).copied());
+        }
+
+        Ok(ExtractReport {
+            documents: results,
+            manifest,
+            duplicates,
+            existing,
+            unchanged: 0,
+            resumed: 0,
+            limited: 0,
+        })
+    }
+
+    /// Processes a document into `{dir}/{document}.zip`, handing its manifest entries to
+    /// `sink`. Returns the outcome and the document's metadata. An archive that would
+    /// stay empty is not left behind.
+    fn process_into_own_archive(
+        &self,
+        dir: &Path,
+        input: &Input,
+        sink: &mut OutputSink,
+    ) -> (Result<usize>, Vec<(String, String)>) {
+        let path = input.path();
+        let stem = path
+            .file_stem()
+            .map(|s| common::sanitize_filename(&s.to_string_lossy()))
+            .unwrap_or_default();
+        let archive = dir.join(format!("{}.zip", stem));
+        let existed = archive.exists();
+
+        let mut archive_sink = match fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create output directory: {}", dir.display()))
+            .and_then(|()| OutputSink::new_zip(archive.clone(), self.output_options.clone()))
+        {
+            Ok(archive_sink) => archive_sink.join_run(sink),
+            Err(e) => return (Err(e), Vec::new()),
+        };
+        archive_sink.begin_document(path);
+        let outcome = input.process(&mut archive_sink, &self.options);
+        let metadata = archive_sink.take_metadata();
+        sink.add_timings(&archive_sink.take_timings());
+        sink.add_damaged(archive_sink.take_damaged());
+        let merged = sink.merge(archive_sink);
+        if !existed && matches!(outcome, Ok(0)) {
+            let _ = fs::remove_file(&archive);
+        }
+        (outcome.and_then(|count| merged.map(|()| count)), metadata)
+    }
+
+    /// Creates the batch progress bar, if enabled and stdout is a terminal
+    fn progress_bar(&self, documents: usize) -> Option<ProgressBar> {
+        if !self.progress || documents < 2 || !std::io::stdout().is_terminal() {
+            return None;
+        }
+        let style = ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:30} {pos}/{len} documents, {msg}",
+        )
+        .expect("progress template is valid");
+        Some(ProgressBar::new(documents as u64).with_style(style))
+    }
+
+    /// Passes a warning about the whole run to the observer, with an empty document
+    /// path, and prints it to stderr unless progress messages are suppressed
+    fn warn(&self, message: &str) {
+        if let Some(observer) = &self.observer {
+            observer.0.warning(Path::new(""), message);
+        }
+        if !common::is_quiet() {
+            eprintln!("Warning: {}", message);
+        }
+    }
+
+    /// Processes one document into `sink`, first pausing if requested and configured
+    fn process_document(
+        &self,
+        pause: bool,
+        input: &Input,
+        sink: &mut OutputSink,
+    ) -> DocumentResult {
+        let path = input.path();
+        if pause && let Some(pause) = self.pause {
+            thread::sleep(pause);
+        }
+        if let Some(observer) = &self.observer {
+            observer.0.document_started(path);
+        }
+
+        sink.begin_document(path);
+        let mirror_dir = self.mirror_dir(path);
+        let started = Instant::now();
+        let (outcome, metadata) = match &self.target {
+            OutputTarget::ZipPerDocument(dir) => match mirror_dir {
+                Some(relative) => self.process_into_own_archive(&dir.join(relative), input, sink),
+                None => self.process_into_own_archive(dir, input, sink),
+            },
+            _ => {
+                if let Some(relative) = mirror_dir {
+                    sink.set_document_dir(relative);
+                }
+                (input.process(sink, &self.options), sink.take_metadata())
+            }
+        };
+        let mut result = DocumentResult {
+            path: path.to_path_buf(),
+            images: 0,
+            error: None,
+            error_chain: Vec::new(),
+            skipped: None,
+            elapsed: started.elapsed(),
+            metadata,
+            damaged: sink.take_damaged(),
+            timings: sink.take_timings(),
+        };
+        match outcome {
+            Ok(count) => result.images = count,
+            // DRM-protected documents are skipped, not failed
+            Err(e) => match e.downcast_ref::<extract::DrmProtected>() {
+                Some(drm) => result.skipped = Some(drm.to_string()),
+                None => {
+                    result.error = Some(format!("{:#}", e));
+                    result.error_chain = e.chain().map(ToString::to_string).collect();
+                }
+            },
+        }
+        if result.error.is_none()
+            && let Some(journal) = sink.journal()
+            && let Err(e) = journal.record_done(path)
+        {
+            result.error = Some(format!("{:#}", e));
+            result.error_chain = e.chain().map(ToString::to_string).collect();
+        }
+        if let Some(observer) = &self.observer {
+            observer.0.document_finished(&result);
+        }
+        result
+    }
+}
+
+/// A document to extract images from
+enum Input<'a> {
+    File(&'a Path),
+    /// Document data already in memory, with the file name it stands for
+    Memory {
+        name: &'a Path,
+        data: &'a [u8],
+    },
+    /// A document inside an archive, read when it is processed
+    Member {
+        archive: &'a bundle::Archive,
+        index: usize,
+        name: &'a Path,
+    },
+    /// An archive of documents that could not be read, reported as a failed document
+    Unreadable {
+        path: &'a Path,
+        error: &'a str,
+    },
+}
+
+impl Input<'_> {
+    /// The path reported and recorded for the document
+    fn path(&self) -> &Path {
+        match self {
+            Input::File(path) => path,
+            Input::Memory { name, .. } | Input::Member { name, .. } => name,
+            Input::Unreadable { path, .. } => path,
+        }
+    }
+
+    /// Extracts the document into `sink` and finishes it there. Returns the number of
+    /// images written, not counting those left out or skipped on the way.
+    fn process(&self, sink: &mut OutputSink, options: &ExtractOptions) -> Result<usize> {
+        let outcome = match self {
+            Input::File(path) => extract::process_file(path, sink, options),
+            Input::Memory { name, data } => {
+                extract::process_bytes(data.to_vec(), name, sink, options)
+            }
+            Input::Member {
+                archive,
+                index,
+                name,
+            } => archive
+                .take(*index, options)
+                .and_then(|data| extract::process_bytes(data, name, sink, options)),
+            Input::Unreadable { error, .. } => Err(anyhow::anyhow!("{}", error)),
+        };
+        // Images held back are written even if the document failed part way
+        let left_out = sink.finish_document();
+        Ok(outcome?.saturating_sub(left_out?))
+    }
+}
+
+/// Shows the document being processed on the progress bar
+fn show_progress(progress: Option<&ProgressBar>, path: &Path, images: &AtomicUsize) {
+    if let Some(bar) = progress {
+        bar.set_message(format!(
+            "{} images: {}",
+            images.load(Ordering::Relaxed),
+            path.display()
+        ));
+    }
+}
+
+/// Counts a processed document and its images on the progress bar
+fn finish_progress(progress: Option<&ProgressBar>, result: &DocumentResult, images: &AtomicUsize) {
+    if let Some(bar) = progress {
+        images.fetch_add(result.images, Ordering::Relaxed);
+        bar.inc(1);
+    }
+}
+
+/// Extracts images from a document or directory into `output_dir` with default output
+/// settings. Shorthand for [`Extractor::extract_from_path`].
+pub fn extract_from_path(
+    path: &Path,
+    output_dir: &Path,
+    options: ExtractOptions,
+) -> Result<ExtractReport> {
+    Extractor::new(OutputTarget::Directory(output_dir.to_path_buf()), options)
+        .extract_from_path(path)
+}
+
+/// Extracts the images of a document held in memory and returns them, touching neither
+/// the filesystem nor any other output: no progress messages or warnings are printed,
+/// whatever [`common::set_quiet`] says. This is the entry point for front-ends without a
+/// filesystem, such as a browser page built for `wasm32-unknown-unknown`. `name` stands
+/// in for the document's file name: its extension selects the document type and its stem
+/// names the images. Empty `allowed_extensions` means every supported image format.
+pub fn extract_images(
+    data: &[u8],
+    name: &str,
+    mut options: ExtractOptions,
+) -> Result<Vec<ExtractedImage>> {
+    if options.allowed_extensions.is_empty() {
+        options.allowed_extensions = common::get_supported_extensions(&options.extra_formats);
+    }
+    let mut sink = OutputSink::new_memory(OutputOptions::default());
+    common::silently(|| {
+        extract::process_bytes(data.to_vec(), Path::new(name), &mut sink, &options)
+    })?;
+    Ok(sink.take_images())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::temp_dir;
+
+    /// An HTML page holding a single 1x1 GIF
+    const PAGE: &[u8] = b"<img src=\"data:image/gif;base64,R0lGODlhAQABAA==\">";
+
+    /// Writes [`PAGE`] to `dir/name`, returning its path
+    fn write_page(dir: &Path, name: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, PAGE).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_extract_from_path() {
+        let dir = temp_dir("lib");
+        let input = dir.join("input");
+        std::fs::create_dir_all(&input).unwrap();
+        write_page(&input, "page.html");
+        std::fs::write(input.join("broken.pdf"), b"not a pdf").unwrap();
+        std::fs::write(input.join("notes.txt"), b"ignored").unwrap();
+
+        let output = dir.join("output");
+        let report = extract_from_path(&input, &output, ExtractOptions::default()).unwrap();
+
+        assert_eq!(report.documents.len(), 2);
+        assert_eq!(report.images(), 1);
+        assert_eq!(report.documents_with_images(), 1);
+        assert_eq!(report.failures().count(), 1);
+        assert!(output.join("page.gif").exists());
+        assert!(
+            extract_from_path(&dir.join("missing"), &output, ExtractOptions::default()).is_err()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_extract_from_bytes() {
+        let dir = temp_dir("bytes");
+        let extractor = Extractor::new(
+            OutputTarget::Directory(dir.clone()),
+            ExtractOptions::default(),
+        );
+
+        let report = extractor
+            .extract_from_bytes(PAGE, Path::new("stdin.html"))
+            .unwrap();
+        assert_eq!(report.images(), 1);
+        assert_eq!(report.documents[0].path, Path::new("stdin.html"));
+        assert!(dir.join("stdin.gif").exists());
+
+        let report = extractor
+            .extract_from_bytes(PAGE, Path::new("stdin.txt"))
+            .unwrap();
+        assert_eq!(report.failures().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_observer_receives_events() {
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+
+        impl ProgressObserver for Recorder {
+            fn document_started(&self, document: &Path) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("started {}", document.display()));
+            }
+            fn image_found(&self, _document: &Path, output: &Path) {
+                let name = output.file_name().unwrap().to_string_lossy();
+                self.0.lock().unwrap().push(format!("found {}", name));
+            }
+            fn image_written(&self, _document: &Path, output: &Path, size: u64) {
+                let name = output.file_name().unwrap().to_string_lossy();
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("written {} {}", name, size));
+            }
+            fn warning(&self, _document: &Path, message: &str) {
+                assert!(message.starts_with("Skipping damaged entry page.png: "));
+                self.0.lock().unwrap().push("warning".to_string());
+            }
+            fn document_finished(&self, result: &DocumentResult) {
+                self.0.lock().unwrap().push(format!(
+                    "finished {} {}",
+                    result.images,
+                    result.error.is_some()
+                ));
+            }
+        }
+
+        let dir = temp_dir("observer");
+        let recorder = Arc::new(Recorder::default());
+        let extractor = Extractor::new(
+            OutputTarget::Directory(dir.clone()),
+            ExtractOptions::default(),
+        )
+        .output_options(OutputOptions {
+            convert_to: Some(convert::ConvertFormat::Png),
+            ..OutputOptions::default()
+        })
+        .observer(recorder.clone());
+
+        // The truncated GIF cannot be decoded, so it is skipped rather than written as a PNG
+        extractor
+            .extract_from_bytes(PAGE, Path::new("page.html"))
+            .unwrap();
+        extractor
+            .extract_from_bytes(PAGE, Path::new("page.txt"))
+            .unwrap();
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "started page.html",
+                "found page.png",
+                "warning",
+                "finished 0 false",
+                "started page.txt",
+                "finished 0 true",
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_warnings_reach_the_observer() {
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Warnings(Mutex<Vec<(PathBuf, String)>>);
+
+        impl ProgressObserver for Warnings {
+            fn warning(&self, document: &Path, message: &str) {
+                let warning = (document.to_path_buf(), message.to_string());
+                self.0.lock().unwrap().push(warning);
+            }
+        }
+
+        let dir = temp_dir("run-warnings");
+        let documents = ["first.html", "second.html"].map(|name| write_page(&dir, name));
+        let warnings = Arc::new(Warnings::default());
+        let extractor = Extractor::new(
+            OutputTarget::Zip(dir.join("images.zip")),
+            ExtractOptions::default(),
+        )
+        .jobs(2)
+        .observer(warnings.clone());
+        extractor.extract_documents(&documents).unwrap();
+        assert_eq!(
+            *warnings.0.lock().unwrap(),
+            [(
+                PathBuf::new(),
+                "--jobs has no effect with archive output; processing sequentially.".to_string()
+            )]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_extract_images_in_memory() {
+        let page = b"<img src=\"data:image/gif;base64,R0lGODlhAQABAA==\">\
+            <img src=\"data:image/gif;base64,R0lGODlhAgACAA==\">";
+        let images = extract_images(page, "page.html", ExtractOptions::default()).unwrap();
+        let names: Vec<_> = images.iter().map(|image| image.name.as_str()).collect();
+        assert_eq!(names, ["page_1.gif", "page_2.gif"]);
+        assert!(images[0].data.starts_with(b"GIF89a"));
+        assert!(extract_images(page, "page.txt", ExtractOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_incremental_skips_unchanged_documents() {
+        let dir = temp_dir("incremental-run");
+        let page = write_page(&dir, "page.html");
+        let broken = dir.join("broken.pdf");
+        std::fs::write(&broken, b"not a pdf").unwrap();
+
+        let extractor = Extractor::new(
+            OutputTarget::Directory(dir.join("out")),
+            ExtractOptions::default(),
+        )
+        .incremental(dir.join("state"));
+        let documents = [page, broken];
+        let first = extractor.extract_documents(&documents).unwrap();
+        assert_eq!((first.documents.len(), first.unchanged), (2, 0));
+
+        // The failed document is retried
+        let second = extractor.extract_documents(&documents).unwrap();
+        assert_eq!((second.documents.len(), second.unchanged), (1, 1));
+        assert_eq!(second.documents[0].path, documents[1]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_limited_incremental_runs_advance() {
+        let dir = temp_dir("incremental-limit");
+        let documents = ["first.html", "second.html"].map(|name| write_page(&dir, name));
+
+        let extractor = Extractor::new(
+            OutputTarget::Directory(dir.join("out")),
+            ExtractOptions::default(),
+        )
+        .incremental(dir.join("state"))
+        .limit(1);
+        let first = extractor.extract_documents(&documents).unwrap();
+        assert_eq!(first.documents[0].path, documents[0]);
+        assert_eq!((first.unchanged, first.limited), (0, 1));
+
+        // The unchanged first document does not use up the limit
+        let second = extractor.extract_documents(&documents).unwrap();
+        assert_eq!(second.documents.len(), 1);
+        assert_eq!(second.documents[0].path, documents[1]);
+        assert_eq!((second.unchanged, second.limited), (1, 0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resume_continues_interrupted_run() {
+        use std::io::Write;
+
+        let dir = temp_dir("resume-run");
+        let page = write_page(&dir, "page.html");
+        let broken = dir.join("broken.pdf");
+        std::fs::write(&broken, b"not a pdf").unwrap();
+        let out = dir.join("out");
+        let journal = dir.join("journal");
+
+        let extractor = Extractor::new(
+            OutputTarget::Directory(out.clone()),
+            ExtractOptions::default(),
+        )
+        .resume(journal.clone());
+        let first = extractor
+            .extract_documents(&[page.clone(), broken.clone()])
+            .unwrap();
+        assert_eq!((first.documents.len(), first.resumed), (2, 0));
+        assert!(out.join("page.gif").exists());
+
+        // The run was interrupted while writing an image of the broken document
+        std::fs::write(out.join("broken.gif"), b"GIF8").unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&journal)
+            .unwrap();
+        writeln!(
+            file,
+            "file\t{}\t{}",
+            broken.display(),
+            out.join("broken.gif").display()
+        )
+        .unwrap();
+        drop(file);
+
+        let second = extractor
+            .extract_documents(&[page.clone(), broken])
+            .unwrap();
+        assert_eq!((second.documents.len(), second.resumed), (1, 1));
+        assert!(!out.join("broken.gif").exists());
+        assert!(journal.exists());
+
+        // Finishing every document without failures ends the run
+        let third = extractor.extract_documents(&[page]).unwrap();
+        assert_eq!((third.documents.len(), third.resumed), (0, 1));
+        assert!(!journal.exists());
+        assert!(!out.join("page_1.gif").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_archive_of_documents() {
+        use std::io::Write;
+
+        let dir = temp_dir("archive-input");
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for name in ["chapters/one.html", "two.html"] {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(PAGE).unwrap();
+        }
+        let archive = dir.join("pages.zip");
+        std::fs::write(&archive, writer.finish().unwrap().into_inner()).unwrap();
+        let broken = dir.join("broken.tar.gz");
+        std::fs::write(&broken, b"not gzip").unwrap();
+
+        let out = dir.join("out");
+        let extractor = Extractor::new(
+            OutputTarget::Directory(out.clone()),
+            ExtractOptions::default(),
+        )
+        .incremental(dir.join("state"));
+        let documents = [archive.clone(), broken.clone()];
+        let report = extractor.extract_documents(&documents).unwrap();
+        let paths: Vec<_> = report.documents.iter().map(|d| d.path.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                archive.join("chapters/one.html"),
+                archive.join("two.html"),
+                broken.clone()
+            ]
+        );
+        assert_eq!(report.images(), 2);
+        assert_eq!(report.failures().count(), 1);
+        assert!(out.join("one.gif").exists() && out.join("two.gif").exists());
+
+        // The archive is unchanged; the unreadable one is tried again
+        let again = extractor.extract_documents(&documents).unwrap();
+        assert_eq!((again.documents.len(), again.unchanged), (1, 1));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dry_run_writes_nothing() {
+        let dir = temp_dir("dry-run");
+        let page = write_page(&dir, "page.html");
+
+        let out = dir.join("out");
+        let report = Extractor::new(OutputTarget::DryRun(out.clone()), ExtractOptions::default())
+            .output_options(OutputOptions {
+                record_manifest: true,
+                ..Default::default()
+            })
+            .incremental(dir.join("state"))
+            .extract_documents(std::slice::from_ref(&page))
+            .unwrap();
+        assert_eq!(report.images(), 1);
+        let entries = report.manifest.unwrap().entries;
+        assert_eq!(
+            entries[0].output.as_deref(),
+            Some(out.join("page.gif").as_path())
+        );
+        assert_eq!(entries[0].size, 10);
+        assert!(!out.exists() && !dir.join("state").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_mirror_recreates_input_folders() {
+        let dir = temp_dir("mirror");
+        let input = dir.join("input");
+        std::fs::create_dir_all(input.join("a/b")).unwrap();
+        write_page(&input, "top.html");
+        write_page(&input.join("a/b"), "page.html");
+
+        let output = dir.join("output");
+        let report = Extractor::new(
+            OutputTarget::Directory(output.clone()),
+            ExtractOptions::default(),
+        )
+        .recursive(true)
+        .mirror(vec![input.clone()])
+        .extract_from_path(&input)
+        .unwrap();
+        assert_eq!(report.images(), 2);
+        assert!(output.join("top.gif").exists());
+        assert!(output.join("a/b/page.gif").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fail_fast_stops_at_first_failure() {
+        let dir = temp_dir("fail-fast");
+        let broken = dir.join("broken.pdf");
+        std::fs::write(&broken, b"not a pdf").unwrap();
+        let page = write_page(&dir, "page.html");
+        let documents = [broken, page];
+
+        let extractor = Extractor::new(
+            OutputTarget::Directory(dir.join("out")),
+            ExtractOptions::default(),
+        );
+        let report = extractor.extract_documents(&documents).unwrap();
+        assert_eq!((report.documents.len(), report.images()), (2, 1));
+
+        let report = extractor
+            .fail_fast(true)
+            .extract_documents(&documents)
+            .unwrap();
+        assert_eq!(report.documents.len(), 1);
+        assert_eq!(report.failures().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parallel_jobs_do_not_collide() {
+        let dir = temp_dir("jobs");
+        // Every document wants to write `page.gif`
+        let mut documents = Vec::new();
+        for n in 0..8 {
+            let input = dir.join(format!("input{}", n));
+            std::fs::create_dir_all(&input).unwrap();
+            documents.push(write_page(&input, "page.html"));
+        }
+
+        let output = dir.join("output");
+        let report = Extractor::new(
+            OutputTarget::Directory(output.clone()),
+            ExtractOptions::default(),
+        )
+        .output_options(OutputOptions {
+            record_manifest: true,
+            ..Default::default()
+        })
+        .jobs(4)
+        .extract_documents(&documents)
+        .unwrap();
+
+        assert_eq!(report.images(), 8);
+        assert_eq!(std::fs::read_dir(&output).unwrap().count(), 8);
+        let result_paths: Vec<_> = report.documents.iter().map(|d| d.path.clone()).collect();
+        assert_eq!(result_paths, documents);
+        let manifest_documents: Vec<_> = report
+            .manifest
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|e| e.document)
+            .collect();
+        assert_eq!(manifest_documents, documents);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_archive_per_document() {
+        let dir = temp_dir("per-doc");
+        let page = write_page(&dir, "page.html");
+        let empty = dir.join("empty.html");
+        std::fs::write(&empty, b"<p>no images</p>").unwrap();
+
+        let output = dir.join("archives");
+        let report = Extractor::new(
+            OutputTarget::ZipPerDocument(output.clone()),
+            ExtractOptions::default(),
+        )
+        .output_options(OutputOptions {
+            record_manifest: true,
+            ..Default::default()
+        })
+        .extract_documents(&[page, empty])
+        .unwrap();
+
+        assert_eq!(report.images(), 1);
+        assert_eq!(report.manifest.unwrap().entries.len(), 1);
+        let archive = std::fs::File::open(output.join("page.zip")).unwrap();
+        let mut archive = zip::ZipArchive::new(archive).unwrap();
+        assert_eq!(archive.by_index(0).unwrap().name(), "page.gif");
+        assert!(!output.join("empty.zip").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
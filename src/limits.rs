@@ -0,0 +1,231 @@
+//! Limits on how much a document may make the extractor read
+//!
+//! A malicious or corrupt zip container can declare millions of entries, or entries that
+//! inflate to gigabytes from a few kilobytes. Archives are checked against these limits
+//! before anything is decompressed, and entry data is read through [`LimitedReader`] so a
+//! header understating an entry's size cannot get past them either.
+
+use anyhow::Result;
+use std::fmt;
+use std::io::{self, Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+
+/// Default largest uncompressed size of a single image or embedded document
+pub const DEFAULT_MAX_IMAGE_SIZE: u64 = 1 << 30;
+/// Default largest total uncompressed size of the entries of one archive
+pub const DEFAULT_MAX_TOTAL_SIZE: u64 = 8 << 30;
+/// Default largest number of entries in one archive
+pub const DEFAULT_MAX_ENTRIES: usize = 100_000;
+
+/// Highest uncompressed to compressed size ratio accepted for large entries. Deflate
+/// cannot exceed about 1032:1, so only crafted archives come close.
+const MAX_COMPRESSION_RATIO: u64 = 1000;
+/// Entries smaller than this are not checked for their compression ratio, since a
+/// small block of zeros legitimately compresses extremely well
+const RATIO_CHECK_MIN_SIZE: u64 = 1 << 20;
+
+/// Resource limits applied to every archive-based document
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResourceLimits {
+    /// Largest uncompressed size of a single image or embedded document
+    pub max_image_size: u64,
+    /// Largest total uncompressed size of all entries of an archive
+    pub max_total_size: u64,
+    /// Largest number of entries in an archive
+    pub max_entries: usize,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            max_image_size: DEFAULT_MAX_IMAGE_SIZE,
+            max_total_size: DEFAULT_MAX_TOTAL_SIZE,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+}
+
+impl ResourceLimits {
+    /// Checks the entry count and the declared sizes of `archive` before any entry is read
+    pub fn check_archive<R: Read + Seek>(
+        &self,
+        archive: &mut ZipArchive<R>,
+        input_path: &Path,
+    ) -> Result<()> {
+        if archive.len() > self.max_entries {
+            anyhow::bail!(
+                "{} has {} entries, more than the limit of {} (--max-entries)",
+                input_path.display(),
+                archive.len(),
+                self.max_entries
+            );
+        }
+        let mut total: u64 = 0;
+        for index in 0..archive.len() {
+            let file = archive.by_index_raw(index)?;
+            let size = file.size();
+            if size >= RATIO_CHECK_MIN_SIZE
+                && size / file.compressed_size().max(1) > MAX_COMPRESSION_RATIO
+            {
+                anyhow::bail!(
+                    "{} in {} inflates from {} to {} bytes, which looks like a zip bomb",
+                    file.name(),
+                    input_path.display(),
+                    file.compressed_size(),
+                    size
+                );
+            }
+            total = total.saturating_add(size);
+        }
+        if total > self.max_total_size {
+            anyhow::bail!(
+                "{} inflates to {}, more than the limit of {} (--max-total-size)",
+                input_path.display(),
+                Size(total),
+                Size(self.max_total_size)
+            );
+        }
+        Ok(())
+    }
+
+    /// Reads the whole entry `name` from `reader`, failing once it exceeds `max_image_size`
+    pub fn read_entry(&self, reader: impl Read, name: &str) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.limit(reader, name).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Wraps `reader` so reading more than `max_image_size` bytes from it fails
+    pub fn limit<R: Read>(&self, reader: R, name: &str) -> LimitedReader<R> {
+        LimitedReader {
+            inner: reader,
+            remaining: self.max_image_size,
+            limit: self.max_image_size,
+            name: name.to_string(),
+        }
+    }
+}
+
+/// Reader that fails with an error instead of returning more than a set number of bytes
+pub struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+    limit: u64,
+    name: String,
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            // Only fail if there really is more data
+            if buf.is_empty() || self.inner.read(&mut [0u8])? == 0 {
+                return Ok(0);
+            }
+            return Err(io::Error::other(format!(
+                "{} is larger than the limit of {} (--max-image-size)",
+                self.name,
+                Size(self.limit)
+            )));
+        }
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buf[..max])?;
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// A byte count shown with a binary unit, as limits are usually given
+pub(crate) struct Size(pub(crate) u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 5] = ["bytes", "KiB", "MiB", "GiB", "TiB"];
+        let mut value = self.0 as f64;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            write!(f, "{} bytes", self.0)
+        } else {
+            write!(f, "{:.1} {}", value, UNITS[unit])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::ZipWriter;
+    use zip::write::SimpleFileOptions;
+
+    fn archive(entries: &[(&str, Vec<u8>)]) -> ZipArchive<Cursor<Vec<u8>>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        ZipArchive::new(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_check_archive() {
+        let path = Path::new("a.docx");
+        let limits = ResourceLimits {
+            max_total_size: 100,
+            max_entries: 2,
+            ..Default::default()
+        };
+        let small = vec![b'x'; 40];
+        assert!(
+            limits
+                .check_archive(&mut archive(&[("a", small.clone())]), path)
+                .is_ok()
+        );
+        let err = limits
+            .check_archive(
+                &mut archive(&[("a", small.clone()), ("b", small.clone()), ("c", vec![])]),
+                path,
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("--max-entries"));
+        let err = limits
+            .check_archive(
+                &mut archive(&[("a", small.clone()), ("b", vec![0; 80])]),
+                path,
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a.docx inflates to 120 bytes, more than the limit of 100 bytes (--max-total-size)"
+        );
+
+        // 4 MiB of zeros deflates to a few kilobytes
+        let err = ResourceLimits::default()
+            .check_archive(&mut archive(&[("bomb.png", vec![0; 4 << 20])]), path)
+            .unwrap_err();
+        assert!(err.to_string().contains("zip bomb"));
+    }
+
+    #[test]
+    fn test_read_entry_limit() {
+        let limits = ResourceLimits {
+            max_image_size: 4,
+            ..Default::default()
+        };
+        assert_eq!(limits.read_entry(&b"1234"[..], "a.png").unwrap(), b"1234");
+        let err = limits.read_entry(&b"12345"[..], "a.png").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a.png is larger than the limit of 4 bytes (--max-image-size)"
+        );
+        assert_eq!(Size(3 << 29).to_string(), "1.5 GiB");
+    }
+}
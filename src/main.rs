@@ -1,152 +1,1005 @@
-//! Word Image Extractor - A CLI tool for extracting images from DOCX and EPUB files.
+//! Word Image Extractor - command line interface.
 //!
-//! This tool treats DOCX and EPUB files as ZIP archives and extracts image files
-//! matching specified formats.
+//! Parses arguments and hands the documents to the library's `Extractor`.
 
-mod common;
-mod docx;
-mod epub;
-
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::fs;
+use std::io::{IsTerminal, Read};
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use word_image_extractor::common::{
+    self, DocumentFilter, ExtraFormat, NameForm, Numbering, normalize_format,
+};
+use word_image_extractor::config::Config;
+use word_image_extractor::convert::{AnimatedFormat, ColorProfile, ConvertFormat};
+use word_image_extractor::epub;
+use word_image_extractor::extract::{
+    CoverFallback, ExtractOptions, SUPPORTED_DOCUMENTS, ScanOptions, collect_documents,
+    get_document_type, parse_file_list, remove_duplicate_documents,
+};
+use word_image_extractor::filter::{self, ImageFilter, ImageSelection, Orientation};
+use word_image_extractor::glob::Glob;
+use word_image_extractor::hook;
+use word_image_extractor::limits::{
+    DEFAULT_MAX_ENTRIES, DEFAULT_MAX_IMAGE_SIZE, DEFAULT_MAX_TOTAL_SIZE, ResourceLimits,
+};
+use word_image_extractor::messages::{self, Lang, Message, SummaryTemplate};
+use word_image_extractor::naming::{self, NameTemplate};
+use word_image_extractor::output::{
+    self, CasLink, ConflictPolicy, FormatRoutes, GroupBy, OriginalNames, OutputOptions,
+};
+use word_image_extractor::{
+    DocumentResult, ExtractReport, Extractor, OutputTarget, ProgressObserver, compare, report,
+    timings, update, validate,
+};
+
+// Exit statuses of an extraction run. Fatal errors (bad arguments, unwritable output)
+// exit with 1 through the error returned from main.
+/// Exit status when some documents could not be processed
+const EXIT_DOCUMENTS_FAILED: i32 = 2;
+/// Exit status when the documents processed held no images to extract
+const EXIT_NO_IMAGES: i32 = 3;
+/// Exit status when documents were skipped because they are DRM-protected
+const EXIT_DRM_PROTECTED: i32 = 4;
 
-use common::{get_supported_extensions, normalize_format};
-use epub::EpubFilter;
+/// State file used by a bare `--incremental`, in the working directory
+const INCREMENTAL_STATE_FILE: &str = "word-gif-extractor.state";
+/// Journal used by a bare `--resume`, in the working directory
+const RESUME_JOURNAL_FILE: &str = "word-gif-extractor.resume";
 
 #[derive(Parser, Debug)]
-#[command(author, version, about = "Extract images from Word (.docx) and EPUB files", long_about = None)]
+#[command(author, version, about = "Extract images from Office, OpenDocument, EPUB, Kindle, FB2, RTF, HTML and PDF files", long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Paths to input .docx/.epub files or directories (positional)
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Paths to input .docx/.doc/.pptx/.xlsx/.odt/.epub/.mobi/.fb2/.rtf/.html/.pdf files or directories (positional).
+    /// Any number may be given; all are processed with the same options.
     inputs: Vec<PathBuf>,
 
-    /// Paths to input .docx/.epub files or directories (named)
+    /// Paths to input .docx/.doc/.pptx/.xlsx/.odt/.epub/.mobi/.fb2/.rtf/.html/.pdf files or directories (named)
     #[arg(short = 'i', long = "input", num_args = 1..)]
     named_inputs: Vec<PathBuf>,
 
+    /// Read document paths from FILE, or stdin if FILE is `-`, one per line or NUL-separated
+    /// (as from `find -print0`)
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// Read a single document from stdin instead of input paths (requires --type)
+    #[arg(long, requires = "stdin_type", conflicts_with_all = ["inputs", "named_inputs", "files_from"])]
+    stdin: bool,
+
+    /// Document type of the data read with --stdin, given as its file extension (e.g. docx, epub, pdf)
+    #[arg(long = "type", value_name = "EXT", requires = "stdin")]
+    stdin_type: Option<String>,
+
     /// Optional output directory (defaults to current directory)
     #[arg(short, long)]
     output: Option<PathBuf>,
 
-    /// Recursively search for .docx/.epub files if input is a directory
-    #[arg(short, long)]
+    /// Write all extracted images into this archive instead of loose files: a zip archive
+    /// (appended to if it exists), or a .tar, .tar.gz or .tgz tarball
+    #[arg(
+        long,
+        visible_alias = "output-zip",
+        value_name = "ARCHIVE",
+        conflicts_with = "output"
+    )]
+    output_archive: Option<PathBuf>,
+
+    /// Write one archive per document (DIR/{document}.zip) into the --output-archive
+    /// directory instead of a single archive
+    #[arg(long, requires = "output_archive")]
+    archive_per_document: bool,
+
+    /// What to do when an output file or archive entry already exists [default: rename]
+    #[arg(long, value_enum)]
+    on_conflict: Option<ConflictPolicy>,
+
+    /// Split archive output into numbered volumes of at most this size (e.g. 4G, 700M)
+    #[arg(long, value_name = "SIZE", requires = "output_archive", value_parser = common::parse_size)]
+    split_size: Option<u64>,
+
+    /// Write each unique image once under DIR/by-hash/ and link it from a per-document
+    /// tree under DIR/by-document/
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["output", "output_archive"])]
+    cas_store: Option<PathBuf>,
+
+    /// Write the extracted image to standard output instead of a file, for pipelines.
+    /// Only one image may be extracted (requires --cover-only or --only with one position)
+    #[arg(
+        long,
+        conflicts_with_all = ["output", "output_archive", "cas_store", "json"]
+    )]
+    stdout: bool,
+
+    /// Link type for the --cas-store document tree
+    #[arg(long, value_enum, default_value_t = CasLink::Symlink, requires = "cas_store")]
+    cas_link: CasLink,
+
+    /// Output name template, e.g. "{author}/{title}_{index:03}.{ext}". Placeholders:
+    /// {doc}, {title}, {author}, {index}, {ext}, {original}
+    #[arg(long, value_name = "TEMPLATE", value_parser = naming::parse_name_template)]
+    name_template: Option<NameTemplate>,
+
+    /// Recreate each image's path inside its document (e.g. word/media/image3.png) under
+    /// a directory named after the document
+    #[arg(long, conflicts_with = "name_template")]
+    keep_paths: bool,
+
+    /// On Windows, write through extended-length (\\?\) paths so deep output paths may
+    /// exceed 260 characters
+    #[arg(long)]
+    long_paths: bool,
+
+    /// Transliterate output names to ASCII: accents are dropped (Brontë -> Bronte) and
+    /// characters without a Latin equivalent, such as CJK, are replaced by _
+    #[arg(long)]
+    ascii_names: bool,
+
+    /// Normalize output names to Unicode form `nfc` (composed) or `nfd` (decomposed)
+    #[arg(long, value_enum, value_name = "FORM", conflicts_with = "ascii_names")]
+    normalize_names: Option<NameForm>,
+
+    /// Name images after their file names inside the document (e.g. image42.png) instead
+    /// of numbering them; `prefixed` puts the document name in front (report_image42.png)
+    #[arg(
+        long,
+        value_enum,
+        value_name = "bare|prefixed",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "bare",
+        conflicts_with_all = ["name_template", "keep_paths"]
+    )]
+    original_names: Option<OriginalNames>,
+
+    /// Name DOCX and EPUB images after their alt text where they have one
+    /// (`Figure 3 - Network topology.png`); others are named as usual
+    #[arg(long, conflicts_with = "keep_paths")]
+    name_from_alt: bool,
+
+    /// Zero-pad image numbers to N digits (doc_001.png), so names sort naturally
+    #[arg(long, value_name = "N")]
+    pad_width: Option<usize>,
+
+    /// Number the images of each document from N instead of 1
+    #[arg(long, value_name = "N")]
+    start_index: Option<usize>,
+
+    /// Sort output into subdirectories, e.g. `format` for `png/`, `jpg/`, `svg/`
+    #[arg(long, value_enum, value_name = "KEY")]
+    group_by: Option<GroupBy>,
+
+    /// Write images of some formats into their own subdirectories, e.g.
+    /// "svg=vectors/,jpg=photos/,png=diagrams/"; other formats follow --group-by
+    #[arg(long, value_name = "FORMAT=DIR,...", value_parser = output::parse_routes)]
+    route: Option<FormatRoutes>,
+
+    /// Re-encode raster images (PNG, JPEG, GIF, BMP, TIFF, WebP, ICO) to one format.
+    /// Vector images are written unchanged.
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    convert_to: Option<ConvertFormat>,
+
+    /// For --convert-to and --thumbnails: keep each image's ICC profile, embedding it in
+    /// the converted image, or convert its colors to sRGB. CMYK JPEGs always become sRGB.
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = ColorProfile::Keep)]
+    color_profile: ColorProfile,
+
+    /// Also write each frame of animated GIFs as a PNG in `{name}_frames/`, with the
+    /// frame delays in `frames.json`
+    #[arg(long)]
+    split_gif_frames: bool,
+
+    /// Re-encode GIFs as animated WebP or APNG, keeping every frame and its delay.
+    /// Takes precedence over --convert-to for GIFs.
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    animated_to: Option<AnimatedFormat>,
+
+    /// Re-encode GIFs so each frame only stores the pixels that changed, keeping the
+    /// result only when it is smaller
+    #[arg(long)]
+    optimize_gif: bool,
+
+    /// Also write a copy of each raster image scaled to fit within PX pixels into `thumbs/`
+    #[arg(long, value_name = "PX", value_parser = clap::value_parser!(u32).range(1..))]
+    thumbnails: Option<u32>,
+
+    /// Remove EXIF (camera, GPS), XMP, ICC profile and text metadata from JPEG, PNG and
+    /// WebP images before writing them
+    #[arg(long)]
+    strip_metadata: bool,
+
+    /// Recursively search for .docx/.doc/.pptx/.xlsx/.odt/.epub/.mobi/.fb2/.rtf/.html/.pdf files if input is a directory
+    #[arg(short, long, overrides_with = "no_recursive")]
     recursive: bool,
 
+    /// Do not search input directories recursively, even if the configuration file sets `recursive`
+    #[arg(long, overrides_with = "recursive")]
+    no_recursive: bool,
+
+    /// Skip files and directories matching GLOB when searching input directories, e.g.
+    /// `.git`, `*draft*` or `**/backup/**` (can be repeated)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<Glob>,
+
+    /// Search at most N directory levels deep; 1 is the input directory itself (implies --recursive)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_depth: Option<u64>,
+
+    /// Descend into symlinked directories when searching recursively
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Do not cross into other file systems (mounted drives and shares) when searching recursively
+    #[arg(long)]
+    same_filesystem: bool,
+
+    /// Recreate the folders of input directories in the output: images from `in/a/b/x.docx`
+    /// go into `a/b/` of the output
+    #[arg(long, conflicts_with = "stdout")]
+    mirror: bool,
+
     /// Image formats to extract (e.g., "png,jpg"). Defaults to all supported formats.
     #[arg(short, long, value_delimiter = ',', num_args = 0..)]
     formats: Option<Vec<String>>,
 
-    /// Extract only cover image from EPUB files
-    #[arg(short = 'c', long)]
+    /// Register an image format the built-in tables do not know, as EXT=MIME (e.g.
+    /// `avif=image/avif`, `jxl=image/jxl`), so it is extracted like the others (can be repeated)
+    #[arg(long, value_name = "EXT=MIME", value_parser = common::parse_extra_format)]
+    extra_format: Vec<ExtraFormat>,
+
+    /// Extract only cover image from EPUB, MOBI/AZW3 and FB2 files, or the thumbnail (else
+    /// the first image) of Office documents
+    #[arg(short = 'c', long, overrides_with = "no_cover_only")]
     cover_only: bool,
 
-    /// Fallback to extracting all images if cover not found (EPUB only, requires --cover-only)
+    /// Do not extract only covers, even if the configuration file sets `cover-only`
+    #[arg(long, overrides_with = "cover_only")]
+    no_cover_only: bool,
+
+    /// Fallback if no cover is found: extract all images, or with `=largest` only the
+    /// image with the most pixels (requires --cover-only)
+    #[arg(
+        long,
+        requires = "cover_only",
+        value_name = "all|largest",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "all"
+    )]
+    cover_fallback: Option<CoverFallback>,
+
+    /// Skip covers that look the same as one already extracted in this run (requires --cover-only)
     #[arg(long, requires = "cover_only")]
-    cover_fallback: bool,
+    skip_similar_covers: bool,
+
+    /// Also extract images from .docx/.doc/.pptx/.xlsx/.odt/.epub/.mobi/.fb2/.rtf/.html/.pdf documents embedded inside documents
+    #[arg(long, overrides_with = "no_recurse_embedded")]
+    recurse_embedded: bool,
+
+    /// Do not extract images from embedded documents, even if the configuration file sets `recurse-embedded`
+    #[arg(long, overrides_with = "recurse_embedded")]
+    no_recurse_embedded: bool,
+
+    /// Recurse into documents and zip archives embedded in documents, following at most
+    /// DEPTH levels of nesting (default 4)
+    #[arg(
+        long,
+        value_name = "DEPTH",
+        num_args = 0..=1,
+        require_equals = true,
+        conflicts_with = "no_recurse_embedded"
+    )]
+    nested: Option<Option<usize>>,
 
-    /// Filter EPUB files by title (case-insensitive substring match)
+    /// Only extract images whose detected format differs from their extension or declared MIME type
     #[arg(long)]
-    title: Option<String>,
+    only_mismatched: bool,
+
+    /// Only extract images the document actually uses: drawn in a DOCX, or referenced by an
+    /// EPUB page or stylesheet, skipping boilerplate and media left behind by edits
+    #[arg(long)]
+    referenced_only: bool,
+
+    /// Sort DOCX images into body/, headers/, footers/, footnotes/, endnotes/ and comments/
+    /// subdirectories by where they are used
+    #[arg(long)]
+    sections: bool,
 
-    /// Filter EPUB files by author (case-insensitive substring match)
+    /// Append _footnote, _endnote or _comment to the names of DOCX images only used in
+    /// footnotes, endnotes or comments
     #[arg(long)]
+    tag_notes: bool,
+
+    /// Sort EPUB images into a subdirectory per chapter, named from the table of contents
+    #[arg(long)]
+    by_chapter: bool,
+
+    /// Extract the preview image of each embedded object (Excel sheet, Visio drawing, ...)
+    /// in Word documents, whatever its format
+    #[arg(long)]
+    embedded_objects: bool,
+
+    /// Also write the embedded object files themselves (implies --embedded-objects)
+    #[arg(long)]
+    embedded_object_files: bool,
+
+    /// Also extract EPUB fonts (TTF, OTF, WOFF) into fonts/, undoing font obfuscation
+    #[arg(long)]
+    extract_fonts: bool,
+
+    /// Take DOCX, ODF and EPUB image formats from their file extensions and declared MIME
+    /// types instead of detecting them from the content
+    #[arg(long)]
+    trust_extension: bool,
+
+    /// Skip DOCX and ODF entries that cannot be read (bad headers, CRC or decompression
+    /// errors) instead of failing the document, and list them at the end
+    #[arg(long)]
+    salvage: bool,
+
+    /// Password for encrypted Office documents. Without it, the password of each encrypted
+    /// document is asked for when running in a terminal
+    #[arg(long)]
+    password: Option<String>,
+
+    /// Refuse to read an image or embedded document that inflates to more than this
+    /// (default 1G)
+    #[arg(long, value_name = "SIZE", value_parser = common::parse_size)]
+    max_image_size: Option<u64>,
+
+    /// Refuse to open a DOCX, ODF or EPUB whose entries inflate to more than this in total
+    /// (default 8G)
+    #[arg(long, value_name = "SIZE", value_parser = common::parse_size)]
+    max_total_size: Option<u64>,
+
+    /// Refuse to open a DOCX, ODF or EPUB with more entries than this (default 100000)
+    #[arg(long, value_name = "N")]
+    max_entries: Option<usize>,
+
+    /// Skip images smaller than this, e.g. 10k
+    #[arg(long, value_name = "SIZE", value_parser = common::parse_size)]
+    min_size: Option<u64>,
+
+    /// Skip images larger than this, e.g. 20M
+    #[arg(long, value_name = "SIZE", value_parser = common::parse_size)]
+    max_size: Option<u64>,
+
+    /// Skip images narrower than this many pixels
+    #[arg(long, value_name = "PX")]
+    min_width: Option<u32>,
+
+    /// Skip images shorter than this many pixels
+    #[arg(long, value_name = "PX")]
+    min_height: Option<u32>,
+
+    /// Skip images wider than this many pixels
+    #[arg(long, value_name = "PX")]
+    max_width: Option<u32>,
+
+    /// Skip images taller than this many pixels
+    #[arg(long, value_name = "PX")]
+    max_height: Option<u32>,
+
+    /// Skip images that are not of this shape, judged by their pixel dimensions
+    #[arg(long, value_enum)]
+    orientation: Option<Orientation>,
+
+    /// Skip images whose width to height ratio is below this, e.g. 0.5 or 2:3
+    #[arg(long, value_name = "RATIO", value_parser = filter::parse_aspect)]
+    min_aspect: Option<f64>,
+
+    /// Skip images whose width to height ratio is above this, e.g. 2 or 16:9
+    #[arg(long, value_name = "RATIO", value_parser = filter::parse_aspect)]
+    max_aspect: Option<f64>,
+
+    /// Extract only the N images of each document with the most pixels
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    largest: Option<u64>,
+
+    /// Extract only these images of each document: positions as numbered in the output
+    /// and ranges (1,5-8,10-) or patterns for the name inside it ('word/media/image1*.png')
+    #[arg(long, value_name = "LIST", value_parser = filter::parse_selection)]
+    only: Option<ImageSelection>,
+
+    /// Also extract the document thumbnail (docProps/thumbnail.* or Thumbnails/thumbnail.png)
+    #[arg(
+        long,
+        visible_alias = "package-thumbnail",
+        overrides_with = "no_doc_thumbnail"
+    )]
+    doc_thumbnail: bool,
+
+    /// Do not extract the document thumbnail, even if the configuration file sets `doc-thumbnail`
+    #[arg(long, overrides_with = "doc_thumbnail")]
+    no_doc_thumbnail: bool,
+
+    /// Write the bitmap drawn by EMF/WMF package thumbnails instead of the metafile
+    #[arg(long, requires = "doc_thumbnail")]
+    rasterize_thumbnail: bool,
+
+    /// Scan zip files with unrecognised extensions (.zip, .cbz, renamed documents) for images
+    #[arg(long, overrides_with = "no_treat_as_zip")]
+    treat_as_zip: bool,
+
+    /// Do not scan zip files with unrecognised extensions, even if the configuration file sets `treat-as-zip`
+    #[arg(long, overrides_with = "treat_as_zip")]
+    no_treat_as_zip: bool,
+
+    /// Skip macro-enabled Office files (.docm, .dotm, .xlsm, .xltm, .pptm, .potm, .ppsm)
+    #[arg(long, overrides_with = "no_skip_macro_enabled")]
+    skip_macro_enabled: bool,
+
+    /// Do not skip macro-enabled Office files, even if the configuration file sets `skip-macro-enabled`
+    #[arg(long, overrides_with = "skip_macro_enabled")]
+    no_skip_macro_enabled: bool,
+
+    /// EPUB rendition to extract when a book declares several: a number, a label, or `all`
+    #[arg(long, value_name = "N|LABEL|all", value_parser = epub::parse_rendition)]
+    rendition: Option<epub::RenditionChoice>,
+
+    /// Only process documents whose title contains this (case-insensitive)
+    #[arg(long = "filter-title", visible_alias = "title", value_name = "TEXT")]
+    title: Option<String>,
+
+    /// Only process documents whose author contains this (case-insensitive)
+    #[arg(long = "filter-author", visible_alias = "author", value_name = "TEXT")]
     author: Option<String>,
+
+    /// Only process documents whose subject contains this (case-insensitive)
+    #[arg(long = "filter-subject", value_name = "TEXT")]
+    subject: Option<String>,
+
+    /// Only process ebooks whose series contains this (case-insensitive)
+    #[arg(long = "filter-series", value_name = "TEXT")]
+    series: Option<String>,
+
+    /// Only process ebooks whose publisher contains this (case-insensitive)
+    #[arg(long = "filter-publisher", value_name = "TEXT")]
+    publisher: Option<String>,
+
+    /// Only process ebooks in this language; `en` also matches `en-US` and `en-GB`
+    #[arg(long = "filter-language", value_name = "LANG")]
+    language: Option<String>,
+
+    /// Only process ebooks with this ISBN (hyphens are ignored)
+    #[arg(long = "filter-isbn", value_name = "ISBN")]
+    isbn: Option<String>,
+
+    /// Number of documents to process at once (0 = one per CPU) [default: 1]
+    #[arg(short = 'j', long, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Number of images of a document to convert, strip or thumbnail at once
+    /// (0 = one per CPU) [default: 1]
+    #[arg(long, value_name = "N")]
+    image_jobs: Option<usize>,
+
+    /// Do not show a progress bar for multi-document runs
+    #[arg(long)]
+    no_progress: bool,
+
+    /// Process at most N documents per run (applied after --sort; with --incremental,
+    /// unchanged documents do not count)
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Order in which documents are processed (defaults to discovery order)
+    #[arg(long, value_enum)]
+    sort: Option<SortOrder>,
+
+    /// Skip images byte-identical to one already written in this run
+    #[arg(long)]
+    dedupe: bool,
+
+    /// Also skip images extracted by earlier runs, as recorded in this database file
+    /// (created if missing; new images are added to it). Implies --dedupe.
+    #[arg(long, value_name = "FILE")]
+    dedupe_db: Option<PathBuf>,
+
+    /// Skip documents unchanged (same size and modification time) since an earlier run,
+    /// as recorded in this state file [default: word-gif-extractor.state]
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = INCREMENTAL_STATE_FILE
+    )]
+    incremental: Option<PathBuf>,
+
+    /// Continue an interrupted run: skip the documents it finished and delete the files
+    /// it wrote for the others, as recorded in this journal (deleted once a run finishes
+    /// without failures) [default: word-gif-extractor.resume]
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = RESUME_JOURNAL_FILE,
+        conflicts_with_all = ["output_archive", "stdout", "estimate"]
+    )]
+    resume: Option<PathBuf>,
+
+    /// Hard-link byte-identical images to the copy already written in this run
+    #[arg(long)]
+    hardlink_duplicates: bool,
+
+    /// Permission bits for extracted files, in octal (e.g. 644). Unix only, subject to umask.
+    #[arg(long, value_name = "MODE", value_parser = output::parse_mode)]
+    chmod: Option<u32>,
+
+    /// Permission bits for created directories, in octal (e.g. 755). Unix only, subject to umask.
+    #[arg(long, value_name = "MODE", value_parser = output::parse_mode)]
+    dir_mode: Option<u32>,
+
+    /// Numeric owner for created files and directories (UID[:GID]). Unix only.
+    #[arg(long, value_name = "UID[:GID]", value_parser = output::parse_owner)]
+    chown: Option<output::Owner>,
+
+    /// Give extracted files the modification time stored for them in the document's
+    /// archive, or else the document's own, instead of the time of extraction
+    #[arg(long)]
+    preserve_timestamps: bool,
+
+    /// Run CMD for each extracted image, e.g. "upscale {path}". Placeholders: {path},
+    /// {name} (file name), {doc} (source document) and {format} (extension)
+    #[arg(
+        long,
+        value_name = "CMD",
+        value_parser = hook::parse_exec_command,
+        conflicts_with_all = ["output_archive", "stdout", "estimate"]
+    )]
+    exec: Option<hook::ExecCommand>,
+
+    /// Limit the average write rate (e.g. 10M for 10 MiB/s) to leave I/O for other users
+    #[arg(long, value_name = "SIZE", value_parser = common::parse_size)]
+    throttle_rate: Option<u64>,
+
+    /// Pause between documents (e.g. 200ms, 1s)
+    #[arg(long, value_name = "DURATION", value_parser = common::parse_duration)]
+    throttle_sleep: Option<Duration>,
+
+    /// Run with background CPU and I/O priority
+    #[arg(long)]
+    low_priority: bool,
+
+    /// Write a manifest of every extracted (or skipped) image with its SHA-256: CSV if FILE
+    /// ends in .csv, JSON otherwise
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+
+    /// Write the alt text and caption of each DOCX image to FILE (CSV if it ends in .csv,
+    /// JSON otherwise)
+    #[arg(long, value_name = "FILE")]
+    export_captions: Option<PathBuf>,
+
+    /// Print a JSON report of every document and image instead of progress messages
+    #[arg(long)]
+    json: bool,
+
+    /// Print a breakdown of the run: images and bytes per format, images per document,
+    /// skipped documents with the reason and the elapsed time (added to --json as "stats")
+    #[arg(long)]
+    stats: bool,
+
+    /// Print the time spent opening, scanning, reading, converting and writing, and the
+    /// slowest documents (added to --json as "timings")
+    #[arg(long)]
+    timings: bool,
+
+    /// Process everything without writing, then print how many images and bytes each
+    /// document would produce, the total and the free space at the destination
+    #[arg(long, conflicts_with = "stdout")]
+    estimate: bool,
+
+    /// Language of the summary and skip messages (defaults to the language of the locale,
+    /// or English). JSON, manifests and reports are always in English.
+    #[arg(long, value_enum, value_name = "LANG")]
+    lang: Option<Lang>,
+
+    /// Print this line instead of "Processing complete! ..." at the end of the run, with
+    /// {images}, {documents}, {processed}, {failed}, {drm}, {duplicates}, {existing},
+    /// {unchanged} and {resumed} filled in ({{ and }} for literal braces)
+    #[arg(long, value_name = "TEMPLATE", value_parser = messages::parse_summary_template, conflicts_with_all = ["json", "estimate"])]
+    summary_template: Option<SummaryTemplate>,
+
+    /// Exit with status 2 for per-file errors that did not fail their document: damaged
+    /// or unconvertible images that were skipped, and documents skipped as DRM-protected
+    #[arg(long)]
+    strict: bool,
+
+    /// Stop at the first document that fails to process
+    #[arg(long, overrides_with = "keep_going")]
+    fail_fast: bool,
+
+    /// Process the remaining documents after one fails (the default; overrides an
+    /// earlier --fail-fast)
+    #[arg(long, overrides_with = "fail_fast")]
+    keep_going: bool,
+
+    /// Write a JUnit XML report with one test case per document
+    #[arg(long, value_name = "FILE")]
+    report_junit: Option<PathBuf>,
+
+    /// Write every failed document with its type and error chain to this JSON file
+    #[arg(long, value_name = "FILE")]
+    error_log: Option<PathBuf>,
+
+    /// Read option defaults from this file instead of ./word-gif-extractor.toml
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
 }
 
-/// Supported document types
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum DocumentType {
-    Docx,
-    Epub,
+impl Args {
+    /// Fills in options not given on the command line from a configuration file.
+    /// A boolean the file enables is switched off again by its `--no-` flag, and
+    /// `--exclude` patterns replace those of the file.
+    fn apply_config(&mut self, config: Config) {
+        self.output = self.output.take().or(config.output);
+        self.formats = self.formats.take().or(config.formats);
+        self.on_conflict = self.on_conflict.or(config.on_conflict);
+        self.group_by = self.group_by.or(config.group_by);
+        self.route = self.route.take().or(config.route);
+        self.lang = self.lang.or(config.lang);
+        self.summary_template = self.summary_template.take().or(config.summary_template);
+        self.name_template = self.name_template.take().or(config.name_template);
+        self.title = self.title.take().or(config.title);
+        self.author = self.author.take().or(config.author);
+        self.subject = self.subject.take().or(config.subject);
+        self.series = self.series.take().or(config.series);
+        self.publisher = self.publisher.take().or(config.publisher);
+        self.language = self.language.take().or(config.language);
+        self.isbn = self.isbn.take().or(config.isbn);
+        self.jobs = self.jobs.or(config.jobs);
+        self.recursive = flag(self.recursive, self.no_recursive, config.recursive);
+        if self.exclude.is_empty() {
+            self.exclude = config.exclude.unwrap_or_default();
+        }
+        self.extra_format
+            .extend(config.extra_formats.unwrap_or_default());
+        self.cover_only = flag(self.cover_only, self.no_cover_only, config.cover_only);
+        self.cover_fallback = self.cover_fallback.or(config.cover_fallback);
+        // --nested implies --recurse-embedded
+        self.recurse_embedded = flag(
+            self.recurse_embedded || self.nested.is_some(),
+            self.no_recurse_embedded,
+            config.recurse_embedded,
+        );
+        self.doc_thumbnail = flag(
+            self.doc_thumbnail,
+            self.no_doc_thumbnail,
+            config.doc_thumbnail,
+        );
+        self.treat_as_zip = flag(self.treat_as_zip, self.no_treat_as_zip, config.treat_as_zip);
+        self.skip_macro_enabled = flag(
+            self.skip_macro_enabled,
+            self.no_skip_macro_enabled,
+            config.skip_macro_enabled,
+        );
+    }
+
+    /// Standard output holds one image per run, so `--stdout` needs a selection that picks
+    /// at most one image of the document: its cover or a single `--only` position
+    fn check_stdout(&self) -> Result<()> {
+        if !self.stdout || self.cover_only {
+            return Ok(());
+        }
+        match &self.only {
+            None => anyhow::bail!("--stdout requires --cover-only or --only with one position"),
+            Some(only) if only.single_position().is_none() => anyhow::bail!(
+                "--stdout can only write one image, but --only selects more than one position \
+                 or selects by name; pass a single position such as --only 3"
+            ),
+            Some(_) => Ok(()),
+        }
+    }
 }
 
-/// Determines the document type based on file extension
-fn get_document_type(path: &Path) -> Option<DocumentType> {
-    path.extension()
-        .and_then(|ext| ext.to_str())
-        .map(|ext| ext.to_lowercase())
-        .and_then(|ext| match ext.as_str() {
-            "docx" => Some(DocumentType::Docx),
-            "epub" => Some(DocumentType::Epub),
-            _ => None,
-        })
+/// A boolean option from its flag, its `--no-` flag and the configuration file, in
+/// that order of precedence
+fn flag(on: bool, off: bool, config: Option<bool>) -> bool {
+    on || (!off && config.unwrap_or(false))
 }
 
-/// Checks if a path is a supported document type
-fn is_supported_document(path: &Path) -> bool {
-    get_document_type(path).is_some()
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check documents for extractability without writing anything
+    Validate(ValidateArgs),
+    /// Compare the images embedded in two versions of a document
+    Compare(CompareArgs),
+    /// Download the latest release and replace this executable with it, after comparing
+    /// its checksum with the release's SHA256SUMS (releases are not signed)
+    SelfUpdate(SelfUpdateArgs),
 }
 
-/// Processes a single file based on its type
-fn process_file(
-    input_path: &Path,
-    output_base_dir: &Path,
-    allowed_extensions: &HashSet<&str>,
-    cover_only: bool,
-    cover_fallback: bool,
-    epub_filter: &EpubFilter,
-) -> Result<usize> {
-    match get_document_type(input_path) {
-        Some(DocumentType::Docx) => {
-            docx::process_file(input_path, output_base_dir, allowed_extensions)
-        }
-        Some(DocumentType::Epub) => epub::process_file(
-            input_path,
-            output_base_dir,
-            allowed_extensions,
-            cover_only,
-            cover_fallback,
-            epub_filter,
-        ),
-        None => {
-            anyhow::bail!(
-                "Unsupported file type: {}. Supported types: .docx, .epub",
-                input_path.display()
-            );
+#[derive(clap::Args, Debug)]
+struct SelfUpdateArgs {
+    /// Only report whether a newer release is available
+    #[arg(long)]
+    check: bool,
+
+    /// Reinstall even if the installed version is current
+    #[arg(long, conflicts_with = "check")]
+    force: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct CompareArgs {
+    /// Earlier version of the document
+    old: PathBuf,
+
+    /// Later version of the document
+    new: PathBuf,
+
+    /// Also compare how images look, matching renamed images that were re-encoded
+    #[arg(long)]
+    perceptual: bool,
+
+    /// Print results as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ValidateArgs {
+    /// Documents or directories to check
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Recursively search directories for .docx/.doc/.pptx/.xlsx/.odt/.epub/.mobi/.fb2/.rtf/.html/.pdf files
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Print results as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// Processing order for discovered documents
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum SortOrder {
+    /// Alphabetical by path
+    Name,
+    /// Most recently modified first
+    Newest,
+    /// Least recently modified first
+    Oldest,
+    /// Largest file first
+    Largest,
+    /// Smallest file first
+    Smallest,
+}
+
+/// Sorts the document list according to the requested processing order.
+/// Documents whose metadata cannot be read sort last for size/time orders.
+fn sort_documents(documents: &mut Vec<PathBuf>, order: SortOrder) {
+    match order {
+        SortOrder::Name => documents.sort(),
+        SortOrder::Newest => sort_by_metadata(documents, |m| m.modified().ok(), true),
+        SortOrder::Oldest => sort_by_metadata(documents, |m| m.modified().ok(), false),
+        SortOrder::Largest => sort_by_metadata(documents, |m| Some(m.len()), true),
+        SortOrder::Smallest => sort_by_metadata(documents, |m| Some(m.len()), false),
+    }
+}
+
+/// Stable sort of documents by a key derived from their filesystem metadata
+fn sort_by_metadata<K: Ord>(
+    documents: &mut Vec<PathBuf>,
+    key: impl Fn(&fs::Metadata) -> Option<K>,
+    descending: bool,
+) {
+    let mut keyed: Vec<(Option<K>, PathBuf)> = documents
+        .drain(..)
+        .map(|p| (fs::metadata(&p).ok().and_then(|m| key(&m)), p))
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) if descending => b.cmp(a),
+        (Some(a), Some(b)) => a.cmp(b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+    documents.extend(keyed.into_iter().map(|(_, p)| p));
+}
+
+/// Whether an --output-archive path names a tarball rather than a zip archive
+fn is_tar_path(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    name.ends_with(".tar") || name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+/// Runs the `validate` subcommand. Exits with status 1 if any document has errors.
+fn run_validate(args: ValidateArgs) -> Result<()> {
+    let mut documents = Vec::new();
+    for path in &args.paths {
+        if path.is_dir() {
+            let scan = ScanOptions {
+                recursive: args.recursive,
+                ..Default::default()
+            };
+            collect_documents(path, &scan, &mut documents);
+        } else {
+            // Missing and unsupported files are reported by the validator
+            documents.push(path.clone());
         }
     }
+
+    let results: Vec<_> = documents
+        .iter()
+        .map(|path| validate::validate_document(path))
+        .collect();
+
+    if args.json {
+        print!("{}", validate::to_json(&results).to_pretty_string());
+    } else {
+        validate::print_text(&results);
+    }
+
+    if results.iter().any(|r| r.has_errors()) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Runs the `compare` subcommand. Exits with status 1 if the documents' images differ.
+fn run_compare(args: CompareArgs) -> Result<()> {
+    let comparison = compare::compare_documents(&args.old, &args.new, args.perceptual)?;
+
+    if args.json {
+        print!(
+            "{}",
+            comparison.to_json(&args.old, &args.new).to_pretty_string()
+        );
+    } else {
+        comparison.print_text(&args.old, &args.new);
+    }
+
+    if comparison.has_differences() {
+        std::process::exit(1);
+    }
+    Ok(())
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    // clap exits with 2 on usage errors, which is the status for failed documents here
+    let mut args = Args::try_parse().unwrap_or_else(|e| {
+        let _ = e.print();
+        std::process::exit(if e.use_stderr() { 1 } else { 0 });
+    });
+
+    match args.command.take() {
+        Some(Command::Validate(validate_args)) => return run_validate(validate_args),
+        Some(Command::Compare(compare_args)) => return run_compare(compare_args),
+        Some(Command::SelfUpdate(update_args)) => {
+            return update::self_update(update_args.check, update_args.force);
+        }
+        None => {}
+    }
 
-    // Combine positional and named inputs
-    let all_inputs: Vec<PathBuf> = args.inputs.into_iter().chain(args.named_inputs).collect();
+    args.apply_config(Config::load(args.config.as_deref())?);
+    args.check_stdout()?;
 
-    if all_inputs.is_empty() {
+    // Combine positional and named inputs with any listed in --files-from
+    let mut all_inputs: Vec<PathBuf> = args.inputs.into_iter().chain(args.named_inputs).collect();
+    if let Some(list) = &args.files_from {
+        let data = if list.as_os_str() == "-" {
+            let mut data = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut data)
+                .context("Failed to read file list from stdin")?;
+            data
+        } else {
+            fs::read(list)
+                .with_context(|| format!("Failed to read file list {}", list.display()))?
+        };
+        all_inputs.extend(parse_file_list(&data));
+    } else if all_inputs.is_empty() && !args.stdin {
         anyhow::bail!("At least one input path is required");
     }
 
+    // The piped document is named `stdin.{type}`, so its images are `stdin_1.png`, ...
+    let stdin_document = match &args.stdin_type {
+        Some(extension) => {
+            let name = PathBuf::from(format!("stdin.{}", extension.trim_start_matches('.')));
+            if get_document_type(&name).is_none() && !args.treat_as_zip {
+                anyhow::bail!(
+                    "Unsupported --type '{}'. Supported types: {}",
+                    extension,
+                    SUPPORTED_DOCUMENTS
+                );
+            }
+            let mut data = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut data)
+                .context("Failed to read document from stdin")?;
+            Some((name, data))
+        }
+        None => None,
+    };
+
     let output_dir = args.output.unwrap_or_else(|| PathBuf::from("."));
+    // Progress messages would mix with the image bytes on stdout, and an estimate
+    // extracts nothing for them to announce
+    common::set_quiet(args.json || args.stdout || args.estimate);
+    messages::set_lang(args.lang.unwrap_or_else(Lang::from_env));
 
     // Determine allowed extensions
     let mut target_extensions = HashSet::new();
     if let Some(formats) = &args.formats {
         for fmt in formats {
-            let normalized = normalize_format(fmt);
+            let normalized = normalize_format(fmt, &args.extra_format);
             for ext in normalized {
                 target_extensions.insert(ext);
             }
         }
     }
 
-    // Fallback if empty or no formats specified
-    if target_extensions.is_empty() {
-        target_extensions = get_supported_extensions();
-    }
-
-    // Create EPUB filter from CLI args
-    let epub_filter = EpubFilter {
-        title: args.title,
-        author: args.author,
+    // An empty set (no --formats) means every supported format
+    let options = ExtractOptions {
+        allowed_extensions: target_extensions,
+        extra_formats: args.extra_format,
+        cover_only: args.cover_only,
+        cover_fallback: args.cover_fallback,
+        document_filter: DocumentFilter {
+            title: args.title,
+            author: args.author,
+            subject: args.subject,
+            series: args.series,
+            publisher: args.publisher,
+            language: args.language,
+            isbn: args.isbn,
+        },
+        recurse_embedded: args.recurse_embedded,
+        max_embed_depth: args.nested.flatten(),
+        only_mismatched: args.only_mismatched,
+        trust_extension: args.trust_extension,
+        prompt_password: args.password.is_none() && std::io::stdin().is_terminal(),
+        password: args.password,
+        referenced_only: args.referenced_only,
+        sections: args.sections,
+        tag_notes: args.tag_notes,
+        by_chapter: args.by_chapter,
+        embedded_objects: args.embedded_objects || args.embedded_object_files,
+        embedded_object_files: args.embedded_object_files,
+        extract_fonts: args.extract_fonts,
+        skip_similar_covers: args.skip_similar_covers,
+        rendition: args.rendition.unwrap_or_default(),
+        doc_thumbnail: args.doc_thumbnail,
+        rasterize_thumbnail: args.rasterize_thumbnail,
+        treat_as_zip: args.treat_as_zip,
+        skip_macro_enabled: args.skip_macro_enabled,
+        limits: ResourceLimits {
+            max_image_size: args.max_image_size.unwrap_or(DEFAULT_MAX_IMAGE_SIZE),
+            max_total_size: args.max_total_size.unwrap_or(DEFAULT_MAX_TOTAL_SIZE),
+            max_entries: args.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES),
+        },
+        salvage: args.salvage,
+        extractors: Default::default(),
     };
 
-    let mut total_images = 0usize;
-    let mut total_documents = 0usize;
-
+    // Gather every candidate document up front so ordering and limits apply to the whole run
+    let mut documents: Vec<PathBuf> = Vec::new();
+    let scan = ScanOptions {
+        recursive: args.recursive || args.max_depth.is_some(),
+        treat_as_zip: args.treat_as_zip,
+        exclude: args.exclude.clone(),
+        max_depth: args.max_depth.map(|depth| depth as usize),
+        follow_symlinks: args.follow_symlinks,
+        same_filesystem: args.same_filesystem,
+        ..Default::default()
+    };
     for input_path_buf in &all_inputs {
         if !input_path_buf.exists() {
             eprintln!(
@@ -157,104 +1010,512 @@ fn main() -> Result<()> {
         }
 
         if input_path_buf.is_file() {
-            match process_file(
-                input_path_buf,
-                &output_dir,
-                &target_extensions,
-                args.cover_only,
-                args.cover_fallback,
-                &epub_filter,
-            ) {
-                Ok(count) => {
-                    total_images += count;
-                    if count > 0 {
-                        total_documents += 1;
-                    }
-                }
-                Err(e) => eprintln!("Error processing {}: {}", input_path_buf.display(), e),
-            }
+            documents.push(input_path_buf.clone());
         } else if input_path_buf.is_dir() {
-            if args.recursive {
-                for entry in WalkDir::new(input_path_buf) {
-                    let entry = match entry {
-                        Ok(e) => e,
-                        Err(e) => {
-                            eprintln!("Warning: Could not access path: {}", e);
-                            continue;
-                        }
-                    };
-                    let path = entry.path();
-                    if path.is_file() && is_supported_document(path) {
-                        match process_file(
-                            path,
-                            &output_dir,
-                            &target_extensions,
-                            args.cover_only,
-                            args.cover_fallback,
-                            &epub_filter,
-                        ) {
-                            Ok(count) => {
-                                total_images += count;
-                                if count > 0 {
-                                    total_documents += 1;
-                                }
-                            }
-                            Err(e) => eprintln!("Error processing {}: {}", path.display(), e),
-                        }
-                    }
-                }
-            } else {
-                let entries = match fs::read_dir(input_path_buf) {
-                    Ok(entries) => entries,
-                    Err(e) => {
-                        eprintln!(
-                            "Warning: Could not read directory {}: {}",
-                            input_path_buf.display(),
-                            e
-                        );
-                        continue;
-                    }
-                };
-                for entry in entries {
-                    let entry = match entry {
-                        Ok(e) => e,
-                        Err(e) => {
-                            eprintln!("Warning: Could not access entry: {}", e);
-                            continue;
-                        }
-                    };
-                    let path = entry.path();
-                    if path.is_file() && is_supported_document(&path) {
-                        match process_file(
-                            &path,
-                            &output_dir,
-                            &target_extensions,
-                            args.cover_only,
-                            args.cover_fallback,
-                            &epub_filter,
-                        ) {
-                            Ok(count) => {
-                                total_images += count;
-                                if count > 0 {
-                                    total_documents += 1;
-                                }
-                            }
-                            Err(e) => eprintln!("Error processing {}: {}", path.display(), e),
-                        }
-                    }
-                }
-            }
+            collect_documents(input_path_buf, &scan, &mut documents);
         }
     }
 
-    if total_images > 0 {
+    let repeated = remove_duplicate_documents(&mut documents);
+    if repeated > 0 && !common::is_quiet() {
         println!(
-            "Processing complete! Extracted {} images from {} document(s).",
-            total_images, total_documents
+            "{}",
+            messages::text(Message::RepeatedDocuments, &[("count", &repeated)])
         );
-    } else {
-        println!("Processing complete! No images found.");
     }
 
+    if let Some(order) = args.sort {
+        sort_documents(&mut documents, order);
+    }
+
+    if !cfg!(windows) && args.long_paths {
+        eprintln!("Warning: --long-paths only has an effect on Windows; ignoring.");
+    }
+
+    if !cfg!(unix) && (args.chmod.is_some() || args.dir_mode.is_some() || args.chown.is_some()) {
+        eprintln!("Warning: --chmod, --dir-mode and --chown are only supported on Unix; ignoring.");
+    }
+
+    if args.hardlink_duplicates && (args.output_archive.is_some() || args.cas_store.is_some()) {
+        eprintln!(
+            "Warning: --hardlink-duplicates has no effect with --output-archive or --cas-store."
+        );
+    }
+
+    if args.preserve_timestamps && args.output_archive.is_some() {
+        eprintln!("Warning: --preserve-timestamps has no effect with --output-archive.");
+    }
+
+    let output_options = OutputOptions {
+        link_duplicates: args.hardlink_duplicates,
+        file_mode: args.chmod,
+        dir_mode: args.dir_mode,
+        owner: args.chown,
+        on_conflict: args.on_conflict.unwrap_or_default(),
+        split_size: args.split_size,
+        max_write_rate: args.throttle_rate,
+        record_manifest: args.manifest.is_some()
+            || args.json
+            || args.stats
+            || args.estimate
+            || args.export_captions.is_some(),
+        group_by: args.group_by,
+        routes: args.route,
+        cas_link: args.cas_link,
+        name_template: args.name_template,
+        keep_paths: args.keep_paths,
+        original_names: args.original_names,
+        name_from_alt: args.name_from_alt,
+        numbering: Numbering {
+            pad_width: args.pad_width.unwrap_or(0),
+            start_index: args.start_index.unwrap_or(1),
+        },
+        dedupe: args.dedupe,
+        filter: ImageFilter {
+            min_size: args.min_size,
+            max_size: args.max_size,
+            min_width: args.min_width,
+            min_height: args.min_height,
+            max_width: args.max_width,
+            max_height: args.max_height,
+            orientation: args.orientation,
+            min_aspect: args.min_aspect,
+            max_aspect: args.max_aspect,
+        },
+        convert_to: args.convert_to,
+        color_profile: args.color_profile,
+        split_gif_frames: args.split_gif_frames,
+        animated_to: args.animated_to,
+        optimize_gif: args.optimize_gif,
+        thumbnail_size: args.thumbnails,
+        strip_metadata: args.strip_metadata,
+        largest: args.largest.map(|n| n as usize),
+        only: args.only.clone(),
+        long_paths: args.long_paths,
+        name_form: args.normalize_names,
+        ascii_names: args.ascii_names,
+        preserve_timestamps: args.preserve_timestamps,
+        image_threads: match args.image_jobs {
+            Some(0) => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            jobs => jobs.unwrap_or(1),
+        },
+        exec: args.exec,
+    };
+    // Where the images would go, for the free space an estimate compares with
+    let destination = match (&args.output_archive, &args.cas_store) {
+        (Some(archive), _) if !args.archive_per_document => {
+            archive.parent().unwrap_or(Path::new(".")).to_path_buf()
+        }
+        (Some(dir), _) | (None, Some(dir)) => dir.clone(),
+        (None, None) => output_dir.clone(),
+    };
+    let target = match (args.output_archive, args.cas_store) {
+        _ if args.estimate => OutputTarget::DryRun(output_dir),
+        (Some(archive_dir), _) if args.archive_per_document => {
+            OutputTarget::ZipPerDocument(archive_dir)
+        }
+        (Some(archive_path), _) if is_tar_path(&archive_path) => OutputTarget::Tar(archive_path),
+        (Some(archive_path), _) => OutputTarget::Zip(archive_path),
+        (None, Some(store_dir)) => OutputTarget::Cas(store_dir),
+        (None, None) if args.stdout => OutputTarget::Stdout,
+        (None, None) => OutputTarget::Directory(output_dir),
+    };
+    let mut extractor = Extractor::new(target, options)
+        .observer(Arc::new(FailurePrinter))
+        .output_options(output_options)
+        .jobs(args.jobs.unwrap_or(1))
+        .progress(!args.no_progress && !common::is_quiet());
+    if let Some(pause) = args.throttle_sleep {
+        extractor = extractor.pause_between_documents(pause);
+    }
+    if let Some(db) = &args.dedupe_db {
+        extractor = extractor.dedupe_db(db.clone());
+    }
+    if let Some(state) = &args.incremental {
+        extractor = extractor.incremental(state.clone());
+    }
+    if let Some(journal) = &args.resume {
+        extractor = extractor.resume(journal.clone());
+    }
+    let fail_fast = args.fail_fast && !args.keep_going;
+    extractor = extractor.fail_fast(fail_fast);
+    if let Some(limit) = args.limit {
+        extractor = extractor.limit(limit);
+    }
+    if args.mirror {
+        let roots = all_inputs.iter().filter(|path| path.is_dir()).cloned();
+        extractor = extractor.mirror(roots.collect());
+    }
+
+    if args.low_priority
+        && let Err(e) = common::lower_process_priority()
+    {
+        eprintln!("Warning: {}", e);
+    }
+
+    let started = Instant::now();
+    let run = match &stdin_document {
+        Some((name, data)) => extractor.extract_from_bytes(data, name)?,
+        None => extractor.extract_documents(&documents)?,
+    };
+    if args.estimate {
+        let estimate = report::Estimate::new(&run.documents, run.manifest.as_ref());
+        let available = common::available_space(&destination);
+        if args.json {
+            print!("{}", estimate.to_json(available).to_pretty_string());
+        } else {
+            print!("{}", estimate.render(available));
+        }
+        return Ok(());
+    }
+    if let (Some(manifest_path), Some(manifest)) = (&args.manifest, &run.manifest) {
+        manifest.write(manifest_path)?;
+    }
+    if let (Some(captions_path), Some(manifest)) = (&args.export_captions, &run.manifest) {
+        manifest.write_captions(captions_path)?;
+    }
+
+    if let Some(report_path) = &args.report_junit {
+        report::write_junit(report_path, &run.documents)?;
+    }
+    if let Some(log_path) = &args.error_log {
+        report::write_error_log(log_path, &run.documents)?;
+    }
+
+    let stats = args
+        .stats
+        .then(|| report::Stats::new(&run.documents, run.manifest.as_ref(), started.elapsed()));
+    let timings = args
+        .timings
+        .then(|| timings::Timings::new(&run.documents, started.elapsed()));
+    if args.json {
+        print!(
+            "{}",
+            report::render_json(
+                &run.documents,
+                run.manifest.as_ref(),
+                stats.as_ref(),
+                timings.as_ref()
+            )
+            .to_pretty_string()
+        );
+    } else if let Some(template) = &args.summary_template {
+        // With --stdout the images themselves go to standard output
+        let line = template.render(&summary(&run));
+        if args.stdout {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    } else if args.stdout {
+        if run.images() == 0 {
+            eprintln!("{}", messages::text(Message::NoImageOnStdout, &[]));
+        }
+    } else if run.images() > 0 {
+        println!(
+            "{}",
+            messages::text(
+                Message::Complete,
+                &[
+                    ("images", &run.images()),
+                    ("documents", &run.documents_with_images())
+                ]
+            )
+        );
+    } else if run.duplicates + run.existing == 0 {
+        println!("{}", messages::text(Message::NoImages, &[]));
+    }
+    if run.duplicates > 0 && !common::is_quiet() {
+        println!(
+            "{}",
+            messages::text(Message::DuplicateImages, &[("count", &run.duplicates)])
+        );
+    }
+    if run.existing > 0 && !common::is_quiet() {
+        println!(
+            "{}",
+            messages::text(Message::ExistingImages, &[("count", &run.existing)])
+        );
+    }
+    if run.unchanged > 0 && !common::is_quiet() {
+        println!(
+            "{}",
+            messages::text(Message::UnchangedDocuments, &[("count", &run.unchanged)])
+        );
+    }
+    if run.resumed > 0 && !common::is_quiet() {
+        println!(
+            "{}",
+            messages::text(Message::ResumedDocuments, &[("count", &run.resumed)])
+        );
+    }
+    // The errors scrolled past among the progress messages, so list them together
+    let failed: Vec<_> = run.failures().collect();
+    if !failed.is_empty() && !args.json {
+        eprintln!(
+            "{}",
+            messages::text(Message::FailedDocuments, &[("count", &failed.len())])
+        );
+        for result in &failed {
+            eprintln!(
+                "  {}: {}",
+                result.path.display(),
+                result.error.as_deref().unwrap_or_default()
+            );
+        }
+        let not_processed = documents
+            .len()
+            .saturating_sub(run.documents.len() + run.unchanged + run.resumed + run.limited);
+        if fail_fast && stdin_document.is_none() && not_processed > 0 {
+            eprintln!(
+                "{}",
+                messages::text(Message::StoppedAfterFailure, &[("count", &not_processed)])
+            );
+        }
+    }
+    let salvaged: Vec<_> = run.damaged().collect();
+    if !salvaged.is_empty() && !args.json {
+        eprintln!(
+            "{}",
+            messages::text(Message::DamagedDocuments, &[("count", &salvaged.len())])
+        );
+        for result in &salvaged {
+            for (entry, error) in &result.damaged {
+                eprintln!("  {}: {}: {}", result.path.display(), entry, error);
+            }
+        }
+    }
+    let drm_protected = run.drm_protected().count();
+    if drm_protected > 0 && !common::is_quiet() {
+        println!(
+            "{}",
+            messages::text(Message::DrmDocuments, &[("count", &drm_protected)])
+        );
+    }
+    // With --stdout the images themselves go to standard output
+    match &stats {
+        Some(stats) if args.stdout => eprint!("{}", stats.render()),
+        Some(stats) if !args.json => print!("{}", stats.render()),
+        _ => {}
+    }
+    match &timings {
+        Some(timings) if args.stdout => eprint!("{}", timings.render()),
+        Some(timings) if !args.json => print!("{}", timings.render()),
+        _ => {}
+    }
+
+    let status = exit_status(&run, args.strict);
+    if status != 0 {
+        std::process::exit(status);
+    }
     Ok(())
 }
+
+/// Prints each document that failed or was skipped as DRM-protected when it finishes,
+/// so the failure shows among the progress messages of its document
+struct FailurePrinter;
+
+impl ProgressObserver for FailurePrinter {
+    fn document_finished(&self, result: &DocumentResult) {
+        if let Some(error) = result.error_chain.first() {
+            eprintln!("Error processing {}: {}", result.path.display(), error);
+        } else if let Some(reason) = &result.skipped {
+            eprintln!(
+                "{}",
+                messages::text(
+                    Message::SkippingDocument,
+                    &[("path", &result.path.display()), ("reason", reason)]
+                )
+            );
+        }
+    }
+}
+
+/// Counts of a finished run for --summary-template
+fn summary(run: &ExtractReport) -> messages::Summary {
+    messages::Summary {
+        images: run.images(),
+        documents: run.documents_with_images(),
+        processed: run.documents.len(),
+        failed: run.failures().count(),
+        drm: run.drm_protected().count(),
+        duplicates: run.duplicates,
+        existing: run.existing,
+        unchanged: run.unchanged,
+        resumed: run.resumed,
+    }
+}
+
+/// Exit status of a finished run: failures first, then DRM-protected documents, then runs
+/// that found no image in the documents they processed. With `strict`, per-file
+/// errors that did not fail their document (damaged or unconvertible images skipped, DRM
+/// protection) are failures too. A run that only skipped unchanged or already finished
+/// documents, or images whose output already exists, succeeds.
+fn exit_status(run: &ExtractReport, strict: bool) -> i32 {
+    let drm_protected = run.drm_protected().next().is_some();
+    let damaged = run.damaged().next().is_some();
+    if run.failures().next().is_some() || (strict && (drm_protected || damaged)) {
+        EXIT_DOCUMENTS_FAILED
+    } else if drm_protected {
+        EXIT_DRM_PROTECTED
+    } else if run.images() + run.duplicates + run.existing == 0
+        && (!run.documents.is_empty() || run.unchanged + run.resumed == 0)
+    {
+        EXIT_NO_IMAGES
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(flags: &[&str]) -> Args {
+        let argv = ["word-image-extractor"]
+            .iter()
+            .chain(flags)
+            .chain(&["book.epub"]);
+        Args::try_parse_from(argv).unwrap()
+    }
+
+    #[test]
+    fn test_command_line_overrides_config() {
+        let config =
+            || Config::parse("recursive = true\ncover-only = true\nexclude = [\".git\"]").unwrap();
+
+        let mut defaults = args(&[]);
+        defaults.apply_config(config());
+        assert!(defaults.recursive && defaults.cover_only);
+        assert_eq!(defaults.exclude, [".git".parse::<Glob>().unwrap()]);
+
+        let mut overridden = args(&["--no-recursive", "--exclude", "drafts"]);
+        overridden.apply_config(config());
+        assert!(!overridden.recursive && overridden.cover_only);
+        assert_eq!(overridden.exclude, ["drafts".parse::<Glob>().unwrap()]);
+
+        // The last of a flag and its --no- counterpart wins
+        let mut last = args(&["--no-cover-only", "--cover-only", "-r", "--no-recursive"]);
+        last.apply_config(config());
+        assert!(last.cover_only && !last.recursive);
+    }
+
+    #[test]
+    fn test_nested_implies_recurse_embedded() {
+        let config = || Config::parse("recurse-embedded = true").unwrap();
+
+        let mut nested = args(&["--nested=2"]);
+        nested.apply_config(Config::default());
+        assert!(nested.recurse_embedded);
+
+        let mut off = args(&["--no-recurse-embedded"]);
+        off.apply_config(config());
+        assert!(!off.recurse_embedded);
+
+        let argv = [
+            "word-image-extractor",
+            "--nested",
+            "--no-recurse-embedded",
+            "a.docx",
+        ];
+        assert!(Args::try_parse_from(argv).is_err());
+    }
+
+    #[test]
+    fn test_stdout_needs_single_image_selection() {
+        assert!(args(&["--stdout", "--cover-only"]).check_stdout().is_ok());
+        assert!(args(&["--stdout", "--only", "3"]).check_stdout().is_ok());
+        assert!(args(&["--only", "1-4"]).check_stdout().is_ok());
+
+        assert!(args(&["--stdout"]).check_stdout().is_err());
+        for only in ["1,3", "2-4", "5-", "word/media/image1.png"] {
+            let error = args(&["--stdout", "--only", only])
+                .check_stdout()
+                .unwrap_err();
+            assert!(
+                error.to_string().contains("only write one image"),
+                "{}",
+                only
+            );
+        }
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let report = |documents: Vec<DocumentResult>| ExtractReport {
+            documents,
+            manifest: None,
+            duplicates: 0,
+            existing: 0,
+            unchanged: 0,
+            resumed: 0,
+            limited: 0,
+        };
+        let extracted = || DocumentResult {
+            images: 2,
+            ..Default::default()
+        };
+        let failed = || DocumentResult {
+            error: Some("corrupt".to_string()),
+            ..Default::default()
+        };
+        let drm = || DocumentResult {
+            skipped: Some("DRM-protected (Adobe ADEPT)".to_string()),
+            ..Default::default()
+        };
+        let damaged = || DocumentResult {
+            images: 1,
+            damaged: vec![("word/media/image2.png".to_string(), "bad CRC".to_string())],
+            ..Default::default()
+        };
+
+        for strict in [false, true] {
+            assert_eq!(exit_status(&report(vec![extracted()]), strict), 0);
+            assert_eq!(
+                exit_status(&report(vec![extracted(), failed(), drm()]), strict),
+                EXIT_DOCUMENTS_FAILED
+            );
+            assert_eq!(
+                exit_status(&report(vec![DocumentResult::default()]), strict),
+                EXIT_NO_IMAGES
+            );
+        }
+        assert_eq!(
+            exit_status(&report(vec![extracted(), drm()]), false),
+            EXIT_DRM_PROTECTED
+        );
+        assert_eq!(
+            exit_status(&report(vec![extracted(), drm()]), true),
+            EXIT_DOCUMENTS_FAILED
+        );
+        assert_eq!(exit_status(&report(vec![damaged()]), false), 0);
+        assert_eq!(
+            exit_status(&report(vec![damaged()]), true),
+            EXIT_DOCUMENTS_FAILED
+        );
+
+        // Every document was already done in an earlier run
+        let mut unchanged = report(Vec::new());
+        unchanged.unchanged = 3;
+        assert_eq!(exit_status(&unchanged, false), 0);
+        assert_eq!(exit_status(&report(Vec::new()), false), EXIT_NO_IMAGES);
+
+        // Every image was already written by an earlier run
+        let mut existing = report(vec![DocumentResult::default()]);
+        existing.existing = 1;
+        assert_eq!(exit_status(&existing, false), 0);
+    }
+
+    #[test]
+    fn test_last_of_fail_fast_and_keep_going_wins() {
+        let fail_fast = |flags: &[&str]| {
+            let args = args(flags);
+            args.fail_fast && !args.keep_going
+        };
+        assert!(!fail_fast(&[]));
+        assert!(fail_fast(&["--fail-fast"]));
+        assert!(!fail_fast(&["--keep-going"]));
+        assert!(!fail_fast(&["--fail-fast", "--keep-going"]));
+        assert!(fail_fast(&["--keep-going", "--fail-fast"]));
+    }
+}
@@ -0,0 +1,291 @@
+//! JSON manifest describing every image written (or deliberately skipped) during a run
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::common::{Digest, to_hex};
+use crate::json::Json;
+
+/// What happened to an image
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryStatus {
+    Written,
+    /// Not written, with the reason
+    Skipped(String),
+}
+
+/// One image in the manifest
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    /// Document the image came from
+    pub document: PathBuf,
+    /// Where the image was written, if it was
+    pub output: Option<PathBuf>,
+    pub size: u64,
+    pub sha256: Option<Digest>,
+    pub status: EntryStatus,
+    /// Additional processor-specific fields
+    pub fields: Vec<(String, Json)>,
+}
+
+impl ManifestEntry {
+    /// Value of an additional field
+    fn field(&self, key: &str) -> Option<&Json> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub(crate) fn to_json(&self) -> Json {
+        let (status, reason) = match &self.status {
+            EntryStatus::Written => ("written", None),
+            EntryStatus::Skipped(reason) => ("skipped", Some(reason.clone())),
+        };
+        let mut fields = vec![
+            (
+                "document".to_string(),
+                Json::from(self.document.display().to_string()),
+            ),
+            (
+                "output".to_string(),
+                Json::from(self.output.as_ref().map(|p| p.display().to_string())),
+            ),
+            ("size".to_string(), Json::from(self.size)),
+            (
+                "sha256".to_string(),
+                Json::from(self.sha256.as_ref().map(|d| to_hex(d))),
+            ),
+            ("status".to_string(), Json::from(status)),
+        ];
+        if let Some(reason) = reason {
+            fields.push(("reason".to_string(), Json::from(reason)));
+        }
+        fields.extend(self.fields.iter().cloned());
+        Json::Object(fields)
+    }
+}
+
+/// Collected manifest entries for a run
+#[derive(Debug, Default)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn to_json(&self) -> Json {
+        Json::object([(
+            "images",
+            Json::Array(self.entries.iter().map(ManifestEntry::to_json).collect()),
+        )])
+    }
+
+    /// Writes the manifest to `path`: CSV if the file name ends in `.csv`, with one row
+    /// per image and only the common fields, pretty-printed JSON otherwise
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = if is_csv_path(path) {
+            self.to_csv()
+        } else {
+            self.to_json().to_pretty_string()
+        };
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write manifest: {}", path.display()))
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("document,entry,output,size,sha256,status,reason\n");
+        for entry in &self.entries {
+            let (status, reason) = match &entry.status {
+                EntryStatus::Written => ("written", ""),
+                EntryStatus::Skipped(reason) => ("skipped", reason.as_str()),
+            };
+            let original = match entry.field("entry") {
+                Some(Json::String(original)) => original.clone(),
+                _ => String::new(),
+            };
+            let fields = [
+                entry.document.display().to_string(),
+                original,
+                entry
+                    .output
+                    .as_ref()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_default(),
+                entry.size.to_string(),
+                entry.sha256.as_ref().map(|d| to_hex(d)).unwrap_or_default(),
+                status.to_string(),
+                reason.to_string(),
+            ];
+            let quoted: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&quoted.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// Writes the alt text and caption of every written image to `path`: CSV if the file
+    /// name ends in `.csv`, JSON otherwise. Derived files (GIF frames, thumbnails) are left out.
+    pub fn write_captions(&self, path: &Path) -> Result<()> {
+        let rows: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.status == EntryStatus::Written)
+            .filter(|entry| {
+                entry.field("frame_of").is_none() && entry.field("thumbnail_of").is_none()
+            })
+            .filter_map(|entry| {
+                let output = entry.output.as_ref()?.display().to_string();
+                let text = |key| match entry.field(key) {
+                    Some(Json::String(text)) => Some(text.clone()),
+                    _ => None,
+                };
+                Some((
+                    output,
+                    entry.document.display().to_string(),
+                    text("alt_text"),
+                    text("caption"),
+                ))
+            })
+            .collect();
+
+        let content = if is_csv_path(path) {
+            let mut csv = String::from("file,document,alt_text,caption\n");
+            for (file, document, alt_text, caption) in &rows {
+                let fields = [
+                    file.as_str(),
+                    document.as_str(),
+                    alt_text.as_deref().unwrap_or(""),
+                    caption.as_deref().unwrap_or(""),
+                ];
+                let quoted: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+                csv.push_str(&quoted.join(","));
+                csv.push('\n');
+            }
+            csv
+        } else {
+            let images = rows
+                .into_iter()
+                .map(|(file, document, alt_text, caption)| {
+                    Json::object([
+                        ("file", Json::from(file)),
+                        ("document", Json::from(document)),
+                        ("alt_text", Json::from(alt_text)),
+                        ("caption", Json::from(caption)),
+                    ])
+                })
+                .collect();
+            Json::object([("images", Json::Array(images))]).to_pretty_string()
+        };
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write captions: {}", path.display()))
+    }
+}
+
+fn is_csv_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"))
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::sha256;
+    use crate::common::tests::temp_dir;
+
+    #[test]
+    fn test_manifest_json() {
+        let manifest = Manifest {
+            entries: vec![
+                ManifestEntry {
+                    document: PathBuf::from("a.epub"),
+                    output: Some(PathBuf::from("out/a.jpg")),
+                    size: 3,
+                    sha256: Some(sha256(b"abc")),
+                    status: EntryStatus::Written,
+                    fields: vec![("entry".to_string(), Json::from("OEBPS/a.jpg"))],
+                },
+                ManifestEntry {
+                    document: PathBuf::from("b.epub"),
+                    output: None,
+                    size: 3,
+                    sha256: None,
+                    status: EntryStatus::Skipped("near-duplicate cover".to_string()),
+                    fields: vec![("similar_to".to_string(), Json::from("out/a.jpg"))],
+                },
+            ],
+        };
+
+        let json = manifest.to_json().to_string();
+        assert!(
+            json.contains(
+                r#"{"document":"a.epub","output":"out/a.jpg","size":3,"sha256":"ba7816bf"#
+            )
+        );
+        assert!(json.contains(
+            r#""output":null,"size":3,"sha256":null,"status":"skipped","reason":"near-duplicate cover","similar_to":"out/a.jpg"}"#
+        ));
+
+        assert_eq!(
+            manifest.to_csv(),
+            "document,entry,output,size,sha256,status,reason\n\
+             a.epub,OEBPS/a.jpg,out/a.jpg,3,\
+             ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad,written,\n\
+             b.epub,,,3,,skipped,near-duplicate cover\n"
+        );
+    }
+
+    #[test]
+    fn test_write_captions() {
+        let entry = |output: &str, fields: Vec<(&str, Json)>| ManifestEntry {
+            document: PathBuf::from("report.docx"),
+            output: Some(PathBuf::from(output)),
+            size: 3,
+            sha256: None,
+            status: EntryStatus::Written,
+            fields: fields
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        };
+        let manifest = Manifest {
+            entries: vec![
+                entry(
+                    "out/report_1.png",
+                    vec![
+                        ("alt_text", Json::from("Sales, by \"region\"")),
+                        ("caption", Json::from("Figure 1")),
+                    ],
+                ),
+                entry("out/report_2.png", vec![]),
+                entry(
+                    "out/thumbs/report_1.png",
+                    vec![("thumbnail_of", Json::from("out/report_1.png"))],
+                ),
+            ],
+        };
+
+        let dir = temp_dir("captions");
+        let csv_path = dir.join("captions.csv");
+        manifest.write_captions(&csv_path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&csv_path).unwrap(),
+            "file,document,alt_text,caption\n\
+             out/report_1.png,report.docx,\"Sales, by \"\"region\"\"\",Figure 1\n\
+             out/report_2.png,report.docx,,\n"
+        );
+
+        let json_path = dir.join("captions.json");
+        manifest.write_captions(&json_path).unwrap();
+        let json = fs::read_to_string(&json_path).unwrap();
+        assert!(json.contains(r#""caption": "Figure 1""#));
+        assert!(!json.contains("thumbs"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,412 @@
+//! Translated user-facing messages (`--lang`) and the custom summary line
+//! (`--summary-template`)
+//!
+//! Messages are templates with named `{placeholders}`, looked up by [`Message`] in the
+//! language set with [`set_lang`]. Warnings about single images, error chains and every
+//! machine-readable output (JSON, manifests, reports) stay in English.
+
+use clap::ValueEnum;
+use std::fmt::Display;
+use std::sync::RwLock;
+
+use crate::common::{Segment, parse_placeholders};
+
+/// Languages messages are available in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Lang {
+    #[default]
+    En,
+    De,
+    Fr,
+    Es,
+}
+
+impl Lang {
+    /// The language of a POSIX locale name such as `de_DE.UTF-8`, if it is available
+    pub fn from_locale(locale: &str) -> Option<Self> {
+        let language = locale
+            .split(['_', '.', '@', '-'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        Self::from_str(&language, true).ok()
+    }
+
+    /// The language of the environment's locale: the first of `LC_ALL`, `LC_MESSAGES`
+    /// and `LANG` that is set decides, and an unavailable language means English
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|locale| Self::from_locale(&locale))
+            .unwrap_or_default()
+    }
+}
+
+/// Language of the messages printed by the library and the CLI
+static LANG: RwLock<Lang> = RwLock::new(Lang::En);
+
+/// Sets the language of the messages printed from now on
+pub fn set_lang(lang: Lang) {
+    *LANG.write().unwrap_or_else(|e| e.into_inner()) = lang;
+}
+
+/// The language messages are printed in
+pub fn lang() -> Lang {
+    *LANG.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The translated messages. The placeholders each one is given are listed with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    /// Final line of a run with images: `images`, `documents`
+    Complete,
+    /// Final line of a run without images
+    NoImages,
+    /// A `--stdout` run found no image
+    NoImageOnStdout,
+    /// `count` documents were given more than once
+    RepeatedDocuments,
+    /// `--limit` kept `limit` of `total` documents
+    LimitedRun,
+    /// `count` duplicate images were not written
+    DuplicateImages,
+    /// `count` images were not written because their output already exists
+    ExistingImages,
+    /// `count` documents were unchanged since an incremental run
+    UnchangedDocuments,
+    /// `count` documents were finished by the run being resumed
+    ResumedDocuments,
+    /// `count` files of documents the resumed run did not finish were removed
+    UnfinishedFilesRemoved,
+    /// Header of the list of `count` failed documents
+    FailedDocuments,
+    /// `--fail-fast` left `count` documents unprocessed
+    StoppedAfterFailure,
+    /// Header of the list of `count` documents with damaged entries
+    DamagedDocuments,
+    /// `count` documents were DRM-protected
+    DrmDocuments,
+    /// The document at `path` is skipped for `reason`
+    SkippingDocument,
+    /// The macro-enabled document at `path` is skipped
+    SkippingMacroEnabled,
+    /// The macro-enabled embedded document at `path` is skipped
+    SkippingMacroEnabledEmbedded,
+    /// `count` images of the document at `path` are not placed in its text
+    SkippingUnreferenced,
+}
+
+impl Message {
+    /// The message's template in `lang`
+    fn template(self, lang: Lang) -> &'static str {
+        use Lang::*;
+        use Message::*;
+        match (self, lang) {
+            (Complete, En) => {
+                "Processing complete! Extracted {images} images from {documents} document(s)."
+            }
+            (Complete, De) => {
+                "Verarbeitung abgeschlossen! {images} Bilder aus {documents} Dokument(en) extrahiert."
+            }
+            (Complete, Fr) => {
+                "Traitement terminé ! {images} images extraites de {documents} document(s)."
+            }
+            (Complete, Es) => {
+                "¡Procesamiento completado! Se extrajeron {images} imágenes de {documents} documento(s)."
+            }
+            (NoImages, En) => "Processing complete! No images found.",
+            (NoImages, De) => "Verarbeitung abgeschlossen! Keine Bilder gefunden.",
+            (NoImages, Fr) => "Traitement terminé ! Aucune image trouvée.",
+            (NoImages, Es) => "¡Procesamiento completado! No se encontraron imágenes.",
+            (NoImageOnStdout, En) => {
+                "Warning: No image found; nothing was written to standard output."
+            }
+            (NoImageOnStdout, De) => {
+                "Warnung: Kein Bild gefunden; auf die Standardausgabe wurde nichts geschrieben."
+            }
+            (NoImageOnStdout, Fr) => {
+                "Avertissement : aucune image trouvée ; rien n'a été écrit sur la sortie standard."
+            }
+            (NoImageOnStdout, Es) => {
+                "Advertencia: no se encontró ninguna imagen; no se escribió nada en la salida estándar."
+            }
+            (RepeatedDocuments, En) => "Skipping {count} document(s) given more than once.",
+            (RepeatedDocuments, De) => "Überspringe {count} mehrfach angegebene(s) Dokument(e).",
+            (RepeatedDocuments, Fr) => "{count} document(s) indiqué(s) plusieurs fois ignoré(s).",
+            (RepeatedDocuments, Es) => "Se omiten {count} documento(s) indicados más de una vez.",
+            (LimitedRun, En) => "Limiting run to {limit} of {total} document(s).",
+            (LimitedRun, De) => "Lauf auf {limit} von {total} Dokument(en) begrenzt.",
+            (LimitedRun, Fr) => "Exécution limitée à {limit} document(s) sur {total}.",
+            (LimitedRun, Es) => "Ejecución limitada a {limit} de {total} documento(s).",
+            (DuplicateImages, En) => "Skipped {count} duplicate image(s).",
+            (DuplicateImages, De) => "{count} doppelte(s) Bild(er) übersprungen.",
+            (DuplicateImages, Fr) => "{count} image(s) en double ignorée(s).",
+            (DuplicateImages, Es) => "Se omitieron {count} imagen(es) duplicada(s).",
+            (ExistingImages, En) => "Skipped {count} image(s) whose output already exists.",
+            (ExistingImages, De) => {
+                "{count} Bild(er) übersprungen, deren Ausgabe bereits existiert."
+            }
+            (ExistingImages, Fr) => "{count} image(s) dont la sortie existe déjà ignorée(s).",
+            (ExistingImages, Es) => "Se omitieron {count} imagen(es) cuya salida ya existe.",
+            (UnchangedDocuments, En) => "Skipped {count} document(s) unchanged since the last run.",
+            (UnchangedDocuments, De) => {
+                "{count} seit dem letzten Lauf unveränderte(s) Dokument(e) übersprungen."
+            }
+            (UnchangedDocuments, Fr) => {
+                "{count} document(s) inchangé(s) depuis la dernière exécution ignoré(s)."
+            }
+            (UnchangedDocuments, Es) => {
+                "Se omitieron {count} documento(s) sin cambios desde la última ejecución."
+            }
+            (ResumedDocuments, En) => {
+                "Skipped {count} document(s) finished by the interrupted run."
+            }
+            (ResumedDocuments, De) => {
+                "{count} vom unterbrochenen Lauf fertiggestellte(s) Dokument(e) übersprungen."
+            }
+            (ResumedDocuments, Fr) => {
+                "{count} document(s) déjà traité(s) par l'exécution interrompue ignoré(s)."
+            }
+            (ResumedDocuments, Es) => {
+                "Se omitieron {count} documento(s) ya terminados por la ejecución interrumpida."
+            }
+            (UnfinishedFilesRemoved, En) => {
+                "Removed {count} file(s) of documents the interrupted run did not finish."
+            }
+            (UnfinishedFilesRemoved, De) => {
+                "{count} Datei(en) von Dokumenten entfernt, die der unterbrochene Lauf nicht fertiggestellt hat."
+            }
+            (UnfinishedFilesRemoved, Fr) => {
+                "{count} fichier(s) des documents non terminés par l'exécution interrompue supprimé(s)."
+            }
+            (UnfinishedFilesRemoved, Es) => {
+                "Se eliminaron {count} archivo(s) de documentos que la ejecución interrumpida no terminó."
+            }
+            (FailedDocuments, En) => "Failed to process {count} document(s):",
+            (FailedDocuments, De) => "{count} Dokument(e) konnten nicht verarbeitet werden:",
+            (FailedDocuments, Fr) => "Échec du traitement de {count} document(s) :",
+            (FailedDocuments, Es) => "No se pudieron procesar {count} documento(s):",
+            (StoppedAfterFailure, En) => {
+                "Stopped after the first failure; {count} document(s) were not processed."
+            }
+            (StoppedAfterFailure, De) => {
+                "Nach dem ersten Fehler angehalten; {count} Dokument(e) wurden nicht verarbeitet."
+            }
+            (StoppedAfterFailure, Fr) => {
+                "Arrêt après le premier échec ; {count} document(s) n'ont pas été traités."
+            }
+            (StoppedAfterFailure, Es) => {
+                "Detenido tras el primer error; {count} documento(s) no se procesaron."
+            }
+            (DamagedDocuments, En) => "Skipped damaged entries in {count} document(s):",
+            (DamagedDocuments, De) => "Beschädigte Einträge in {count} Dokument(en) übersprungen:",
+            (DamagedDocuments, Fr) => "Entrées endommagées ignorées dans {count} document(s) :",
+            (DamagedDocuments, Es) => "Se omitieron entradas dañadas en {count} documento(s):",
+            (DrmDocuments, En) => "Skipped {count} DRM-protected document(s).",
+            (DrmDocuments, De) => "{count} DRM-geschützte(s) Dokument(e) übersprungen.",
+            (DrmDocuments, Fr) => "{count} document(s) protégé(s) par DRM ignoré(s).",
+            (DrmDocuments, Es) => "Se omitieron {count} documento(s) protegidos con DRM.",
+            (SkippingDocument, En) => "Skipping {path}: {reason}",
+            (SkippingDocument, De) => "Überspringe {path}: {reason}",
+            (SkippingDocument, Fr) => "{path} ignoré : {reason}",
+            (SkippingDocument, Es) => "Omitiendo {path}: {reason}",
+            (SkippingMacroEnabled, En) => "Skipping macro-enabled document {path}",
+            (SkippingMacroEnabled, De) => "Überspringe Dokument mit Makros {path}",
+            (SkippingMacroEnabled, Fr) => "Document avec macros {path} ignoré",
+            (SkippingMacroEnabled, Es) => "Omitiendo el documento con macros {path}",
+            (SkippingMacroEnabledEmbedded, En) => "Skipping macro-enabled embedded document {path}",
+            (SkippingMacroEnabledEmbedded, De) => {
+                "Überspringe eingebettetes Dokument mit Makros {path}"
+            }
+            (SkippingMacroEnabledEmbedded, Fr) => "Document incorporé avec macros {path} ignoré",
+            (SkippingMacroEnabledEmbedded, Es) => {
+                "Omitiendo el documento incrustado con macros {path}"
+            }
+            (SkippingUnreferenced, En) => "Skipping {count} unreferenced image(s) in {path}.",
+            (SkippingUnreferenced, De) => {
+                "Überspringe {count} nicht referenzierte(s) Bild(er) in {path}."
+            }
+            (SkippingUnreferenced, Fr) => {
+                "{count} image(s) non référencée(s) ignorée(s) dans {path}."
+            }
+            (SkippingUnreferenced, Es) => "Omitiendo {count} imagen(es) sin referencia en {path}.",
+        }
+    }
+
+    /// The message in `lang` with its placeholders filled in from `fields`
+    pub fn render_in(self, lang: Lang, fields: &[(&str, &dyn Display)]) -> String {
+        fill(self.template(lang), fields).expect("message templates only use their fields")
+    }
+}
+
+/// The message in the current language with its placeholders filled in from `fields`
+pub fn text(message: Message, fields: &[(&str, &dyn Display)]) -> String {
+    message.render_in(lang(), fields)
+}
+
+/// Fills the `{name}` placeholders of `template` from `fields`. `{{` and `}}` stand for
+/// literal braces.
+fn fill(template: &str, fields: &[(&str, &dyn Display)]) -> Result<String, String> {
+    let segments = parse_placeholders(template, |name| {
+        fields
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| *value)
+    })?;
+    Ok(segments
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Literal(text) => text,
+            Segment::Placeholder(value) => value.to_string(),
+        })
+        .collect())
+}
+
+/// Counts of a finished run, for `--summary-template`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Summary {
+    /// Images extracted
+    pub images: usize,
+    /// Documents at least one image was extracted from
+    pub documents: usize,
+    /// Documents processed
+    pub processed: usize,
+    /// Documents that failed
+    pub failed: usize,
+    /// Documents skipped because they are DRM-protected
+    pub drm: usize,
+    /// Images not written because they were duplicates
+    pub duplicates: usize,
+    /// Images not written because their output already exists
+    pub existing: usize,
+    /// Documents skipped because they are unchanged since an incremental run
+    pub unchanged: usize,
+    /// Documents skipped because the resumed run finished them
+    pub resumed: usize,
+}
+
+impl Summary {
+    fn fields(&self) -> [(&'static str, &dyn Display); 9] {
+        [
+            ("images", &self.images),
+            ("documents", &self.documents),
+            ("processed", &self.processed),
+            ("failed", &self.failed),
+            ("drm", &self.drm),
+            ("duplicates", &self.duplicates),
+            ("existing", &self.existing),
+            ("unchanged", &self.unchanged),
+            ("resumed", &self.resumed),
+        ]
+    }
+}
+
+/// A `--summary-template`: the final line of a run, printed in place of the
+/// translated "Processing complete!" line
+#[derive(Debug, Clone, PartialEq)]
+pub struct SummaryTemplate(String);
+
+/// Parses a summary template, checking its placeholders.
+/// Used as a clap value parser.
+pub fn parse_summary_template(template: &str) -> Result<SummaryTemplate, String> {
+    fill(template, &Summary::default().fields())?;
+    Ok(SummaryTemplate(template.to_string()))
+}
+
+impl SummaryTemplate {
+    /// The summary line for a run
+    pub fn render(&self, summary: &Summary) -> String {
+        fill(&self.0, &summary.fields()).expect("summary template was checked when parsed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGES: [Message; 18] = [
+        Message::Complete,
+        Message::NoImages,
+        Message::NoImageOnStdout,
+        Message::RepeatedDocuments,
+        Message::LimitedRun,
+        Message::DuplicateImages,
+        Message::ExistingImages,
+        Message::UnchangedDocuments,
+        Message::ResumedDocuments,
+        Message::UnfinishedFilesRemoved,
+        Message::FailedDocuments,
+        Message::StoppedAfterFailure,
+        Message::DamagedDocuments,
+        Message::DrmDocuments,
+        Message::SkippingDocument,
+        Message::SkippingMacroEnabled,
+        Message::SkippingMacroEnabledEmbedded,
+        Message::SkippingUnreferenced,
+    ];
+
+    /// The placeholder names of a template, sorted
+    fn placeholders(template: &str) -> Vec<&str> {
+        let mut names: Vec<_> = template
+            .split('{')
+            .skip(1)
+            .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    #[test]
+    fn test_translations_use_the_same_placeholders() {
+        for message in MESSAGES {
+            let english = placeholders(message.template(Lang::En));
+            for lang in Lang::value_variants() {
+                assert_eq!(
+                    placeholders(message.template(*lang)),
+                    english,
+                    "{:?} in {:?}",
+                    message,
+                    lang
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_messages() {
+        let fields: [(&str, &dyn Display); 2] = [("images", &3), ("documents", &2)];
+        assert_eq!(
+            Message::Complete.render_in(Lang::En, &fields),
+            "Processing complete! Extracted 3 images from 2 document(s)."
+        );
+        assert_eq!(
+            Message::Complete.render_in(Lang::De, &fields),
+            "Verarbeitung abgeschlossen! 3 Bilder aus 2 Dokument(en) extrahiert."
+        );
+    }
+
+    #[test]
+    fn test_lang_from_locale() {
+        assert_eq!(Lang::from_locale("de_DE.UTF-8"), Some(Lang::De));
+        assert_eq!(Lang::from_locale("fr"), Some(Lang::Fr));
+        assert_eq!(Lang::from_locale("es-MX"), Some(Lang::Es));
+        assert_eq!(Lang::from_locale("C.UTF-8"), None);
+        assert_eq!(Lang::from_locale("ja_JP"), None);
+    }
+
+    #[test]
+    fn test_summary_template() {
+        let template = parse_summary_template("{{done}} images={images} failed={failed}").unwrap();
+        let summary = Summary {
+            images: 4,
+            failed: 1,
+            ..Summary::default()
+        };
+        assert_eq!(template.render(&summary), "{done} images=4 failed=1");
+        assert!(parse_summary_template("{pages}").is_err());
+        assert!(parse_summary_template("{images").is_err());
+        assert!(parse_summary_template("images}").is_err());
+    }
+}
@@ -0,0 +1,264 @@
+//! Bitmaps embedded in EMF and WMF metafiles.
+//!
+//! Office writes package thumbnails and OLE previews as metafiles that usually
+//! just draw one device-independent bitmap. Rather than rendering vector records,
+//! this finds the largest such bitmap and returns it as a standalone image.
+
+use crate::sniff::sniff_image_format;
+
+/// EMF records carrying a DIB: (record type, offset of the offBmiSrc field)
+const EMF_BITMAP_RECORDS: [(u32, usize); 4] = [
+    (76, 84), // EMR_BITBLT
+    (77, 84), // EMR_STRETCHBLT
+    (80, 48), // EMR_SETDIBITSTODEVICE
+    (81, 48), // EMR_STRETCHDIBITS
+];
+const EMR_EOF: u32 = 14;
+
+/// WMF records carrying a packed DIB: (function, offset of the DIB within the record)
+const WMF_BITMAP_RECORDS: [(u16, usize); 4] = [
+    (0x0940, 22), // META_DIBBITBLT
+    (0x0B41, 26), // META_DIBSTRETCHBLT
+    (0x0D33, 24), // META_SETDIBTODEV
+    (0x0F43, 28), // META_STRETCHDIB
+];
+/// Key of the optional "placeable" header preceding a WMF
+const WMF_PLACEABLE_KEY: u32 = 0x9AC6_CDD7;
+
+/// DIB compression values for bitmaps that are really a JPEG or PNG stream
+const BI_BITFIELDS: u32 = 3;
+const BI_JPEG: u32 = 4;
+const BI_PNG: u32 = 5;
+
+fn le_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Returns the largest bitmap drawn by an EMF or WMF metafile as `(extension, data)`.
+/// Plain DIBs become BMP files; JPEG and PNG payloads are returned as-is.
+pub fn embedded_bitmap(data: &[u8]) -> Option<(&'static str, Vec<u8>)> {
+    let (header, bits) = match sniff_image_format(data)? {
+        "emf" => largest_emf_bitmap(data)?,
+        "wmf" => largest_wmf_bitmap(data)?,
+        _ => return None,
+    };
+    bitmap_file(header, bits)
+}
+
+/// Finds the largest DIB in an EMF, returning its header (with colour table) and pixel bits
+fn largest_emf_bitmap(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let mut best: Option<(&[u8], &[u8])> = None;
+    let mut pos = 0;
+    while let (Some(kind), Some(size)) = (le_u32(data, pos), le_u32(data, pos + 4)) {
+        let size = size as usize;
+        if size < 8 || kind == EMR_EOF {
+            break;
+        }
+        let Some(record) = data.get(pos..pos + size) else {
+            break;
+        };
+        if let Some(&(_, field)) = EMF_BITMAP_RECORDS.iter().find(|(k, _)| *k == kind) {
+            let slice = |offset: Option<u32>, len: Option<u32>| {
+                let start = offset? as usize;
+                record.get(start..start.checked_add(len? as usize)?)
+            };
+            let header = slice(le_u32(record, field), le_u32(record, field + 4));
+            let bits = slice(le_u32(record, field + 8), le_u32(record, field + 12));
+            if let (Some(header), Some(bits)) = (header, bits)
+                && !header.is_empty()
+                && best.is_none_or(|(_, b)| bits.len() > b.len())
+            {
+                best = Some((header, bits));
+            }
+        }
+        pos += size;
+    }
+    best
+}
+
+/// Finds the largest packed DIB in a WMF, split into header (with colour table) and bits
+fn largest_wmf_bitmap(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let mut pos = if le_u32(data, 0)? == WMF_PLACEABLE_KEY {
+        22
+    } else {
+        0
+    };
+    // Header size is given in 16-bit words
+    pos += le_u16(data, pos + 2)? as usize * 2;
+
+    let mut best: Option<(&[u8], &[u8])> = None;
+    while let (Some(words), Some(function)) = (le_u32(data, pos), le_u16(data, pos + 4)) {
+        let size = words as usize * 2;
+        if size < 6 || function == 0 {
+            break;
+        }
+        let Some(record) = data.get(pos..pos + size) else {
+            break;
+        };
+        if let Some(&(_, offset)) = WMF_BITMAP_RECORDS.iter().find(|(f, _)| *f == function)
+            && let Some(dib) = record.get(offset..)
+            && let Some(header_len) = dib_header_len(dib)
+            && dib.len() > header_len
+        {
+            let (header, bits) = dib.split_at(header_len);
+            if best.is_none_or(|(_, b)| bits.len() > b.len()) {
+                best = Some((header, bits));
+            }
+        }
+        pos += size;
+    }
+    best
+}
+
+/// Turns a packed DIB (header, colour table and bits) into a standalone image file
+pub fn packed_dib_file(dib: &[u8]) -> Option<(&'static str, Vec<u8>)> {
+    let header_len = dib_header_len(dib)?;
+    if dib.len() <= header_len {
+        return None;
+    }
+    let (header, bits) = dib.split_at(header_len);
+    bitmap_file(header, bits)
+}
+
+/// Length of a packed DIB's header plus colour table
+fn dib_header_len(dib: &[u8]) -> Option<usize> {
+    let header_size = le_u32(dib, 0)? as usize;
+    if header_size == 12 {
+        // BITMAPCOREHEADER: RGBTRIPLE palette entries
+        let bit_count = le_u16(dib, 10)?;
+        let colors = if bit_count <= 8 { 1 << bit_count } else { 0 };
+        return Some(12 + colors * 3);
+    }
+    if header_size < 40 {
+        return None;
+    }
+    let bit_count = le_u16(dib, 14)?;
+    let compression = le_u32(dib, 16)?;
+    let used = le_u32(dib, 32)? as usize;
+    let colors = match used {
+        0 if bit_count <= 8 => 1 << bit_count,
+        n => n,
+    };
+    let masks = if compression == BI_BITFIELDS && header_size == 40 {
+        12
+    } else {
+        0
+    };
+    Some(header_size + masks + colors * 4)
+}
+
+/// Wraps a DIB header and its bits into a `.bmp` file, or unwraps an embedded JPEG/PNG
+fn bitmap_file(header: &[u8], bits: &[u8]) -> Option<(&'static str, Vec<u8>)> {
+    if le_u32(header, 0)? >= 40 {
+        match le_u32(header, 16)? {
+            BI_JPEG => return Some(("jpg", bits.to_vec())),
+            BI_PNG => return Some(("png", bits.to_vec())),
+            _ => {}
+        }
+    }
+
+    let offset = 14 + header.len();
+    let total = u32::try_from(offset + bits.len()).ok()?;
+    let mut bmp = Vec::with_capacity(offset + bits.len());
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&total.to_le_bytes());
+    bmp.extend_from_slice(&[0; 4]);
+    bmp.extend_from_slice(&(offset as u32).to_le_bytes());
+    bmp.extend_from_slice(header);
+    bmp.extend_from_slice(bits);
+    Some(("bmp", bmp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::decode_luma;
+
+    /// BITMAPINFOHEADER for an uncompressed 24-bit DIB
+    fn dib_header(width: i32, height: i32) -> Vec<u8> {
+        let mut header = vec![0u8; 40];
+        header[0..4].copy_from_slice(&40u32.to_le_bytes());
+        header[4..8].copy_from_slice(&width.to_le_bytes());
+        header[8..12].copy_from_slice(&height.to_le_bytes());
+        header[12..14].copy_from_slice(&1u16.to_le_bytes());
+        header[14..16].copy_from_slice(&24u16.to_le_bytes());
+        header
+    }
+
+    fn emf_with_bitmap(header: &[u8], bits: &[u8]) -> Vec<u8> {
+        let mut emf = vec![0u8; 88];
+        emf[0..4].copy_from_slice(&1u32.to_le_bytes());
+        emf[4..8].copy_from_slice(&88u32.to_le_bytes());
+        emf[40..44].copy_from_slice(b" EMF");
+
+        let mut record = vec![0u8; 80];
+        let bmi_offset = record.len() as u32;
+        record.extend_from_slice(header);
+        let bits_offset = record.len() as u32;
+        record.extend_from_slice(bits);
+        record[0..4].copy_from_slice(&81u32.to_le_bytes());
+        let size = record.len() as u32;
+        record[4..8].copy_from_slice(&size.to_le_bytes());
+        record[48..52].copy_from_slice(&bmi_offset.to_le_bytes());
+        record[52..56].copy_from_slice(&(header.len() as u32).to_le_bytes());
+        record[56..60].copy_from_slice(&bits_offset.to_le_bytes());
+        record[60..64].copy_from_slice(&(bits.len() as u32).to_le_bytes());
+        emf.extend_from_slice(&record);
+
+        let mut eof = vec![0u8; 20];
+        eof[0..4].copy_from_slice(&EMR_EOF.to_le_bytes());
+        eof[4..8].copy_from_slice(&20u32.to_le_bytes());
+        emf.extend_from_slice(&eof);
+        emf
+    }
+
+    #[test]
+    fn test_emf_stretchdibits_to_bmp() {
+        // Bottom-up rows: black, then white
+        let bits = [0, 0, 0, 0, 255, 255, 255, 0];
+        let emf = emf_with_bitmap(&dib_header(1, 2), &bits);
+        let (extension, bmp) = embedded_bitmap(&emf).unwrap();
+        assert_eq!(extension, "bmp");
+        assert_eq!(decode_luma(&bmp).unwrap().pixels, vec![255, 0]);
+    }
+
+    #[test]
+    fn test_emf_embedded_jpeg() {
+        let mut header = dib_header(1, 1);
+        header[16..20].copy_from_slice(&BI_JPEG.to_le_bytes());
+        let jpeg = b"\xFF\xD8\xFF\xE0jpeg";
+        let emf = emf_with_bitmap(&header, jpeg);
+        assert_eq!(embedded_bitmap(&emf), Some(("jpg", jpeg.to_vec())));
+    }
+
+    #[test]
+    fn test_wmf_stretchdib_to_bmp() {
+        let mut wmf = vec![0u8; 18];
+        wmf[0..2].copy_from_slice(&1u16.to_le_bytes());
+        wmf[2..4].copy_from_slice(&9u16.to_le_bytes());
+        wmf[4..6].copy_from_slice(&0x0300u16.to_le_bytes());
+
+        let mut record = vec![0u8; 28];
+        record.extend_from_slice(&dib_header(1, 1));
+        record.extend_from_slice(&[255, 255, 255, 0]);
+        let words = (record.len() / 2) as u32;
+        record[0..4].copy_from_slice(&words.to_le_bytes());
+        record[4..6].copy_from_slice(&0x0F43u16.to_le_bytes());
+        wmf.extend_from_slice(&record);
+        wmf.extend_from_slice(&[3, 0, 0, 0, 0, 0]);
+
+        let (extension, bmp) = embedded_bitmap(&wmf).unwrap();
+        assert_eq!(extension, "bmp");
+        assert_eq!(decode_luma(&bmp).unwrap().pixels, vec![255]);
+    }
+
+    #[test]
+    fn test_vector_only_metafile() {
+        assert_eq!(embedded_bitmap(&emf_with_bitmap(&[], &[])), None);
+        assert_eq!(embedded_bitmap(b"\x89PNG\r\n\x1a\n"), None);
+    }
+}
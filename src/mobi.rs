@@ -0,0 +1,424 @@
+//! MOBI and AZW3 (Kindle) file processing module
+//!
+//! Kindle books are Palm databases: record 0 holds the PalmDOC and MOBI headers plus
+//! the EXTH metadata block, and images are stored one per record starting at the
+//! header's first image index.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use crate::common::{DocumentMetadata, normalize_isbn};
+use crate::epub::{format_epub_base_name, write_cover, write_largest_cover};
+use crate::extract::{CoverFallback, ExtractOptions};
+use crate::output::OutputSink;
+use crate::sniff::sniff_image_format;
+
+/// Size of the Palm database header, after which the record list starts
+const PDB_HEADER_SIZE: usize = 78;
+/// Offset of the MOBI header within record 0 (after the PalmDOC header)
+const MOBI_HEADER_OFFSET: usize = 16;
+/// "No image" value of the first image index
+const NO_INDEX: u32 = 0xFFFF_FFFF;
+/// EXTH flag in the MOBI header's flags field
+const EXTH_FLAG: u32 = 0x40;
+/// Text encoding value for UTF-8; anything else is treated as Windows-1252
+const ENCODING_UTF8: u32 = 65001;
+
+const EXTH_AUTHOR: u32 = 100;
+const EXTH_PUBLISHER: u32 = 101;
+const EXTH_ISBN: u32 = 104;
+const EXTH_SUBJECT: u32 = 105;
+const EXTH_COVER_OFFSET: u32 = 201;
+const EXTH_THUMB_OFFSET: u32 = 202;
+const EXTH_TITLE: u32 = 503;
+const EXTH_LANGUAGE: u32 = 524;
+
+fn be_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// An image record of a Kindle book
+pub struct BookImage<'a> {
+    pub extension: &'static str,
+    pub data: &'a [u8],
+}
+
+/// Metadata and images of a Kindle book
+pub struct Book<'a> {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub publisher: Option<String>,
+    pub language: Option<String>,
+    pub isbn: Option<String>,
+    /// Whether the text is DRM-encrypted (images are still readable)
+    pub encrypted: bool,
+    /// Images in record order, excluding the Kindle thumbnail
+    pub images: Vec<BookImage<'a>>,
+    /// Position of the cover in `images`
+    pub cover: Option<usize>,
+}
+
+/// Parses a MOBI/AZW3 file
+pub fn parse(data: &[u8]) -> Result<Book<'_>> {
+    let records = pdb_records(data).context("Not a Palm database")?;
+    let header = *records.first().context("Book has no header record")?;
+    if header.get(MOBI_HEADER_OFFSET..MOBI_HEADER_OFFSET + 4) != Some(b"MOBI") {
+        anyhow::bail!("Not a MOBI book");
+    }
+    let field = |offset: usize| be_u32(header, MOBI_HEADER_OFFSET + offset);
+    let encrypted = be_u16(header, 12).is_some_and(|e| e != 0);
+    let utf8 = field(12) == Some(ENCODING_UTF8);
+    let decode = |bytes: &[u8]| {
+        let text = if utf8 {
+            String::from_utf8_lossy(bytes).into_owned()
+        } else {
+            bytes.iter().map(|&b| b as char).collect()
+        };
+        Some(text.trim().to_string()).filter(|t| !t.is_empty())
+    };
+
+    let exth = if field(0x70).is_some_and(|flags| flags & EXTH_FLAG != 0) {
+        let header_len = field(4).unwrap_or(0) as usize;
+        parse_exth(
+            header
+                .get(MOBI_HEADER_OFFSET + header_len..)
+                .unwrap_or_default(),
+        )
+    } else {
+        Vec::new()
+    };
+    let exth_value = |kind: u32| exth.iter().find(|(k, _)| *k == kind).map(|(_, v)| *v);
+    let exth_number = |kind: u32| exth_value(kind).and_then(|v| be_u32(v, 0));
+
+    let full_name = field(0x44)
+        .zip(field(0x48))
+        .and_then(|(offset, len)| header.get(offset as usize..offset as usize + len as usize));
+    let title = exth_value(EXTH_TITLE)
+        .and_then(decode)
+        .or_else(|| full_name.and_then(decode));
+    let author = exth_value(EXTH_AUTHOR).and_then(decode);
+    let subject = exth_value(EXTH_SUBJECT).and_then(decode);
+    let isbn = exth_value(EXTH_ISBN)
+        .and_then(decode)
+        .map(|isbn| normalize_isbn(&isbn))
+        .filter(|isbn| !isbn.is_empty());
+
+    let mut book = Book {
+        title,
+        author,
+        subject,
+        publisher: exth_value(EXTH_PUBLISHER).and_then(decode),
+        language: exth_value(EXTH_LANGUAGE).and_then(decode),
+        isbn,
+        encrypted,
+        images: Vec::new(),
+        cover: None,
+    };
+    let first_image = field(0x5C).unwrap_or(NO_INDEX);
+    if first_image == NO_INDEX {
+        return Ok(book);
+    }
+    let first_image = first_image as usize;
+    let cover = exth_number(EXTH_COVER_OFFSET).map(|offset| first_image + offset as usize);
+    let thumbnail = exth_number(EXTH_THUMB_OFFSET).map(|offset| first_image + offset as usize);
+    for (index, record) in records.iter().enumerate().skip(first_image) {
+        if Some(index) == thumbnail {
+            continue;
+        }
+        // Font, resource and end-of-file records follow the images; only keep pictures
+        let Some(extension @ ("jpg" | "png" | "gif" | "bmp")) = sniff_image_format(record) else {
+            continue;
+        };
+        if Some(index) == cover {
+            book.cover = Some(book.images.len());
+        }
+        book.images.push(BookImage {
+            extension,
+            data: record,
+        });
+    }
+    Ok(book)
+}
+
+/// Splits a Palm database into its records
+fn pdb_records(data: &[u8]) -> Option<Vec<&[u8]>> {
+    let count = be_u16(data, 76)? as usize;
+    let offsets: Vec<usize> = (0..count)
+        .map(|i| be_u32(data, PDB_HEADER_SIZE + i * 8).map(|o| o as usize))
+        .collect::<Option<_>>()?;
+    let records = offsets
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            let end = offsets.get(i + 1).copied().unwrap_or(data.len());
+            data.get(start..end.max(start)).unwrap_or_default()
+        })
+        .collect();
+    Some(records)
+}
+
+/// Parses an EXTH block into `(type, value)` pairs
+fn parse_exth(data: &[u8]) -> Vec<(u32, &[u8])> {
+    let mut records = Vec::new();
+    if !data.starts_with(b"EXTH") {
+        return records;
+    }
+    let count = be_u32(data, 8).unwrap_or(0);
+    let mut pos = 12;
+    for _ in 0..count {
+        let (Some(kind), Some(len)) = (be_u32(data, pos), be_u32(data, pos + 4)) else {
+            break;
+        };
+        let len = len as usize;
+        let Some(value) = data.get(pos + 8..pos + len.max(8)) else {
+            break;
+        };
+        records.push((kind, value));
+        pos += len.max(8);
+    }
+    records
+}
+
+/// Processes a single .mobi/.azw3 file, extracting images matching the allowed extensions.
+/// Returns the number of images extracted.
+pub fn process_file(
+    input_path: &Path,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let fallback_name = input_path
+        .file_stem()
+        .context("Invalid filename")?
+        .to_string_lossy()
+        .to_string();
+
+    let file = fs::File::open(input_path)
+        .with_context(|| format!("Failed to open input file: {}", input_path.display()))?;
+
+    process_reader(file, input_path, &fallback_name, sink, options)
+}
+
+/// Processes a Kindle book from any reader.
+/// `input_path` is only used for messages; `fallback_name` is the output base name used
+/// when the book has no author or title.
+pub fn process_reader<R: Read>(
+    mut reader: R,
+    input_path: &Path,
+    fallback_name: &str,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let mut data = Vec::new();
+    reader
+        .read_to_end(&mut data)
+        .with_context(|| format!("Failed to read {}", input_path.display()))?;
+    let book = parse(&data).with_context(|| format!("Failed to read {}", input_path.display()))?;
+
+    // Same metadata filter as EPUBs - silently skip non-matching books
+    let metadata = DocumentMetadata {
+        title: book.title.clone(),
+        author: book.author.clone(),
+        subject: book.subject.clone(),
+        series: None,
+        series_index: None,
+        publisher: book.publisher.clone(),
+        language: book.language.clone(),
+        isbn: book.isbn.clone(),
+    };
+    if !options.document_filter.matches(&metadata) {
+        return Ok(0);
+    }
+
+    let base_name = format_epub_base_name(
+        book.author.as_deref(),
+        book.title.as_deref(),
+        None,
+        None,
+        fallback_name,
+    );
+    if let Some(ref t) = book.title {
+        status!("MOBI Title: {}", t);
+        sink.record_metadata("title", t);
+    }
+    if let Some(ref a) = book.author {
+        status!("MOBI Author: {}", a);
+        sink.record_metadata("author", a);
+    }
+    if book.encrypted {
+        sink.warn(&format!(
+            "{} is DRM-protected; only its unencrypted images can be extracted",
+            input_path.display()
+        ));
+    }
+
+    if options.cover_only {
+        match book.cover.map(|i| &book.images[i]) {
+            Some(cover) => {
+                return write_cover(
+                    cover.extension,
+                    cover.data,
+                    &base_name,
+                    input_path,
+                    sink,
+                    options,
+                );
+            }
+            None if options.cover_fallback == Some(CoverFallback::All) => status!(
+                "No cover image found in {}, falling back to extracting all images.",
+                input_path.display()
+            ),
+            None if options.cover_fallback == Some(CoverFallback::Largest) => {
+                let images = book
+                    .images
+                    .iter()
+                    .map(|image| (image.extension, image.data));
+                return write_largest_cover(images, &base_name, input_path, sink, options);
+            }
+            None => {
+                status!("No cover image found in {}", input_path.display());
+                return Ok(0);
+            }
+        }
+    }
+
+    let images: Vec<&BookImage> = book
+        .images
+        .iter()
+        .filter(|image| options.allowed_extensions.contains(image.extension))
+        .collect();
+    let total_images = images.len();
+    if total_images > 0 {
+        sink.create_base_dir()?;
+
+        status!(
+            "Found {} image files in {}.",
+            total_images,
+            input_path.display()
+        );
+    }
+
+    for (seq_index, image) in images.iter().enumerate() {
+        let output_path = sink.output_path(&base_name, seq_index, total_images, image.extension)?;
+
+        status!("Extracting to: {}", output_path.display());
+
+        sink.write_image(&output_path, image.data)?;
+    }
+
+    Ok(total_images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::temp_dir;
+    use crate::output::OutputOptions;
+
+    /// Builds a minimal MOBI with the given EXTH records and image records
+    fn mobi(exth: &[(u32, &[u8])], images: &[&[u8]]) -> Vec<u8> {
+        let mut exth_block = Vec::new();
+        for (kind, value) in exth {
+            exth_block.extend_from_slice(&kind.to_be_bytes());
+            exth_block.extend_from_slice(&(value.len() as u32 + 8).to_be_bytes());
+            exth_block.extend_from_slice(value);
+        }
+        let mut header = vec![0u8; MOBI_HEADER_OFFSET + 0xE8];
+        header[MOBI_HEADER_OFFSET..MOBI_HEADER_OFFSET + 4].copy_from_slice(b"MOBI");
+        let mut set = |offset: usize, value: u32| {
+            header[MOBI_HEADER_OFFSET + offset..MOBI_HEADER_OFFSET + offset + 4]
+                .copy_from_slice(&value.to_be_bytes())
+        };
+        set(4, 0xE8);
+        set(12, ENCODING_UTF8);
+        set(0x5C, 2);
+        set(0x70, EXTH_FLAG);
+        header.extend_from_slice(b"EXTH");
+        header.extend_from_slice(&(exth_block.len() as u32 + 12).to_be_bytes());
+        header.extend_from_slice(&(exth.len() as u32).to_be_bytes());
+        header.extend_from_slice(&exth_block);
+
+        let text: &[u8] = b"book text";
+        let mut records: Vec<&[u8]> = vec![&header, text];
+        records.extend_from_slice(images);
+        records.push(b"FLIS\0\0\0\x08");
+
+        let mut pdb = vec![0u8; PDB_HEADER_SIZE];
+        pdb[60..68].copy_from_slice(b"BOOKMOBI");
+        pdb[76..78].copy_from_slice(&(records.len() as u16).to_be_bytes());
+        let mut offset = PDB_HEADER_SIZE + records.len() * 8;
+        for record in &records {
+            pdb.extend_from_slice(&(offset as u32).to_be_bytes());
+            pdb.extend_from_slice(&[0; 4]);
+            offset += record.len();
+        }
+        for record in &records {
+            pdb.extend_from_slice(record);
+        }
+        pdb
+    }
+
+    #[test]
+    fn test_parse_mobi() {
+        let data = mobi(
+            &[
+                (EXTH_AUTHOR, b"Jane Doe"),
+                (EXTH_TITLE, b"A Book"),
+                (EXTH_COVER_OFFSET, &1u32.to_be_bytes()),
+                (EXTH_THUMB_OFFSET, &2u32.to_be_bytes()),
+            ],
+            &[
+                b"GIF89a inside",
+                b"\xFF\xD8\xFF\xE0cover",
+                b"\xFF\xD8\xFF\xE0thumb",
+            ],
+        );
+        let book = parse(&data).unwrap();
+        assert_eq!(book.title.as_deref(), Some("A Book"));
+        assert_eq!(book.author.as_deref(), Some("Jane Doe"));
+        assert!(!book.encrypted);
+        let extensions: Vec<&str> = book.images.iter().map(|i| i.extension).collect();
+        assert_eq!(extensions, vec!["gif", "jpg"]);
+        assert_eq!(book.cover, Some(1));
+    }
+
+    #[test]
+    fn test_mobi_cover_only() {
+        let dir = temp_dir("mobi");
+
+        let data = mobi(
+            &[
+                (EXTH_TITLE, b"Cover Test"),
+                (EXTH_COVER_OFFSET, &1u32.to_be_bytes()),
+            ],
+            &[b"GIF89a inside", b"\xFF\xD8\xFF\xE0cover"],
+        );
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            cover_only: true,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count = process_reader(
+            data.as_slice(),
+            Path::new("b.azw3"),
+            "b",
+            &mut sink,
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(
+            std::fs::read(dir.join("Cover Test.jpg")).unwrap(),
+            b"\xFF\xD8\xFF\xE0cover"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,289 @@
+//! Output filename templates (`--name-template`)
+//!
+//! A template such as `{author}/{title}_{index:03}.{ext}` is rendered once per image.
+//! `/` in the template creates subdirectories; placeholder values are sanitized so
+//! they never add path components of their own.
+
+use crate::common::{Numbering, format_series_index, sanitize_filename};
+
+/// Values a template can refer to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    /// Base name the default naming scheme uses (document name, "Author - Title", ...)
+    Doc,
+    Title,
+    Author,
+    /// 1-based position of the image within its document
+    Index,
+    Ext,
+    /// Name of the image inside the document, without extension
+    Original,
+    /// Series the book belongs to
+    Series,
+    /// Position of the book in its series, with the width as zero-padding
+    SeriesIndex,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    /// A placeholder, zero-padded to `width` digits (only meaningful for `{index}`)
+    Field {
+        field: Field,
+        width: usize,
+    },
+}
+
+/// A parsed `--name-template`
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+/// Placeholder values for one image
+#[derive(Debug, Default)]
+pub struct NameFields<'a> {
+    pub doc: &'a str,
+    pub title: Option<&'a str>,
+    pub author: Option<&'a str>,
+    /// 0-based sequence index of the image
+    pub seq_index: usize,
+    pub extension: &'a str,
+    pub original: Option<&'a str>,
+    pub series: Option<&'a str>,
+    /// Position in the series as recorded, e.g. `4` or `4.0`
+    pub series_index: Option<&'a str>,
+    /// First number and padding of `{index}`; a width in the template takes precedence
+    pub numbering: Numbering,
+}
+
+/// Parses a template. `{{` and `}}` stand for literal braces.
+/// Used as a clap value parser.
+pub fn parse_name_template(template: &str) -> Result<NameTemplate, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err(format!("unterminated placeholder '{{{}'", spec)),
+                    }
+                }
+                let (name, width) = match spec.split_once(':') {
+                    Some((name, width)) => (
+                        name,
+                        width
+                            .parse()
+                            .map_err(|_| format!("'{{{}}}' has an invalid width", spec))?,
+                    ),
+                    None => (spec.as_str(), 0),
+                };
+                let field = match name {
+                    "doc" => Field::Doc,
+                    "title" => Field::Title,
+                    "author" => Field::Author,
+                    "index" => Field::Index,
+                    "ext" => Field::Ext,
+                    "original" => Field::Original,
+                    "series" => Field::Series,
+                    "series_index" => Field::SeriesIndex,
+                    _ => return Err(format!("unknown placeholder '{{{}}}'", name)),
+                };
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Field { field, width });
+            }
+            '}' => return Err("unmatched '}' (write '}}' for a literal brace)".to_string()),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    if parts.is_empty() {
+        return Err("the template is empty".to_string());
+    }
+    Ok(NameTemplate { parts })
+}
+
+impl NameTemplate {
+    /// Renders the relative output path for an image. `.{ext}` is appended when the
+    /// template does not place the extension itself.
+    pub fn render(&self, fields: &NameFields) -> String {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => rendered.push_str(text),
+                Part::Field {
+                    field: Field::Index,
+                    width,
+                } => {
+                    let width = if *width > 0 {
+                        *width
+                    } else {
+                        fields.numbering.pad_width
+                    };
+                    let number = fields.numbering.number(fields.seq_index);
+                    rendered.push_str(&format!("{:0width$}", number, width = width))
+                }
+                Part::Field {
+                    field: Field::SeriesIndex,
+                    width,
+                } => {
+                    let index = fields
+                        .series_index
+                        .and_then(|index| format_series_index(index, *width));
+                    rendered.push_str(&sanitize_filename(&index.unwrap_or_default()))
+                }
+                Part::Field { field, .. } => rendered.push_str(&self.value(*field, fields)),
+            }
+        }
+        if !self.uses(Field::Ext) {
+            rendered = format!("{}.{}", rendered, fields.extension);
+        }
+
+        // Drop empty, `.` and `..` components so the result stays inside the output directory
+        rendered
+            .split(['/', '\\'])
+            .map(str::trim)
+            .filter(|c| !c.is_empty() && *c != "." && *c != "..")
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn value(&self, field: Field, fields: &NameFields) -> String {
+        let value = match field {
+            Field::Doc => Some(fields.doc.to_string()),
+            Field::Title => fields.title.map(str::to_string),
+            Field::Author => fields.author.map(str::to_string),
+            Field::Index => Some(fields.numbering.format(fields.seq_index)),
+            Field::Ext => Some(fields.extension.to_string()),
+            Field::Series => fields.series.map(str::to_string),
+            Field::SeriesIndex => fields.series_index.map(str::to_string),
+            Field::Original => fields.original.map(|name| {
+                let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+                match name.rsplit_once('.') {
+                    Some((stem, _)) if !stem.is_empty() => stem.to_string(),
+                    _ => name.to_string(),
+                }
+            }),
+        };
+        let value = value
+            .map(|v| sanitize_filename(&v))
+            .filter(|v| !v.is_empty());
+        match (value, field) {
+            (Some(value), _) => value,
+            (None, Field::Title) => sanitize_filename(fields.doc),
+            (None, Field::Author) => "Unknown".to_string(),
+            // Books outside a series leave it out, e.g. for `{series}/{title}`
+            (None, Field::Series | Field::SeriesIndex) => String::new(),
+            (None, _) => format!(
+                "{}_{}",
+                sanitize_filename(fields.doc),
+                fields.numbering.format(fields.seq_index)
+            ),
+        }
+    }
+
+    fn uses(&self, field: Field) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Field { field: f, .. } if *f == field))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_name_template() {
+        let template = parse_name_template("{author}/{title}_{index:03}.{ext}").unwrap();
+        let fields = NameFields {
+            doc: "Herbert - Dune",
+            title: Some("Dune: Messiah"),
+            author: Some("Frank Herbert"),
+            seq_index: 4,
+            extension: "jpg",
+            original: Some("OEBPS/images/map.jpeg"),
+            series: Some("Dune"),
+            series_index: Some("2.0"),
+            numbering: Numbering::default(),
+        };
+        assert_eq!(
+            template.render(&fields),
+            "Frank Herbert/Dune_ Messiah_005.jpg"
+        );
+
+        let template = parse_name_template("../{{{original}}}").unwrap();
+        assert_eq!(template.render(&fields), "{map}.jpg");
+
+        let plain = NameFields {
+            doc: "report",
+            extension: "png",
+            ..Default::default()
+        };
+        let template = parse_name_template("{author}/{title}/{original}").unwrap();
+        assert_eq!(template.render(&plain), "Unknown/report/report_1.png");
+
+        let numbered = NameFields {
+            seq_index: 7,
+            numbering: Numbering {
+                pad_width: 3,
+                start_index: 0,
+            },
+            ..plain
+        };
+        let template = parse_name_template("{doc}-{index}").unwrap();
+        assert_eq!(template.render(&numbered), "report-007.png");
+        let template = parse_name_template("{doc}-{index:2}").unwrap();
+        assert_eq!(template.render(&numbered), "report-07.png");
+    }
+
+    #[test]
+    fn test_render_series_placeholders() {
+        let template = parse_name_template("{series} {series_index:02} - {title}").unwrap();
+        let fields = NameFields {
+            doc: "Pratchett - Mort",
+            title: Some("Mort"),
+            series: Some("Discworld"),
+            series_index: Some("4.0"),
+            extension: "jpg",
+            ..Default::default()
+        };
+        assert_eq!(template.render(&fields), "Discworld 04 - Mort.jpg");
+        let template = parse_name_template("{series}/{series_index}/{title}").unwrap();
+        assert_eq!(template.render(&fields), "Discworld/4/Mort.jpg");
+
+        // Books outside a series leave the placeholders empty
+        let standalone = NameFields {
+            series: None,
+            series_index: None,
+            ..fields
+        };
+        assert_eq!(template.render(&standalone), "Mort.jpg");
+    }
+
+    #[test]
+    fn test_parse_name_template_errors() {
+        assert!(parse_name_template("{name}").is_err());
+        assert!(parse_name_template("{index:x}").is_err());
+        assert!(parse_name_template("a}b").is_err());
+        assert!(parse_name_template("{index").is_err());
+        assert!(parse_name_template("").is_err());
+    }
+}
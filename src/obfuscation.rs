@@ -0,0 +1,230 @@
+//! EPUB resource de-obfuscation (IDPF and Adobe font mangling)
+//!
+//! Obfuscated resources are listed in `META-INF/encryption.xml`. Both schemes XOR
+//! the start of the file with a key derived from the book's identifier, so
+//! de-obfuscation is the same operation as obfuscation.
+
+use regex::Regex;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use crate::common::percent_decode;
+
+/// Algorithm URI for the IDPF font obfuscation scheme (EPUB OCF 3.0)
+pub const IDPF_ALGORITHM: &str = "http://www.idpf.org/2008/embedding";
+/// Algorithm URI for Adobe's older font mangling scheme
+pub const ADOBE_ALGORITHM: &str = "http://ns.adobe.com/pdf/enc#RC";
+
+/// Number of leading bytes obfuscated by the IDPF algorithm
+const IDPF_OBFUSCATED_LEN: usize = 1040;
+/// Number of leading bytes obfuscated by the Adobe algorithm
+const ADOBE_OBFUSCATED_LEN: usize = 1024;
+
+static ENCRYPTED_DATA_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?s)<(?:\w+:)?EncryptedData\b.*?</(?:\w+:)?EncryptedData>").unwrap()
+});
+static ALGORITHM_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"<(?:\w+:)?EncryptionMethod\b[^>]*\bAlgorithm\s*=\s*["']([^"']+)["']"#).unwrap()
+});
+static CIPHER_REFERENCE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"<(?:\w+:)?CipherReference\b[^>]*\bURI\s*=\s*["']([^"']+)["']"#).unwrap()
+});
+
+/// Obfuscation scheme applied to a resource
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Obfuscation {
+    Idpf,
+    Adobe,
+}
+
+/// Parses `encryption.xml`, returning every referenced resource path (relative to the
+/// container root) mapped to its encryption algorithm URI
+pub fn parse_encryption_xml(xml: &str) -> HashMap<String, String> {
+    let mut entries = HashMap::new();
+    for block in ENCRYPTED_DATA_RE.find_iter(xml) {
+        let block = block.as_str();
+        let algorithm = ALGORITHM_RE.captures(block).map(|c| c[1].to_string());
+        let uri = CIPHER_REFERENCE_RE
+            .captures(block)
+            .map(|c| percent_decode(&c[1]));
+        if let (Some(algorithm), Some(uri)) = (algorithm, uri) {
+            entries.insert(uri.trim_start_matches('/').to_string(), algorithm);
+        }
+    }
+    entries
+}
+
+/// Maps an algorithm URI to a supported obfuscation scheme
+pub fn obfuscation_for(algorithm: &str) -> Option<Obfuscation> {
+    match algorithm {
+        IDPF_ALGORITHM => Some(Obfuscation::Idpf),
+        ADOBE_ALGORITHM => Some(Obfuscation::Adobe),
+        _ => None,
+    }
+}
+
+/// Derives the IDPF key: SHA-1 of the unique identifier with all whitespace removed
+pub fn idpf_key(unique_identifier: &str) -> Vec<u8> {
+    let stripped: String = unique_identifier
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '\t' | '\n' | '\r'))
+        .collect();
+    Sha1::digest(stripped.as_bytes()).to_vec()
+}
+
+/// Derives the Adobe key: the 16 raw bytes of the book's UUID identifier
+pub fn adobe_key(identifier: &str) -> Option<Vec<u8>> {
+    let hex: String = identifier
+        .trim()
+        .trim_start_matches("urn:uuid:")
+        .chars()
+        .filter(|c| *c != '-')
+        .collect();
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+    (0..32)
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Reverses the obfuscation of `data` in place using the given key
+pub fn deobfuscate(data: &mut [u8], scheme: Obfuscation, key: &[u8]) {
+    if key.is_empty() {
+        return;
+    }
+    let len = match scheme {
+        Obfuscation::Idpf => IDPF_OBFUSCATED_LEN,
+        Obfuscation::Adobe => ADOBE_OBFUSCATED_LEN,
+    };
+    for (i, byte) in data.iter_mut().take(len).enumerate() {
+        *byte ^= key[i % key.len()];
+    }
+}
+
+/// Obfuscated resources of one book, with the keys needed to restore them
+#[derive(Debug, Default)]
+pub struct ObfuscatedResources {
+    /// Archive path -> scheme and key
+    entries: HashMap<String, (Obfuscation, Vec<u8>)>,
+}
+
+impl ObfuscatedResources {
+    /// Builds the resource table from `encryption.xml`.
+    /// `unique_identifier` keys the IDPF scheme and `uuid_identifier` the Adobe scheme.
+    /// Resources using other algorithms (real encryption) are not included.
+    pub fn from_encryption_xml(
+        xml: &str,
+        unique_identifier: Option<&str>,
+        uuid_identifier: Option<&str>,
+    ) -> Self {
+        let idpf = unique_identifier.map(idpf_key);
+        let adobe = uuid_identifier.and_then(adobe_key);
+
+        let entries = parse_encryption_xml(xml)
+            .into_iter()
+            .filter_map(|(path, algorithm)| {
+                let scheme = obfuscation_for(&algorithm)?;
+                let key = match scheme {
+                    Obfuscation::Idpf => idpf.clone()?,
+                    Obfuscation::Adobe => adobe.clone()?,
+                };
+                Some((path, (scheme, key)))
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// Restores `data` in place if the resource at `path` is obfuscated.
+    /// Returns true if de-obfuscation was applied.
+    pub fn apply(&self, path: &str, data: &mut [u8]) -> bool {
+        match self.entries.get(path.trim_start_matches('/')) {
+            Some((scheme, key)) => {
+                deobfuscate(data, *scheme, key);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCRYPTION_XML: &str = r#"<?xml version="1.0"?>
+<encryption xmlns="urn:oasis:names:tc:opendocument:xmlns:container"
+            xmlns:enc="http://www.w3.org/2001/04/xmlenc#">
+  <enc:EncryptedData>
+    <enc:EncryptionMethod Algorithm="http://www.idpf.org/2008/embedding"/>
+    <enc:CipherData>
+      <enc:CipherReference URI="OEBPS/Fonts/My%20Font.otf"/>
+    </enc:CipherData>
+  </enc:EncryptedData>
+  <enc:EncryptedData>
+    <enc:EncryptionMethod Algorithm="http://ns.adobe.com/pdf/enc#RC"/>
+    <enc:CipherData><enc:CipherReference URI="OEBPS/Images/logo.png"/></enc:CipherData>
+  </enc:EncryptedData>
+</encryption>"#;
+
+    #[test]
+    fn test_parse_encryption_xml() {
+        let entries = parse_encryption_xml(ENCRYPTION_XML);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries["OEBPS/Fonts/My Font.otf"], IDPF_ALGORITHM);
+        assert_eq!(entries["OEBPS/Images/logo.png"], ADOBE_ALGORITHM);
+    }
+
+    #[test]
+    fn test_idpf_key_ignores_whitespace() {
+        assert_eq!(
+            idpf_key(" urn:uuid:1234 \n"),
+            Sha1::digest(b"urn:uuid:1234").to_vec()
+        );
+    }
+
+    #[test]
+    fn test_adobe_key() {
+        let key = adobe_key("urn:uuid:0f1e2d3c-4b5a-6978-8796-a5b4c3d2e1f0").unwrap();
+        assert_eq!(key.len(), 16);
+        assert_eq!(key[0], 0x0f);
+        assert_eq!(key[15], 0xf0);
+        assert!(adobe_key("isbn:9780000000000").is_none());
+        // 32 bytes, but not on character boundaries
+        assert!(adobe_key("urn:uuid:é0f1e2d3c4b5a69788796a5b4c3d2e1").is_none());
+    }
+
+    #[test]
+    fn test_deobfuscate_round_trip() {
+        let original: Vec<u8> = (0..2000u32).map(|i| (i % 256) as u8).collect();
+        let key = idpf_key("urn:uuid:test");
+
+        let mut data = original.clone();
+        deobfuscate(&mut data, Obfuscation::Idpf, &key);
+        assert_ne!(data[..1040], original[..1040]);
+        // Bytes past the obfuscated prefix are untouched
+        assert_eq!(data[1040..], original[1040..]);
+
+        deobfuscate(&mut data, Obfuscation::Idpf, &key);
+        assert_eq!(data, original);
+    }
+
+    #[test]
+    fn test_obfuscated_resources_apply() {
+        let resources =
+            ObfuscatedResources::from_encryption_xml(ENCRYPTION_XML, Some("urn:uuid:test"), None);
+        let original = vec![7u8; 64];
+
+        let mut font = original.clone();
+        deobfuscate(&mut font, Obfuscation::Idpf, &idpf_key("urn:uuid:test"));
+        assert!(resources.apply("OEBPS/Fonts/My Font.otf", &mut font));
+        assert_eq!(font, original);
+
+        // No Adobe key available, so the Adobe entry is left alone
+        let mut logo = original.clone();
+        assert!(!resources.apply("OEBPS/Images/logo.png", &mut logo));
+        assert_eq!(logo, original);
+    }
+}
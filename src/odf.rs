@@ -0,0 +1,289 @@
+//! OpenDocument (.odt, .odp, .ods) file processing module
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::{Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+
+use crate::common::{
+    ImageToExtract, declared_extension, dublin_core_metadata, get_supported_extensions,
+    is_safe_archive_path,
+};
+use crate::extract::{self, ExtractOptions, nested_base_name};
+use crate::output::OutputSink;
+use crate::sniff::{detect_extension, format_mismatch, read_head};
+
+/// Archive path of the preview image LibreOffice stores with every document
+const THUMBNAIL_PATH: &str = "Thumbnails/thumbnail.png";
+/// Archive path of the document's metadata (title, author, subject)
+const META_XML: &str = "meta.xml";
+
+/// Whether an archive entry lives in a `Pictures/` folder, either the document's own
+/// or that of an embedded object (`Object 1/Pictures/...`)
+pub fn is_picture(name: &str) -> bool {
+    name.starts_with("Pictures/") || name.contains("/Pictures/")
+}
+
+/// Processes a single OpenDocument file, extracting images matching the allowed extensions.
+/// Returns the number of images extracted.
+pub fn process_file(
+    input_path: &Path,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let doc_name = input_path
+        .file_stem()
+        .context("Invalid filename")?
+        .to_string_lossy()
+        .to_string();
+
+    let file = fs::File::open(input_path)
+        .with_context(|| format!("Failed to open input file: {}", input_path.display()))?;
+
+    process_reader(file, input_path, &doc_name, 0, sink, options)
+}
+
+/// Processes an OpenDocument package from any seekable reader.
+/// `input_path` is only used for messages; `doc_name` is the output base name and
+/// `depth` is the embedding depth (0 for a top-level document).
+pub fn process_reader<R: Read + Seek>(
+    reader: R,
+    input_path: &Path,
+    doc_name: &str,
+    depth: usize,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let mut archive = ZipArchive::new(reader)
+        .with_context(|| format!("Failed to read zip archive: {}", input_path.display()))?;
+    options.limits.check_archive(&mut archive, input_path)?;
+
+    // Silently skip documents that don't match the metadata filter
+    if !options.document_filter.is_empty() {
+        let mut xml = String::new();
+        if let Ok(mut meta) = archive.by_name(META_XML) {
+            let _ = meta.read_to_string(&mut xml);
+        }
+        if !options.document_filter.matches(&dublin_core_metadata(&xml)) {
+            return Ok(0);
+        }
+    }
+
+    let mut images: Vec<ImageToExtract> = Vec::new();
+    let mut embedded_documents: Vec<usize> = Vec::new();
+    let mut thumbnail: Option<usize> = None;
+
+    let supported = get_supported_extensions(&options.extra_formats);
+
+    for i in 0..archive.len() {
+        let listed = archive.name_for_index(i).unwrap_or_default().to_string();
+        let mut file = match archive.by_index(i) {
+            Ok(file) => file,
+            Err(e) => {
+                extract::skip_damaged(e.into(), &listed, sink, options)?;
+                continue;
+            }
+        };
+        let name = file.name().to_string();
+        let name = name.as_str();
+
+        // Defense-in-depth: skip entries with path traversal patterns
+        if !is_safe_archive_path(name) {
+            continue;
+        }
+
+        if options.doc_thumbnail && name == THUMBNAIL_PATH {
+            thumbnail = Some(i);
+            continue;
+        }
+
+        if options.recurse_embedded && extract::is_embedded_document(name, options) {
+            embedded_documents.push(i);
+            continue;
+        }
+
+        if !is_picture(name) {
+            continue;
+        }
+        if let Some(ext) = Path::new(name).extension().and_then(|e| e.to_str()) {
+            let ext_lower = ext.to_lowercase();
+            if !supported.contains(ext_lower.as_str()) {
+                continue;
+            }
+            let extension = if options.trust_extension {
+                ext_lower
+            } else {
+                let head = match read_head(&mut file).context("Failed to read image from archive") {
+                    Ok(head) => head,
+                    Err(e) => {
+                        extract::skip_damaged(e, &listed, sink, options)?;
+                        continue;
+                    }
+                };
+                detect_extension(&ext_lower, &head, &options.extra_formats)
+            };
+            if options.allowed_extensions.contains(extension.as_str()) {
+                images.push(ImageToExtract {
+                    index: i,
+                    extension,
+                });
+            }
+        }
+    }
+
+    let total_images = images.len();
+    if total_images > 0 {
+        sink.create_base_dir()?;
+
+        status!(
+            "Found {} image files in {}.",
+            total_images,
+            input_path.display()
+        );
+    }
+
+    let mut extracted = 0;
+    for (seq_index, image) in images.iter().enumerate() {
+        let mut file = archive.by_index(image.index)?;
+        let entry_name = file.name().to_string();
+
+        // Forensic mode: keep only entries whose content disagrees with their extension.
+        // The content is needed for the check, so these images are read into memory, as
+        // are all images when salvaging, so a damaged one is skipped before it is written.
+        let mut data = None;
+        if options.only_mismatched || options.salvage {
+            let content = match options.limits.read_entry(&mut file, &entry_name) {
+                Ok(content) => content,
+                Err(e) => {
+                    let e = e.context(format!("Failed to extract {}", entry_name));
+                    extract::skip_damaged(e, &entry_name, sink, options)?;
+                    continue;
+                }
+            };
+            if options.only_mismatched {
+                let declared = declared_extension(&entry_name);
+                let Some(actual) = format_mismatch(&declared, &content, &options.extra_formats)
+                else {
+                    continue;
+                };
+                status!(
+                    "Format mismatch: {} is declared as {} but contains {}",
+                    entry_name,
+                    declared,
+                    actual
+                );
+            }
+            data = Some(content);
+        }
+
+        let output_path = sink.original_output_path(
+            doc_name,
+            seq_index,
+            total_images,
+            &image.extension,
+            &entry_name,
+        )?;
+
+        status!("Extracting to: {}", output_path.display());
+        sink.set_entry_time(&output_path, file.last_modified());
+
+        match data {
+            Some(data) => sink.write_image(&output_path, &data)?,
+            None => {
+                // Stream the entry so very large images never have to fit in memory
+                let size = file.size();
+                let mut reader = options.limits.limit(file, &entry_name);
+                sink.write_image_from_reader(&output_path, &mut reader, size)
+                    .with_context(|| format!("Failed to extract {}", entry_name))?;
+            }
+        }
+        extracted += 1;
+    }
+
+    if let Some(index) = thumbnail {
+        let file = archive.by_index(index)?;
+        let entry_name = file.name().to_string();
+        let data = options
+            .limits
+            .read_entry(file, &entry_name)
+            .context("Failed to read document thumbnail from archive")?;
+        sink.create_base_dir()?;
+        let output_path = sink.output_path(&format!("{}_thumbnail", doc_name), 0, 1, "png")?;
+        status!(
+            "Extracting document thumbnail to: {}",
+            output_path.display()
+        );
+        sink.write_image(&output_path, &data)?;
+        extracted += 1;
+    }
+
+    for index in embedded_documents {
+        let file = archive.by_index(index)?;
+        let entry_name = file.name().to_string();
+        let data = options
+            .limits
+            .read_entry(file, &entry_name)
+            .context("Failed to read embedded document from archive")?;
+
+        let virtual_path = input_path.join(&entry_name);
+        let base_name = nested_base_name(doc_name, &entry_name);
+        match extract::process_embedded(data, &virtual_path, &base_name, depth + 1, sink, options) {
+            Ok(count) => extracted += count,
+            Err(e) => sink.warn(&format!("Skipping {}: {}", virtual_path.display(), e)),
+        }
+    }
+
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::temp_dir;
+    use crate::output::OutputOptions;
+    use std::io::{Cursor, Write};
+
+    #[test]
+    fn test_is_picture() {
+        assert!(is_picture("Pictures/10000000000001.png"));
+        assert!(is_picture("Object 1/Pictures/chart.svg"));
+        assert!(!is_picture("Thumbnails/thumbnail.png"));
+        assert!(!is_picture("media/Pictures.png"));
+    }
+
+    #[test]
+    fn test_extract_odt_pictures() {
+        let dir = temp_dir("odf");
+
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in [
+            ("mimetype", &b"application/vnd.oasis.opendocument.text"[..]),
+            ("Pictures/1000.png", b"png"),
+            ("Thumbnails/thumbnail.png", b"thumb"),
+        ] {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let odt = writer.finish().unwrap();
+
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            doc_thumbnail: true,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count =
+            process_reader(odt, Path::new("notes.odt"), "notes", 0, &mut sink, &options).unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(std::fs::read(dir.join("notes.png")).unwrap(), b"png");
+        assert_eq!(
+            std::fs::read(dir.join("notes_thumbnail.png")).unwrap(),
+            b"thumb"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,440 @@
+//! Decryption of password-protected Office packages (ECMA-376 Agile encryption)
+//!
+//! Office stores an encrypted `.docx`, `.pptx` or `.xlsx` as an OLE compound file holding an
+//! `EncryptionInfo` stream (an XML description of the key derivation) and an
+//! `EncryptedPackage` stream (the original zip, encrypted with AES-CBC in 4096-byte segments).
+//! The older "standard" encryption of Office 2007 is not supported.
+
+use aes::cipher::{BlockDecrypt, KeyInit, generic_array::GenericArray};
+use aes::{Aes128, Aes192, Aes256};
+use anyhow::{Context, Result};
+use regex::Regex;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::sync::LazyLock;
+
+use crate::cfb::CompoundFile;
+use crate::common::base64_decode;
+use crate::docx_layout::attributes;
+
+/// Block keys mixed into the password hash for each purpose (MS-OFFCRYPTO 2.3.4.13)
+const VERIFIER_INPUT_BLOCK_KEY: [u8; 8] = [0xfe, 0xa7, 0xd2, 0x76, 0x3b, 0x4b, 0x9e, 0x79];
+const VERIFIER_HASH_BLOCK_KEY: [u8; 8] = [0xd7, 0xaa, 0x0f, 0x6d, 0x30, 0x61, 0x34, 0x4e];
+const ENCRYPTED_KEY_BLOCK_KEY: [u8; 8] = [0x14, 0x6e, 0x0b, 0xe7, 0xab, 0xac, 0xd0, 0xd6];
+
+/// Size of the segments the package is encrypted in, each with its own IV
+const SEGMENT_LEN: usize = 4096;
+
+/// Largest spinCount MS-OFFCRYPTO allows (2.3.4.11). A descriptor asking for more would
+/// keep the password check hashing for hours.
+const MAX_SPIN_COUNT: u32 = 10_000_000;
+
+/// The AES block size, the only blockSize an AES descriptor may give. Larger values would
+/// be used to pad every segment's IV, so an unchecked one could ask for gigabytes.
+const AES_BLOCK_LEN: usize = 16;
+
+static KEY_DATA_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<keyData\b[^>]*>").unwrap());
+static ENCRYPTED_KEY_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<(?:\w+:)?encryptedKey\b[^>]*>").unwrap());
+
+/// Hash algorithms allowed by the Agile encryption descriptor
+#[derive(Debug, Clone, Copy)]
+enum HashAlgorithm {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "SHA1" => Ok(HashAlgorithm::Sha1),
+            "SHA256" => Ok(HashAlgorithm::Sha256),
+            "SHA384" => Ok(HashAlgorithm::Sha384),
+            "SHA512" => Ok(HashAlgorithm::Sha512),
+            other => anyhow::bail!("Unsupported hash algorithm {}", other),
+        }
+    }
+
+    /// Hashes the concatenation of `parts`
+    fn hash(self, parts: &[&[u8]]) -> Vec<u8> {
+        fn digest<D: Digest>(parts: &[&[u8]]) -> Vec<u8> {
+            let mut hasher = D::new();
+            for part in parts {
+                hasher.update(part);
+            }
+            hasher.finalize().to_vec()
+        }
+        match self {
+            HashAlgorithm::Sha1 => {
+                let mut hasher = <Sha1 as sha1::Digest>::new();
+                for part in parts {
+                    sha1::Digest::update(&mut hasher, part);
+                }
+                sha1::Digest::finalize(hasher).to_vec()
+            }
+            HashAlgorithm::Sha256 => digest::<Sha256>(parts),
+            HashAlgorithm::Sha384 => digest::<Sha384>(parts),
+            HashAlgorithm::Sha512 => digest::<Sha512>(parts),
+        }
+    }
+}
+
+/// Key derivation and cipher parameters of a `keyData` or `encryptedKey` element
+struct KeyParams {
+    salt: Vec<u8>,
+    hash: HashAlgorithm,
+    key_len: usize,
+    block_len: usize,
+}
+
+impl KeyParams {
+    fn parse(tag: &str) -> Result<Self> {
+        let attrs = attributes(tag);
+        let field = |key: &str| {
+            attrs
+                .get(key)
+                .copied()
+                .with_context(|| format!("Encryption descriptor has no {}", key))
+        };
+        if field("cipherAlgorithm")? != "AES" {
+            anyhow::bail!("Unsupported cipher {}", field("cipherAlgorithm")?);
+        }
+        Ok(KeyParams {
+            salt: base64_decode(field("saltValue")?.as_bytes()).context("Invalid saltValue")?,
+            hash: HashAlgorithm::from_name(field("hashAlgorithm")?)?,
+            key_len: parse_key_bits(field("keyBits")?)? / 8,
+            block_len: parse_block_size(field("blockSize")?)?,
+        })
+    }
+}
+
+/// Whether `data` is an OLE compound file holding an encrypted Office package
+pub fn is_encrypted_package(data: &[u8]) -> bool {
+    CompoundFile::parse(data).is_ok_and(|cfb| cfb.read_stream("EncryptedPackage").is_some())
+}
+
+/// Decrypts an encrypted Office package, returning the zip it contains.
+/// Fails with "Incorrect password" if `password` does not match.
+pub fn decrypt_package(data: &[u8], password: &str) -> Result<Vec<u8>> {
+    let cfb = CompoundFile::parse(data)?;
+    let info = cfb
+        .read_stream("EncryptionInfo")
+        .context("Encrypted package has no EncryptionInfo stream")?;
+    let package = cfb
+        .read_stream("EncryptedPackage")
+        .context("Encrypted package has no EncryptedPackage stream")?;
+
+    let version = (le_u16(&info, 0), le_u16(&info, 2));
+    if version != (Some(4), Some(4)) {
+        anyhow::bail!("Only Agile encryption (Office 2010 and later) is supported");
+    }
+    let xml = String::from_utf8_lossy(info.get(8..).unwrap_or_default());
+    let key_data = KeyParams::parse(
+        KEY_DATA_RE
+            .find(&xml)
+            .context("Encryption descriptor has no keyData")?
+            .as_str(),
+    )?;
+    let encrypted_key_tag = ENCRYPTED_KEY_RE
+        .find(&xml)
+        .context("Encryption descriptor has no password key")?
+        .as_str();
+    let encrypted_key = KeyParams::parse(encrypted_key_tag)?;
+    let attrs = attributes(encrypted_key_tag);
+    let value = |key: &str| -> Result<Vec<u8>> {
+        let text = attrs
+            .get(key)
+            .with_context(|| format!("Encryption descriptor has no {}", key))?;
+        base64_decode(text.as_bytes()).with_context(|| format!("Invalid {}", key))
+    };
+    let spin_count = parse_spin_count(
+        attrs
+            .get("spinCount")
+            .context("Encryption descriptor has no spinCount")?,
+    )?;
+
+    // Check the password against the verifier before decrypting anything large
+    let password_hash = hash_password(password, &encrypted_key, spin_count);
+    let derive = |block_key: &[u8]| {
+        let mut key = encrypted_key.hash.hash(&[&password_hash, block_key]);
+        key.resize(encrypted_key.key_len, 0x36);
+        key
+    };
+    let verifier_input = aes_cbc_decrypt(
+        &derive(&VERIFIER_INPUT_BLOCK_KEY),
+        &encrypted_key.salt,
+        &value("encryptedVerifierHashInput")?,
+    )?;
+    let verifier_hash = aes_cbc_decrypt(
+        &derive(&VERIFIER_HASH_BLOCK_KEY),
+        &encrypted_key.salt,
+        &value("encryptedVerifierHashValue")?,
+    )?;
+    let expected = encrypted_key
+        .hash
+        .hash(&[&verifier_input[..verifier_input.len().min(encrypted_key.salt.len())]]);
+    if verifier_hash.get(..expected.len()) != Some(&expected[..]) {
+        anyhow::bail!("Incorrect password");
+    }
+
+    let mut secret = aes_cbc_decrypt(
+        &derive(&ENCRYPTED_KEY_BLOCK_KEY),
+        &encrypted_key.salt,
+        &value("encryptedKeyValue")?,
+    )?;
+    secret.truncate(key_data.key_len);
+
+    let size = package
+        .get(..8)
+        .and_then(|b| b.try_into().ok())
+        .map(u64::from_le_bytes)
+        .context("Encrypted package is truncated")? as usize;
+    let mut out = Vec::with_capacity(package.len());
+    for (index, segment) in package[8..].chunks(SEGMENT_LEN).enumerate() {
+        let mut iv = key_data
+            .hash
+            .hash(&[&key_data.salt, &(index as u32).to_le_bytes()]);
+        iv.resize(key_data.block_len, 0x36);
+        out.extend(aes_cbc_decrypt(&secret, &iv, segment)?);
+    }
+    if out.len() < size {
+        anyhow::bail!("Encrypted package is truncated");
+    }
+    out.truncate(size);
+    Ok(out)
+}
+
+/// Parses the number of hashing rounds, which may not exceed [`MAX_SPIN_COUNT`]
+fn parse_spin_count(text: &str) -> Result<u32> {
+    let spin_count: u32 = text.parse().context("Invalid spinCount")?;
+    if spin_count > MAX_SPIN_COUNT {
+        anyhow::bail!(
+            "spinCount {} exceeds the maximum of {}",
+            spin_count,
+            MAX_SPIN_COUNT
+        );
+    }
+    Ok(spin_count)
+}
+
+/// Parses the key size in bits, which must be one AES supports
+fn parse_key_bits(text: &str) -> Result<usize> {
+    let key_bits: usize = text.parse().context("Invalid keyBits")?;
+    if !matches!(key_bits, 128 | 192 | 256) {
+        anyhow::bail!("Unsupported keyBits {}: AES uses 128, 192 or 256", key_bits);
+    }
+    Ok(key_bits)
+}
+
+/// Parses the cipher block size, which must be [`AES_BLOCK_LEN`]
+fn parse_block_size(text: &str) -> Result<usize> {
+    let block_len: usize = text.parse().context("Invalid blockSize")?;
+    if block_len != AES_BLOCK_LEN {
+        anyhow::bail!(
+            "Unsupported blockSize {}: AES uses {}",
+            block_len,
+            AES_BLOCK_LEN
+        );
+    }
+    Ok(block_len)
+}
+
+/// Iterated hash of the salted UTF-16LE password
+fn hash_password(password: &str, params: &KeyParams, spin_count: u32) -> Vec<u8> {
+    let utf16: Vec<u8> = password.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let mut hash = params.hash.hash(&[&params.salt, &utf16]);
+    for i in 0..spin_count {
+        hash = params.hash.hash(&[&i.to_le_bytes(), &hash]);
+    }
+    hash
+}
+
+/// AES-CBC decryption without padding; `data` must be a whole number of blocks
+fn aes_cbc_decrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    fn decrypt<C: BlockDecrypt + KeyInit>(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        let cipher = C::new_from_slice(key).map_err(|_| anyhow::anyhow!("Invalid key length"))?;
+        let mut previous = iv.get(..16).context("IV is too short")?.to_vec();
+        let mut out = Vec::with_capacity(data.len());
+        for chunk in data.chunks(16) {
+            if chunk.len() != 16 {
+                anyhow::bail!("Encrypted data is not a whole number of blocks");
+            }
+            let mut block = GenericArray::clone_from_slice(chunk);
+            cipher.decrypt_block(&mut block);
+            out.extend(block.iter().zip(&previous).map(|(b, p)| b ^ p));
+            previous = chunk.to_vec();
+        }
+        Ok(out)
+    }
+    match key.len() {
+        16 => decrypt::<Aes128>(key, iv, data),
+        24 => decrypt::<Aes192>(key, iv, data),
+        32 => decrypt::<Aes256>(key, iv, data),
+        other => anyhow::bail!("Unsupported AES key length {}", other * 8),
+    }
+}
+
+fn le_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::cfb::tests::compound_file;
+    use aes::cipher::BlockEncrypt;
+
+    fn aes_cbc_encrypt(key: &[u8], iv: &[u8], data: &[u8]) -> Vec<u8> {
+        let cipher = Aes256::new_from_slice(key).unwrap();
+        let mut previous = iv[..16].to_vec();
+        let mut out = Vec::new();
+        for chunk in data.chunks(16) {
+            let mut block = GenericArray::clone_from_slice(chunk);
+            for (b, p) in block.iter_mut().zip(&previous) {
+                *b ^= p;
+            }
+            cipher.encrypt_block(&mut block);
+            previous = block.to_vec();
+            out.extend_from_slice(&block);
+        }
+        out
+    }
+
+    fn base64(data: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut out = String::new();
+        for chunk in data.chunks(3) {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+                } else {
+                    out.push('=');
+                }
+            }
+        }
+        out
+    }
+
+    /// Encrypts `package` the way Office does (AES-256, SHA-512, few spins to keep tests fast)
+    pub(crate) fn encrypted_package(package: &[u8], password: &str) -> Vec<u8> {
+        let params = KeyParams {
+            salt: (0..16).collect(),
+            hash: HashAlgorithm::Sha512,
+            key_len: 32,
+            block_len: 16,
+        };
+        let key_salt: Vec<u8> = (16..32).collect();
+        let secret: Vec<u8> = (100..132).collect();
+        let spin_count = 1000;
+
+        let password_hash = hash_password(password, &params, spin_count);
+        let derive = |block_key: &[u8]| {
+            let mut key = params.hash.hash(&[&password_hash, block_key]);
+            key.truncate(32);
+            key
+        };
+        let verifier_input: Vec<u8> = (50..66).collect();
+        let verifier_hash = params.hash.hash(&[&verifier_input]);
+
+        let mut descriptor = String::from("<encryption>");
+        let tag = |name: &str, salt: &[u8], extra: &str| {
+            format!(
+                r#"<{} saltValue="{}" hashAlgorithm="SHA512" cipherAlgorithm="AES" keyBits="256" blockSize="16" {}/>"#,
+                name,
+                base64(salt),
+                extra
+            )
+        };
+        descriptor += &tag("keyData", &key_salt, "");
+        descriptor += &tag(
+            "p:encryptedKey",
+            &params.salt,
+            &format!(
+                r#"spinCount="{}" encryptedVerifierHashInput="{}" encryptedVerifierHashValue="{}" encryptedKeyValue="{}""#,
+                spin_count,
+                base64(&aes_cbc_encrypt(
+                    &derive(&VERIFIER_INPUT_BLOCK_KEY),
+                    &params.salt,
+                    &verifier_input
+                )),
+                base64(&aes_cbc_encrypt(
+                    &derive(&VERIFIER_HASH_BLOCK_KEY),
+                    &params.salt,
+                    &verifier_hash
+                )),
+                base64(&aes_cbc_encrypt(
+                    &derive(&ENCRYPTED_KEY_BLOCK_KEY),
+                    &params.salt,
+                    &secret
+                )),
+            ),
+        );
+        descriptor += "</encryption>";
+        let mut info = vec![4, 0, 4, 0, 0x40, 0, 0, 0];
+        info.extend_from_slice(descriptor.as_bytes());
+
+        let mut encrypted = (package.len() as u64).to_le_bytes().to_vec();
+        for (index, segment) in package.chunks(SEGMENT_LEN).enumerate() {
+            let mut iv = params
+                .hash
+                .hash(&[&key_salt, &(index as u32).to_le_bytes()]);
+            iv.truncate(16);
+            let mut segment = segment.to_vec();
+            segment.resize(segment.len().div_ceil(16) * 16, 0);
+            encrypted.extend(aes_cbc_encrypt(&secret, &iv, &segment));
+        }
+        compound_file(&[("EncryptionInfo", &info), ("EncryptedPackage", &encrypted)])
+    }
+
+    #[test]
+    fn test_decrypt_package() {
+        let package: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let encrypted = encrypted_package(&package, "secret");
+
+        assert!(is_encrypted_package(&encrypted));
+        assert_eq!(decrypt_package(&encrypted, "secret").unwrap(), package);
+        let err = decrypt_package(&encrypted, "wrong").unwrap_err();
+        assert_eq!(err.to_string(), "Incorrect password");
+    }
+
+    #[test]
+    fn test_spin_count_is_bounded() {
+        assert_eq!(parse_spin_count("100000").unwrap(), 100_000);
+        assert_eq!(parse_spin_count("10000000").unwrap(), MAX_SPIN_COUNT);
+        assert!(parse_spin_count("10000001").is_err());
+        assert!(parse_spin_count("4294967295").is_err());
+        assert!(parse_spin_count("-1").is_err());
+    }
+
+    #[test]
+    fn test_key_bits_must_be_aes() {
+        for valid in [128, 192, 256] {
+            assert_eq!(parse_key_bits(&valid.to_string()).unwrap(), valid);
+        }
+        for invalid in ["0", "64", "512", "80000000000", "-128"] {
+            assert!(parse_key_bits(invalid).is_err(), "{}", invalid);
+        }
+
+        let tag = r#"<keyData saltValue="AAAA" hashAlgorithm="SHA512" cipherAlgorithm="AES" keyBits="80000000000" blockSize="16"/>"#;
+        let err = KeyParams::parse(tag).err().unwrap();
+        assert!(err.to_string().contains("keyBits"), "{}", err);
+    }
+
+    #[test]
+    fn test_block_size_must_be_aes() {
+        assert_eq!(parse_block_size("16").unwrap(), AES_BLOCK_LEN);
+        for invalid in ["0", "8", "32", "4294967295", "-16"] {
+            assert!(parse_block_size(invalid).is_err(), "{}", invalid);
+        }
+
+        let tag = r#"<keyData saltValue="AAAA" hashAlgorithm="SHA512" cipherAlgorithm="AES" keyBits="256" blockSize="1073741824"/>"#;
+        let err = KeyParams::parse(tag).err().unwrap();
+        assert!(err.to_string().contains("blockSize"), "{}", err);
+    }
+}
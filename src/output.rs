@@ -0,0 +1,3806 @@
+//! Output sink shared by all document processors
+//!
+//! Processors decide *what* to extract and *where* it should go; the sink owns
+//! the run-wide state involved in actually writing it (such as the dedup index).
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use sha2::{Digest as _, Sha256};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::common::{
+    Digest, NameForm, Numbering, ascii_name, get_unique_output_path, long_path_dir,
+    next_available_name, normalize_name, numbered_filename, safe_relative_path, sanitize_filename,
+    sha256, to_hex, write_image_to_file, write_reader_to_file,
+};
+use crate::convert::{
+    self, AnimatedFormat, ColorProfile, ConvertFormat, GifFrame, gif_frames, is_decodable,
+};
+use crate::filter::{ImageFilter, ImageSelection};
+use crate::hook::{ExecCommand, ExecFields};
+use crate::json::Json;
+use crate::manifest::{EntryStatus, Manifest, ManifestEntry};
+use crate::naming::{NameFields, NameTemplate};
+use crate::phash::hamming_distance;
+use crate::progress::{ProgressObserver, SharedObserver};
+use crate::raster::image_dimensions;
+use crate::resume::Journal;
+use crate::sniff::{canonical_extension, sniff_image_format};
+use crate::strip::strip_metadata;
+use crate::timings::{self, Phase, PhaseTimes};
+
+/// Settings controlling how the sink writes files
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    /// Hard-link byte-identical images to the first copy written in this run
+    pub link_duplicates: bool,
+    /// Permission bits for created files (Unix only, subject to the umask)
+    pub file_mode: Option<u32>,
+    /// Permission bits for created directories (Unix only, subject to the umask)
+    pub dir_mode: Option<u32>,
+    /// Owner applied to created files and directories (Unix only)
+    pub owner: Option<Owner>,
+    /// What to do when an output file (or archive entry) exists from an earlier run
+    pub on_conflict: ConflictPolicy,
+    /// Split archive output into numbered volumes of at most this many bytes
+    pub split_size: Option<u64>,
+    /// Maximum average write rate in bytes per second
+    pub max_write_rate: Option<u64>,
+    /// Record every written or skipped image for the run manifest
+    pub record_manifest: bool,
+    /// Sort output into subdirectories
+    pub group_by: Option<GroupBy>,
+    /// Subdirectories that images of particular formats go into, ahead of `group_by`
+    pub routes: Option<FormatRoutes>,
+    /// How the per-document tree of a content-addressable store links to stored objects
+    pub cas_link: CasLink,
+    /// Template for output names instead of `{base}_{n}.{ext}`
+    pub name_template: Option<NameTemplate>,
+    /// Write images that have a path inside their document to `{base}/{that path}`
+    pub keep_paths: bool,
+    /// Name images that have a name inside their document after it
+    pub original_names: Option<OriginalNames>,
+    /// Name images that have alternative text after it
+    pub name_from_alt: bool,
+    /// First number and zero-padding of numbered output names
+    pub numbering: Numbering,
+    /// Skip images whose content was already written during this run
+    pub dedupe: bool,
+    /// Skip images outside these limits
+    pub filter: ImageFilter,
+    /// Only write the images of each document this selects
+    pub only: Option<ImageSelection>,
+    /// Re-encode raster images to this format
+    pub convert_to: Option<ConvertFormat>,
+    /// What happens to the ICC profiles of converted images and thumbnails
+    pub color_profile: ColorProfile,
+    /// Also write the frames of animated GIFs as PNGs
+    pub split_gif_frames: bool,
+    /// Re-encode GIFs, keeping their animation, in this format instead of `convert_to`
+    pub animated_to: Option<AnimatedFormat>,
+    /// Re-encode GIFs that remain GIFs so each frame only stores what changed, when that
+    /// makes them smaller
+    pub optimize_gif: bool,
+    /// Also write a copy of each raster image scaled to fit within this many pixels
+    /// into `thumbs/`
+    pub thumbnail_size: Option<u32>,
+    /// Remove EXIF, XMP, ICC and text metadata from JPEG, PNG and WebP images
+    pub strip_metadata: bool,
+    /// Write only this many of each document's images, those with the most pixels
+    pub largest: Option<usize>,
+    /// On Windows, address loose output files by extended-length paths (`\\?\C:\...`),
+    /// which may be longer than 260 characters
+    pub long_paths: bool,
+    /// Unicode normalization form of output names
+    pub name_form: Option<NameForm>,
+    /// Transliterate output names to ASCII
+    pub ascii_names: bool,
+    /// Give written files the modification time of their archive entry, or else that of
+    /// their document, instead of the time they were written
+    pub preserve_timestamps: bool,
+    /// Threads converting, stripping and thumbnailing the images of one document;
+    /// 0 and 1 process them one at a time
+    pub image_threads: usize,
+    /// Command run for each image written to a file
+    pub exec: Option<ExecCommand>,
+}
+
+/// Paces writes so the average rate stays under a byte-per-second budget
+#[derive(Debug)]
+struct WriteThrottle {
+    bytes_per_sec: u64,
+    started: Instant,
+    written: u64,
+}
+
+impl WriteThrottle {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            started: Instant::now(),
+            written: 0,
+        }
+    }
+
+    /// Records `bytes` as written and sleeps until the average rate is back under budget
+    fn record(&mut self, bytes: u64) {
+        self.written += bytes;
+        let due = Duration::from_secs_f64(self.written as f64 / self.bytes_per_sec as f64);
+        let elapsed = self.started.elapsed();
+        if due > elapsed {
+            std::thread::sleep(due - elapsed);
+        }
+    }
+}
+
+/// Hashes (and paces) data as it is streamed through
+struct HashingReader<'a> {
+    inner: &'a mut dyn Read,
+    hasher: Sha256,
+    length: u64,
+    throttle: Option<&'a mut WriteThrottle>,
+}
+
+impl Read for HashingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.length += n as u64;
+        if let Some(throttle) = &mut self.throttle {
+            throttle.record(n as u64);
+        }
+        Ok(n)
+    }
+}
+
+/// Numeric ownership applied with `chown`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+/// How to resolve an output name that already exists at the destination
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum ConflictPolicy {
+    /// Write under a new name with a numeric suffix
+    #[default]
+    Rename,
+    /// Replace the existing entry
+    Overwrite,
+    /// Keep the existing entry and skip the new image
+    Skip,
+    /// Abort the document with an error
+    Error,
+}
+
+/// How `--original-names` names an image after its name inside the document
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum OriginalNames {
+    /// The internal name alone: `image42.png`
+    #[default]
+    Bare,
+    /// Prefixed with the document name: `report_image42.png`
+    Prefixed,
+}
+
+/// How output files are sorted into subdirectories of the output location
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum GroupBy {
+    /// One subdirectory per image format (`png/`, `jpg/`, `svg/`, ...)
+    Format,
+}
+
+/// Subdirectories of the output location that images of particular formats go into,
+/// e.g. `svg=vectors/,jpg=photos/`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatRoutes {
+    /// Canonical extension and subdirectory, in the order given
+    routes: Vec<(String, String)>,
+}
+
+impl FormatRoutes {
+    /// Subdirectory images with the canonical `extension` are routed to, if any
+    fn dir(&self, extension: &str) -> Option<&str> {
+        self.routes
+            .iter()
+            .find(|(format, _)| format == extension)
+            .map(|(_, dir)| dir.as_str())
+    }
+}
+
+/// Link type used for the per-document tree of a content-addressable store
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum CasLink {
+    /// Relative symbolic links, so the store can be moved as a whole
+    #[default]
+    Symlink,
+    /// Hard links to the stored objects
+    Hardlink,
+}
+
+/// Subdirectory of a content-addressable store holding one file per unique image
+const CAS_OBJECTS_DIR: &str = "by-hash";
+/// Subdirectory of a content-addressable store holding the per-document link tree
+const CAS_DOCUMENTS_DIR: &str = "by-document";
+/// Subdirectory of the output location holding `--thumbnails` copies
+const THUMBNAILS_DIR: &str = "thumbs";
+
+/// Images held back per image thread before a batch is prepared and written
+const IMAGES_PER_THREAD: usize = 8;
+
+/// Where the sink puts extracted images
+#[derive(Debug)]
+enum Target {
+    /// Loose files under the base directory
+    Directory,
+    /// Unique objects under `by-hash/` of the store root (the base directory),
+    /// linked from a per-document tree under `by-document/`
+    Cas,
+    /// Entries inside a single zip archive
+    Zip(Box<ZipTarget>),
+    /// Entries inside a single tar archive, optionally gzip-compressed
+    Tar(Box<TarTarget>),
+    /// The bytes of a single image on standard output; true once it has been written
+    Stdout(bool),
+    /// Images kept in memory, for callers without a filesystem
+    Memory(Vec<ExtractedImage>),
+    /// Nothing is written; images are named and recorded as if they were written under
+    /// the base directory, to estimate a run
+    DryRun,
+}
+
+/// What is known about an image besides its position in the document, for naming it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImageDetails<'a> {
+    /// Name inside the document, e.g. `word/media/image1.png`
+    pub original: Option<&'a str>,
+    /// Subdirectory of the output the image goes into, e.g. `headers`
+    pub section: Option<&'a str>,
+    /// Alternative text, which names the image with `OutputOptions::name_from_alt`
+    pub alt_text: Option<&'a str>,
+    /// Label appended to the file stem, e.g. `footnote` for `report_3_footnote.png`
+    pub suffix: Option<&'a str>,
+}
+
+/// An image extracted into memory by a sink created with [`OutputSink::new_memory`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractedImage {
+    /// Output name, with `/` separating any subdirectories (`cover.jpg`, `png/doc_1.png`)
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Run-wide state shared by a sink and the sinks forked from it for parallel workers
+#[derive(Debug, Default)]
+struct SharedState {
+    /// Output paths handed out this run, so concurrent workers never pick the same name
+    claimed: Mutex<HashSet<PathBuf>>,
+    /// Content hash -> first output path written with that content
+    written: Mutex<HashMap<Digest, PathBuf>>,
+    /// Perceptual hash -> output path, for near-duplicate detection
+    perceptual: Mutex<Vec<(u64, PathBuf)>>,
+    /// Images not written because identical content was already written this run
+    duplicates: AtomicUsize,
+    /// Images not written because their output already exists
+    existing: AtomicUsize,
+    /// Output path -> name of the image inside its document, for the manifest's `entry`,
+    /// until the image is written
+    originals: Mutex<HashMap<PathBuf, String>>,
+    /// Output path -> files from earlier runs whose names it was renamed away from,
+    /// so an image identical to one of them is not written again
+    collisions: Mutex<HashMap<PathBuf, Vec<PathBuf>>>,
+    /// With `--resume`, the journal each loose output file is noted in before it is written
+    journal: OnceLock<Arc<Journal>>,
+    /// Observer told about the run's images and warnings
+    observer: OnceLock<SharedObserver>,
+}
+
+/// Destination for extracted images during a single run
+#[derive(Debug)]
+pub struct OutputSink {
+    base_dir: PathBuf,
+    options: OutputOptions,
+    target: Target,
+    shared: Arc<SharedState>,
+    throttle: Option<WriteThrottle>,
+    /// Document currently being processed, for manifest entries
+    document: PathBuf,
+    /// Metadata of the current document, for the run report
+    metadata: Vec<(String, String)>,
+    /// Entries of the current document that could not be read, with the error, for the
+    /// run report
+    damaged: Vec<(String, String)>,
+    /// Subdirectory of the output that the current document's images go into
+    document_dir: Option<String>,
+    /// Images of the current document held back until it is finished, to pick the
+    /// largest of them
+    pending: Vec<PendingImage>,
+    /// Time the current document spent in each phase, for the run report
+    timings: PhaseTimes,
+    /// Output paths handed out for images of the current document that `only` leaves out
+    unselected: RefCell<HashSet<PathBuf>>,
+    /// Images of the current document that were found but not written: left out by
+    /// `only`, or skipped while writing
+    skipped_count: usize,
+    /// With `preserve_timestamps`, the modification time of the current document
+    document_time: Option<SystemTime>,
+    /// With `preserve_timestamps`, output path -> modification time of the archive entry
+    /// the image came from
+    entry_times: HashMap<PathBuf, SystemTime>,
+    manifest: Option<Manifest>,
+}
+
+/// An image waiting for its document to finish, with the fields annotated on it
+#[derive(Debug)]
+struct PendingImage {
+    output_path: PathBuf,
+    data: Vec<u8>,
+    fields: Vec<(String, Json)>,
+}
+
+/// The work on an image's content that does not depend on the sink's state, so it can
+/// be done for several images at once
+struct Prepared {
+    /// Converted and stripped data, if it differs from the extracted image
+    data: Option<Vec<u8>>,
+    /// With `thumbnail_size`, the thumbnail's format and data
+    thumbnail: Option<Result<(ConvertFormat, Vec<u8>)>>,
+    /// With `split_gif_frames`, the frames of a GIF
+    frames: Option<Result<Vec<GifFrame>>>,
+    /// Warnings about the conversions, given once the image is written
+    warnings: Vec<String>,
+    /// Why the image could not be converted to the format its output path names, in
+    /// which case it is skipped rather than written under the wrong extension
+    unconvertible: Option<anyhow::Error>,
+}
+
+impl Prepared {
+    /// Converts, optimizes and strips the image at `output_path`, then encodes its
+    /// thumbnail and GIF frames from the data that will be written
+    fn new(options: &OutputOptions, output_path: &Path, data: &[u8]) -> Self {
+        let mut warnings = Vec::new();
+        let converted = match animate(options, output_path, data) {
+            Ok(None) => convert(options, output_path, data),
+            animated => animated,
+        };
+        let (converted, unconvertible) = match converted {
+            Ok(converted) => (converted, None),
+            Err(e) => (None, Some(e)),
+        };
+        let optimized = optimize_gif(
+            options,
+            output_path,
+            converted.as_deref().unwrap_or(data),
+            &mut warnings,
+        );
+        let converted = optimized.or(converted);
+        let stripped = options
+            .strip_metadata
+            .then(|| strip_metadata(converted.as_deref().unwrap_or(data)))
+            .flatten();
+        let prepared = stripped.or(converted);
+        let data = prepared.as_deref().unwrap_or(data);
+        let frames = (options.split_gif_frames && sniff_image_format(data) == Some("gif"))
+            .then(|| gif_frames(data));
+        let thumbnail = options
+            .thumbnail_size
+            .and_then(|max_edge| thumbnail(options, data, max_edge));
+        Self {
+            data: prepared,
+            thumbnail,
+            frames,
+            warnings,
+            unconvertible,
+        }
+    }
+}
+
+/// With `animated_to`, re-encodes a GIF whose output path was given the target extension,
+/// keeping its animation. Returns None if the data is written as it is.
+fn animate(options: &OutputOptions, output_path: &Path, data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let Some(format) = options.animated_to else {
+        return Ok(None);
+    };
+    let targeted = output_path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(format.extension()));
+    if !targeted || sniff_image_format(data) != Some("gif") {
+        return Ok(None);
+    }
+    format.convert(data).map(Some)
+}
+
+/// With `optimize_gif`, the smaller re-encoding of a GIF, if there is one
+fn optimize_gif(
+    options: &OutputOptions,
+    output_path: &Path,
+    data: &[u8],
+    warnings: &mut Vec<String>,
+) -> Option<Vec<u8>> {
+    if !options.optimize_gif || sniff_image_format(data) != Some("gif") {
+        return None;
+    }
+    match convert::optimize_gif(data) {
+        Ok(optimized) => optimized,
+        Err(e) => {
+            warnings.push(format!("Not optimizing {}: {:#}", output_path.display(), e));
+            None
+        }
+    }
+}
+
+/// With `convert_to`, re-encodes an image whose output path was given the target
+/// extension. Returns None if the data is written as it is.
+fn convert(options: &OutputOptions, output_path: &Path, data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let (Some(format), Some(actual)) = (options.convert_to, sniff_image_format(data)) else {
+        return Ok(None);
+    };
+    let targeted = output_path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(format.extension()));
+    if !targeted || !format.converts(actual) {
+        return Ok(None);
+    }
+    format.convert(data, options.color_profile).map(Some)
+}
+
+/// Scales an image down to fit within `max_edge` pixels. Thumbnails of JPEGs are JPEGs
+/// and those of other raster images PNGs, unless `convert_to` names a format.
+/// Vector images get no thumbnail.
+fn thumbnail(
+    options: &OutputOptions,
+    data: &[u8],
+    max_edge: u32,
+) -> Option<Result<(ConvertFormat, Vec<u8>)>> {
+    let actual = sniff_image_format(data).filter(|format| is_decodable(format))?;
+    let format = options.convert_to.unwrap_or(if actual == "jpg" {
+        ConvertFormat::Jpg
+    } else {
+        ConvertFormat::Png
+    });
+    Some(
+        format
+            .thumbnail(data, max_edge, options.color_profile)
+            .map(|data| (format, data)),
+    )
+}
+
+impl OutputSink {
+    /// Creates a sink writing loose files into `base_dir`
+    pub fn new(base_dir: PathBuf, options: OutputOptions) -> Self {
+        let base_dir = match options.long_paths {
+            true => long_path_dir(&base_dir),
+            false => base_dir,
+        };
+        Self {
+            base_dir,
+            throttle: options.max_write_rate.map(WriteThrottle::new),
+            manifest: options.record_manifest.then(Manifest::default),
+            options,
+            target: Target::Directory,
+            shared: Arc::default(),
+            document: PathBuf::new(),
+            metadata: Vec::new(),
+            damaged: Vec::new(),
+            document_dir: None,
+            pending: Vec::new(),
+            timings: PhaseTimes::default(),
+            unselected: RefCell::default(),
+            skipped_count: 0,
+            document_time: None,
+            entry_times: HashMap::new(),
+        }
+    }
+
+    /// Creates a sink writing each unique image once into the content-addressable store
+    /// at `store_dir`, linked from a human-readable tree organized by document
+    pub fn new_cas(store_dir: PathBuf, options: OutputOptions) -> Self {
+        Self {
+            target: Target::Cas,
+            ..Self::new(store_dir, options)
+        }
+    }
+
+    /// Creates a sink writing every image into the zip archive at `archive_path`.
+    /// An existing archive is appended to, resolving name clashes with `options.on_conflict`.
+    pub fn new_zip(archive_path: PathBuf, options: OutputOptions) -> Result<Self> {
+        let zip = ZipTarget::open(
+            archive_path.clone(),
+            options.on_conflict,
+            options.split_size,
+        )?;
+        Ok(Self {
+            base_dir: archive_path,
+            throttle: options.max_write_rate.map(WriteThrottle::new),
+            manifest: options.record_manifest.then(Manifest::default),
+            options,
+            target: Target::Zip(Box::new(zip)),
+            shared: Arc::default(),
+            document: PathBuf::new(),
+            metadata: Vec::new(),
+            damaged: Vec::new(),
+            document_dir: None,
+            pending: Vec::new(),
+            timings: PhaseTimes::default(),
+            unselected: RefCell::default(),
+            skipped_count: 0,
+            document_time: None,
+            entry_times: HashMap::new(),
+        })
+    }
+
+    /// Creates a sink writing every image into the tar archive at `archive_path`, gzip
+    /// compressed if `gzip` is set. Tar archives cannot be appended to: an existing
+    /// archive or volume is only replaced with `ConflictPolicy::Overwrite`.
+    pub fn new_tar(archive_path: PathBuf, gzip: bool, options: OutputOptions) -> Result<Self> {
+        let tar = TarTarget::create(
+            archive_path.clone(),
+            gzip,
+            options.on_conflict,
+            options.split_size,
+        )?;
+        Ok(Self {
+            target: Target::Tar(Box::new(tar)),
+            ..Self::new(archive_path, options)
+        })
+    }
+
+    /// Creates a sink writing the bytes of a single image to standard output, for
+    /// pipelines. Writing a second image is an error. Output paths are bare file names,
+    /// used only in messages and the manifest.
+    pub fn new_stdout(options: OutputOptions) -> Self {
+        Self {
+            target: Target::Stdout(false),
+            ..Self::new(PathBuf::new(), options)
+        }
+    }
+
+    /// Creates a sink that keeps images in memory instead of writing them anywhere, for
+    /// callers without a filesystem such as a WebAssembly front-end. Output paths are the
+    /// names of [`ExtractedImage`]s, and [`OutputSink::take_images`] hands them over.
+    pub fn new_memory(options: OutputOptions) -> Self {
+        Self {
+            target: Target::Memory(Vec::new()),
+            ..Self::new(PathBuf::new(), options)
+        }
+    }
+
+    /// Creates a sink that goes through every step of writing loose files into
+    /// `base_dir` except the writing itself, so the manifest shows what a run would write
+    pub fn new_dry_run(base_dir: PathBuf, options: OutputOptions) -> Self {
+        Self {
+            target: Target::DryRun,
+            ..Self::new(base_dir, options)
+        }
+    }
+
+    /// Removes and returns the images kept by a memory sink, in the order they were
+    /// extracted. Other sinks keep no images and return none.
+    pub fn take_images(&mut self) -> Vec<ExtractedImage> {
+        match &mut self.target {
+            Target::Memory(images) => std::mem::take(images),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Creates a sink for a parallel worker: it writes to the same place and shares the
+    /// names, duplicates and perceptual hashes seen this run, but records its own manifest.
+    /// Zip output has a single writer and cannot be forked.
+    pub fn fork(&self) -> Result<Self> {
+        let target = match self.target {
+            Target::Directory => Target::Directory,
+            Target::Cas => Target::Cas,
+            Target::DryRun => Target::DryRun,
+            Target::Zip(_) | Target::Tar(_) => {
+                anyhow::bail!("Archive output cannot be written in parallel")
+            }
+            Target::Stdout(_) => anyhow::bail!("Standard output cannot be written in parallel"),
+            Target::Memory(_) => anyhow::bail!("In-memory output cannot be written in parallel"),
+        };
+        Ok(Self {
+            base_dir: self.base_dir.clone(),
+            options: self.options.clone(),
+            target,
+            shared: Arc::clone(&self.shared),
+            throttle: self.options.max_write_rate.map(WriteThrottle::new),
+            document: PathBuf::new(),
+            metadata: Vec::new(),
+            damaged: Vec::new(),
+            document_dir: None,
+            pending: Vec::new(),
+            timings: PhaseTimes::default(),
+            unselected: RefCell::default(),
+            skipped_count: 0,
+            document_time: None,
+            entry_times: HashMap::new(),
+            manifest: self.options.record_manifest.then(Manifest::default),
+        })
+    }
+
+    /// Makes this sink share the run-wide state (claimed names, written content,
+    /// duplicate count) of `other`, for a separate output that belongs to the same run
+    pub fn join_run(mut self, other: &OutputSink) -> Self {
+        self.shared = Arc::clone(&other.shared);
+        self
+    }
+
+    /// Notes every loose output file of the run in `journal` before it is written, so a
+    /// resumed run can delete the files of documents this run did not finish
+    pub(crate) fn set_journal(&self, journal: Arc<Journal>) {
+        let _ = self.shared.journal.set(journal);
+    }
+
+    /// The run's resume journal, if any
+    pub(crate) fn journal(&self) -> Option<&Journal> {
+        self.shared.journal.get().map(Arc::as_ref)
+    }
+
+    /// Tells `observer` about the images and warnings of this sink and of those forked
+    /// from or joined to it
+    pub(crate) fn set_observer(&self, observer: SharedObserver) {
+        let _ = self.shared.observer.set(observer);
+    }
+
+    /// The run's progress observer, if any
+    fn observer(&self) -> Option<&dyn ProgressObserver> {
+        self.shared
+            .observer
+            .get()
+            .map(|observer| observer.0.as_ref())
+    }
+
+    /// Prints a warning about the current document and passes it to the observer.
+    /// In-memory output prints nothing.
+    pub(crate) fn warn(&self, message: &str) {
+        if !matches!(self.target, Target::Memory(_)) {
+            eprintln!("Warning: {}", message);
+        }
+        if let Some(observer) = self.observer() {
+            observer.warning(&self.document, message);
+        }
+    }
+
+    /// Warns when an archive entry is larger than a whole `--split-size` volume
+    fn warn_oversized(&self, name: &str, size: u64) {
+        let oversized = match &self.target {
+            Target::Zip(zip) => zip.is_oversized(name, size),
+            Target::Tar(tar) => tar.is_oversized(name, size),
+            _ => false,
+        };
+        if oversized {
+            self.warn(&format!(
+                "'{}' is larger than --split-size; writing it to its own volume.",
+                name
+            ));
+        }
+    }
+
+    /// Notes in the resume journal that `path` is about to be written for the current
+    /// document. Only loose files are noted; archives and dry runs leave nothing behind.
+    fn record_output(&self, path: &Path) -> Result<()> {
+        match (&self.target, self.journal()) {
+            (Target::Directory | Target::Cas, Some(journal)) => {
+                journal.record_file(&self.document, path)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes over the manifest entries recorded by a forked worker sink
+    pub fn merge(&mut self, worker: OutputSink) -> Result<()> {
+        if let (Some(manifest), Some(worker_manifest)) = (&mut self.manifest, worker.finish()?) {
+            manifest.entries.extend(worker_manifest.entries);
+        }
+        Ok(())
+    }
+
+    /// Generates the output path for an image.
+    /// For zip output this is the archive path joined with the entry name.
+    pub fn output_path(
+        &self,
+        base_name: &str,
+        seq_index: usize,
+        total_images: usize,
+        extension: &str,
+    ) -> Result<PathBuf> {
+        let details = ImageDetails::default();
+        self.detailed_output_path(base_name, seq_index, total_images, extension, details)
+    }
+
+    /// Like [`OutputSink::output_path`], for an image with a name of its own inside the
+    /// document (e.g. `word/media/image1.png`), available to name templates as `{original}`
+    /// and kept as-is with `keep_paths`
+    pub fn original_output_path(
+        &self,
+        base_name: &str,
+        seq_index: usize,
+        total_images: usize,
+        extension: &str,
+        original: &str,
+    ) -> Result<PathBuf> {
+        let details = ImageDetails {
+            original: Some(original),
+            ..Default::default()
+        };
+        self.detailed_output_path(base_name, seq_index, total_images, extension, details)
+    }
+
+    /// Like [`OutputSink::original_output_path`], placing the image in the `section`
+    /// subdirectory (e.g. `headers/`) of the output
+    pub fn section_output_path(
+        &self,
+        section: &str,
+        base_name: &str,
+        seq_index: usize,
+        total_images: usize,
+        extension: &str,
+        original: &str,
+    ) -> Result<PathBuf> {
+        let details = ImageDetails {
+            original: Some(original),
+            section: Some(section),
+            ..Default::default()
+        };
+        self.detailed_output_path(base_name, seq_index, total_images, extension, details)
+    }
+
+    /// Like [`OutputSink::output_path`], using everything `details` knows about the image
+    pub fn detailed_output_path(
+        &self,
+        base_name: &str,
+        seq_index: usize,
+        total_images: usize,
+        extension: &str,
+        details: ImageDetails,
+    ) -> Result<PathBuf> {
+        let output_path =
+            self.claim_output_path(base_name, seq_index, total_images, extension, details)?;
+        if let Some(observer) = self.observer() {
+            observer.image_found(&self.document, &output_path);
+        }
+        if let Some(only) = &self.options.only
+            && !only.selects(seq_index, details.original)
+        {
+            // Free the name, so the selected images are named as in a full run
+            self.shared.claimed.lock().unwrap().remove(&output_path);
+            self.unselected.borrow_mut().insert(output_path.clone());
+            return Ok(output_path);
+        }
+        // Kept for the manifest, and to name an image that cannot be converted
+        let converts = self.options.convert_to.is_some() || self.options.animated_to.is_some();
+        if let Some(original) = details.original
+            && (self.manifest.is_some() || converts)
+        {
+            self.shared
+                .originals
+                .lock()
+                .unwrap()
+                .insert(output_path.clone(), original.to_string());
+        }
+        Ok(output_path)
+    }
+
+    fn claim_output_path(
+        &self,
+        base_name: &str,
+        seq_index: usize,
+        total_images: usize,
+        extension: &str,
+        details: ImageDetails,
+    ) -> Result<PathBuf> {
+        let ImageDetails {
+            original, section, ..
+        } = details;
+        let converted = match self.options.animated_to {
+            Some(format) if canonical_extension(extension) == "gif" => Some(format.extension()),
+            _ => self
+                .options
+                .convert_to
+                .filter(|format| format.converts(extension))
+                .map(ConvertFormat::extension),
+        };
+        let extension = converted.unwrap_or(extension);
+        let filename = match (&self.options.name_template, original) {
+            (_, Some(original)) if self.options.keep_paths => {
+                let mut path = sanitize_filename(base_name);
+                for component in original.split(['/', '\\']) {
+                    let component = sanitize_filename(component);
+                    if !component.is_empty() && component != "." && component != ".." {
+                        path = format!("{}/{}", path, component);
+                    }
+                }
+                match converted {
+                    Some(extension) => Path::new(&path)
+                        .with_extension(extension)
+                        .to_string_lossy()
+                        .to_string(),
+                    None => path,
+                }
+            }
+            _ if self.options.name_from_alt
+                && let Some(stem) = details.alt_text.and_then(alt_stem) =>
+            {
+                format!("{}.{}", stem, extension)
+            }
+            (_, Some(original))
+                if let Some(mode) = self.options.original_names
+                    && let Some(stem) = original_stem(original) =>
+            {
+                match mode {
+                    OriginalNames::Bare => format!("{}.{}", stem, extension),
+                    OriginalNames::Prefixed => {
+                        format!("{}_{}.{}", sanitize_filename(base_name), stem, extension)
+                    }
+                }
+            }
+            (Some(template), _) => template.render(&NameFields {
+                doc: base_name,
+                title: self.metadata_value("title"),
+                author: self.metadata_value("author"),
+                seq_index,
+                extension,
+                original,
+                series: self.metadata_value("series"),
+                series_index: self.metadata_value("series_index"),
+                numbering: self.options.numbering,
+            }),
+            (None, _) => numbered_filename(
+                base_name,
+                seq_index,
+                total_images,
+                extension,
+                self.options.numbering,
+            ),
+        };
+        let filename = match details.suffix {
+            Some(suffix) => with_stem_suffix(&filename, suffix),
+            None => filename,
+        };
+        let filename = self.portable_path(&filename);
+        let group = match (section, self.group_dir(extension)) {
+            (Some(section), Some(group)) => Some(format!("{}/{}", section, group)),
+            (section, group) => group.or(section.map(str::to_string)),
+        };
+        let group = match (&self.document_dir, group) {
+            (Some(dir), Some(group)) => Some(format!("{}/{}", dir, group)),
+            (dir, group) => group.or(dir.clone()),
+        };
+        let group = group.map(|group| self.portable_path(&group));
+        match &self.target {
+            Target::Directory | Target::Cas | Target::DryRun => {
+                let mut dir = self.base_dir.clone();
+                if let Target::Cas = self.target {
+                    let document = self
+                        .document
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    dir = dir.join(CAS_DOCUMENTS_DIR).join(document);
+                }
+                if let Some(group) = &group {
+                    dir = dir.join(group);
+                }
+                // Claim the name under the lock so parallel workers never share it
+                let mut claimed = self.shared.claimed.lock().unwrap();
+                let output_path =
+                    get_unique_output_path(&dir, &filename, self.options.on_conflict, |path| {
+                        claimed.contains(path)
+                    })?;
+                if self.options.on_conflict == ConflictPolicy::Rename {
+                    let existing = earlier_names(&dir, &filename, &output_path)
+                        .into_iter()
+                        .filter(|path| !claimed.contains(path) && path.is_file())
+                        .collect::<Vec<_>>();
+                    if !existing.is_empty() {
+                        self.shared
+                            .collisions
+                            .lock()
+                            .unwrap()
+                            .insert(output_path.clone(), existing);
+                    }
+                }
+                claimed.insert(output_path.clone());
+                self.record_output(&output_path)?;
+                Ok(output_path)
+            }
+            Target::Zip(zip) => {
+                let mut filename = filename;
+                if let Some(group) = group {
+                    filename = format!("{}/{}", group, filename);
+                }
+                Ok(self.base_dir.join(zip.entry_name_for(&filename)?))
+            }
+            Target::Tar(tar) => {
+                let mut filename = filename;
+                if let Some(group) = group {
+                    filename = format!("{}/{}", group, filename);
+                }
+                Ok(self.base_dir.join(tar.entry_name_for(&filename)?))
+            }
+            Target::Stdout(_) => Ok(PathBuf::from(filename)),
+            Target::Memory(_) => {
+                let mut filename = filename;
+                if let Some(group) = group {
+                    filename = format!("{}/{}", group, filename);
+                }
+                self.claim_memory_name(&filename)
+            }
+        }
+    }
+
+    /// Subdirectory an image with `extension` is grouped into: the one it is routed to,
+    /// or else its group if grouping is enabled
+    fn group_dir(&self, extension: &str) -> Option<String> {
+        let extension = canonical_extension(extension);
+        if let Some(dir) = self.options.routes.as_ref().and_then(|r| r.dir(&extension)) {
+            return Some(dir.to_string());
+        }
+        match self.options.group_by? {
+            GroupBy::Format => Some(extension),
+        }
+    }
+
+    /// Writes image data to `output_path`, pacing writes if a rate limit is set.
+    /// When duplicate linking is enabled and identical content was already written
+    /// during this run, the new path is hard-linked to the existing file instead.
+    pub fn write_image(&mut self, output_path: &Path, data: &[u8]) -> Result<()> {
+        if self.skip_unselected(output_path) {
+            self.record_skipped(data, "not selected", Vec::new());
+            return Ok(());
+        }
+        if let Some(reason) = self.options.filter.rejects(data) {
+            status!("  Skipping {}: {}", output_path.display(), reason);
+            self.record_skipped(
+                data,
+                "filtered",
+                vec![("detail".to_string(), Json::from(reason))],
+            );
+            self.skipped_count += 1;
+            return Ok(());
+        }
+        if self.options.largest.is_some() || self.prepares_in_parallel() {
+            self.pending.push(PendingImage {
+                output_path: output_path.to_path_buf(),
+                data: data.to_vec(),
+                fields: Vec::new(),
+            });
+            // Without --largest, images are written in batches as the document goes on
+            if self.options.largest.is_none()
+                && self.pending.len() >= self.options.image_threads * IMAGES_PER_THREAD
+            {
+                let batch = std::mem::take(&mut self.pending);
+                self.write_batch(batch)?;
+            }
+            return Ok(());
+        }
+        let converting = timings::start();
+        let prepared = Prepared::new(&self.options, output_path, data);
+        self.record_time(Phase::Convert, converting);
+        let writing = timings::start();
+        self.write_accepted(output_path, data, prepared)?;
+        self.record_time(Phase::Write, writing);
+        Ok(())
+    }
+
+    /// Whether the image at `output_path` is one `only` leaves out, counting it if so
+    fn skip_unselected(&mut self, output_path: &Path) -> bool {
+        if !self.unselected.get_mut().remove(output_path) {
+            return false;
+        }
+        status!("  Skipping {}: not selected", output_path.display());
+        self.skipped_count += 1;
+        true
+    }
+
+    /// Whether images are held back to be prepared on several threads, which only pays
+    /// off when there is conversion or encoding work to do
+    fn prepares_in_parallel(&self) -> bool {
+        self.options.image_threads > 1
+            && (self.options.convert_to.is_some()
+                || self.options.animated_to.is_some()
+                || self.options.optimize_gif
+                || self.options.thumbnail_size.is_some()
+                || self.options.split_gif_frames
+                || self.options.strip_metadata)
+    }
+
+    /// Prepares held-back images on `image_threads` threads, then writes them in their
+    /// original order with the fields annotated on them
+    fn write_batch(&mut self, images: Vec<PendingImage>) -> Result<()> {
+        let threads = self.options.image_threads.clamp(1, images.len().max(1));
+        let chunk_size = images.len().div_ceil(threads).max(1);
+        let options = &self.options;
+        let converting = timings::start();
+        let prepared: Vec<Prepared> = thread::scope(|scope| {
+            let handles: Vec<_> = images
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|image| Prepared::new(options, &image.output_path, &image.data))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("image preparation thread panicked"))
+                .collect()
+        });
+        self.record_time(Phase::Convert, converting);
+        let writing = timings::start();
+        for (image, prepared) in images.into_iter().zip(prepared) {
+            self.write_accepted(&image.output_path, &image.data, prepared)?;
+            for (key, value) in image.fields {
+                self.annotate(&key, value);
+            }
+        }
+        self.record_time(Phase::Write, writing);
+        Ok(())
+    }
+
+    /// Writes an image that passed the filters, unless it is a duplicate
+    fn write_accepted(
+        &mut self,
+        output_path: &Path,
+        data: &[u8],
+        prepared: Prepared,
+    ) -> Result<()> {
+        for warning in &prepared.warnings {
+            self.warn(warning);
+        }
+        if let Some(error) = prepared.unconvertible {
+            // Free the name, so the next image takes it rather than leaving a gap
+            self.shared.claimed.lock().unwrap().remove(output_path);
+            let entry = self.shared.originals.lock().unwrap().remove(output_path);
+            let entry = entry.unwrap_or_else(|| {
+                let name = output_path.file_name().unwrap_or(output_path.as_os_str());
+                name.to_string_lossy().to_string()
+            });
+            self.record_damaged(&entry, &error.context("cannot convert the image"));
+            self.skipped_count += 1;
+            return Ok(());
+        }
+        if self.options.dedupe && self.skip_duplicate(output_path, data) {
+            self.skipped_count += 1;
+            return Ok(());
+        }
+        let data = prepared.data.as_deref().unwrap_or(data);
+        if self.skip_identical_existing(output_path, data) {
+            self.count_existing();
+            return Ok(());
+        }
+        if !self.write_image_unthrottled(output_path, data)? {
+            self.record_skipped(data, "output exists", Vec::new());
+            self.count_existing();
+            return Ok(());
+        }
+        if let Some(throttle) = &mut self.throttle {
+            throttle.record(data.len() as u64);
+        }
+        if self.manifest.is_some() {
+            self.record_written(output_path, data.len() as u64, sha256(data));
+        } else {
+            self.shared.originals.lock().unwrap().remove(output_path);
+        }
+        if let Some(observer) = self.observer() {
+            observer.image_written(&self.document, output_path, data.len() as u64);
+        }
+        self.run_exec(output_path);
+        if let Some(frames) = prepared.frames {
+            self.write_gif_frames(output_path, frames)?;
+        }
+        if let Some(thumbnail) = prepared.thumbnail {
+            self.write_thumbnail(output_path, thumbnail)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the thumbnail of the image at `output_path` to the same relative path
+    /// under `thumbs/`
+    fn write_thumbnail(
+        &mut self,
+        output_path: &Path,
+        thumbnail: Result<(ConvertFormat, Vec<u8>)>,
+    ) -> Result<()> {
+        let (format, thumbnail) = match thumbnail {
+            Ok(thumbnail) => thumbnail,
+            Err(e) => {
+                self.warn(&format!(
+                    "No thumbnail for {}: {:#}",
+                    output_path.display(),
+                    e
+                ));
+                return Ok(());
+            }
+        };
+
+        let mut relative = output_path
+            .strip_prefix(&self.base_dir)
+            .unwrap_or(output_path)
+            .to_path_buf();
+        let extension = relative
+            .extension()
+            .map(|e| canonical_extension(&e.to_string_lossy()))
+            .unwrap_or_default();
+        if extension != format.extension() {
+            relative.set_extension(format.extension());
+        }
+        let dir = self.base_dir.join(THUMBNAILS_DIR);
+        let thumbnail_path =
+            self.derived_output_path(&dir, &relative.to_string_lossy().replace('\\', "/"))?;
+        if self.write_image_unthrottled(&thumbnail_path, &thumbnail)? && self.manifest.is_some() {
+            self.record_written(&thumbnail_path, thumbnail.len() as u64, sha256(&thumbnail));
+            self.annotate(
+                "thumbnail_of",
+                Json::from(output_path.display().to_string()),
+            );
+        }
+        Ok(())
+    }
+
+    /// Writes the frames of an animated GIF as PNGs in `{gif name}_frames/` beside it,
+    /// with each frame's delay listed in `frames.json`. GIFs with a single frame are left
+    /// alone, and GIFs that cannot be decoded only produce a warning.
+    fn write_gif_frames(&mut self, gif_path: &Path, frames: Result<Vec<GifFrame>>) -> Result<()> {
+        let frames = match frames {
+            Ok(frames) if frames.len() > 1 => frames,
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                self.warn(&format!(
+                    "Not splitting {} into frames: {:#}",
+                    gif_path.display(),
+                    e
+                ));
+                return Ok(());
+            }
+        };
+        let gif_name = gif_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let stem = gif_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let frames_dir = format!("{}_frames", stem);
+        let dir = gif_path.parent().unwrap_or(&self.base_dir).to_path_buf();
+        let width = frames.len().to_string().len().max(3);
+        status!("  Splitting {} frames into {}/", frames.len(), frames_dir);
+
+        let mut listing = Vec::new();
+        for (index, frame) in frames.iter().enumerate() {
+            let name = format!(
+                "{}/frame_{:0width$}.png",
+                frames_dir,
+                index + 1,
+                width = width
+            );
+            let frame_path = self.derived_output_path(&dir, &name)?;
+            if self.write_image_unthrottled(&frame_path, &frame.png)? && self.manifest.is_some() {
+                self.record_written(&frame_path, frame.png.len() as u64, sha256(&frame.png));
+                self.annotate("frame_of", Json::from(gif_path.display().to_string()));
+            }
+            let file = frame_path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            listing.push(Json::object([
+                ("file", Json::from(file)),
+                ("delay_ms", Json::from(frame.delay_ms)),
+            ]));
+        }
+
+        let sidecar = Json::object([
+            ("source", Json::from(gif_name)),
+            ("frames", Json::Array(listing)),
+        ]);
+        let sidecar_path =
+            self.derived_output_path(&dir, &format!("{}/frames.json", frames_dir))?;
+        self.write_image_unthrottled(&sidecar_path, sidecar.to_pretty_string().as_bytes())?;
+        Ok(())
+    }
+
+    /// Makes a relative output path safe to write everywhere, normalizing or transliterating
+    /// it first as the options ask
+    fn portable_path(&self, path: &str) -> String {
+        if self.options.ascii_names {
+            safe_relative_path(&ascii_name(path))
+        } else if let Some(form) = self.options.name_form {
+            safe_relative_path(&normalize_name(path, form))
+        } else {
+            safe_relative_path(path)
+        }
+    }
+
+    /// Claims the output path of a file derived from an image, `name` being relative to `dir`
+    fn derived_output_path(&self, dir: &Path, name: &str) -> Result<PathBuf> {
+        let name = &self.portable_path(name);
+        match &self.target {
+            Target::Directory | Target::Cas | Target::DryRun => {
+                let mut claimed = self.shared.claimed.lock().unwrap();
+                let path = get_unique_output_path(dir, name, self.options.on_conflict, |path| {
+                    claimed.contains(path)
+                })?;
+                claimed.insert(path.clone());
+                self.record_output(&path)?;
+                Ok(path)
+            }
+            Target::Zip(zip) => {
+                let entry = self.entry_name(&dir.join(name))?;
+                Ok(self.base_dir.join(zip.entry_name_for(&entry)?))
+            }
+            Target::Tar(tar) => {
+                let entry = self.entry_name(&dir.join(name))?;
+                Ok(self.base_dir.join(tar.entry_name_for(&entry)?))
+            }
+            Target::Stdout(_) => Ok(dir.join(name)),
+            Target::Memory(_) => self.claim_memory_name(&dir.join(name).to_string_lossy()),
+        }
+    }
+
+    /// Claims a unique image name for a memory sink
+    fn claim_memory_name(&self, name: &str) -> Result<PathBuf> {
+        let mut claimed = self.shared.claimed.lock().unwrap();
+        let name = next_available_name(&name.replace('\\', "/"), |name| {
+            claimed.contains(Path::new(name))
+        })?;
+        let path = PathBuf::from(name);
+        claimed.insert(path.clone());
+        Ok(path)
+    }
+
+    /// Claims `data`'s content for `output_path`, or, if identical content was already
+    /// written this run, records the image as a skipped duplicate and returns true
+    fn skip_duplicate(&mut self, output_path: &Path, data: &[u8]) -> bool {
+        let digest = sha256(data);
+        let existing = {
+            let mut written = self.shared.written.lock().unwrap();
+            match written.get(&digest) {
+                Some(existing) => existing.clone(),
+                None => {
+                    written.insert(digest, output_path.to_path_buf());
+                    return false;
+                }
+            }
+        };
+        status!("  Skipping duplicate of {}", existing.display());
+        self.shared.duplicates.fetch_add(1, Ordering::Relaxed);
+        self.record_skipped(
+            data,
+            "duplicate",
+            vec![(
+                "duplicate_of".to_string(),
+                Json::from(existing.display().to_string()),
+            )],
+        );
+        true
+    }
+
+    /// Skips an image that was renamed away from a file left by an earlier run, when that
+    /// file already holds exactly the same bytes. Re-running into the same directory then
+    /// leaves one copy instead of adding `_1`, `_2`, ... files.
+    fn skip_identical_existing(&mut self, output_path: &Path, data: &[u8]) -> bool {
+        let Some(existing) = self.shared.collisions.lock().unwrap().remove(output_path) else {
+            return false;
+        };
+        let digest = sha256(data);
+        let identical = existing.into_iter().find(|path| {
+            fs::metadata(path).is_ok_and(|m| m.len() == data.len() as u64)
+                && fs::read(path).is_ok_and(|content| sha256(&content) == digest)
+        });
+        let Some(identical) = identical else {
+            return false;
+        };
+        status!(
+            "  Skipping {}: identical to {}",
+            output_path.display(),
+            identical.display()
+        );
+        // Free the name, so the next new image takes it rather than leaving a gap
+        self.shared.claimed.lock().unwrap().remove(output_path);
+        self.shared.originals.lock().unwrap().remove(output_path);
+        self.record_skipped(
+            data,
+            "identical file exists",
+            vec![(
+                "identical_to".to_string(),
+                Json::from(identical.display().to_string()),
+            )],
+        );
+        true
+    }
+
+    /// Marks content as already written, e.g. by an earlier run, so that with
+    /// `OutputOptions::dedupe` matching images are skipped as duplicates of `known`'s paths
+    pub(crate) fn seed_written(&self, known: &HashMap<Digest, PathBuf>) {
+        let mut written = self.shared.written.lock().unwrap();
+        for (digest, path) in known {
+            written.entry(*digest).or_insert_with(|| path.clone());
+        }
+    }
+
+    /// Content hash and first output path of every unique image seen this run,
+    /// including any seeded with [`OutputSink::seed_written`]
+    pub(crate) fn written_content(&self) -> HashMap<Digest, PathBuf> {
+        self.shared.written.lock().unwrap().clone()
+    }
+
+    /// Number of images skipped as duplicates this run, across all forked sinks
+    pub fn duplicates_skipped(&self) -> usize {
+        self.shared.duplicates.load(Ordering::Relaxed)
+    }
+
+    /// Number of images skipped this run because their output already exists, across
+    /// all forked sinks
+    pub fn existing_skipped(&self) -> usize {
+        self.shared.existing.load(Ordering::Relaxed)
+    }
+
+    /// Counts an image left out of the current document because its output already exists
+    fn count_existing(&mut self) {
+        self.shared.existing.fetch_add(1, Ordering::Relaxed);
+        self.skipped_count += 1;
+    }
+
+    /// Removes a file left by an earlier run that is about to be overwritten. Unlinking
+    /// rather than truncating keeps other links to the old file intact.
+    fn remove_overwritten(&self, output_path: &Path) -> Result<()> {
+        if self.options.on_conflict == ConflictPolicy::Overwrite
+            && output_path.symlink_metadata().is_ok()
+        {
+            fs::remove_file(output_path).with_context(|| {
+                format!("Failed to replace existing file: {}", output_path.display())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Whether `output_path` is a file left by an earlier run that the conflict policy
+    /// says to keep
+    fn skip_existing(&self, output_path: &Path) -> bool {
+        if self.options.on_conflict == ConflictPolicy::Skip && output_path.exists() {
+            status!("  Skipping existing file {}", output_path.display());
+            return true;
+        }
+        false
+    }
+
+    /// Streams an image from `reader` to `output_path` without holding it in memory.
+    /// `size` is the expected length, used to decide whether an archive entry needs ZIP64.
+    /// Duplicate linking happens after the copy, once the content hash is known.
+    pub fn write_image_from_reader(
+        &mut self,
+        output_path: &Path,
+        reader: &mut dyn Read,
+        size: u64,
+    ) -> Result<()> {
+        if self.skip_unselected(output_path) {
+            if let Some(manifest) = &mut self.manifest {
+                manifest.entries.push(ManifestEntry {
+                    document: self.document.clone(),
+                    output: None,
+                    size,
+                    sha256: None,
+                    status: EntryStatus::Skipped("not selected".to_string()),
+                    fields: Vec::new(),
+                });
+            }
+            return Ok(());
+        }
+        // Size limits are checked before reading, so an oversized image is never loaded
+        if let Some(reason) = self.options.filter.rejects_size(size) {
+            status!("  Skipping {}: {}", output_path.display(), reason);
+            if let Some(manifest) = &mut self.manifest {
+                manifest.entries.push(ManifestEntry {
+                    document: self.document.clone(),
+                    output: None,
+                    size,
+                    sha256: None,
+                    status: EntryStatus::Skipped("filtered".to_string()),
+                    fields: vec![("detail".to_string(), Json::from(reason))],
+                });
+            }
+            self.skipped_count += 1;
+            return Ok(());
+        }
+        if matches!(
+            self.target,
+            Target::Cas | Target::Tar(_) | Target::Stdout(_) | Target::Memory(_) | Target::DryRun
+        ) || self.options.dedupe
+            || self.options.filter.needs_content()
+            || self.options.convert_to.is_some()
+            || self.options.animated_to.is_some()
+            || self.options.optimize_gif
+            || self.options.split_gif_frames
+            || self.options.thumbnail_size.is_some()
+            || self.options.strip_metadata
+            || self.options.largest.is_some()
+            || self
+                .shared
+                .collisions
+                .lock()
+                .unwrap()
+                .contains_key(output_path)
+        {
+            // Objects are named after their hash, tar headers hold the exact size,
+            // duplicates are detected by hash and the filters and image transformations
+            // work on the content, so these need the whole image before writing. So do
+            // comparing it with a file from an earlier run it was renamed away from and
+            // holding it back to compare with the document's other images.
+            let reading = timings::start();
+            let mut data = Vec::new();
+            reader
+                .read_to_end(&mut data)
+                .context("Failed to read image data")?;
+            self.record_time(Phase::Read, reading);
+            return self.write_image(output_path, &data);
+        }
+
+        let entry_name = match &self.target {
+            Target::Zip(_) => Some(self.entry_name(output_path)?),
+            _ if self.skip_existing(output_path) => {
+                if self.manifest.is_some() {
+                    let mut data = Vec::new();
+                    reader
+                        .read_to_end(&mut data)
+                        .context("Failed to read image data")?;
+                    self.record_skipped(&data, "output exists", Vec::new());
+                }
+                self.count_existing();
+                return Ok(());
+            }
+            _ => {
+                if let Some(parent) = output_path.parent() {
+                    self.create_dir(parent)?;
+                }
+                self.remove_overwritten(output_path)?;
+                None
+            }
+        };
+
+        if let Some(name) = &entry_name {
+            self.warn_oversized(name, size);
+        }
+
+        // Reading and writing are interleaved, so streamed images count as writing
+        let writing = timings::start();
+        let mut source = HashingReader {
+            inner: reader,
+            hasher: Sha256::new(),
+            length: 0,
+            throttle: self.throttle.as_mut(),
+        };
+        let written = match (&mut self.target, entry_name) {
+            (Target::Zip(zip), Some(name)) => {
+                let written = zip.write_entry_from_reader(&name, &mut source, size)?;
+                if !written && self.manifest.is_some() {
+                    // Still hash the skipped entry so the manifest describes the image
+                    io::copy(&mut source, &mut io::sink()).context("Failed to read image data")?;
+                }
+                written
+            }
+            _ => {
+                write_reader_to_file(output_path, &mut source, self.options.file_mode)?;
+                true
+            }
+        };
+        let (digest, length) = (source.hasher.finalize().into(), source.length);
+        self.record_time(Phase::Write, writing);
+        if !written {
+            if let Some(manifest) = &mut self.manifest {
+                manifest.entries.push(ManifestEntry {
+                    document: self.document.clone(),
+                    output: None,
+                    size: length,
+                    sha256: Some(digest),
+                    status: EntryStatus::Skipped("output exists".to_string()),
+                    fields: Vec::new(),
+                });
+            }
+            self.count_existing();
+            return Ok(());
+        }
+
+        if !matches!(self.target, Target::Zip(_)) {
+            self.apply_owner(output_path)?;
+            self.apply_time(output_path)?;
+            if self.options.link_duplicates {
+                self.link_streamed_duplicate(output_path, digest)?;
+            }
+        }
+        if self.manifest.is_some() {
+            self.record_written(output_path, length, digest);
+        }
+        if let Some(observer) = self.observer() {
+            observer.image_written(&self.document, output_path, length);
+        }
+        self.run_exec(output_path);
+        Ok(())
+    }
+
+    /// Replaces a just-written file with a hard link if identical content was written earlier
+    fn link_streamed_duplicate(&mut self, output_path: &Path, digest: Digest) -> Result<()> {
+        let existing = {
+            let mut written = self.shared.written.lock().unwrap();
+            match written.get(&digest) {
+                Some(existing) => existing.clone(),
+                None => {
+                    written.insert(digest, output_path.to_path_buf());
+                    return Ok(());
+                }
+            }
+        };
+        let existing = existing.as_path();
+
+        // Link under a temporary name first so a failure leaves the copy in place
+        let mut temp_name = output_path.as_os_str().to_owned();
+        temp_name.push(".link");
+        let temp_path = PathBuf::from(temp_name);
+        let linked =
+            fs::hard_link(existing, &temp_path).and_then(|()| fs::rename(&temp_path, output_path));
+        match linked {
+            Ok(()) => status!("  Hard-linked duplicate of {}", existing.display()),
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                self.warn(&format!(
+                    "Could not hard-link {} to {}: {}. Keeping a copy instead.",
+                    output_path.display(),
+                    existing.display(),
+                    e
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a manifest entry for a written image, with its name inside the document
+    /// as `entry` if it has one
+    fn record_written(&mut self, output_path: &Path, size: u64, digest: Digest) {
+        if let Some(manifest) = &mut self.manifest {
+            let original = self.shared.originals.lock().unwrap().remove(output_path);
+            manifest.entries.push(ManifestEntry {
+                document: self.document.clone(),
+                output: Some(output_path.to_path_buf()),
+                size,
+                sha256: Some(digest),
+                status: EntryStatus::Written,
+                fields: original
+                    .map(|original| vec![("entry".to_string(), Json::from(original))])
+                    .unwrap_or_default(),
+            });
+        }
+    }
+
+    /// With `exec`, runs the command for an image just written to `output_path`. Images
+    /// that only exist inside an archive, on stdout or in memory have no file to run it
+    /// on. A failing command is warned about and does not stop the document.
+    fn run_exec(&self, output_path: &Path) {
+        let Some(command) = &self.options.exec else {
+            return;
+        };
+        if !matches!(self.target, Target::Directory | Target::Cas) {
+            return;
+        }
+        let extension = output_path
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let fields = ExecFields {
+            path: output_path,
+            doc: &self.document,
+            format: &canonical_extension(&extension),
+        };
+        if let Err(e) = command.run(&fields) {
+            self.warn(&format!(
+                "--exec command failed for {}: {:#}",
+                output_path.display(),
+                e
+            ));
+        }
+    }
+
+    /// Archive entry name for an output path inside the output archive
+    fn entry_name(&self, output_path: &Path) -> Result<String> {
+        Ok(output_path
+            .strip_prefix(&self.base_dir)
+            .context("Output path is outside the output archive")?
+            .to_string_lossy()
+            .replace('\\', "/"))
+    }
+
+    /// Whether images are named after their alternative text, so processors know to
+    /// look it up
+    pub fn names_from_alt(&self) -> bool {
+        self.options.name_from_alt
+    }
+
+    /// Whether a manifest is being recorded, so processors can skip gathering
+    /// details nobody will read
+    pub fn records_manifest(&self) -> bool {
+        self.manifest.is_some()
+    }
+
+    /// Adds a field to the most recent manifest entry
+    pub fn annotate(&mut self, key: &str, value: Json) {
+        if let Some(image) = self.pending.last_mut() {
+            image.fields.push((key.to_string(), value));
+            return;
+        }
+        if let Some(entry) = self
+            .manifest
+            .as_mut()
+            .and_then(|manifest| manifest.entries.last_mut())
+        {
+            entry.fields.push((key.to_string(), value));
+        }
+    }
+
+    /// Sets the document that subsequent manifest entries are attributed to
+    pub fn begin_document(&mut self, path: &Path) {
+        self.document = path.to_path_buf();
+        self.metadata.clear();
+        self.damaged.clear();
+        self.document_dir = None;
+        self.timings = PhaseTimes::default();
+        self.entry_times.clear();
+        self.unselected.get_mut().clear();
+        self.skipped_count = 0;
+        self.document_time = self
+            .options
+            .preserve_timestamps
+            .then(|| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .flatten();
+    }
+
+    /// Notes the modification time of the archive entry the image at `output_path` comes
+    /// from, given to the file with `OutputOptions::preserve_timestamps`. Entries without
+    /// a time of their own (the zip default of 1980-01-01 that Office writes) are left to
+    /// the document's time.
+    pub fn set_entry_time(&mut self, output_path: &Path, modified: Option<zip::DateTime>) {
+        if !self.options.preserve_timestamps {
+            return;
+        }
+        if let Some(time) = modified.and_then(zip_time) {
+            self.entry_times.insert(output_path.to_path_buf(), time);
+        }
+    }
+
+    /// Writes the images held back for the current document. With
+    /// [`OutputOptions::largest`] only that many are written, those with the most pixels
+    /// (then the most bytes, so images whose dimensions cannot be read rank last), in
+    /// their original order. Must be called once the document is processed; returns the
+    /// number of images left out, including those [`OutputOptions::only`] left out and
+    /// those skipped while writing.
+    pub fn finish_document(&mut self) -> Result<usize> {
+        let pending = std::mem::take(&mut self.pending);
+        let Some(largest) = self.options.largest else {
+            self.write_batch(pending)?;
+            return Ok(std::mem::take(&mut self.skipped_count));
+        };
+        let mut ranked: Vec<usize> = (0..pending.len()).collect();
+        ranked.sort_by_key(|&index| {
+            let data = &pending[index].data;
+            let pixels = image_dimensions(data).map_or(0, |(w, h)| u64::from(w) * u64::from(h));
+            std::cmp::Reverse((pixels, data.len()))
+        });
+        let kept: HashSet<usize> = ranked.into_iter().take(largest).collect();
+
+        let mut dropped = 0;
+        let mut accepted = Vec::new();
+        for (index, image) in pending.into_iter().enumerate() {
+            if kept.contains(&index) {
+                accepted.push(image);
+                continue;
+            }
+            status!(
+                "  Skipping {}: not among the {} largest images",
+                image.output_path.display(),
+                largest
+            );
+            // Free the name, so numbering does not depend on which images were kept
+            self.shared
+                .claimed
+                .lock()
+                .unwrap()
+                .remove(&image.output_path);
+            self.shared
+                .originals
+                .lock()
+                .unwrap()
+                .remove(&image.output_path);
+            let mut fields = vec![(
+                "detail".to_string(),
+                Json::from(format!("--largest {}", largest)),
+            )];
+            fields.extend(image.fields);
+            self.record_skipped(&image.data, "not among the largest", fields);
+            dropped += 1;
+        }
+        self.write_batch(accepted)?;
+        Ok(std::mem::take(&mut self.skipped_count) + dropped)
+    }
+
+    /// Places the images of the current document in `dir`, a relative path below the
+    /// output location, e.g. to mirror where the document was found. Cleared by
+    /// [`OutputSink::begin_document`].
+    pub fn set_document_dir(&mut self, dir: &Path) {
+        let dir = dir
+            .components()
+            .filter_map(|component| match component {
+                std::path::Component::Normal(name) => {
+                    Some(sanitize_filename(&name.to_string_lossy()))
+                }
+                _ => None,
+            })
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>()
+            .join("/");
+        self.document_dir = (!dir.is_empty()).then_some(dir);
+    }
+
+    /// Records a metadata field (title, author, ...) of the current document.
+    /// The first value wins, so embedded documents do not replace their container's.
+    pub fn record_metadata(&mut self, key: &str, value: &str) {
+        if !self.metadata.iter().any(|(k, _)| k == key) {
+            self.metadata.push((key.to_string(), value.to_string()));
+        }
+    }
+
+    fn metadata_value(&self, key: &str) -> Option<&str> {
+        self.metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Takes the metadata recorded for the current document
+    pub fn take_metadata(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.metadata)
+    }
+
+    /// Notes that `entry` of the current document could not be read or converted and was
+    /// skipped, as `ExtractOptions::salvage` asks for unreadable entries. It is listed in
+    /// the run report and recorded in the manifest as skipped.
+    pub fn record_damaged(&mut self, entry: &str, error: &anyhow::Error) {
+        self.warn(&format!("Skipping damaged entry {}: {:#}", entry, error));
+        if let Some(manifest) = &mut self.manifest {
+            manifest.entries.push(ManifestEntry {
+                document: self.document.clone(),
+                output: None,
+                size: 0,
+                sha256: None,
+                status: EntryStatus::Skipped("damaged".to_string()),
+                fields: vec![
+                    ("entry".to_string(), Json::from(entry)),
+                    ("detail".to_string(), Json::from(format!("{:#}", error))),
+                ],
+            });
+        }
+        self.damaged
+            .push((entry.to_string(), format!("{:#}", error)));
+    }
+
+    /// Takes the damaged entries recorded for the current document
+    pub fn take_damaged(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.damaged)
+    }
+
+    /// Adds damaged entries recorded by another sink, e.g. a per-document archive's
+    pub fn add_damaged(&mut self, damaged: Vec<(String, String)>) {
+        self.damaged.extend(damaged);
+    }
+
+    /// Adds the time since `started`, from [`timings::start`], to a phase of the current
+    /// document
+    pub fn record_time(&mut self, phase: Phase, started: Option<Instant>) {
+        if let Some(started) = started {
+            self.timings.add(phase, started.elapsed());
+        }
+    }
+
+    /// Adds phase times measured by another sink, e.g. a per-document archive's
+    pub fn add_timings(&mut self, timings: &PhaseTimes) {
+        self.timings.merge(timings);
+    }
+
+    /// Returns the current document's phase times and resets them
+    pub fn take_timings(&mut self) -> PhaseTimes {
+        std::mem::take(&mut self.timings)
+    }
+
+    /// Records an image that was deliberately not written, with the reason and any
+    /// extra details for the manifest
+    pub fn record_skipped(&mut self, data: &[u8], reason: &str, fields: Vec<(String, Json)>) {
+        if let Some(manifest) = &mut self.manifest {
+            manifest.entries.push(ManifestEntry {
+                document: self.document.clone(),
+                output: None,
+                size: data.len() as u64,
+                sha256: Some(sha256(data)),
+                status: EntryStatus::Skipped(reason.to_string()),
+                fields,
+            });
+        }
+    }
+
+    /// Finds an image written this run whose perceptual hash is within `max_distance`
+    /// bits of `hash`, returning its path and the distance
+    pub fn find_similar(&self, hash: u64, max_distance: u32) -> Option<(PathBuf, u32)> {
+        self.shared
+            .perceptual
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(known, path)| (path, hamming_distance(*known, hash)))
+            .filter(|(_, distance)| *distance <= max_distance)
+            .min_by_key(|(_, distance)| *distance)
+            .map(|(path, distance)| (path.clone(), distance))
+    }
+
+    /// Remembers the perceptual hash of an image written to `path`
+    pub fn remember_perceptual_hash(&mut self, hash: u64, path: &Path) {
+        self.shared
+            .perceptual
+            .lock()
+            .unwrap()
+            .push((hash, path.to_path_buf()));
+    }
+
+    /// Writes the image, returning false if it was skipped because of the conflict policy
+    fn write_image_unthrottled(&mut self, output_path: &Path, data: &[u8]) -> Result<bool> {
+        if let Target::Stdout(written) = &mut self.target {
+            if *written {
+                anyhow::bail!(
+                    "Standard output holds a single image, but {} would be a second one",
+                    output_path.display()
+                );
+            }
+            let mut stdout = io::stdout().lock();
+            stdout
+                .write_all(data)
+                .and_then(|()| stdout.flush())
+                .context("Failed to write image to standard output")?;
+            *written = true;
+            return Ok(true);
+        }
+        if let Target::Memory(images) = &mut self.target {
+            images.push(ExtractedImage {
+                name: output_path.to_string_lossy().replace('\\', "/"),
+                data: data.to_vec(),
+            });
+            return Ok(true);
+        }
+        if let Target::DryRun = self.target {
+            return Ok(true);
+        }
+        if let Target::Zip(_) | Target::Tar(_) = self.target {
+            let entry_name = self.entry_name(output_path)?;
+            self.warn_oversized(&entry_name, data.len() as u64);
+            match &mut self.target {
+                Target::Zip(zip) => return zip.write_entry(&entry_name, data),
+                Target::Tar(tar) => {
+                    tar.write_entry(&entry_name, data)?;
+                    return Ok(true);
+                }
+                _ => {}
+            }
+        }
+        if self.skip_existing(output_path) {
+            return Ok(false);
+        }
+
+        // Grouped output lands in subdirectories that may not exist yet
+        if let Some(parent) = output_path.parent() {
+            self.create_dir(parent)?;
+        }
+
+        self.remove_overwritten(output_path)?;
+
+        if let Target::Cas = self.target {
+            self.write_cas(output_path, data)?;
+            return Ok(true);
+        }
+
+        if !self.options.link_duplicates {
+            write_image_to_file(output_path, data, self.options.file_mode)?;
+            self.apply_owner(output_path)?;
+            self.apply_time(output_path)?;
+            return Ok(true);
+        }
+
+        let digest = sha256(data);
+        let existing = self.shared.written.lock().unwrap().get(&digest).cloned();
+        // With deduplication the content was claimed for this very path before writing
+        if let Some(existing) = existing.filter(|existing| existing != output_path) {
+            match fs::hard_link(&existing, output_path) {
+                Ok(()) => {
+                    status!("  Hard-linked duplicate of {}", existing.display());
+                    return Ok(true);
+                }
+                Err(e) => {
+                    // Typically a cross-device link or a filesystem without link support
+                    self.warn(&format!(
+                        "Could not hard-link {} to {}: {}. Writing a copy instead.",
+                        output_path.display(),
+                        existing.display(),
+                        e
+                    ));
+                }
+            }
+        }
+
+        write_image_to_file(output_path, data, self.options.file_mode)?;
+        self.apply_owner(output_path)?;
+        self.apply_time(output_path)?;
+        self.shared
+            .written
+            .lock()
+            .unwrap()
+            .entry(digest)
+            .or_insert_with(|| output_path.to_path_buf());
+        Ok(true)
+    }
+
+    /// Stores `data` under its content hash unless already present, then links
+    /// `output_path` in the document tree to the stored object
+    fn write_cas(&mut self, output_path: &Path, data: &[u8]) -> Result<()> {
+        let hex = to_hex(&sha256(data));
+        let mut object_name = hex[2..].to_string();
+        if let Some(extension) = output_path.extension() {
+            object_name = format!("{}.{}", object_name, extension.to_string_lossy());
+        }
+        let object_dir = self.base_dir.join(CAS_OBJECTS_DIR).join(&hex[..2]);
+        let object = object_dir.join(&object_name);
+
+        if object.exists() {
+            status!("  Already stored as {}", object.display());
+        } else {
+            self.create_dir(&object_dir)?;
+            write_image_to_file(&object, data, self.options.file_mode)?;
+            self.apply_owner(&object)?;
+        }
+
+        if self.options.cas_link == CasLink::Symlink {
+            // Relative target so the store can be moved or synced as a whole
+            let depth = output_path
+                .parent()
+                .and_then(|p| p.strip_prefix(&self.base_dir).ok())
+                .map_or(0, |p| p.components().count());
+            let mut target = PathBuf::new();
+            for _ in 0..depth {
+                target.push("..");
+            }
+            let target = target
+                .join(CAS_OBJECTS_DIR)
+                .join(&hex[..2])
+                .join(&object_name);
+            match symlink_file(&target, output_path) {
+                Ok(()) => return Ok(()),
+                Err(e) => self.warn(&format!(
+                    "Could not symlink {}: {}. Trying a hard link instead.",
+                    output_path.display(),
+                    e
+                )),
+            }
+        }
+
+        match fs::hard_link(&object, output_path) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.warn(&format!(
+                    "Could not hard-link {} to {}: {}. Writing a copy instead.",
+                    output_path.display(),
+                    object.display(),
+                    e
+                ));
+                write_image_to_file(output_path, data, self.options.file_mode)?;
+                self.apply_owner(output_path)?;
+                self.apply_time(output_path)
+            }
+        }
+    }
+
+    /// Ensures the base output directory exists
+    pub fn create_base_dir(&self) -> Result<()> {
+        match self.target {
+            Target::Directory | Target::Cas => self.create_dir(&self.base_dir),
+            // The archive's parent directory is created when the archive is opened
+            Target::Zip(_)
+            | Target::Tar(_)
+            | Target::Stdout(_)
+            | Target::Memory(_)
+            | Target::DryRun => Ok(()),
+        }
+    }
+
+    /// Flushes any buffered output. Must be called once all documents are processed.
+    /// Returns the run manifest if recording was enabled.
+    pub fn finish(self) -> Result<Option<Manifest>> {
+        match self.target {
+            Target::Zip(zip) => zip.finish()?,
+            Target::Tar(tar) => tar.finish()?,
+            Target::Directory
+            | Target::Cas
+            | Target::Stdout(_)
+            | Target::Memory(_)
+            | Target::DryRun => {}
+        }
+        Ok(self.manifest)
+    }
+
+    /// Creates a directory (and any missing parents) with the configured mode and owner
+    fn create_dir(&self, dir: &Path) -> Result<()> {
+        if dir.is_dir() {
+            return Ok(());
+        }
+
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        if let Some(mode) = self.options.dir_mode {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(mode);
+        }
+
+        // DirBuilder is idempotent in recursive mode - succeeds if directory exists
+        builder
+            .create(dir)
+            .context("Failed to create output directory")?;
+        self.apply_owner(dir)
+    }
+
+    /// Applies the configured owner to a created path
+    fn apply_owner(&self, path: &Path) -> Result<()> {
+        #[cfg(unix)]
+        if let Some(owner) = self.options.owner {
+            std::os::unix::fs::chown(path, owner.uid, owner.gid)
+                .with_context(|| format!("Failed to change owner of {}", path.display()))?;
+        }
+        #[cfg(not(unix))]
+        let _ = path;
+        Ok(())
+    }
+
+    /// With `preserve_timestamps`, gives a written file the time of the archive entry it
+    /// came from, or else that of its document
+    fn apply_time(&self, path: &Path) -> Result<()> {
+        let time = self.entry_times.get(path).copied().or(self.document_time);
+        if let Some(time) = time {
+            filetime::set_file_mtime(path, filetime::FileTime::from_system_time(time))
+                .with_context(|| {
+                    format!("Failed to set modification time of {}", path.display())
+                })?;
+        }
+        Ok(())
+    }
+}
+
+/// Time of a zip entry. Zip writers store local time without a zone, so it is read in
+/// the local time zone, or as UTC where that cannot be determined. The zip default of
+/// 1980-01-01 00:00:00 means the writer recorded no time.
+fn zip_time(time: zip::DateTime) -> Option<SystemTime> {
+    if time == zip::DateTime::default() {
+        return None;
+    }
+    // zip converts the fields as they are, so this is the local time labelled UTC
+    let local = time::OffsetDateTime::try_from(time).ok()?;
+    let offset = time::UtcOffset::local_offset_at(local).unwrap_or(time::UtcOffset::UTC);
+    Some(local.replace_offset(offset).into())
+}
+
+/// Creates a symbolic link to a file
+fn symlink_file(target: &Path, link: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(target, link);
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(target, link);
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (target, link);
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+}
+
+/// Bytes reserved per volume for the end-of-central-directory records (including ZIP64)
+const ZIP_END_RECORDS_SIZE: u64 = 22 + 56 + 20;
+
+/// Estimated per-entry overhead: local header, central directory header,
+/// data descriptor and ZIP64 extra fields, excluding the name itself
+const ZIP_ENTRY_OVERHEAD: u64 = 30 + 46 + 16 + 40;
+
+/// Size limit state for multi-volume output
+#[derive(Debug)]
+struct VolumeSplit {
+    /// Maximum size of a single volume in bytes
+    limit: u64,
+    /// Path the user asked for; volumes are numbered variants of it
+    base_path: PathBuf,
+    /// Number of the volume currently being written (1-based)
+    volume: u32,
+    /// Estimated bytes used in the current volume
+    used: u64,
+    /// Entries written to the current volume
+    entries: usize,
+}
+
+/// Appends `_suffix` to the stem of the last component of `filename`:
+/// `a/report_3.png` becomes `a/report_3_footnote.png`
+fn with_stem_suffix(filename: &str, suffix: &str) -> String {
+    let name_start = filename.rfind('/').map_or(0, |slash| slash + 1);
+    match filename[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, extension) = filename.split_at(name_start + dot);
+            format!("{}_{}{}", stem, suffix, extension)
+        }
+        _ => format!("{}_{}", filename, suffix),
+    }
+}
+
+/// File name of an image's path inside its document without the extension, sanitized.
+/// None if nothing usable is left.
+fn original_stem(original: &str) -> Option<String> {
+    let name = original.rsplit(['/', '\\']).next().unwrap_or(original);
+    let stem = match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => name,
+    };
+    Some(sanitize_filename(stem)).filter(|stem| !stem.is_empty() && stem != "." && stem != "..")
+}
+
+/// Longest file name stem made from alternative text, in characters
+const MAX_ALT_STEM_CHARS: usize = 100;
+
+/// File name stem made from an image's alternative text: sanitized, whitespace collapsed
+/// and shortened to [`MAX_ALT_STEM_CHARS`]. None if nothing usable is left.
+fn alt_stem(alt_text: &str) -> Option<String> {
+    let collapsed = alt_text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let stem: String = sanitize_filename(&collapsed)
+        .chars()
+        .take(MAX_ALT_STEM_CHARS)
+        .collect();
+    // Windows drops trailing dots and spaces from file names
+    let stem = stem.trim_end_matches(['.', ' ']);
+    Some(stem.to_string()).filter(|stem| !stem.is_empty())
+}
+
+/// Paths in `dir` that renaming `filename` tried, in order, before settling on `chosen`
+fn earlier_names(dir: &Path, filename: &str, chosen: &Path) -> Vec<PathBuf> {
+    let tried = std::cell::RefCell::new(Vec::new());
+    let _ = next_available_name(filename, |name| {
+        let path = dir.join(name);
+        if path == chosen {
+            return false;
+        }
+        tried.borrow_mut().push(path);
+        true
+    });
+    tried.into_inner()
+}
+
+/// Returns the path of volume `n` for a split archive: `out.zip` -> `out.003.zip`
+fn volume_path(base_path: &Path, n: u32) -> PathBuf {
+    let stem = base_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let filename = match base_path.extension() {
+        Some(ext) => format!("{}.{:03}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}.{:03}", stem, n),
+    };
+    base_path.with_file_name(filename)
+}
+
+/// Reads the entry names of an existing zip archive
+fn read_entry_names(path: &Path) -> Result<HashSet<String>> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Failed to open archive: {}", path.display()))?;
+    let archive = ZipArchive::new(file)
+        .with_context(|| format!("Failed to read existing archive: {}", path.display()))?;
+    Ok(archive.file_names().map(str::to_string).collect())
+}
+
+/// A zip archive receiving extracted images
+#[derive(Debug)]
+struct ZipTarget {
+    /// Archive currently being written (the current volume when splitting)
+    path: PathBuf,
+    writer: ZipWriter<fs::File>,
+    on_conflict: ConflictPolicy,
+    /// Entry names present in the archive before this run
+    existing: HashSet<String>,
+    /// Entry names written during this run
+    written: HashSet<String>,
+    /// Temporary file being written when existing entries may be overwritten.
+    /// Surviving entries are copied over from the original archive on finish.
+    rebuild_path: Option<PathBuf>,
+    /// Set when output is split into size-limited volumes
+    split: Option<VolumeSplit>,
+}
+
+impl ZipTarget {
+    fn open(path: PathBuf, on_conflict: ConflictPolicy, split_size: Option<u64>) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).context("Failed to create output directory")?;
+        }
+
+        if let Some(limit) = split_size {
+            return Self::open_split(path, on_conflict, limit);
+        }
+
+        if !path.exists() {
+            let file = fs::File::create(&path)
+                .with_context(|| format!("Failed to create archive: {}", path.display()))?;
+            return Ok(Self {
+                path,
+                writer: ZipWriter::new(file),
+                on_conflict,
+                existing: HashSet::new(),
+                written: HashSet::new(),
+                rebuild_path: None,
+                split: None,
+            });
+        }
+
+        let existing = read_entry_names(&path)?;
+
+        if on_conflict == ConflictPolicy::Overwrite {
+            // Entries cannot be removed in place, so build a fresh archive alongside
+            let mut rebuild_name = path.as_os_str().to_owned();
+            rebuild_name.push(".partial");
+            let rebuild_path = PathBuf::from(rebuild_name);
+            let file = fs::File::create(&rebuild_path)
+                .with_context(|| format!("Failed to create archive: {}", rebuild_path.display()))?;
+            return Ok(Self {
+                path,
+                writer: ZipWriter::new(file),
+                on_conflict,
+                existing,
+                written: HashSet::new(),
+                rebuild_path: Some(rebuild_path),
+                split: None,
+            });
+        }
+
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open archive: {}", path.display()))?;
+        let writer = ZipWriter::new_append(file)
+            .with_context(|| format!("Failed to append to archive: {}", path.display()))?;
+        status!(
+            "Appending to existing archive {} ({} entries).",
+            path.display(),
+            existing.len()
+        );
+
+        Ok(Self {
+            path,
+            writer,
+            on_conflict,
+            existing,
+            written: HashSet::new(),
+            rebuild_path: None,
+            split: None,
+        })
+    }
+
+    /// Opens a new numbered volume after any volumes left by previous runs.
+    /// Names in earlier volumes still count as existing entries for conflict handling.
+    fn open_split(base_path: PathBuf, on_conflict: ConflictPolicy, limit: u64) -> Result<Self> {
+        if on_conflict == ConflictPolicy::Overwrite {
+            anyhow::bail!("--on-conflict overwrite cannot be combined with --split-size");
+        }
+
+        let mut existing = HashSet::new();
+        let mut volume = 1;
+        while volume_path(&base_path, volume).exists() {
+            existing.extend(read_entry_names(&volume_path(&base_path, volume))?);
+            volume += 1;
+        }
+        if volume > 1 {
+            status!(
+                "Continuing after {} existing volume(s) of {}.",
+                volume - 1,
+                base_path.display()
+            );
+        }
+
+        let path = volume_path(&base_path, volume);
+        let file = fs::File::create(&path)
+            .with_context(|| format!("Failed to create archive: {}", path.display()))?;
+        Ok(Self {
+            path,
+            writer: ZipWriter::new(file),
+            on_conflict,
+            existing,
+            written: HashSet::new(),
+            rebuild_path: None,
+            split: Some(VolumeSplit {
+                limit,
+                base_path,
+                volume,
+                used: ZIP_END_RECORDS_SIZE,
+                entries: 0,
+            }),
+        })
+    }
+
+    /// The room an entry takes in a volume: its data, headers and name
+    fn volume_space(name: &str, size: u64) -> u64 {
+        size + ZIP_ENTRY_OVERHEAD + 2 * name.len() as u64
+    }
+
+    /// Whether an entry would not fit even in a volume of its own
+    fn is_oversized(&self, name: &str, size: u64) -> bool {
+        self.split.as_ref().is_some_and(|split| {
+            ZIP_END_RECORDS_SIZE + Self::volume_space(name, size) > split.limit
+        })
+    }
+
+    /// Accounts for an entry about to be written, starting a new volume if it would not fit
+    fn reserve_volume_space(&mut self, name: &str, size: u64) -> Result<()> {
+        let Some(split) = &mut self.split else {
+            return Ok(());
+        };
+
+        let needed = Self::volume_space(name, size);
+        if split.entries > 0 && split.used + needed > split.limit {
+            split.volume += 1;
+            split.used = ZIP_END_RECORDS_SIZE;
+            split.entries = 0;
+
+            let next_path = volume_path(&split.base_path, split.volume);
+            let file = fs::File::create(&next_path)
+                .with_context(|| format!("Failed to create archive: {}", next_path.display()))?;
+            let previous = std::mem::replace(&mut self.writer, ZipWriter::new(file));
+            previous
+                .finish()
+                .with_context(|| format!("Failed to finalize archive: {}", self.path.display()))?;
+            status!("  Starting volume {}", next_path.display());
+            self.path = next_path;
+        }
+
+        split.used += needed;
+        split.entries += 1;
+        Ok(())
+    }
+
+    /// Picks the entry name for `filename`, applying the conflict policy against
+    /// pre-existing entries. Entries written during this run are always renamed around.
+    fn entry_name_for(&self, filename: &str) -> Result<String> {
+        if self.written.contains(filename) {
+            return next_available_name(filename, |n| {
+                self.written.contains(n) || self.existing.contains(n)
+            });
+        }
+        if !self.existing.contains(filename) {
+            return Ok(filename.to_string());
+        }
+        match self.on_conflict {
+            ConflictPolicy::Rename => next_available_name(filename, |n| {
+                self.written.contains(n) || self.existing.contains(n)
+            }),
+            ConflictPolicy::Overwrite | ConflictPolicy::Skip => Ok(filename.to_string()),
+            ConflictPolicy::Error => anyhow::bail!(
+                "Entry '{}' already exists in {}",
+                filename,
+                self.path.display()
+            ),
+        }
+    }
+
+    /// Writes an entry, returning false if it was skipped because of the conflict policy
+    fn write_entry(&mut self, name: &str, mut data: &[u8]) -> Result<bool> {
+        let size = data.len() as u64;
+        self.write_entry_from_reader(name, &mut data, size)
+    }
+
+    /// Streams an entry into the archive. `size` is the expected length; entries of
+    /// 4 GiB or more are written with ZIP64 extensions. Returns false if the entry was
+    /// skipped because of the conflict policy, in which case `reader` is left unread.
+    fn write_entry_from_reader(
+        &mut self,
+        name: &str,
+        reader: &mut dyn Read,
+        size: u64,
+    ) -> Result<bool> {
+        if self.on_conflict == ConflictPolicy::Skip && self.existing.contains(name) {
+            status!("  Skipping existing archive entry {}", name);
+            return Ok(false);
+        }
+
+        let extension = Path::new(name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        // Re-compressing already compressed formats only costs time
+        let method = match extension.as_str() {
+            "jpg" | "jpeg" | "png" | "gif" | "webp" => CompressionMethod::Stored,
+            _ => CompressionMethod::Deflated,
+        };
+        let options = SimpleFileOptions::default()
+            .compression_method(method)
+            .large_file(size >= u32::MAX as u64);
+
+        self.reserve_volume_space(name, size)?;
+        self.writer
+            .start_file(name, options)
+            .with_context(|| format!("Failed to add '{}' to {}", name, self.path.display()))?;
+        io::copy(reader, &mut self.writer)
+            .with_context(|| format!("Failed to write '{}' to {}", name, self.path.display()))?;
+        self.written.insert(name.to_string());
+        Ok(true)
+    }
+
+    fn finish(mut self) -> Result<()> {
+        if let Some(rebuild_path) = &self.rebuild_path {
+            let file = fs::File::open(&self.path)
+                .with_context(|| format!("Failed to open archive: {}", self.path.display()))?;
+            let mut original = ZipArchive::new(file)?;
+            for i in 0..original.len() {
+                let entry = original.by_index_raw(i)?;
+                if self.written.contains(entry.name()) {
+                    continue;
+                }
+                self.writer.raw_copy_file(entry)?;
+            }
+            self.writer.finish()?;
+            fs::rename(rebuild_path, &self.path)
+                .with_context(|| format!("Failed to replace archive: {}", self.path.display()))?;
+            return Ok(());
+        }
+
+        self.writer
+            .finish()
+            .with_context(|| format!("Failed to finalize archive: {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+/// Output stream of a tar archive
+enum TarWriter {
+    Plain(fs::File),
+    Gzip(GzEncoder<fs::File>),
+}
+
+impl Write for TarWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            TarWriter::Plain(file) => file.write(buf),
+            TarWriter::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            TarWriter::Plain(file) => file.flush(),
+            TarWriter::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// A tar archive receiving extracted images
+struct TarTarget {
+    /// Archive currently being written (the current volume when splitting)
+    path: PathBuf,
+    builder: tar::Builder<TarWriter>,
+    gzip: bool,
+    on_conflict: ConflictPolicy,
+    /// Entry names written during this run
+    written: HashSet<String>,
+    /// Set when output is split into size-limited volumes
+    split: Option<VolumeSplit>,
+}
+
+impl std::fmt::Debug for TarTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TarTarget")
+            .field("path", &self.path)
+            .field("written", &self.written)
+            .field("split", &self.split)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Bytes at the end of every tar archive: two zero blocks
+const TAR_END_SIZE: u64 = 1024;
+
+impl TarTarget {
+    fn create(
+        path: PathBuf,
+        gzip: bool,
+        on_conflict: ConflictPolicy,
+        split_size: Option<u64>,
+    ) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).context("Failed to create output directory")?;
+        }
+        let split = split_size.map(|limit| VolumeSplit {
+            limit,
+            base_path: path.clone(),
+            volume: 1,
+            used: TAR_END_SIZE,
+            entries: 0,
+        });
+        let path = match &split {
+            Some(_) => tar_volume_path(&path, 1),
+            None => path,
+        };
+        Ok(Self {
+            builder: tar::Builder::new(Self::create_file(&path, gzip, on_conflict)?),
+            path,
+            gzip,
+            on_conflict,
+            written: HashSet::new(),
+            split,
+        })
+    }
+
+    /// Creates the archive file at `path`, which may only replace an existing one with
+    /// `ConflictPolicy::Overwrite`
+    fn create_file(path: &Path, gzip: bool, on_conflict: ConflictPolicy) -> Result<TarWriter> {
+        if path.exists() && on_conflict != ConflictPolicy::Overwrite {
+            anyhow::bail!(
+                "Archive already exists: {} (tar archives cannot be appended to; use --on-conflict overwrite to replace it)",
+                path.display()
+            );
+        }
+        let file = fs::File::create(path)
+            .with_context(|| format!("Failed to create archive: {}", path.display()))?;
+        Ok(if gzip {
+            TarWriter::Gzip(GzEncoder::new(file, Compression::default()))
+        } else {
+            TarWriter::Plain(file)
+        })
+    }
+
+    /// Picks an entry name for `filename` that was not written yet
+    fn entry_name_for(&self, filename: &str) -> Result<String> {
+        next_available_name(filename, |n| self.written.contains(n))
+    }
+
+    /// The room an entry takes in a volume: a header block, the data padded to whole
+    /// blocks and, for names too long for the header, a GNU long name entry
+    fn volume_space(name: &str, size: u64) -> u64 {
+        let mut needed = 512 + size.div_ceil(512) * 512;
+        if name.len() >= 100 {
+            needed += 512 + (name.len() as u64 + 1).div_ceil(512) * 512;
+        }
+        needed
+    }
+
+    /// Whether an entry would not fit even in a volume of its own
+    fn is_oversized(&self, name: &str, size: u64) -> bool {
+        self.split
+            .as_ref()
+            .is_some_and(|split| TAR_END_SIZE + Self::volume_space(name, size) > split.limit)
+    }
+
+    /// Accounts for an entry about to be written, starting a new volume if it would not
+    /// fit. Sizes are those of the uncompressed tar, which a gzipped volume stays within.
+    fn reserve_volume_space(&mut self, name: &str, size: u64) -> Result<()> {
+        let Some(split) = &mut self.split else {
+            return Ok(());
+        };
+
+        let needed = Self::volume_space(name, size);
+        if split.entries > 0 && split.used + needed > split.limit {
+            split.volume += 1;
+            split.used = TAR_END_SIZE;
+            split.entries = 0;
+
+            let next_path = tar_volume_path(&split.base_path, split.volume);
+            let writer = Self::create_file(&next_path, self.gzip, self.on_conflict)?;
+            let previous = std::mem::replace(&mut self.builder, tar::Builder::new(writer));
+            let previous_path = std::mem::replace(&mut self.path, next_path);
+            finish_tar(previous, &previous_path)?;
+            status!("  Starting volume {}", self.path.display());
+        }
+
+        split.used += needed;
+        split.entries += 1;
+        Ok(())
+    }
+
+    fn write_entry(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        self.reserve_volume_space(name, data.len() as u64)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        );
+        self.builder
+            .append_data(&mut header, name, data)
+            .with_context(|| format!("Failed to write '{}' to {}", name, self.path.display()))?;
+        self.written.insert(name.to_string());
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        finish_tar(self.builder, &self.path)
+    }
+}
+
+/// Writes the end of the tar archive at `path` and flushes it
+fn finish_tar(builder: tar::Builder<TarWriter>, path: &Path) -> Result<()> {
+    let context = || format!("Failed to finalize archive: {}", path.display());
+    let writer = builder.into_inner().with_context(context)?;
+    match writer {
+        TarWriter::Plain(mut file) => file.flush().with_context(context)?,
+        TarWriter::Gzip(encoder) => {
+            encoder.finish().with_context(context)?;
+        }
+    }
+    Ok(())
+}
+
+/// Path of volume `n` of a split tar archive, numbered before the whole `.tar`,
+/// `.tar.gz` or `.tgz` suffix: `out.tar.gz` becomes `out.001.tar.gz`
+fn tar_volume_path(base_path: &Path, n: u32) -> PathBuf {
+    let name = base_path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let lower = name.to_ascii_lowercase();
+    let suffix_len = [".tar.gz", ".tgz", ".tar"]
+        .iter()
+        .find(|suffix| lower.ends_with(*suffix))
+        .map_or(0, |suffix| suffix.len());
+    let (stem, suffix) = name.split_at(name.len() - suffix_len);
+    base_path.with_file_name(format!("{}.{:03}{}", stem, n, suffix))
+}
+
+/// Parses an octal permission string such as `644` or `0755`
+pub fn parse_mode(value: &str) -> Result<u32, String> {
+    let digits = value.trim().trim_start_matches("0o");
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("'{}' is not a valid octal mode", value)),
+    }
+}
+
+/// Parses comma-separated `FORMAT=DIR` routes such as `svg=vectors/,jpg=photos/`.
+/// Formats are matched by canonical extension, so `jpeg=` also routes `.jpg` images.
+pub fn parse_routes(value: &str) -> Result<FormatRoutes, String> {
+    let mut routes: Vec<(String, String)> = Vec::new();
+    for route in value.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+        let Some((format, dir)) = route.split_once('=') else {
+            return Err(format!("'{}' is not FORMAT=DIR", route));
+        };
+        let format = canonical_extension(format.trim().trim_start_matches('.'));
+        if format.is_empty() {
+            return Err(format!("'{}' has no format", route));
+        }
+        let dir = safe_relative_path(&dir.trim().replace('\\', "/"));
+        if dir.is_empty() {
+            return Err(format!("'{}' has no directory", route));
+        }
+        if routes.iter().any(|(routed, _)| *routed == format) {
+            return Err(format!("{} is routed more than once", format));
+        }
+        routes.push((format, dir));
+    }
+    if routes.is_empty() {
+        return Err("expected FORMAT=DIR[,FORMAT=DIR...]".to_string());
+    }
+    Ok(FormatRoutes { routes })
+}
+
+/// Parses a numeric `UID[:GID]` or `:GID` owner specification
+pub fn parse_owner(value: &str) -> Result<Owner, String> {
+    let parse_id = |s: &str| -> Result<Option<u32>, String> {
+        if s.is_empty() {
+            Ok(None)
+        } else {
+            s.parse()
+                .map(Some)
+                .map_err(|_| format!("'{}' is not a numeric id", s))
+        }
+    };
+
+    let (uid, gid) = match value.split_once(':') {
+        Some((uid, gid)) => (parse_id(uid)?, parse_id(gid)?),
+        None => (parse_id(value)?, None),
+    };
+    if uid.is_none() && gid.is_none() {
+        return Err("expected UID[:GID]".to_string());
+    }
+    Ok(Owner { uid, gid })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::temp_dir;
+
+    #[test]
+    fn test_duplicates_are_hard_linked() {
+        let dir = temp_dir("output-link");
+        let mut sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                link_duplicates: true,
+                ..Default::default()
+            },
+        );
+
+        sink.write_image(&dir.join("a.png"), b"same bytes").unwrap();
+        sink.write_image(&dir.join("b.png"), b"same bytes").unwrap();
+        sink.write_image(&dir.join("c.png"), b"other bytes")
+            .unwrap();
+
+        assert_eq!(fs::read(dir.join("b.png")).unwrap(), b"same bytes");
+        assert_eq!(fs::read(dir.join("c.png")).unwrap(), b"other bytes");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let a = fs::metadata(dir.join("a.png")).unwrap();
+            let b = fs::metadata(dir.join("b.png")).unwrap();
+            let c = fs::metadata(dir.join("c.png")).unwrap();
+            assert_eq!(a.ino(), b.ino());
+            assert_ne!(a.ino(), c.ino());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_duplicates_are_copied_without_linking() {
+        let dir = temp_dir("output-copy");
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+
+        sink.write_image(&dir.join("a.png"), b"same bytes").unwrap();
+        sink.write_image(&dir.join("b.png"), b"same bytes").unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let a = fs::metadata(dir.join("a.png")).unwrap();
+            let b = fs::metadata(dir.join("b.png")).unwrap();
+            assert_ne!(a.ino(), b.ino());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_streamed_writes_are_hashed_and_linked() {
+        let dir = temp_dir("output-stream");
+        let mut sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                link_duplicates: true,
+                record_manifest: true,
+                ..Default::default()
+            },
+        );
+
+        for name in ["a.png", "b.png"] {
+            let mut reader: &[u8] = b"streamed bytes";
+            sink.write_image_from_reader(&dir.join(name), &mut reader, 14)
+                .unwrap();
+        }
+
+        assert_eq!(fs::read(dir.join("b.png")).unwrap(), b"streamed bytes");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let a = fs::metadata(dir.join("a.png")).unwrap();
+            let b = fs::metadata(dir.join("b.png")).unwrap();
+            assert_eq!(a.ino(), b.ino());
+        }
+        let manifest = sink.finish().unwrap().unwrap();
+        assert_eq!(manifest.entries[1].size, 14);
+        assert_eq!(manifest.entries[1].sha256, Some(sha256(b"streamed bytes")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("644"), Ok(0o644));
+        assert_eq!(parse_mode("0755"), Ok(0o755));
+        assert!(parse_mode("999").is_err());
+        assert!(parse_mode("rwx").is_err());
+    }
+
+    #[test]
+    fn test_parse_owner() {
+        assert_eq!(
+            parse_owner("1000:33"),
+            Ok(Owner {
+                uid: Some(1000),
+                gid: Some(33)
+            })
+        );
+        assert_eq!(
+            parse_owner(":33"),
+            Ok(Owner {
+                uid: None,
+                gid: Some(33)
+            })
+        );
+        assert!(parse_owner(":").is_err());
+        assert!(parse_owner("www-data").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_and_dir_modes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("output-modes");
+        let nested = dir.join("nested");
+        let mut sink = OutputSink::new(
+            nested.clone(),
+            OutputOptions {
+                file_mode: Some(0o600),
+                dir_mode: Some(0o700),
+                ..Default::default()
+            },
+        );
+        sink.create_base_dir().unwrap();
+        sink.write_image(&nested.join("a.png"), b"data").unwrap();
+
+        let dir_mode = fs::metadata(&nested).unwrap().permissions().mode() & 0o777;
+        let file_mode = fs::metadata(nested.join("a.png"))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777;
+        assert_eq!(dir_mode, 0o700);
+        assert_eq!(file_mode, 0o600);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn zip_options(on_conflict: ConflictPolicy) -> OutputOptions {
+        OutputOptions {
+            on_conflict,
+            ..Default::default()
+        }
+    }
+
+    fn write_to_zip(archive: &Path, on_conflict: ConflictPolicy, data: &[u8]) -> Result<()> {
+        let mut sink = OutputSink::new_zip(archive.to_path_buf(), zip_options(on_conflict))?;
+        let path = sink.output_path("doc", 0, 1, "png")?;
+        sink.write_image(&path, data)?;
+        sink.finish().map(|_| ())
+    }
+
+    fn read_zip(archive: &Path) -> Vec<(String, Vec<u8>)> {
+        use std::io::Read;
+        let mut zip = ZipArchive::new(fs::File::open(archive).unwrap()).unwrap();
+        let mut entries: Vec<(String, Vec<u8>)> = (0..zip.len())
+            .map(|i| {
+                let mut entry = zip.by_index(i).unwrap();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                (entry.name().to_string(), data)
+            })
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn test_zip_append_renames_existing_entries() {
+        let dir = temp_dir("zip-rename");
+        let archive = dir.join("out.zip");
+
+        write_to_zip(&archive, ConflictPolicy::Rename, b"first").unwrap();
+        write_to_zip(&archive, ConflictPolicy::Rename, b"second").unwrap();
+
+        assert_eq!(
+            read_zip(&archive),
+            vec![
+                ("doc.png".to_string(), b"first".to_vec()),
+                ("doc_1.png".to_string(), b"second".to_vec()),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_zip_append_skip_and_overwrite() {
+        let dir = temp_dir("zip-policies");
+        let archive = dir.join("out.zip");
+
+        write_to_zip(&archive, ConflictPolicy::Rename, b"first").unwrap();
+        write_to_zip(&archive, ConflictPolicy::Skip, b"second").unwrap();
+        assert_eq!(
+            read_zip(&archive),
+            vec![("doc.png".to_string(), b"first".to_vec())]
+        );
+
+        write_to_zip(&archive, ConflictPolicy::Overwrite, b"third").unwrap();
+        assert_eq!(
+            read_zip(&archive),
+            vec![("doc.png".to_string(), b"third".to_vec())]
+        );
+
+        assert!(write_to_zip(&archive, ConflictPolicy::Error, b"fourth").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_directory_conflict_policies() {
+        let dir = temp_dir("dir-policies");
+        let write = |on_conflict: ConflictPolicy, data: &[u8]| -> Result<Manifest> {
+            let mut sink = OutputSink::new(
+                dir.clone(),
+                OutputOptions {
+                    on_conflict,
+                    record_manifest: true,
+                    ..Default::default()
+                },
+            );
+            // Two images with the same name in one run never replace each other
+            for _ in 0..2 {
+                let path = sink.output_path("doc", 0, 1, "png")?;
+                sink.write_image(&path, data)?;
+            }
+            Ok(sink.finish()?.unwrap())
+        };
+        let read = |name: &str| fs::read(dir.join(name)).unwrap();
+
+        write(ConflictPolicy::Rename, b"first").unwrap();
+        write(ConflictPolicy::Rename, b"second").unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+        assert_eq!(read("doc_3.png"), b"second");
+
+        let manifest = write(ConflictPolicy::Skip, b"third").unwrap();
+        assert_eq!(read("doc.png"), b"first");
+        assert_eq!(read("doc_1.png"), b"first");
+        assert!(
+            manifest
+                .entries
+                .iter()
+                .all(|e| e.status == EntryStatus::Skipped("output exists".to_string()))
+        );
+
+        write(ConflictPolicy::Overwrite, b"fourth").unwrap();
+        assert_eq!(read("doc.png"), b"fourth");
+        assert_eq!(read("doc_1.png"), b"fourth");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+
+        assert!(write(ConflictPolicy::Error, b"fifth").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rename_skips_identical_existing_file() {
+        let dir = temp_dir("identical-existing");
+        let run = |images: &[&[u8]]| -> Manifest {
+            let mut sink = OutputSink::new(
+                dir.clone(),
+                OutputOptions {
+                    record_manifest: true,
+                    ..Default::default()
+                },
+            );
+            for (i, data) in images.iter().enumerate() {
+                let path = sink.output_path("doc", 0, 1, "png").unwrap();
+                if i % 2 == 0 {
+                    sink.write_image(&path, data).unwrap();
+                } else {
+                    sink.write_image_from_reader(&path, &mut &data[..], data.len() as u64)
+                        .unwrap();
+                }
+            }
+            sink.finish().unwrap().unwrap()
+        };
+
+        run(&[b"first", b"second"]);
+        // Re-running writes nothing new; a changed image still gets a fresh name
+        let manifest = run(&[b"first", b"second", b"third"]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+        assert_eq!(fs::read(dir.join("doc_2.png")).unwrap(), b"third");
+        assert_eq!(
+            manifest.entries[1].status,
+            EntryStatus::Skipped("identical file exists".to_string())
+        );
+        assert_eq!(
+            manifest.entries[1].fields,
+            vec![(
+                "identical_to".to_string(),
+                Json::from(dir.join("doc_1.png").display().to_string())
+            )]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_volume_path() {
+        assert_eq!(
+            volume_path(Path::new("out/covers.zip"), 2),
+            PathBuf::from("out/covers.002.zip")
+        );
+        assert_eq!(
+            volume_path(Path::new("covers"), 12),
+            PathBuf::from("covers.012")
+        );
+    }
+
+    #[test]
+    fn test_zip_split_into_volumes() {
+        let dir = temp_dir("zip-split");
+        let archive = dir.join("out.zip");
+        let options = OutputOptions {
+            split_size: Some(1500),
+            ..Default::default()
+        };
+
+        let mut sink = OutputSink::new_zip(archive.clone(), options.clone()).unwrap();
+        for i in 0..3 {
+            let path = sink.output_path("doc", i, 3, "png").unwrap();
+            sink.write_image(&path, &[i as u8; 1000]).unwrap();
+        }
+        sink.finish().unwrap();
+
+        assert!(!archive.exists());
+        for n in 1..=3 {
+            let entries = read_zip(&volume_path(&archive, n));
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].0, format!("doc_{}.png", n));
+        }
+
+        // A later run continues with a new volume and renames around earlier entries
+        let mut sink = OutputSink::new_zip(archive.clone(), options).unwrap();
+        let path = sink.output_path("doc", 0, 3, "png").unwrap();
+        sink.write_image(&path, b"again").unwrap();
+        sink.finish().unwrap();
+        assert_eq!(
+            read_zip(&volume_path(&archive, 4)),
+            vec![("doc_1_1.png".to_string(), b"again".to_vec())]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_throttle_paces_writes() {
+        let mut throttle = WriteThrottle::new(1000);
+        throttle.record(50);
+        // 50 bytes at 1000 B/s must take at least 50ms
+        assert!(throttle.started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_group_by_format() {
+        let dir = temp_dir("group-format");
+        let options = OutputOptions {
+            group_by: Some(GroupBy::Format),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), options.clone());
+        let jpeg = sink.output_path("doc", 0, 2, "jpeg").unwrap();
+        assert_eq!(jpeg, dir.join("jpg").join("doc_1.jpeg"));
+        sink.write_image(&jpeg, b"jpeg").unwrap();
+        assert!(dir.join("jpg/doc_1.jpeg").is_file());
+
+        let archive = dir.join("out.zip");
+        let mut sink = OutputSink::new_zip(archive.clone(), options).unwrap();
+        let png = sink.output_path("doc", 0, 1, "png").unwrap();
+        sink.write_image(&png, b"png").unwrap();
+        sink.finish().unwrap();
+        assert_eq!(
+            read_zip(&archive),
+            vec![("png/doc.png".to_string(), b"png".to_vec())]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_routes() {
+        let dir = temp_dir("routes");
+        let options = OutputOptions {
+            routes: Some(parse_routes("svg=vectors/, jpeg=photos/raw,png=../diagrams").unwrap()),
+            group_by: Some(GroupBy::Format),
+            ..Default::default()
+        };
+        let sink = OutputSink::new(dir.clone(), options);
+        let paths = ["svg", "jpg", "png", "gif"]
+            .map(|extension| sink.output_path("doc", 0, 1, extension).unwrap());
+        assert_eq!(
+            paths,
+            [
+                dir.join("vectors/doc.svg"),
+                dir.join("photos/raw/doc.jpg"),
+                dir.join("diagrams/doc.png"),
+                // Formats without a route are still grouped
+                dir.join("gif/doc.gif"),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(parse_routes("").is_err());
+        assert!(parse_routes("svg").is_err());
+        assert!(parse_routes("svg=").is_err());
+        assert!(parse_routes("=vectors").is_err());
+        assert!(parse_routes("jpg=a,jpeg=b").is_err());
+    }
+
+    #[test]
+    fn test_tar_gz_output() {
+        let dir = temp_dir("tar-output");
+        let archive = dir.join("out.tar.gz");
+        let options = OutputOptions {
+            group_by: Some(GroupBy::Format),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new_tar(archive.clone(), true, options.clone()).unwrap();
+        for data in [&b"first"[..], b"second"] {
+            let path = sink.output_path("doc", 0, 1, "png").unwrap();
+            sink.write_image_from_reader(&path, &mut &data[..], data.len() as u64)
+                .unwrap();
+        }
+        sink.finish().unwrap();
+
+        let decoder = flate2::read::GzDecoder::new(fs::File::open(&archive).unwrap());
+        let entries: Vec<(String, Vec<u8>)> = tar::Archive::new(decoder)
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                (entry.path().unwrap().display().to_string(), data)
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("png/doc.png".to_string(), b"first".to_vec()),
+                ("png/doc_1.png".to_string(), b"second".to_vec()),
+            ]
+        );
+
+        // Tarballs cannot be appended to
+        assert!(OutputSink::new_tar(archive.clone(), true, options).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tar_split_into_volumes() {
+        let dir = temp_dir("tar-split");
+        let archive = dir.join("out.tar");
+        let options = OutputOptions {
+            split_size: Some(3000),
+            ..Default::default()
+        };
+        assert_eq!(
+            tar_volume_path(&dir.join("out.tar.gz"), 2),
+            dir.join("out.002.tar.gz")
+        );
+
+        let mut sink = OutputSink::new_tar(archive.clone(), false, options.clone()).unwrap();
+        for i in 0..3 {
+            let path = sink.output_path("doc", i, 3, "png").unwrap();
+            sink.write_image(&path, &[i as u8; 1000]).unwrap();
+        }
+        sink.finish().unwrap();
+
+        assert!(!archive.exists());
+        let names = |n| -> Vec<String> {
+            let file = fs::File::open(tar_volume_path(&archive, n)).unwrap();
+            tar::Archive::new(file)
+                .entries()
+                .unwrap()
+                .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+                .collect()
+        };
+        // Two 1000-byte entries take 3072 bytes with their headers and the end blocks
+        assert_eq!(names(1), ["doc_1.png"]);
+        assert_eq!(names(2), ["doc_2.png"]);
+        assert_eq!(names(3), ["doc_3.png"]);
+        assert!(fs::metadata(tar_volume_path(&archive, 1)).unwrap().len() <= 3000);
+
+        // Volumes are not appended to either
+        assert!(OutputSink::new_tar(archive.clone(), false, options).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_split_gif_frames() {
+        let dir = temp_dir("gif-frames");
+        let mut sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                split_gif_frames: true,
+                ..Default::default()
+            },
+        );
+        // 1x1 GIF with two frames of 100 ms
+        let mut gif = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\0\0\0\xFF\xFF\xFF".to_vec();
+        for code in [0x04u8, 0x0C] {
+            gif.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00, 10, 0x00, 0x00, 0x00]);
+            gif.extend_from_slice(b"\x2C\0\0\0\0\x01\x00\x01\x00\x00");
+            gif.extend_from_slice(&[0x02, 0x02, code | 0x40, 0x01, 0x00]);
+        }
+        gif.push(0x3B);
+
+        let output = dir.join("doc_1.gif");
+        sink.write_image(&output, &gif).unwrap();
+        sink.finish().unwrap();
+
+        assert_eq!(fs::read(&output).unwrap(), gif);
+        let frames = dir.join("doc_1_frames");
+        assert!(frames.join("frame_001.png").exists());
+        assert!(frames.join("frame_002.png").exists());
+        let sidecar = fs::read_to_string(frames.join("frames.json")).unwrap();
+        assert!(sidecar.contains("\"source\": \"doc_1.gif\""));
+        assert!(sidecar.contains("\"delay_ms\": 100"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_only_selected_images() {
+        let dir = temp_dir("only");
+        let mut sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                only: Some(crate::filter::parse_selection("2,word/media/image5.*").unwrap()),
+                record_manifest: true,
+                ..Default::default()
+            },
+        );
+        sink.begin_document(Path::new("doc.docx"));
+        for (index, original) in ["image1.png", "image2.png", "image5.png"]
+            .iter()
+            .enumerate()
+        {
+            let original = format!("word/media/{}", original);
+            let output = sink
+                .original_output_path("doc", index, 3, "png", &original)
+                .unwrap();
+            match index {
+                0 => sink
+                    .write_image_from_reader(&output, &mut &b"one"[..], 3)
+                    .unwrap(),
+                _ => sink.write_image(&output, original.as_bytes()).unwrap(),
+            }
+        }
+        assert_eq!(sink.finish_document().unwrap(), 1);
+
+        assert!(!dir.join("doc_1.png").exists());
+        assert!(dir.join("doc_2.png").exists());
+        assert!(dir.join("doc_3.png").exists());
+        let manifest = sink.finish().unwrap().unwrap();
+        assert_eq!(
+            manifest.entries[0].status,
+            EntryStatus::Skipped("not selected".to_string())
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_size_rejected_stream_is_not_counted() {
+        let dir = temp_dir("stream-size");
+        let mut sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                filter: ImageFilter {
+                    min_size: Some(100),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        sink.begin_document(Path::new("a.docx"));
+        let small = sink.output_path("a", 0, 2, "png").unwrap();
+        sink.write_image_from_reader(&small, &mut &[0u8; 10][..], 10)
+            .unwrap();
+        let large = sink.output_path("a", 1, 2, "png").unwrap();
+        sink.write_image_from_reader(&large, &mut &[0u8; 200][..], 200)
+            .unwrap();
+
+        assert!(!small.exists() && large.exists());
+        assert_eq!(sink.finish_document().unwrap(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unconvertible_image_is_skipped() {
+        let dir = temp_dir("unconvertible");
+        let mut sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                convert_to: Some(ConvertFormat::Png),
+                ..Default::default()
+            },
+        );
+        sink.begin_document(Path::new("report.docx"));
+        let path = sink
+            .original_output_path("report", 0, 1, "gif", "word/media/image1.gif")
+            .unwrap();
+        assert_eq!(path, dir.join("report.png"));
+        sink.write_image(&path, b"GIF89a garbage").unwrap();
+
+        assert!(!path.exists());
+        assert_eq!(sink.finish_document().unwrap(), 1);
+        let damaged = sink.take_damaged();
+        assert_eq!(damaged.len(), 1);
+        assert_eq!(damaged[0].0, "word/media/image1.gif");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_animated_to() {
+        let dir = temp_dir("animated-to");
+        let mut sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                animated_to: Some(AnimatedFormat::Webp),
+                convert_to: Some(ConvertFormat::Png),
+                ..Default::default()
+            },
+        );
+        let gif = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\0\0\0\xFF\xFF\xFF\
+            \x2C\0\0\0\0\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00\x3B";
+        let output = sink.output_path("doc", 0, 2, "gif").unwrap();
+        assert_eq!(output, dir.join("doc_1.webp"));
+        sink.write_image(&output, gif).unwrap();
+        assert_eq!(
+            sniff_image_format(&fs::read(&output).unwrap()),
+            Some("webp")
+        );
+        // Other images still follow --convert-to
+        assert_eq!(
+            sink.output_path("doc", 1, 2, "bmp").unwrap(),
+            dir.join("doc_2.png")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_thumbnails() {
+        let dir = temp_dir("thumbnails");
+        let mut sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                thumbnail_size: Some(50),
+                group_by: Some(GroupBy::Format),
+                ..Default::default()
+            },
+        );
+        let mut bmp = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::new_rgb8(200, 100)
+            .write_to(&mut bmp, image::ImageFormat::Bmp)
+            .unwrap();
+        let output = sink.output_path("doc", 0, 2, "bmp").unwrap();
+        sink.write_image(&output, bmp.get_ref()).unwrap();
+        let svg = sink.output_path("doc", 1, 2, "svg").unwrap();
+        sink.write_image(&svg, b"<svg/>").unwrap();
+
+        let thumbnail = fs::read(dir.join("thumbs/bmp/doc_1.png")).unwrap();
+        let image = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((image.width(), image.height()), (50, 25));
+        assert!(!dir.join("thumbs/svg").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_images_prepared_in_parallel() {
+        let dir = temp_dir("image-threads");
+        let mut sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                convert_to: Some(ConvertFormat::Png),
+                thumbnail_size: Some(4),
+                image_threads: 2,
+                record_manifest: true,
+                ..Default::default()
+            },
+        );
+        sink.begin_document(Path::new("doc.docx"));
+        // More images than one batch holds, so some are written before the document ends
+        let total = 2 * IMAGES_PER_THREAD + 3;
+        for index in 0..total {
+            let mut bmp = std::io::Cursor::new(Vec::new());
+            image::DynamicImage::new_rgb8(10 + index as u32, 10)
+                .write_to(&mut bmp, image::ImageFormat::Bmp)
+                .unwrap();
+            let output = sink.output_path("doc", index, total, "png").unwrap();
+            sink.write_image(&output, bmp.get_ref()).unwrap();
+            sink.annotate("index", Json::from(index));
+        }
+        assert!(sink.pending.len() < total);
+        assert_eq!(sink.finish_document().unwrap(), 0);
+
+        for index in 0..total {
+            let name = format!("doc_{}.png", index + 1);
+            let image = image::load_from_memory(&fs::read(dir.join(&name)).unwrap()).unwrap();
+            assert_eq!(image.width(), 10 + index as u32);
+            assert!(dir.join(THUMBNAILS_DIR).join(&name).exists());
+        }
+        // Annotations follow the images they were made on, written in the original order
+        let manifest = sink.finish().unwrap().unwrap();
+        let annotated: Vec<_> = manifest
+            .entries
+            .iter()
+            .flat_map(|entry| &entry.fields)
+            .filter(|(key, _)| key == "index")
+            .map(|(_, value)| value.clone())
+            .collect();
+        assert_eq!(annotated, (0..total).map(Json::from).collect::<Vec<_>>());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dedupe_skips_identical_content() {
+        let dir = temp_dir("dedupe");
+        let mut sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                dedupe: true,
+                record_manifest: true,
+                ..Default::default()
+            },
+        );
+        let mut worker = sink.fork().unwrap();
+
+        sink.write_image(&dir.join("a.png"), b"logo").unwrap();
+        worker.write_image(&dir.join("b.png"), b"logo").unwrap();
+        worker
+            .write_image_from_reader(&dir.join("c.png"), &mut &b"logo"[..], 4)
+            .unwrap();
+        sink.write_image(&dir.join("d.png"), b"photo").unwrap();
+
+        assert_eq!(sink.duplicates_skipped(), 2);
+        assert!(dir.join("a.png").exists() && dir.join("d.png").exists());
+        assert!(!dir.join("b.png").exists() && !dir.join("c.png").exists());
+        sink.merge(worker).unwrap();
+        let manifest = sink.finish().unwrap().unwrap();
+        let skipped: Vec<_> = manifest
+            .entries
+            .iter()
+            .filter(|e| e.status == EntryStatus::Skipped("duplicate".to_string()))
+            .collect();
+        assert_eq!(skipped.len(), 2);
+        assert_eq!(
+            skipped[0].fields,
+            vec![(
+                "duplicate_of".to_string(),
+                Json::from(dir.join("a.png").display().to_string())
+            )]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_largest_keeps_images_with_most_pixels() {
+        let dir = temp_dir("largest");
+        let mut sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                largest: Some(2),
+                record_manifest: true,
+                ..Default::default()
+            },
+        );
+        let gif = |width, height| {
+            let mut data = std::io::Cursor::new(Vec::new());
+            image::DynamicImage::new_rgb8(width, height)
+                .write_to(&mut data, image::ImageFormat::Gif)
+                .unwrap();
+            data.into_inner()
+        };
+
+        sink.begin_document(Path::new("a.docx"));
+        sink.write_image(&dir.join("icon.gif"), &gif(16, 16))
+            .unwrap();
+        sink.write_image(&dir.join("figure.gif"), &gif(200, 100))
+            .unwrap();
+        sink.annotate("alt", Json::from("Figure 1"));
+        let photo = gif(90, 90);
+        sink.write_image_from_reader(&dir.join("photo.gif"), &mut &photo[..], photo.len() as u64)
+            .unwrap();
+        assert!(!dir.join("figure.gif").exists());
+
+        assert_eq!(sink.finish_document().unwrap(), 1);
+        assert!(dir.join("figure.gif").exists() && dir.join("photo.gif").exists());
+        assert!(!dir.join("icon.gif").exists());
+        assert_eq!(sink.finish_document().unwrap(), 0);
+
+        let manifest = sink.finish().unwrap().unwrap();
+        let statuses: Vec<_> = manifest.entries.iter().map(|e| &e.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                &EntryStatus::Skipped("not among the largest".to_string()),
+                &EntryStatus::Written,
+                &EntryStatus::Written
+            ]
+        );
+        assert_eq!(
+            manifest.entries[1].fields,
+            vec![("alt".to_string(), Json::from("Figure 1"))]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_keep_paths() {
+        let dir = temp_dir("keep-paths");
+        let sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                keep_paths: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            sink.original_output_path("report", 2, 3, "png", "word/media/image3.png")
+                .unwrap(),
+            dir.join("report/word/media/image3.png")
+        );
+        assert_eq!(
+            sink.original_output_path("report", 0, 1, "png", "../a:b/./x.png")
+                .unwrap(),
+            dir.join("report/a_b/x.png")
+        );
+        // Images without a path of their own keep the default names
+        assert_eq!(
+            sink.output_path("report", 0, 2, "png").unwrap(),
+            dir.join("report_1.png")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_original_names() {
+        let dir = temp_dir("original-names");
+        let options = |mode| OutputOptions {
+            original_names: Some(mode),
+            ..Default::default()
+        };
+        let sink = OutputSink::new(dir.clone(), options(OriginalNames::Bare));
+        assert_eq!(
+            sink.original_output_path("report", 2, 3, "png", "word/media/image42.png")
+                .unwrap(),
+            dir.join("image42.png")
+        );
+        // The extension follows the detected format; names that clash are numbered
+        assert_eq!(
+            sink.original_output_path("report", 0, 3, "jpg", "OEBPS/image42.jpeg")
+                .unwrap(),
+            dir.join("image42.jpg")
+        );
+        assert_eq!(
+            sink.original_output_path("book", 1, 3, "png", "media/image42.png")
+                .unwrap(),
+            dir.join("image42_1.png")
+        );
+        assert_eq!(
+            sink.output_path("page", 0, 2, "gif").unwrap(),
+            dir.join("page_1.gif")
+        );
+
+        let sink = OutputSink::new(dir.clone(), options(OriginalNames::Prefixed));
+        assert_eq!(
+            sink.original_output_path("report", 0, 1, "png", "../media/a:b.png")
+                .unwrap(),
+            dir.join("report_a_b.png")
+        );
+        assert_eq!(
+            sink.original_output_path("report", 1, 2, "png", "..")
+                .unwrap(),
+            dir.join("report_2.png")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_name_from_alt() {
+        let dir = temp_dir("name-from-alt");
+        let sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                name_from_alt: true,
+                ..Default::default()
+            },
+        );
+        let with_alt = |alt_text| ImageDetails {
+            original: Some("word/media/image1.png"),
+            alt_text,
+            ..Default::default()
+        };
+        assert_eq!(
+            sink.detailed_output_path(
+                "report",
+                0,
+                3,
+                "png",
+                with_alt(Some("Figure 3 -\n Network topology"))
+            )
+            .unwrap(),
+            dir.join("Figure 3 - Network topology.png")
+        );
+        assert_eq!(
+            sink.detailed_output_path(
+                "report",
+                1,
+                3,
+                "png",
+                with_alt(Some("Figure 3 - Network topology"))
+            )
+            .unwrap(),
+            dir.join("Figure 3 - Network topology_1.png")
+        );
+        // Without usable alt text the numbered name is used
+        assert_eq!(
+            sink.detailed_output_path("report", 2, 3, "png", with_alt(Some(" ... ")))
+                .unwrap(),
+            dir.join("report_3.png")
+        );
+        assert_eq!(alt_stem("a/b: c?").as_deref(), Some("a_b_ c_"));
+        assert_eq!(
+            alt_stem(&"x".repeat(300)).unwrap().len(),
+            MAX_ALT_STEM_CHARS
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ascii_and_normalized_names() {
+        let dir = temp_dir("ascii-names");
+        let sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                ascii_names: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            sink.output_path("Brontë - Jane Eyre", 0, 1, "jpg").unwrap(),
+            dir.join("Bronte - Jane Eyre.jpg")
+        );
+        let sink = OutputSink::new(
+            dir.clone(),
+            OutputOptions {
+                name_form: Some(NameForm::Nfc),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            sink.output_path("Bronte\u{308}", 0, 1, "jpg").unwrap(),
+            dir.join("Bront\u{eb}.jpg")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_with_stem_suffix() {
+        assert_eq!(
+            with_stem_suffix("report_3.png", "footnote"),
+            "report_3_footnote.png"
+        );
+        assert_eq!(
+            with_stem_suffix("v1.2/report.tar.png", "comment"),
+            "v1.2/report.tar_comment.png"
+        );
+        assert_eq!(with_stem_suffix("a/.hidden", "x"), "a/.hidden_x");
+    }
+
+    #[test]
+    fn test_preserve_timestamps() {
+        let dir = temp_dir("output-timestamps");
+        fs::create_dir_all(&dir).unwrap();
+        let document = dir.join("a.docx");
+        fs::write(&document, b"doc").unwrap();
+        let document_time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        filetime::set_file_mtime(
+            &document,
+            filetime::FileTime::from_system_time(document_time),
+        )
+        .unwrap();
+        let options = OutputOptions {
+            preserve_timestamps: true,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.join("out"), options);
+        sink.begin_document(&document);
+
+        let stamped = sink.output_path("a", 0, 3, "png").unwrap();
+        let entry_time = zip::DateTime::from_date_and_time(2020, 2, 29, 12, 30, 10).unwrap();
+        sink.set_entry_time(&stamped, Some(entry_time));
+        sink.write_image(&stamped, b"one").unwrap();
+        let unstamped = sink.output_path("a", 1, 3, "png").unwrap();
+        sink.set_entry_time(&unstamped, Some(zip::DateTime::default()));
+        sink.write_image(&unstamped, b"two").unwrap();
+
+        // The entry's time is local time: 2020-02-29 12:30:10 UTC shifted by the offset
+        let offset =
+            time::UtcOffset::local_offset_at(time::OffsetDateTime::try_from(entry_time).unwrap())
+                .unwrap_or(time::UtcOffset::UTC);
+        let seconds = 1_582_979_410 - offset.whole_seconds() as i64;
+        let modified = |path: &Path| fs::metadata(path).unwrap().modified().unwrap();
+        assert_eq!(
+            modified(&stamped),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(seconds as u64)
+        );
+        assert_eq!(modified(&unstamped), document_time);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cas_store_dedups_and_links() {
+        let dir = temp_dir("cas");
+        let mut sink = OutputSink::new_cas(dir.clone(), OutputOptions::default());
+
+        sink.begin_document(Path::new("in/a.docx"));
+        let first = sink.output_path("a", 0, 1, "png").unwrap();
+        sink.write_image(&first, b"logo").unwrap();
+        sink.begin_document(Path::new("in/b.docx"));
+        let second = sink.output_path("b", 0, 1, "png").unwrap();
+        sink.write_image(&second, b"logo").unwrap();
+
+        assert_eq!(first, dir.join("by-document/a/a.png"));
+        let hex = to_hex(&sha256(b"logo"));
+        let object = dir
+            .join("by-hash")
+            .join(&hex[..2])
+            .join(format!("{}.png", &hex[2..]));
+        assert_eq!(fs::read(&object).unwrap(), b"logo");
+        assert_eq!(fs::read(&second).unwrap(), b"logo");
+        assert_eq!(fs::read_dir(object.parent().unwrap()).unwrap().count(), 1);
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(&first).unwrap(),
+            Path::new("../..")
+                .join("by-hash")
+                .join(&hex[..2])
+                .join(format!("{}.png", &hex[2..]))
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_manifest_records_written_and_skipped() {
+        let dir = temp_dir("manifest");
+        let options = OutputOptions {
+            record_manifest: true,
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), options);
+        sink.begin_document(Path::new("a.epub"));
+        sink.write_image(&dir.join("a.png"), b"cover").unwrap();
+        sink.remember_perceptual_hash(0b1011, &dir.join("a.png"));
+        sink.begin_document(Path::new("b.epub"));
+        let (similar, distance) = sink.find_similar(0b1001, 2).unwrap();
+        assert_eq!((similar, distance), (dir.join("a.png"), 1));
+        sink.record_skipped(b"cover", "near-duplicate cover", Vec::new());
+
+        let manifest = sink.finish().unwrap().unwrap();
+        assert_eq!(manifest.entries.len(), 2);
+        assert_eq!(manifest.entries[0].document, PathBuf::from("a.epub"));
+        assert_eq!(manifest.entries[0].status, EntryStatus::Written);
+        assert_eq!(manifest.entries[1].document, PathBuf::from("b.epub"));
+        assert_eq!(manifest.entries[1].output, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
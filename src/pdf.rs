@@ -0,0 +1,615 @@
+//! PDF file processing module
+//!
+//! Extracts image XObjects drawn on each page. JPEG (`DCTDecode`) and JPEG 2000
+//! (`JPXDecode`) streams are written as-is; other images are stored as raw samples
+//! and are re-encoded as PNG.
+
+use anyhow::{Context, Result};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use lopdf::{Document, Object, ObjectId, Stream};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::common::DocumentMetadata;
+use crate::extract::ExtractOptions;
+use crate::json::Json;
+use crate::output::OutputSink;
+
+/// Largest decoded image accepted, guarding against decompression bombs
+const MAX_DECODED_BYTES: usize = 1 << 30;
+
+/// An image XObject and the page it first appears on
+#[derive(Debug)]
+pub struct PageImage {
+    pub id: ObjectId,
+    /// 1-based page number
+    pub page: u32,
+}
+
+/// Processes a single .pdf file, extracting images matching the allowed extensions.
+/// Returns the number of images extracted.
+pub fn process_file(
+    input_path: &Path,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let doc_name = input_path
+        .file_stem()
+        .context("Invalid filename")?
+        .to_string_lossy()
+        .to_string();
+
+    let file = fs::File::open(input_path)
+        .with_context(|| format!("Failed to open input file: {}", input_path.display()))?;
+
+    process_reader(file, input_path, &doc_name, sink, options)
+}
+
+/// Processes a PDF from any reader.
+/// `input_path` is only used for messages; `doc_name` is the output base name.
+pub fn process_reader<R: Read>(
+    reader: R,
+    input_path: &Path,
+    doc_name: &str,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let doc = load(reader, input_path)?;
+
+    // Silently skip documents that don't match the metadata filter
+    if !options.document_filter.matches(&info_metadata(&doc)) {
+        return Ok(0);
+    }
+
+    let mut images = Vec::new();
+    for image in page_images(&doc) {
+        let Ok(stream) = doc.get_object(image.id).and_then(Object::as_stream) else {
+            continue;
+        };
+        match image_extension(stream) {
+            Some(extension) if options.allowed_extensions.contains(extension) => {
+                images.push((image, stream, extension))
+            }
+            Some(_) => {}
+            None => status!(
+                "Skipping image {} {} on page {}: unsupported encoding",
+                image.id.0,
+                image.id.1,
+                image.page
+            ),
+        }
+    }
+
+    let total_images = images.len();
+    if total_images > 0 {
+        sink.create_base_dir()?;
+
+        status!(
+            "Found {} image files in {}.",
+            total_images,
+            input_path.display()
+        );
+    }
+
+    let mut extracted = 0;
+    for (seq_index, (image, stream, extension)) in images.into_iter().enumerate() {
+        let Some(data) = image_data(&doc, stream)? else {
+            sink.warn(&format!(
+                "Skipping image on page {} of {}: unsupported color space",
+                image.page,
+                input_path.display()
+            ));
+            continue;
+        };
+
+        let output_path = sink.output_path(doc_name, seq_index, total_images, extension)?;
+
+        status!("Extracting to: {}", output_path.display());
+
+        sink.write_image(&output_path, &data)?;
+        if sink.records_manifest() {
+            sink.annotate("page", Json::from(image.page));
+        }
+        extracted += 1;
+    }
+
+    Ok(extracted)
+}
+
+/// Parses a PDF, failing on documents that need a password
+pub fn load<R: Read>(reader: R, input_path: &Path) -> Result<Document> {
+    match Document::load_from(reader) {
+        Ok(doc) if !doc.is_encrypted() => Ok(doc),
+        Ok(_) | Err(lopdf::Error::InvalidPassword | lopdf::Error::Decryption(_)) => {
+            anyhow::bail!("{} is password-protected", input_path.display())
+        }
+        Err(e) => Err(e).with_context(|| format!("Failed to read PDF: {}", input_path.display())),
+    }
+}
+
+/// Reads the Title, Author and Subject entries of the document information dictionary
+fn info_metadata(doc: &Document) -> DocumentMetadata {
+    let info = doc
+        .trailer
+        .get(b"Info")
+        .and_then(|info| match info {
+            Object::Reference(id) => doc.get_dictionary(*id),
+            other => other.as_dict(),
+        })
+        .ok();
+    let field = |key: &[u8]| {
+        let bytes = info?.get(key).ok()?.as_str().ok()?;
+        let text = decode_text_string(bytes);
+        Some(text.trim().to_string()).filter(|t| !t.is_empty())
+    };
+    DocumentMetadata {
+        title: field(b"Title"),
+        author: field(b"Author"),
+        subject: field(b"Subject"),
+        ..Default::default()
+    }
+}
+
+/// Decodes a PDF text string: UTF-16BE with a byte order mark, otherwise
+/// PDFDocEncoding (read as Latin-1)
+fn decode_text_string(bytes: &[u8]) -> String {
+    match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Lists image XObjects in page order, including those drawn inside form XObjects.
+/// Images drawn more than once are listed on the first page that uses them.
+pub fn page_images(doc: &Document) -> Vec<PageImage> {
+    let mut images = Vec::new();
+    let mut seen = HashSet::new();
+    for (page, page_id) in doc.get_pages() {
+        let Ok((inline, resource_ids)) = doc.get_page_resources(page_id) else {
+            continue;
+        };
+        let resources = inline.into_iter().chain(
+            resource_ids
+                .iter()
+                .filter_map(|id| doc.get_dictionary(*id).ok()),
+        );
+        for resources in resources {
+            collect_xobjects(doc, resources, page, &mut seen, &mut images);
+        }
+    }
+    images
+}
+
+fn collect_xobjects(
+    doc: &Document,
+    resources: &lopdf::Dictionary,
+    page: u32,
+    seen: &mut HashSet<ObjectId>,
+    images: &mut Vec<PageImage>,
+) {
+    let Ok(xobjects) = doc.get_dict_in_dict(resources, b"XObject") else {
+        return;
+    };
+    for (_, object) in xobjects.iter() {
+        // Only indirect objects can be streams; `seen` also stops self-referencing forms
+        let Ok(id) = object.as_reference() else {
+            continue;
+        };
+        if !seen.insert(id) {
+            continue;
+        }
+        let Ok(stream) = doc.get_object(id).and_then(Object::as_stream) else {
+            continue;
+        };
+        match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+            Ok(b"Image") => images.push(PageImage { id, page }),
+            Ok(b"Form") => {
+                if let Ok(form_resources) = doc.get_dict_in_dict(&stream.dict, b"Resources") {
+                    collect_xobjects(doc, form_resources, page, seen, images);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Extension an image stream is written with, or `None` for encodings that can't be exported
+pub fn image_extension(stream: &Stream) -> Option<&'static str> {
+    let filters = stream.filters().unwrap_or_default();
+    match filters.last().copied() {
+        Some(b"DCTDecode") if filters.len() == 1 => Some("jpg"),
+        Some(b"JPXDecode") if filters.len() == 1 => Some("jp2"),
+        Some(b"DCTDecode" | b"JPXDecode" | b"CCITTFaxDecode" | b"JBIG2Decode") => None,
+        _ => Some("png"),
+    }
+}
+
+/// Returns the file contents for an image stream: the stream itself for JPEG and
+/// JPEG 2000, otherwise a PNG built from the decoded samples.
+/// `None` means the image uses a color space that can't be converted.
+pub fn image_data(doc: &Document, stream: &Stream) -> Result<Option<Vec<u8>>> {
+    if image_extension(stream) != Some("png") {
+        return Ok(Some(stream.content.clone()));
+    }
+
+    let samples = stream
+        .decompressed_content_with_limit(MAX_DECODED_BYTES)
+        .map_err(|e| anyhow::anyhow!("Failed to decode image stream: {}", e))?;
+    let dict = &stream.dict;
+    let number = |key: &[u8]| {
+        dict.get(key)
+            .and_then(|o| doc.dereference(o))
+            .and_then(|(_, o)| o.as_i64())
+            .ok()
+    };
+    let (Some(width), Some(height)) = (number(b"Width"), number(b"Height")) else {
+        anyhow::bail!("Image stream has no dimensions");
+    };
+
+    let image_mask = dict
+        .get(b"ImageMask")
+        .and_then(Object::as_bool)
+        .unwrap_or(false);
+    let (colors, bits) = if image_mask {
+        // Stencil masks paint 0 samples and leave 1 samples untouched
+        (Colors::Gray, 1)
+    } else {
+        let Some(colors) = dict
+            .get(b"ColorSpace")
+            .ok()
+            .and_then(|space| color_space(doc, space))
+        else {
+            return Ok(None);
+        };
+        (colors, number(b"BitsPerComponent").unwrap_or(8))
+    };
+
+    Ok(encode_png(
+        width.try_into()?,
+        height.try_into()?,
+        bits.try_into()?,
+        &colors,
+        &samples,
+    ))
+}
+
+/// Color spaces that can be expressed as PNG
+#[derive(Debug, PartialEq)]
+enum Colors {
+    Gray,
+    Rgb,
+    Cmyk,
+    /// Palette of RGB triples
+    Indexed(Vec<u8>),
+}
+
+impl Colors {
+    fn components(&self) -> usize {
+        match self {
+            Colors::Gray | Colors::Indexed(_) => 1,
+            Colors::Rgb => 3,
+            Colors::Cmyk => 4,
+        }
+    }
+}
+
+fn color_space(doc: &Document, space: &Object) -> Option<Colors> {
+    let (_, space) = doc.dereference(space).ok()?;
+    let Some(array) = space.as_array().ok().filter(|array| {
+        matches!(
+            array.first().and_then(|f| f.as_name().ok()),
+            Some(b"Indexed" | b"I")
+        )
+    }) else {
+        return base_color_space(doc, space);
+    };
+
+    // The base of an indexed space may not be indexed itself, which also stops a space
+    // that refers to itself
+    let base = base_color_space(doc, doc.dereference(array.get(1)?).ok()?.1)?;
+    let (_, hival) = doc.dereference(array.get(2)?).ok()?;
+    let hival = usize::try_from(hival.as_i64().ok()?)
+        .ok()
+        .filter(|&h| h <= 255)?;
+    let size = (hival + 1) * base.components();
+    let (_, lookup) = doc.dereference(array.get(3)?).ok()?;
+    let mut table = match lookup {
+        Object::String(bytes, _) => bytes.clone(),
+        Object::Stream(stream) => stream.get_plain_content_with_limit(size).ok()?,
+        _ => return None,
+    };
+    table.truncate(size);
+    let palette = match base {
+        Colors::Gray => table.iter().flat_map(|&g| [g, g, g]).collect(),
+        Colors::Rgb => table,
+        Colors::Cmyk => cmyk_to_rgb(&table),
+        Colors::Indexed(_) => return None,
+    };
+    Some(Colors::Indexed(palette))
+}
+
+/// A color space that is not indexed: a device space or an ICC profile
+fn base_color_space(doc: &Document, space: &Object) -> Option<Colors> {
+    if let Ok(name) = space.as_name() {
+        return device_color_space(name);
+    }
+
+    let array = space.as_array().ok()?;
+    let family = array.first()?.as_name().ok()?;
+    match family {
+        b"ICCBased" => {
+            let (_, profile) = doc.dereference(array.get(1)?).ok()?;
+            match profile
+                .as_stream()
+                .ok()?
+                .dict
+                .get(b"N")
+                .ok()?
+                .as_i64()
+                .ok()?
+            {
+                1 => Some(Colors::Gray),
+                3 => Some(Colors::Rgb),
+                4 => Some(Colors::Cmyk),
+                _ => None,
+            }
+        }
+        other => device_color_space(other),
+    }
+}
+
+fn device_color_space(name: &[u8]) -> Option<Colors> {
+    match name {
+        b"DeviceGray" | b"CalGray" | b"G" => Some(Colors::Gray),
+        b"DeviceRGB" | b"CalRGB" | b"RGB" => Some(Colors::Rgb),
+        b"DeviceCMYK" | b"CMYK" => Some(Colors::Cmyk),
+        _ => None,
+    }
+}
+
+/// Naive CMYK to RGB conversion of 8-bit samples
+fn cmyk_to_rgb(samples: &[u8]) -> Vec<u8> {
+    samples
+        .chunks_exact(4)
+        .flat_map(|px| {
+            let k = 255 - px[3] as u32;
+            [0, 1, 2].map(|i| ((255 - px[i] as u32) * k / 255) as u8)
+        })
+        .collect()
+}
+
+/// Encodes decoded PDF image samples as a PNG.
+/// Returns `None` for bit depths PNG can't represent for the color space.
+fn encode_png(
+    width: u32,
+    height: u32,
+    bits: u8,
+    colors: &Colors,
+    samples: &[u8],
+) -> Option<Vec<u8>> {
+    let row_len = (width as usize * colors.components() * bits as usize).div_ceil(8);
+    let pixels = samples.get(..row_len.checked_mul(height as usize)?)?;
+
+    let (color_type, bits, pixels) = match colors {
+        Colors::Gray if matches!(bits, 1 | 2 | 4 | 8 | 16) => (0, bits, pixels.to_vec()),
+        Colors::Rgb if matches!(bits, 8 | 16) => (2, bits, pixels.to_vec()),
+        Colors::Cmyk if bits == 8 => (2, 8, cmyk_to_rgb(pixels)),
+        Colors::Indexed(_) if matches!(bits, 1 | 2 | 4 | 8) => (3, bits, pixels.to_vec()),
+        _ => return None,
+    };
+    let row_len = pixels.len() / height.max(1) as usize;
+
+    // Every row uses filter type 0 (None)
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in pixels.chunks(row_len.max(1)) {
+        encoder.write_all(&[0]).ok()?;
+        encoder.write_all(row).ok()?;
+    }
+    let compressed = encoder.finish().ok()?;
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut chunk = |kind: &[u8], body: &[u8]| {
+        let mut crc = flate2::Crc::new();
+        crc.update(kind);
+        crc.update(body);
+        png.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        png.extend_from_slice(kind);
+        png.extend_from_slice(body);
+        png.extend_from_slice(&crc.sum().to_be_bytes());
+    };
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[bits, color_type, 0, 0, 0]);
+    chunk(b"IHDR", &header);
+    if let Colors::Indexed(palette) = colors {
+        chunk(b"PLTE", palette);
+    }
+    chunk(b"IDAT", &compressed);
+    chunk(b"IEND", &[]);
+    Some(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::temp_dir;
+    use crate::output::OutputOptions;
+    use crate::raster::decode_luma;
+    use lopdf::dictionary;
+
+    /// Builds a one-page PDF drawing the given image XObjects, the second through a form
+    fn pdf_with_images(images: Vec<Stream>) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let mut xobjects = lopdf::Dictionary::new();
+        let mut form_xobjects = lopdf::Dictionary::new();
+        for (i, image) in images.into_iter().enumerate() {
+            let id = doc.add_object(image);
+            let target = if i == 1 {
+                &mut form_xobjects
+            } else {
+                &mut xobjects
+            };
+            target.set(format!("Im{}", i), id);
+        }
+        let form = Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Form",
+                "Resources" => dictionary! { "XObject" => form_xobjects },
+            },
+            Vec::new(),
+        );
+        xobjects.set("Fm0", doc.add_object(form));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Resources" => dictionary! { "XObject" => xobjects },
+        });
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+
+        let mut data = Vec::new();
+        doc.save_to(&mut data).unwrap();
+        data
+    }
+
+    fn image_stream(extra: lopdf::Dictionary, content: Vec<u8>) -> Stream {
+        let mut dict = dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Image",
+        };
+        dict.extend(&extra);
+        Stream::new(dict, content)
+    }
+
+    #[test]
+    fn test_extract_pdf_images() {
+        let dir = temp_dir("pdf");
+
+        let jpeg = b"\xFF\xD8\xFF\xE0jpeg".to_vec();
+        let mut gray = image_stream(
+            dictionary! {
+                "Width" => 2,
+                "Height" => 1,
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 8,
+            },
+            vec![0, 255],
+        );
+        gray.compress().unwrap();
+        let pdf = pdf_with_images(vec![
+            image_stream(
+                dictionary! {
+                    "Width" => 1,
+                    "Height" => 1,
+                    "ColorSpace" => "DeviceRGB",
+                    "BitsPerComponent" => 8,
+                    "Filter" => "DCTDecode",
+                },
+                jpeg.clone(),
+            ),
+            gray,
+            image_stream(
+                dictionary! { "Width" => 1, "Height" => 1, "Filter" => "JBIG2Decode" },
+                vec![0],
+            ),
+        ]);
+
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count = process_reader(
+            pdf.as_slice(),
+            Path::new("doc.pdf"),
+            "doc",
+            &mut sink,
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(std::fs::read(dir.join("doc_1.jpg")).unwrap(), jpeg);
+        let png = std::fs::read(dir.join("doc_2.png")).unwrap();
+        assert_eq!(decode_luma(&png).unwrap().pixels, vec![0, 255]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_indexed_color_spaces() {
+        let mut doc = Document::with_version("1.5");
+        let gray = Object::Array(vec![
+            "Indexed".into(),
+            "DeviceGray".into(),
+            1.into(),
+            Object::string_literal(vec![0, 255, 7, 7]),
+        ]);
+        assert_eq!(
+            color_space(&doc, &gray),
+            Some(Colors::Indexed(vec![0, 0, 0, 255, 255, 255]))
+        );
+
+        // An indexed space whose base is itself
+        let id = doc.new_object_id();
+        doc.objects.insert(
+            id,
+            Object::Array(vec![
+                "Indexed".into(),
+                id.into(),
+                1.into(),
+                Object::string_literal(vec![0, 255]),
+            ]),
+        );
+        assert_eq!(color_space(&doc, &id.into()), None);
+
+        // A lookup stream larger than the palette it describes
+        let lookup = doc.add_object(Stream::new(dictionary! {}, vec![0; 4096]));
+        let oversized = Object::Array(vec![
+            "Indexed".into(),
+            "DeviceRGB".into(),
+            1.into(),
+            lookup.into(),
+        ]);
+        assert_eq!(color_space(&doc, &oversized), None);
+    }
+
+    #[test]
+    fn test_encode_indexed_and_cmyk() {
+        let palette = Colors::Indexed(vec![0, 0, 0, 255, 255, 255]);
+        let png = encode_png(8, 1, 1, &palette, &[0b0000_1111]).unwrap();
+        assert_eq!(
+            decode_luma(&png).unwrap().pixels,
+            [0, 0, 0, 0, 255, 255, 255, 255]
+        );
+
+        assert_eq!(
+            cmyk_to_rgb(&[0, 0, 0, 0, 0, 0, 0, 255]),
+            vec![255, 255, 255, 0, 0, 0]
+        );
+        // Truncated sample data is rejected rather than padded
+        assert_eq!(encode_png(2, 2, 8, &Colors::Rgb, &[0; 6]), None);
+    }
+}
@@ -0,0 +1,76 @@
+//! Perceptual image hashing for near-duplicate detection
+
+use crate::raster::decode_luma;
+
+/// Default largest Hamming distance at which two hashes are considered the same picture
+pub const SIMILAR_MAX_DISTANCE: u32 = 6;
+
+/// Computes a 64-bit difference hash (dHash) of an image.
+/// Each bit records whether brightness increases between horizontally adjacent
+/// cells of a 9x8 downscaled grayscale copy, so the hash survives re-encoding,
+/// resizing and small colour changes. Returns None if the format can't be decoded.
+pub fn perceptual_hash(data: &[u8]) -> Option<u64> {
+    let cells = decode_luma(data)?.resample(9, 8);
+    let mut hash = 0u64;
+    for row in 0..8 {
+        for col in 0..8 {
+            let left = cells[row * 9 + col];
+            let right = cells[row * 9 + col + 1];
+            hash = (hash << 1) | (left < right) as u64;
+        }
+    }
+    Some(hash)
+}
+
+/// Number of differing bits between two hashes
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an uncompressed 24-bit BMP from a grayscale pixel function
+    fn bmp(width: usize, height: usize, pixel: impl Fn(usize, usize) -> u8) -> Vec<u8> {
+        let stride = (width * 3).div_ceil(4) * 4;
+        let mut data = vec![0u8; 54];
+        data[0..2].copy_from_slice(b"BM");
+        data[10..14].copy_from_slice(&54u32.to_le_bytes());
+        data[14..18].copy_from_slice(&40u32.to_le_bytes());
+        data[18..22].copy_from_slice(&(width as u32).to_le_bytes());
+        data[22..26].copy_from_slice(&(-(height as i32)).to_le_bytes());
+        data[26..28].copy_from_slice(&1u16.to_le_bytes());
+        data[28..30].copy_from_slice(&24u16.to_le_bytes());
+        for y in 0..height {
+            let mut row = Vec::with_capacity(stride);
+            for x in 0..width {
+                let v = pixel(x, y);
+                row.extend_from_slice(&[v, v, v]);
+            }
+            row.resize(stride, 0);
+            data.extend_from_slice(&row);
+        }
+        data
+    }
+
+    #[test]
+    fn test_similar_images_have_close_hashes() {
+        let gradient = |x: usize, y: usize| ((x * 7 + y * 3) % 256) as u8;
+        let original = perceptual_hash(&bmp(90, 80, gradient)).unwrap();
+        // Same picture at a different size with slightly different brightness
+        let resized = perceptual_hash(&bmp(45, 40, |x, y| {
+            gradient(x * 2, y * 2).saturating_add(3)
+        }))
+        .unwrap();
+        let different = perceptual_hash(&bmp(90, 80, |x, _| if x < 45 { 255 } else { 0 })).unwrap();
+
+        assert!(hamming_distance(original, resized) <= SIMILAR_MAX_DISTANCE);
+        assert!(hamming_distance(original, different) > SIMILAR_MAX_DISTANCE);
+    }
+
+    #[test]
+    fn test_undecodable_data_has_no_hash() {
+        assert_eq!(perceptual_hash(b"not an image"), None);
+    }
+}
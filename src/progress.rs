@@ -0,0 +1,59 @@
+//! Progress events for programs embedding the library
+//!
+//! A [`ProgressObserver`] given to [`Extractor::observer`](crate::Extractor::observer) is
+//! told about each document and image as the run goes on, so a GUI can show progress
+//! without parsing the messages printed to stdout and stderr. Those are printed as well;
+//! [`common::set_quiet`](crate::common::set_quiet) turns the progress messages off.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::report::DocumentResult;
+
+/// Receives the events of an extraction run. Every method does nothing by default, so
+/// observers only implement the events they care about.
+///
+/// With [`Extractor::jobs`](crate::Extractor::jobs), events come from several worker
+/// threads at once; the `document` of an image event tells them apart.
+pub trait ProgressObserver: Send + Sync {
+    /// Processing of `document` starts
+    fn document_started(&self, document: &Path) {
+        let _ = document;
+    }
+
+    /// An image of `document` was found and named `output`. It may still be left out by
+    /// the filters, as a duplicate or by `OutputOptions::only`.
+    fn image_found(&self, document: &Path, output: &Path) {
+        let _ = (document, output);
+    }
+
+    /// An image of `document` was written to `output` (an entry name joined to the
+    /// archive path for archive output), `size` bytes after any conversion
+    fn image_written(&self, document: &Path, output: &Path, size: u64) {
+        let _ = (document, output, size);
+    }
+
+    /// Something went wrong with part of `document` that does not stop it, such as an
+    /// image that could not be converted. The message is also printed to stderr.
+    /// Warnings about the whole run come with an empty `document`.
+    fn warning(&self, document: &Path, message: &str) {
+        let _ = (document, message);
+    }
+
+    /// Processing of a document ended, with its outcome: images extracted, the error if
+    /// it failed, or why it was skipped
+    fn document_finished(&self, result: &DocumentResult) {
+        let _ = result;
+    }
+}
+
+/// An observer shared by the extractor and all of its sinks
+#[derive(Clone)]
+pub(crate) struct SharedObserver(pub(crate) Arc<dyn ProgressObserver>);
+
+impl fmt::Debug for SharedObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressObserver")
+    }
+}
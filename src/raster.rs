@@ -0,0 +1,163 @@
+//! Decoding of extracted images to grayscale and reading their dimensions, for
+//! perceptual hashing and size filters. Colour is reduced to luma and alpha is ignored;
+//! formats the `image` crate cannot read return None.
+
+use image::{ImageDecoder, ImageReader};
+use std::io::Cursor;
+
+use crate::convert::decode_limits;
+
+/// An 8-bit grayscale image
+#[derive(Debug, Clone)]
+pub struct Luma {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Luma {
+    /// Averages the pixels in each cell of a `cols` x `rows` grid
+    pub fn resample(&self, cols: usize, rows: usize) -> Vec<f32> {
+        let mut cells = Vec::with_capacity(cols * rows);
+        for row in 0..rows {
+            let y0 = row * self.height / rows;
+            let y1 = ((row + 1) * self.height / rows)
+                .max(y0 + 1)
+                .min(self.height);
+            for col in 0..cols {
+                let x0 = col * self.width / cols;
+                let x1 = ((col + 1) * self.width / cols).max(x0 + 1).min(self.width);
+                let mut sum = 0u64;
+                let mut count = 0u64;
+                for y in y0..y1 {
+                    let line = &self.pixels[y * self.width..(y + 1) * self.width];
+                    for &p in &line[x0..x1] {
+                        sum += p as u64;
+                        count += 1;
+                    }
+                }
+                cells.push(if count == 0 {
+                    0.0
+                } else {
+                    sum as f32 / count as f32
+                });
+            }
+        }
+        cells
+    }
+}
+
+/// Decodes image data to grayscale if the format is supported
+pub fn decode_luma(data: &[u8]) -> Option<Luma> {
+    let mut reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?;
+    reader.limits(decode_limits());
+    let luma = reader.decode().ok()?.into_luma8();
+    let (width, height) = (luma.width() as usize, luma.height() as usize);
+    (width > 0 && height > 0).then(|| Luma {
+        width,
+        height,
+        pixels: luma.into_raw(),
+    })
+}
+
+/// Reads an image's pixel size from its header without decoding it. Returns None for
+/// formats the `image` crate cannot read, such as SVG or EMF.
+pub fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let decoder = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    let (width, height) = decoder.dimensions();
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+/// Sort key for picking the largest of several images: the pixel count, then the byte
+/// size, which also ranks images whose dimensions can't be read (SVG, EMF, ...)
+pub fn size_rank(data: &[u8]) -> (u64, usize) {
+    let pixels = image_dimensions(data).map_or(0, |(w, h)| u64::from(w) * u64::from(h));
+    (pixels, data.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, GrayImage, ImageFormat};
+
+    fn encode(image: DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, format).unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn test_decode_png_gray() {
+        let gray = GrayImage::from_fn(4, 2, |x, y| image::Luma([(x * 10 + y * 100) as u8]));
+        let luma = decode_luma(&encode(gray.into(), ImageFormat::Png)).unwrap();
+        assert_eq!((luma.width, luma.height), (4, 2));
+        assert_eq!(luma.pixels, vec![0, 10, 20, 30, 100, 110, 120, 130]);
+    }
+
+    #[test]
+    fn test_decode_bmp_24bit() {
+        let mut bmp = vec![0u8; 54];
+        bmp[0..2].copy_from_slice(b"BM");
+        bmp[10..14].copy_from_slice(&54u32.to_le_bytes());
+        bmp[14..18].copy_from_slice(&40u32.to_le_bytes());
+        bmp[18..22].copy_from_slice(&1u32.to_le_bytes());
+        bmp[22..26].copy_from_slice(&2u32.to_le_bytes());
+        bmp[26..28].copy_from_slice(&1u16.to_le_bytes());
+        bmp[28..30].copy_from_slice(&24u16.to_le_bytes());
+        // Bottom-up rows, padded to 4 bytes: black then white
+        bmp.extend_from_slice(&[0, 0, 0, 0]);
+        bmp.extend_from_slice(&[255, 255, 255, 0]);
+        let luma = decode_luma(&bmp).unwrap();
+        assert_eq!(luma.pixels, vec![255, 0]);
+    }
+
+    #[test]
+    fn test_decode_gif() {
+        // 2x1 image: palette index 0 = black, 1 = white, LZW min code size 2
+        let mut gif = b"GIF89a\x02\x00\x01\x00\x80\x00\x00".to_vec();
+        gif.extend_from_slice(&[0, 0, 0, 255, 255, 255]);
+        gif.extend_from_slice(b"\x2C\0\0\0\0\x02\x00\x01\x00\x00");
+        // Codes (3 bits): clear=4, 0, 1, end=5 -> 100 000 001 101, packed LSB first
+        gif.extend_from_slice(&[0x02, 0x02, 0x44, 0x0A, 0x00, 0x3B]);
+        let luma = decode_luma(&gif).unwrap();
+        assert_eq!((luma.width, luma.height), (2, 1));
+        assert_eq!(luma.pixels, vec![0, 255]);
+        assert!(decode_luma(b"GIF89a garbage").is_none());
+    }
+
+    #[test]
+    fn test_resample_averages_cells() {
+        let luma = Luma {
+            width: 4,
+            height: 2,
+            pixels: vec![0, 10, 20, 30, 40, 50, 60, 70],
+        };
+        assert_eq!(luma.resample(2, 1), vec![25.0, 45.0]);
+    }
+
+    #[test]
+    fn test_image_dimensions() {
+        for format in [
+            ImageFormat::Png,
+            ImageFormat::Gif,
+            ImageFormat::Jpeg,
+            ImageFormat::Bmp,
+            ImageFormat::Tiff,
+            ImageFormat::WebP,
+        ] {
+            let data = encode(DynamicImage::new_rgb8(64, 48), format);
+            assert_eq!(image_dimensions(&data), Some((64, 48)), "{:?}", format);
+            assert_eq!(size_rank(&data), (64 * 48, data.len()));
+        }
+
+        assert_eq!(image_dimensions(b"<svg></svg>"), None);
+        assert_eq!(image_dimensions(b"\x89PNG\r\n\x1a\n"), None);
+        assert_eq!(size_rank(b"<svg></svg>"), (0, 11));
+    }
+}
@@ -0,0 +1,256 @@
+//! Registry of the extractors that handle each document type
+//!
+//! Every supported format is a [`DocumentExtractor`]. The built-in ones are registered
+//! by [`Registry::default`]; library users and forks add their own with
+//! [`Registry::register`] (or `Extractor::extractor`), and those are asked first, so they
+//! can also take over a built-in format.
+
+use anyhow::Result;
+use std::fmt;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::doc;
+use crate::docx;
+use crate::epub;
+use crate::extract::{DocumentType, ExtractOptions, get_document_type};
+use crate::fb2;
+use crate::html;
+use crate::mobi;
+use crate::odf;
+use crate::output::OutputSink;
+use crate::pdf;
+use crate::rtf;
+
+/// Extracts images from one kind of document
+pub trait DocumentExtractor: Send + Sync {
+    /// Short lowercase name used in messages, e.g. "docx"
+    fn name(&self) -> &str;
+
+    /// Whether this extractor processes the document at `path`, judged by its name alone
+    fn can_handle(&self, path: &Path) -> bool;
+
+    /// Extracts the images of the document at `input_path` into `sink`.
+    /// Returns the number of images extracted.
+    fn extract(
+        &self,
+        input_path: &Path,
+        sink: &mut OutputSink,
+        options: &ExtractOptions,
+    ) -> Result<usize>;
+
+    /// Extracts the images of a document held in memory, such as one embedded in another
+    /// document or read from stdin. `virtual_path` is only used for messages, `base_name`
+    /// is the output base name and `depth` the embedding depth (0 for a top-level
+    /// document). Extractors that only work on files need not implement this.
+    fn extract_bytes(
+        &self,
+        data: Vec<u8>,
+        virtual_path: &Path,
+        base_name: &str,
+        depth: usize,
+        sink: &mut OutputSink,
+        options: &ExtractOptions,
+    ) -> Result<usize> {
+        let _ = (data, base_name, depth, sink, options);
+        anyhow::bail!(
+            "The {} extractor cannot process {} from memory",
+            self.name(),
+            virtual_path.display()
+        )
+    }
+}
+
+/// The extractor of a built-in document type
+struct BuiltIn(DocumentType);
+
+impl DocumentExtractor for BuiltIn {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn can_handle(&self, path: &Path) -> bool {
+        get_document_type(path) == Some(self.0)
+    }
+
+    fn extract(
+        &self,
+        input_path: &Path,
+        sink: &mut OutputSink,
+        options: &ExtractOptions,
+    ) -> Result<usize> {
+        match self.0 {
+            DocumentType::Docx | DocumentType::Pptx | DocumentType::Xlsx => {
+                docx::process_file(input_path, sink, options)
+            }
+            DocumentType::Doc => doc::process_file(input_path, sink, options),
+            DocumentType::Odf => odf::process_file(input_path, sink, options),
+            DocumentType::Epub => epub::process_file(input_path, sink, options),
+            DocumentType::Mobi => mobi::process_file(input_path, sink, options),
+            DocumentType::Fb2 => fb2::process_file(input_path, sink, options),
+            DocumentType::Rtf => rtf::process_file(input_path, sink, options),
+            DocumentType::Html => html::process_file(input_path, sink, options),
+            DocumentType::Pdf => pdf::process_file(input_path, sink, options),
+        }
+    }
+
+    fn extract_bytes(
+        &self,
+        data: Vec<u8>,
+        virtual_path: &Path,
+        base_name: &str,
+        depth: usize,
+        sink: &mut OutputSink,
+        options: &ExtractOptions,
+    ) -> Result<usize> {
+        let reader = Cursor::new(data);
+        match self.0 {
+            DocumentType::Docx | DocumentType::Pptx | DocumentType::Xlsx => {
+                docx::process_reader(reader, virtual_path, base_name, depth, sink, options)
+            }
+            DocumentType::Doc => {
+                doc::process_reader(reader, virtual_path, base_name, sink, options)
+            }
+            DocumentType::Odf => {
+                odf::process_reader(reader, virtual_path, base_name, depth, sink, options)
+            }
+            DocumentType::Epub => {
+                epub::process_reader(reader, virtual_path, base_name, depth, sink, options)
+            }
+            DocumentType::Mobi => {
+                mobi::process_reader(reader, virtual_path, base_name, sink, options)
+            }
+            DocumentType::Fb2 => {
+                fb2::process_reader(reader, virtual_path, base_name, sink, options)
+            }
+            DocumentType::Rtf => {
+                rtf::process_reader(reader, virtual_path, base_name, sink, options)
+            }
+            DocumentType::Html => {
+                html::process_reader(reader, virtual_path, base_name, sink, options)
+            }
+            DocumentType::Pdf => {
+                pdf::process_reader(reader, virtual_path, base_name, sink, options)
+            }
+        }
+    }
+}
+
+/// The extractors consulted, in order, for each document
+#[derive(Clone)]
+pub struct Registry {
+    extractors: Vec<Arc<dyn DocumentExtractor>>,
+}
+
+impl Registry {
+    /// Creates a registry without any extractors, not even the built-in ones
+    pub fn empty() -> Self {
+        Self {
+            extractors: Vec::new(),
+        }
+    }
+
+    /// Adds `extractor` ahead of those already registered, so it is asked first
+    pub fn register(&mut self, extractor: impl DocumentExtractor + 'static) {
+        self.extractors.insert(0, Arc::new(extractor));
+    }
+
+    /// The first extractor that handles `path`
+    pub fn find(&self, path: &Path) -> Option<&dyn DocumentExtractor> {
+        self.extractors
+            .iter()
+            .find(|extractor| extractor.can_handle(path))
+            .map(Arc::as_ref)
+    }
+
+    /// Whether any extractor handles `path`
+    pub fn handles(&self, path: &Path) -> bool {
+        self.find(path).is_some()
+    }
+}
+
+impl Default for Registry {
+    /// The built-in extractors
+    fn default() -> Self {
+        let extractors = [
+            DocumentType::Docx,
+            DocumentType::Doc,
+            DocumentType::Pptx,
+            DocumentType::Xlsx,
+            DocumentType::Odf,
+            DocumentType::Epub,
+            DocumentType::Mobi,
+            DocumentType::Fb2,
+            DocumentType::Rtf,
+            DocumentType::Html,
+            DocumentType::Pdf,
+        ]
+        .into_iter()
+        .map(|document_type| Arc::new(BuiltIn(document_type)) as Arc<dyn DocumentExtractor>)
+        .collect();
+        Self { extractors }
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.extractors.iter().map(|extractor| extractor.name()))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Handles `.txt` files, "extracting" one image per file
+    struct Text;
+
+    impl DocumentExtractor for Text {
+        fn name(&self) -> &str {
+            "text"
+        }
+
+        fn can_handle(&self, path: &Path) -> bool {
+            path.extension().is_some_and(|e| e == "txt" || e == "rtf")
+        }
+
+        fn extract(&self, _: &Path, _: &mut OutputSink, _: &ExtractOptions) -> Result<usize> {
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_registry_lookup() {
+        let mut registry = Registry::default();
+        assert_eq!(registry.find(Path::new("a.PPTX")).unwrap().name(), "pptx");
+        assert_eq!(registry.find(Path::new("a.rtf")).unwrap().name(), "rtf");
+        assert!(!registry.handles(Path::new("a.txt")));
+
+        // Registered extractors come first, even for built-in formats
+        registry.register(Text);
+        assert_eq!(registry.find(Path::new("a.txt")).unwrap().name(), "text");
+        assert_eq!(registry.find(Path::new("a.rtf")).unwrap().name(), "text");
+        assert_eq!(format!("{:?}", registry).matches(',').count(), 11);
+
+        let sink = &mut OutputSink::new_memory(Default::default());
+        let text = registry.find(Path::new("a.txt")).unwrap();
+        let err = text
+            .extract_bytes(
+                Vec::new(),
+                Path::new("a.txt"),
+                "a",
+                0,
+                sink,
+                &Default::default(),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The text extractor cannot process a.txt from memory"
+        );
+        assert!(!Registry::empty().handles(Path::new("a.docx")));
+    }
+}
@@ -0,0 +1,748 @@
+//! Per-document results and the reports generated from them
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::extract::{DocumentType, get_document_type};
+use crate::json::Json;
+use crate::limits::Size;
+use crate::manifest::{EntryStatus, Manifest};
+use crate::timings::{PhaseTimes, Timings};
+
+/// Outcome of processing a single document
+#[derive(Debug, Default)]
+pub struct DocumentResult {
+    pub path: PathBuf,
+    /// Number of images extracted
+    pub images: usize,
+    /// Error chain if processing failed
+    pub error: Option<String>,
+    /// The error's messages from the outermost to the root cause
+    pub error_chain: Vec<String>,
+    /// Why the document was skipped without extracting anything, e.g.
+    /// `DRM-protected (Adobe ADEPT)`
+    pub skipped: Option<String>,
+    pub elapsed: Duration,
+    /// Document metadata such as `title` and `author`, where the format has it
+    pub metadata: Vec<(String, String)>,
+    /// Entries skipped as unreadable, with `ExtractOptions::salvage` or because an image
+    /// could not be converted, with their errors
+    pub damaged: Vec<(String, String)>,
+    /// Time spent in each phase of extraction
+    pub timings: PhaseTimes,
+}
+
+impl DocumentResult {
+    /// Short document kind derived from the extension, e.g. "docx"
+    pub fn kind(&self) -> String {
+        self.path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+}
+
+/// Escapes text for use in XML attributes and character data
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than whitespace are not allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders results as a JUnit XML report: one test case per document.
+/// Documents with images pass, failures carry the error, and documents
+/// without any images are reported as skipped.
+pub fn render_junit(results: &[DocumentResult]) -> String {
+    let failures = results.iter().filter(|r| r.error.is_some()).count();
+    let skipped = results
+        .iter()
+        .filter(|r| r.error.is_none() && r.images == 0)
+        .count();
+    let total_time: f64 = results.iter().map(|r| r.elapsed.as_secs_f64()).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"word-image-extractor\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+        results.len(),
+        failures,
+        skipped,
+        total_time
+    );
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"extraction\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{:.3}\">",
+        results.len(),
+        failures,
+        skipped,
+        total_time
+    );
+
+    for result in results {
+        let _ = writeln!(
+            xml,
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\">",
+            escape_xml(&result.kind()),
+            escape_xml(&result.path.display().to_string()),
+            result.elapsed.as_secs_f64()
+        );
+        match (&result.error, result.images) {
+            (Some(error), _) => {
+                let message = error.lines().next().unwrap_or_default();
+                let _ = writeln!(
+                    xml,
+                    "      <failure message=\"{}\" type=\"ExtractionError\">{}</failure>",
+                    escape_xml(message),
+                    escape_xml(error)
+                );
+            }
+            (None, 0) => {
+                let reason = result.skipped.as_deref().unwrap_or("no images found");
+                let _ = writeln!(xml, "      <skipped message=\"{}\"/>", escape_xml(reason));
+            }
+            (None, images) => {
+                let _ = writeln!(
+                    xml,
+                    "      <system-out>Extracted {} image(s)</system-out>",
+                    images
+                );
+            }
+        }
+        xml.push_str("    </testcase>\n");
+    }
+
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+/// Renders every failed document as JSON for later triage: its path, document type,
+/// the full error and the chain of causes
+pub fn render_error_log(results: &[DocumentResult]) -> Json {
+    let failures: Vec<Json> = results
+        .iter()
+        .filter_map(|result| {
+            let error = result.error.as_deref()?;
+            Some(Json::object([
+                ("path", Json::from(result.path.display().to_string())),
+                (
+                    "type",
+                    Json::from(
+                        get_document_type(&result.path)
+                            .map(|kind| kind.name().to_string())
+                            .unwrap_or_else(|| result.kind()),
+                    ),
+                ),
+                ("error", Json::from(error)),
+                (
+                    "chain",
+                    Json::Array(
+                        result
+                            .error_chain
+                            .iter()
+                            .map(|cause| Json::from(cause.as_str()))
+                            .collect(),
+                    ),
+                ),
+                ("elapsed_ms", Json::from(result.elapsed.as_millis() as u64)),
+            ]))
+        })
+        .collect();
+    Json::object([
+        ("documents", Json::from(results.len())),
+        ("failures", Json::from(failures.len())),
+        ("errors", Json::Array(failures)),
+    ])
+}
+
+/// Writes the failures of a run to `path` as JSON. The file is written even when
+/// nothing failed, so a stale log from an earlier run is not mistaken for this one's.
+pub fn write_error_log(path: &Path, results: &[DocumentResult]) -> Result<()> {
+    fs::write(path, render_error_log(results).to_pretty_string())
+        .with_context(|| format!("Failed to write error log: {}", path.display()))
+}
+
+/// What a run would write, measured by `--estimate` without writing anything
+#[derive(Debug, Default)]
+pub struct Estimate {
+    /// Number of images and bytes each document would produce, in processing order
+    pub documents: Vec<(PathBuf, usize, u64)>,
+    pub images: usize,
+    pub bytes: u64,
+    /// Documents that could not be processed, which the estimate leaves out
+    pub failures: usize,
+}
+
+impl Estimate {
+    /// Sums the sizes the manifest of a dry run recorded for each document. The sizes
+    /// are those of the files as written: after conversion, and including thumbnails
+    /// and GIF frames.
+    pub fn new(results: &[DocumentResult], manifest: Option<&Manifest>) -> Self {
+        let mut estimate = Estimate::default();
+        let mut sizes: BTreeMap<&Path, (usize, u64)> = BTreeMap::new();
+        let written = manifest
+            .into_iter()
+            .flat_map(|manifest| &manifest.entries)
+            .filter(|entry| entry.status == EntryStatus::Written);
+        for entry in written {
+            let (count, bytes) = sizes.entry(&entry.document).or_default();
+            *count += 1;
+            *bytes += entry.size;
+            estimate.images += 1;
+            estimate.bytes += entry.size;
+        }
+        for result in results {
+            let (count, bytes) = sizes
+                .get(result.path.as_path())
+                .copied()
+                .unwrap_or_default();
+            estimate.documents.push((result.path.clone(), count, bytes));
+            estimate.failures += usize::from(result.error.is_some());
+        }
+        estimate
+    }
+
+    /// Renders the estimate as indented text, with the free space at the destination
+    /// if it is known
+    pub fn render(&self, available: Option<u64>) -> String {
+        let mut text = String::from(
+            "Estimate (nothing was written):
+",
+        );
+        for (path, images, bytes) in &self.documents {
+            let _ = writeln!(
+                text,
+                "  {}: {} image(s), {}",
+                path.display(),
+                images,
+                Size(*bytes)
+            );
+        }
+        let _ = writeln!(
+            text,
+            "  Total: {} image(s), {}",
+            self.images,
+            Size(self.bytes)
+        );
+        if self.failures > 0 {
+            let _ = writeln!(
+                text,
+                "  Not counted: {} document(s) that failed",
+                self.failures
+            );
+        }
+        if let Some(available) = available {
+            let verdict = if self.bytes <= available {
+                "fits"
+            } else {
+                "does NOT fit"
+            };
+            let _ = writeln!(
+                text,
+                "  Available at destination: {} ({})",
+                Size(available),
+                verdict
+            );
+        }
+        text
+    }
+
+    pub fn to_json(&self, available: Option<u64>) -> Json {
+        Json::object([
+            (
+                "documents",
+                Json::Array(
+                    self.documents
+                        .iter()
+                        .map(|(path, images, bytes)| {
+                            Json::object([
+                                ("path", Json::from(path.display().to_string())),
+                                ("images", Json::from(*images)),
+                                ("bytes", Json::from(*bytes)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            ("images", Json::from(self.images)),
+            ("bytes", Json::from(self.bytes)),
+            ("failures", Json::from(self.failures)),
+            ("available_bytes", Json::from(available)),
+        ])
+    }
+}
+
+/// Breakdown of a whole run, printed by `--stats`
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// Number of images and bytes written per output format
+    pub formats: BTreeMap<String, (usize, u64)>,
+    /// Total bytes written
+    pub bytes: u64,
+    /// Number of images extracted from each document, in processing order
+    pub documents: Vec<(PathBuf, usize)>,
+    /// Documents nothing was extracted from because they were skipped or failed, with
+    /// the reason
+    pub skipped: Vec<(PathBuf, String)>,
+    pub elapsed: Duration,
+}
+
+impl Stats {
+    /// Gathers the statistics of a run from its results and the manifest of the images
+    /// written, which is where formats and sizes come from
+    pub fn new(results: &[DocumentResult], manifest: Option<&Manifest>, elapsed: Duration) -> Self {
+        let mut stats = Stats {
+            elapsed,
+            ..Default::default()
+        };
+        let written = manifest
+            .into_iter()
+            .flat_map(|manifest| &manifest.entries)
+            .filter(|entry| entry.status == EntryStatus::Written);
+        for entry in written {
+            let format = entry
+                .output
+                .as_deref()
+                .and_then(Path::extension)
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_else(|| "unknown".to_string());
+            let (count, bytes) = stats.formats.entry(format).or_default();
+            *count += 1;
+            *bytes += entry.size;
+            stats.bytes += entry.size;
+        }
+        for result in results {
+            stats.documents.push((result.path.clone(), result.images));
+            if let Some(reason) = &result.skipped {
+                stats.skipped.push((result.path.clone(), reason.clone()));
+            } else if let Some(error) = &result.error {
+                stats
+                    .skipped
+                    .push((result.path.clone(), format!("failed: {}", error)));
+            }
+        }
+        stats
+    }
+
+    /// Renders the statistics as indented text for the terminal
+    pub fn render(&self) -> String {
+        let mut text = String::from("Statistics:\n");
+        if !self.formats.is_empty() {
+            text.push_str("  Images by format:\n");
+            for (format, (count, bytes)) in &self.formats {
+                let _ = writeln!(text, "    {:<6} {:>6}  ({})", format, count, Size(*bytes));
+            }
+        }
+        let _ = writeln!(text, "  Total written: {}", Size(self.bytes));
+        if !self.documents.is_empty() {
+            text.push_str("  Images per document:\n");
+            for (path, images) in &self.documents {
+                let _ = writeln!(text, "    {}: {}", path.display(), images);
+            }
+        }
+        if !self.skipped.is_empty() {
+            text.push_str("  Skipped documents:\n");
+            for (path, reason) in &self.skipped {
+                let _ = writeln!(text, "    {}: {}", path.display(), reason);
+            }
+        }
+        let _ = writeln!(text, "  Elapsed: {:.2}s", self.elapsed.as_secs_f64());
+        text
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            (
+                "formats",
+                Json::object(self.formats.iter().map(|(format, (count, bytes))| {
+                    (
+                        format.clone(),
+                        Json::object([
+                            ("images", Json::from(*count)),
+                            ("bytes", Json::from(*bytes)),
+                        ]),
+                    )
+                })),
+            ),
+            ("bytes_written", Json::from(self.bytes)),
+            (
+                "documents",
+                Json::Array(
+                    self.documents
+                        .iter()
+                        .map(|(path, images)| {
+                            Json::object([
+                                ("path", Json::from(path.display().to_string())),
+                                ("images", Json::from(*images)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            (
+                "skipped",
+                Json::Array(
+                    self.skipped
+                        .iter()
+                        .map(|(path, reason)| {
+                            Json::object([
+                                ("path", Json::from(path.display().to_string())),
+                                ("reason", Json::from(reason.as_str())),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            ("elapsed_ms", Json::from(self.elapsed.as_millis() as u64)),
+        ])
+    }
+}
+
+/// Renders results as a JSON report: one object per document with its type, metadata,
+/// outcome and, when `manifest` is given, every image written or skipped for it. `stats`
+/// adds a `stats` object with the breakdown of the run and `timings` a `timings` object
+/// with the time spent per phase.
+pub fn render_json(
+    results: &[DocumentResult],
+    manifest: Option<&Manifest>,
+    stats: Option<&Stats>,
+    timings: Option<&Timings>,
+) -> Json {
+    let documents = results
+        .iter()
+        .map(|result| {
+            let mut fields = vec![
+                (
+                    "path".to_string(),
+                    Json::from(result.path.display().to_string()),
+                ),
+                (
+                    "type".to_string(),
+                    Json::from(get_document_type(&result.path).map(DocumentType::name)),
+                ),
+                (
+                    "metadata".to_string(),
+                    Json::object(
+                        result
+                            .metadata
+                            .iter()
+                            .map(|(key, value)| (key.clone(), Json::from(value.as_str()))),
+                    ),
+                ),
+                ("images_extracted".to_string(), Json::from(result.images)),
+            ];
+            if let Some(manifest) = manifest {
+                let images = manifest
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.document == result.path)
+                    .map(|entry| match entry.to_json() {
+                        Json::Object(fields) => Json::Object(
+                            fields
+                                .into_iter()
+                                .filter(|(k, _)| k != "document")
+                                .collect(),
+                        ),
+                        other => other,
+                    })
+                    .collect();
+                fields.push(("images".to_string(), Json::Array(images)));
+            }
+            fields.push(("error".to_string(), Json::from(result.error.clone())));
+            if let Some(reason) = &result.skipped {
+                fields.push(("skipped".to_string(), Json::from(reason.as_str())));
+            }
+            if !result.damaged.is_empty() {
+                let damaged = result.damaged.iter().map(|(entry, error)| {
+                    Json::object([
+                        ("entry", Json::from(entry.as_str())),
+                        ("error", Json::from(error.as_str())),
+                    ])
+                });
+                fields.push(("damaged".to_string(), Json::Array(damaged.collect())));
+            }
+            fields.push((
+                "elapsed_ms".to_string(),
+                Json::from(result.elapsed.as_millis() as u64),
+            ));
+            Json::Object(fields)
+        })
+        .collect();
+
+    let summary = Json::object([
+        ("documents", Json::from(results.len())),
+        (
+            "images",
+            Json::from(results.iter().map(|r| r.images).sum::<usize>()),
+        ),
+        (
+            "failures",
+            Json::from(results.iter().filter(|r| r.error.is_some()).count()),
+        ),
+        (
+            "drm_protected",
+            Json::from(results.iter().filter(|r| r.skipped.is_some()).count()),
+        ),
+    ]);
+    let mut report = vec![
+        ("documents".to_string(), Json::Array(documents)),
+        ("summary".to_string(), summary),
+    ];
+    if let Some(stats) = stats {
+        report.push(("stats".to_string(), stats.to_json()));
+    }
+    if let Some(timings) = timings {
+        report.push(("timings".to_string(), timings.to_json()));
+    }
+    Json::Object(report)
+}
+
+/// Writes a JUnit XML report to `path`
+pub fn write_junit(path: &Path, results: &[DocumentResult]) -> Result<()> {
+    fs::write(path, render_junit(results))
+        .with_context(|| format!("Failed to write JUnit report: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(
+            escape_xml("a<b> & \"c\" 'd'"),
+            "a&lt;b&gt; &amp; &quot;c&quot; &apos;d&apos;"
+        );
+        assert_eq!(escape_xml("bell\u{7}"), "bell");
+    }
+
+    #[test]
+    fn test_render_junit() {
+        let results = vec![
+            DocumentResult {
+                path: PathBuf::from("ok.docx"),
+                images: 3,
+                error: None,
+                elapsed: Duration::from_millis(10),
+                ..Default::default()
+            },
+            DocumentResult {
+                path: PathBuf::from("empty.epub"),
+                images: 0,
+                error: None,
+                elapsed: Duration::from_millis(5),
+                ..Default::default()
+            },
+            DocumentResult {
+                path: PathBuf::from("bad.docx"),
+                images: 0,
+                error: Some("Failed to read zip archive: bad.docx".to_string()),
+                elapsed: Duration::from_millis(1),
+                ..Default::default()
+            },
+        ];
+
+        let xml = render_junit(&results);
+        assert!(xml.contains("tests=\"3\" failures=\"1\" skipped=\"1\""));
+        assert!(xml.contains("<testcase classname=\"docx\" name=\"ok.docx\" time=\"0.010\">"));
+        assert!(xml.contains("<skipped message=\"no images found\"/>"));
+        assert!(xml.contains(
+            "<failure message=\"Failed to read zip archive: bad.docx\" type=\"ExtractionError\">"
+        ));
+    }
+
+    #[test]
+    fn test_estimate() {
+        use crate::manifest::ManifestEntry;
+
+        let results = vec![
+            DocumentResult {
+                path: PathBuf::from("book.epub"),
+                images: 2,
+                ..Default::default()
+            },
+            DocumentResult {
+                path: PathBuf::from("bad.pdf"),
+                error: Some("not a PDF".to_string()),
+                ..Default::default()
+            },
+        ];
+        let written = |size| ManifestEntry {
+            document: PathBuf::from("book.epub"),
+            output: Some(PathBuf::from("out/x.png")),
+            size,
+            sha256: None,
+            status: EntryStatus::Written,
+            fields: vec![],
+        };
+        let manifest = Manifest {
+            entries: vec![written(1024), written(2048)],
+        };
+
+        let estimate = Estimate::new(&results, Some(&manifest));
+        assert_eq!((estimate.images, estimate.bytes), (2, 3072));
+        let text = estimate.render(Some(2048));
+        assert!(text.contains("  book.epub: 2 image(s), 3.0 KiB\n"));
+        assert!(text.contains("  bad.pdf: 0 image(s), 0 bytes\n"));
+        assert!(text.contains("  Not counted: 1 document(s) that failed\n"));
+        assert!(text.contains("  Available at destination: 2.0 KiB (does NOT fit)\n"));
+        assert!(!estimate.render(None).contains("Available"));
+    }
+
+    #[test]
+    fn test_render_error_log() {
+        let results = vec![
+            DocumentResult {
+                path: PathBuf::from("ok.docx"),
+                images: 2,
+                ..Default::default()
+            },
+            DocumentResult {
+                path: PathBuf::from("scans/bad.docx"),
+                error: Some("Failed to read zip archive: bad.docx: invalid header".to_string()),
+                error_chain: vec![
+                    "Failed to read zip archive: bad.docx".to_string(),
+                    "invalid header".to_string(),
+                ],
+                ..Default::default()
+            },
+            DocumentResult {
+                path: PathBuf::from("notes.odd"),
+                error: Some("Unsupported file type".to_string()),
+                ..Default::default()
+            },
+        ];
+
+        let json = render_error_log(&results).to_string();
+        assert!(json.contains("\"documents\":3,\"failures\":2"));
+        assert!(json.contains(
+            "{\"path\":\"scans/bad.docx\",\"type\":\"docx\",\
+             \"error\":\"Failed to read zip archive: bad.docx: invalid header\",\
+             \"chain\":[\"Failed to read zip archive: bad.docx\",\"invalid header\"]"
+        ));
+        assert!(json.contains("\"type\":\"odd\""));
+        assert!(!json.contains("ok.docx"));
+    }
+
+    #[test]
+    fn test_render_json() {
+        use crate::manifest::{EntryStatus, ManifestEntry};
+
+        let results = vec![
+            DocumentResult {
+                path: PathBuf::from("book.epub"),
+                images: 1,
+                metadata: vec![("title".to_string(), "Dune".to_string())],
+                ..Default::default()
+            },
+            DocumentResult {
+                path: PathBuf::from("bad.pdf"),
+                error: Some("not a PDF".to_string()),
+                ..Default::default()
+            },
+            DocumentResult {
+                path: PathBuf::from("locked.epub"),
+                skipped: Some("DRM-protected (Adobe ADEPT)".to_string()),
+                ..Default::default()
+            },
+        ];
+        let manifest = Manifest {
+            entries: vec![ManifestEntry {
+                document: PathBuf::from("book.epub"),
+                output: Some(PathBuf::from("out/Dune.jpg")),
+                size: 3,
+                sha256: None,
+                status: EntryStatus::Written,
+                fields: vec![],
+            }],
+        };
+
+        let json = render_json(&results, Some(&manifest), None, None).to_string();
+        assert!(json.contains(
+            r#"{"path":"book.epub","type":"epub","metadata":{"title":"Dune"},"images_extracted":1,"images":[{"output":"out/Dune.jpg","size":3,"sha256":null,"status":"written"}],"error":null"#
+        ));
+        assert!(json.contains(
+            r#""type":"pdf","metadata":{},"images_extracted":0,"images":[],"error":"not a PDF""#
+        ));
+        assert!(json.contains(
+            r#""images_extracted":0,"images":[],"error":null,"skipped":"DRM-protected (Adobe ADEPT)""#
+        ));
+        assert!(
+            json.ends_with(
+                r#""summary":{"documents":3,"images":1,"failures":1,"drm_protected":1}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        use crate::manifest::ManifestEntry;
+
+        let entry = |output: Option<&str>, size, status| ManifestEntry {
+            document: PathBuf::from("a.docx"),
+            output: output.map(PathBuf::from),
+            size,
+            sha256: None,
+            status,
+            fields: vec![],
+        };
+        let manifest = Manifest {
+            entries: vec![
+                entry(Some("out/a_1.PNG"), 100, EntryStatus::Written),
+                entry(Some("out/a_2.png"), 50, EntryStatus::Written),
+                entry(Some("out/a_3.gif"), 2048, EntryStatus::Written),
+                entry(None, 9, EntryStatus::Skipped("duplicate".to_string())),
+            ],
+        };
+        let results = vec![
+            DocumentResult {
+                path: PathBuf::from("a.docx"),
+                images: 3,
+                ..Default::default()
+            },
+            DocumentResult {
+                path: PathBuf::from("locked.epub"),
+                skipped: Some("DRM-protected (Adobe ADEPT)".to_string()),
+                ..Default::default()
+            },
+            DocumentResult {
+                path: PathBuf::from("bad.pdf"),
+                error: Some("not a PDF".to_string()),
+                ..Default::default()
+            },
+        ];
+
+        let stats = Stats::new(&results, Some(&manifest), Duration::from_millis(1500));
+        assert_eq!(stats.formats["png"], (2, 150));
+        assert_eq!(stats.formats["gif"], (1, 2048));
+        assert_eq!(stats.bytes, 2198);
+
+        let text = stats.render();
+        assert!(text.contains("    gif         1  (2.0 KiB)\n    png         2  (150 bytes)\n"));
+        assert!(text.contains("  Total written: 2.1 KiB\n"));
+        assert!(text.contains("    a.docx: 3\n"));
+        assert!(text.contains("    locked.epub: DRM-protected (Adobe ADEPT)\n"));
+        assert!(text.contains("    bad.pdf: failed: not a PDF\n"));
+        assert!(text.ends_with("  Elapsed: 1.50s\n"));
+
+        let json = render_json(&results, None, Some(&stats), None).to_string();
+        assert!(json.contains(
+            r#""stats":{"formats":{"gif":{"images":1,"bytes":2048},"png":{"images":2,"bytes":150}},"bytes_written":2198,"#
+        ));
+        assert!(json.ends_with(r#""elapsed_ms":1500}}"#));
+    }
+}
@@ -0,0 +1,171 @@
+//! Journal of an extraction run, so an interrupted run can be resumed (`--resume`)
+//!
+//! A plain text file that is appended to as the run goes: a `file<TAB>document<TAB>output`
+//! line before each output file of a document is written, and a `done<TAB>document` line
+//! once the document is finished. Resuming skips the finished documents and deletes the
+//! files of the unfinished ones, which may be cut short, so writing them again reuses their
+//! names instead of adding `_1` copies. A run that finishes every document deletes the
+//! journal.
+
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// An open journal, shared by every worker of a run
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<fs::File>,
+    /// Documents finished by earlier runs
+    done: HashSet<PathBuf>,
+    /// Output files of unfinished documents that were deleted on opening
+    removed: usize,
+}
+
+impl Journal {
+    /// Opens the journal at `path`, creating it if there is none. Files written for
+    /// documents an earlier run did not finish are deleted, and the journal is rewritten
+    /// with just the finished documents.
+    pub fn open(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read resume journal: {}", path.display()));
+            }
+        };
+
+        // A last line without its newline was cut short by the interruption
+        let complete = text.rfind('\n').map_or("", |end| &text[..=end]);
+        let mut done = HashSet::new();
+        let mut files = Vec::new();
+        for (number, line) in complete.lines().enumerate() {
+            let fields: Vec<&str> = line.splitn(3, '\t').collect();
+            match fields[..] {
+                ["done", document] => {
+                    done.insert(PathBuf::from(document));
+                }
+                ["file", document, output] => {
+                    files.push((PathBuf::from(document), PathBuf::from(output)))
+                }
+                [""] => {}
+                _ => anyhow::bail!(
+                    "Invalid entry on line {} of resume journal {}",
+                    number + 1,
+                    path.display()
+                ),
+            }
+        }
+
+        let mut removed = 0;
+        for (document, output) in &files {
+            if !done.contains(document) && fs::remove_file(output).is_ok() {
+                removed += 1;
+            }
+        }
+
+        let mut entries: Vec<&PathBuf> = done.iter().collect();
+        entries.sort();
+        let compacted: String = entries
+            .iter()
+            .map(|document| format!("done\t{}\n", document.display()))
+            .collect();
+        let mut temp_name = path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+        let temp_path = PathBuf::from(temp_name);
+        fs::write(&temp_path, compacted)
+            .and_then(|()| fs::rename(&temp_path, path))
+            .with_context(|| format!("Failed to write resume journal: {}", path.display()))?;
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open resume journal: {}", path.display()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            done,
+            removed,
+        })
+    }
+
+    /// Whether an earlier run finished `document`
+    pub fn is_done(&self, document: &Path) -> bool {
+        self.done.contains(document)
+    }
+
+    /// Number of files of unfinished documents deleted when the journal was opened
+    pub fn removed(&self) -> usize {
+        self.removed
+    }
+
+    /// Notes that `output` is about to be written for `document`
+    pub fn record_file(&self, document: &Path, output: &Path) -> Result<()> {
+        self.append(&format!(
+            "file\t{}\t{}\n",
+            document.display(),
+            output.display()
+        ))
+    }
+
+    /// Notes that `document` is finished
+    pub fn record_done(&self, document: &Path) -> Result<()> {
+        self.append(&format!("done\t{}\n", document.display()))
+    }
+
+    fn append(&self, line: &str) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())
+            .with_context(|| format!("Failed to update resume journal: {}", self.path.display()))
+    }
+
+    /// Deletes the journal once every document of the run is finished
+    pub fn finish(&self) -> Result<()> {
+        fs::remove_file(&self.path)
+            .with_context(|| format!("Failed to remove resume journal: {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::temp_dir;
+
+    #[test]
+    fn test_journal_removes_files_of_unfinished_documents() {
+        let dir = temp_dir("resume");
+        let path = dir.join("run.resume");
+
+        let journal = Journal::open(&path).unwrap();
+        assert!(!journal.is_done(Path::new("a.docx")));
+        for (document, output) in [("a.docx", "a_1.png"), ("b.docx", "b_1.png")] {
+            fs::write(dir.join(output), b"png").unwrap();
+            journal
+                .record_file(Path::new(document), &dir.join(output))
+                .unwrap();
+        }
+        journal.record_done(Path::new("a.docx")).unwrap();
+        drop(journal);
+        // The interruption cut the last line short
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"file\tb.docx\ta_").unwrap();
+
+        let journal = Journal::open(&path).unwrap();
+        assert!(journal.is_done(Path::new("a.docx")));
+        assert!(!journal.is_done(Path::new("b.docx")));
+        assert_eq!(journal.removed(), 1);
+        assert!(dir.join("a_1.png").exists());
+        assert!(!dir.join("b_1.png").exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "done\ta.docx\n");
+
+        journal.finish().unwrap();
+        assert!(!path.exists());
+        fs::write(&path, "bogus\n").unwrap();
+        assert!(Journal::open(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,378 @@
+//! RTF file processing module
+//!
+//! RTF stores pictures inline as `{\pict ...}` groups: control words describe the
+//! picture type and size, followed by the picture data as hex digits (or raw bytes
+//! after `\binN`).
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use crate::common::DocumentMetadata;
+use crate::extract::ExtractOptions;
+use crate::metafile::packed_dib_file;
+use crate::output::OutputSink;
+use crate::sniff::sniff_image_format;
+
+/// A decoded picture: `(extension, data)`
+type Picture = (&'static str, Vec<u8>);
+
+/// Picture type control words and the extension of their data
+const PICTURE_TYPES: [(&str, &str); 5] = [
+    ("pngblip", "png"),
+    ("jpegblip", "jpg"),
+    ("emfblip", "emf"),
+    ("wmetafile", "wmf"),
+    ("dibitmap", "bmp"),
+];
+
+/// A `\pict` group being read
+struct PictGroup {
+    /// Group depth of the `\pict` group itself; data in nested groups is not picture data
+    depth: usize,
+    extension: Option<&'static str>,
+    data: Vec<u8>,
+    /// First hex digit of a byte whose second digit has not been read yet
+    pending_nibble: Option<u8>,
+}
+
+/// Processes a single .rtf file, extracting images matching the allowed extensions.
+/// Returns the number of images extracted.
+pub fn process_file(
+    input_path: &Path,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let doc_name = input_path
+        .file_stem()
+        .context("Invalid filename")?
+        .to_string_lossy()
+        .to_string();
+
+    let file = fs::File::open(input_path)
+        .with_context(|| format!("Failed to open input file: {}", input_path.display()))?;
+
+    process_reader(file, input_path, &doc_name, sink, options)
+}
+
+/// Processes an RTF document from any reader.
+/// `input_path` is only used for messages; `doc_name` is the output base name.
+pub fn process_reader<R: Read>(
+    mut reader: R,
+    input_path: &Path,
+    doc_name: &str,
+    sink: &mut OutputSink,
+    options: &ExtractOptions,
+) -> Result<usize> {
+    let mut data = Vec::new();
+    reader
+        .read_to_end(&mut data)
+        .with_context(|| format!("Failed to read {}", input_path.display()))?;
+
+    // Silently skip documents that don't match the metadata filter
+    if !options.document_filter.matches(&info_metadata(&data)) {
+        return Ok(0);
+    }
+
+    let images: Vec<Picture> = collect_images(&data, input_path)?
+        .into_iter()
+        .filter(|(extension, _)| options.allowed_extensions.contains(*extension))
+        .collect();
+
+    let total_images = images.len();
+    if total_images > 0 {
+        sink.create_base_dir()?;
+
+        status!(
+            "Found {} image files in {}.",
+            total_images,
+            input_path.display()
+        );
+    }
+
+    for (seq_index, (extension, data)) in images.iter().enumerate() {
+        let output_path = sink.output_path(doc_name, seq_index, total_images, extension)?;
+
+        status!("Extracting to: {}", output_path.display());
+
+        sink.write_image(&output_path, data)?;
+    }
+
+    Ok(total_images)
+}
+
+/// Reads the title, author and subject from the `{\info ...}` group
+fn info_metadata(data: &[u8]) -> DocumentMetadata {
+    let field = |name: &str| {
+        let marker = format!("{{\\{}", name);
+        // `{\title` but not `{\titlepg`
+        let start = data.windows(marker.len()).enumerate().position(|(at, w)| {
+            w == marker.as_bytes()
+                && !data
+                    .get(at + marker.len())
+                    .is_some_and(u8::is_ascii_alphabetic)
+        })? + marker.len();
+        let end = start + data[start..].iter().position(|&b| b == b'}')?;
+        let text = decode_text(&data[start..end]);
+        Some(text.trim().to_string()).filter(|t| !t.is_empty())
+    };
+    DocumentMetadata {
+        title: field("title"),
+        author: field("author"),
+        subject: field("subject"),
+        ..Default::default()
+    }
+}
+
+/// Decodes RTF text: `\'hh` escapes are Windows-1252 bytes (read as Latin-1), `\uN` is
+/// a Unicode character followed by one fallback character, other control words are dropped
+fn decode_text(text: &[u8]) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+    while i < text.len() {
+        match text[i] {
+            b'\\' if text.get(i + 1) == Some(&b'\'') => {
+                let hex = text
+                    .get(i + 2..i + 4)
+                    .and_then(|h| std::str::from_utf8(h).ok());
+                if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    out.push(byte as char);
+                }
+                i += 4;
+            }
+            b'\\' if matches!(text.get(i + 1), Some(b'\\' | b'{' | b'}')) => {
+                out.push(text[i + 1] as char);
+                i += 2;
+            }
+            b'\\' => {
+                let word_end = text[i + 1..]
+                    .iter()
+                    .position(|b| !b.is_ascii_alphabetic())
+                    .map_or(text.len(), |p| i + 1 + p);
+                let word = &text[i + 1..word_end];
+                let number_end = text[word_end..]
+                    .iter()
+                    .position(|b| !(b.is_ascii_digit() || *b == b'-'))
+                    .map_or(text.len(), |p| word_end + p);
+                let number = std::str::from_utf8(&text[word_end..number_end])
+                    .ok()
+                    .and_then(|n| n.parse::<i32>().ok());
+                i = number_end;
+                if text.get(i) == Some(&b' ') {
+                    i += 1;
+                }
+                if word == b"u"
+                    && let Some(code) = number
+                {
+                    out.extend(char::from_u32(code.rem_euclid(65536) as u32));
+                    // Skip the fallback character
+                    i += 1;
+                }
+            }
+            b'\r' | b'\n' => i += 1,
+            byte => {
+                out.push(byte as char);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Reads every picture in an RTF document as `(extension, data)`, in document order.
+/// The WMF fallbacks Word writes in `\nonshppict` groups are skipped, as they duplicate
+/// the `\shppict` picture before them.
+pub fn collect_images(data: &[u8], input_path: &Path) -> Result<Vec<Picture>> {
+    if !data.starts_with(b"{\\rtf") {
+        anyhow::bail!("{} is not an RTF document", input_path.display());
+    }
+
+    let mut images = Vec::new();
+    // For each open group: whether it is inside a `\nonshppict` group
+    let mut groups: Vec<bool> = Vec::new();
+    let mut pict: Option<PictGroup> = None;
+    let mut pos = 0;
+    while pos < data.len() {
+        match data[pos] {
+            b'{' => {
+                groups.push(groups.last().copied().unwrap_or(false));
+                pos += 1;
+            }
+            b'}' => {
+                if let Some(group) = pict.take_if(|p| p.depth == groups.len()) {
+                    images.extend(finish_picture(group));
+                }
+                groups.pop();
+                pos += 1;
+            }
+            b'\\' => {
+                let (word, param, end) = control_word(data, pos + 1);
+                pos = end;
+                match word {
+                    b"pict" if pict.is_none() && groups.last() == Some(&false) => {
+                        pict = Some(PictGroup {
+                            depth: groups.len(),
+                            extension: None,
+                            data: Vec::new(),
+                            pending_nibble: None,
+                        });
+                    }
+                    b"nonshppict" => {
+                        if let Some(skipped) = groups.last_mut() {
+                            *skipped = true;
+                        }
+                    }
+                    b"bin" => {
+                        let len = param.unwrap_or(0).max(0) as usize;
+                        let bytes = data.get(pos..pos + len).unwrap_or(&data[pos..]);
+                        if let Some(group) = pict.as_mut().filter(|p| p.depth == groups.len()) {
+                            group.data.extend_from_slice(bytes);
+                        }
+                        pos += bytes.len();
+                    }
+                    // `\'hh` is an escaped character in text, never picture data
+                    b"'" => pos = (pos + 2).min(data.len()),
+                    word => {
+                        if let Some(group) = pict.as_mut().filter(|p| p.depth == groups.len())
+                            && let Some((_, extension)) =
+                                PICTURE_TYPES.iter().find(|(w, _)| w.as_bytes() == word)
+                        {
+                            group.extension = Some(extension);
+                        }
+                    }
+                }
+            }
+            byte => {
+                if let Some(group) = pict.as_mut().filter(|p| p.depth == groups.len())
+                    && let Some(nibble) = (byte as char).to_digit(16)
+                {
+                    match group.pending_nibble.take() {
+                        Some(high) => group.data.push(high << 4 | nibble as u8),
+                        None => group.pending_nibble = Some(nibble as u8),
+                    }
+                }
+                pos += 1;
+            }
+        }
+    }
+    Ok(images)
+}
+
+/// Reads the control word or symbol after a backslash at `start`.
+/// Returns the word, its numeric parameter and the position after its delimiter.
+fn control_word(data: &[u8], start: usize) -> (&[u8], Option<i64>, usize) {
+    let letters = data[start..]
+        .iter()
+        .take_while(|b| b.is_ascii_alphabetic())
+        .count();
+    if letters == 0 {
+        // Control symbol such as `\*`, `\~` or `\'`
+        let end = (start + 1).min(data.len());
+        return (&data[start..end], None, end);
+    }
+    let word = &data[start..start + letters];
+    let mut pos = start + letters;
+    let digits_start = pos;
+    if data.get(pos) == Some(&b'-') {
+        pos += 1;
+    }
+    pos += data[pos..]
+        .iter()
+        .take_while(|b| b.is_ascii_digit())
+        .count();
+    let param = std::str::from_utf8(&data[digits_start..pos])
+        .ok()
+        .and_then(|digits| digits.parse().ok());
+    // A single space delimits the control word and is not part of the text
+    if data.get(pos) == Some(&b' ') {
+        pos += 1;
+    }
+    (word, param, pos)
+}
+
+/// Converts a completed `\pict` group to a picture, if its type is known and the data
+/// matches it
+fn finish_picture(group: PictGroup) -> Option<Picture> {
+    match group.extension? {
+        "bmp" => packed_dib_file(&group.data),
+        extension => {
+            (sniff_image_format(&group.data) == Some(extension)).then_some((extension, group.data))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::temp_dir;
+    use crate::output::OutputOptions;
+
+    fn hex(data: &[u8]) -> String {
+        data.chunks(16)
+            .map(|chunk| chunk.iter().map(|b| format!("{:02x}", b)).collect())
+            .collect::<Vec<String>>()
+            .join("\r\n")
+    }
+
+    #[test]
+    fn test_collect_rtf_pictures() {
+        let png = b"\x89PNG\r\n\x1a\nrtf png data".to_vec();
+        let jpeg = b"\xFF\xD8\xFF\xE0rtf jpeg".to_vec();
+        let wmf = b"\x01\x00\x09\x00\x00\x03fallback".to_vec();
+        let rtf = format!(
+            concat!(
+                r"{{\rtf1\ansi{{\fonttbl{{\f0 Arial;}}}}\pard caf\'e9 ",
+                r"{{\*\shppict{{\pict{{\*\picprop{{\sp{{\sn x}}{{\sv 00ff}}}}}}",
+                r"\picw100\pich100\pngblip {}}}}}",
+                r"{{\nonshppict{{\pict\wmetafile8 {}}}}}",
+                r"{{\pict\jpegblip\bin{} ",
+            ),
+            hex(&png),
+            hex(&wmf),
+            jpeg.len()
+        );
+        let mut data = rtf.into_bytes();
+        data.extend_from_slice(&jpeg);
+        data.extend_from_slice(b"}\\par}");
+
+        let images = collect_images(&data, Path::new("doc.rtf")).unwrap();
+        assert_eq!(images, vec![("png", png), ("jpg", jpeg)]);
+    }
+
+    #[test]
+    fn test_extract_rtf_pictures() {
+        let dir = temp_dir("rtf");
+
+        let wmf = b"\x01\x00\x09\x00\x00\x03metafile".to_vec();
+        let rtf = format!(r"{{\rtf1{{\pict\wmetafile8\picw10\pich10 {}}}}}", hex(&wmf));
+
+        let options = ExtractOptions {
+            allowed_extensions: crate::common::get_supported_extensions(&[]),
+            ..Default::default()
+        };
+        let mut sink = OutputSink::new(dir.clone(), OutputOptions::default());
+        let count = process_reader(
+            rtf.as_bytes(),
+            Path::new("memo.rtf"),
+            "memo",
+            &mut sink,
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(std::fs::read(dir.join("memo.wmf")).unwrap(), wmf);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_info_metadata() {
+        let rtf =
+            br"{\rtf1{\info{\title Caf\'e9 \u8364? menu}{\author Jo}{\subject}}\titlepg text}";
+        let metadata = info_metadata(rtf);
+        assert_eq!(metadata.title.as_deref(), Some("Café € menu"));
+        assert_eq!(metadata.author.as_deref(), Some("Jo"));
+        assert_eq!(metadata.subject, None);
+    }
+}
@@ -0,0 +1,134 @@
+//! Image format detection from file content (magic bytes)
+
+use std::io::{self, Read};
+
+use crate::common::{ExtraFormat, extra_format_extension};
+
+/// Number of leading bytes `sniff_image_format` looks at
+pub const SNIFF_LEN: u64 = 1024;
+
+/// Detects the image format of `data` from its leading bytes.
+/// Returns the canonical extension (`jpg`, `tiff`, ...) or None if unrecognised.
+pub fn sniff_image_format(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("jpg")
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if data.starts_with(b"BM") && data.len() >= 14 {
+        Some("bmp")
+    } else if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+        Some("tiff")
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some("webp")
+    } else if data.starts_with(&[0x00, 0x00, 0x01, 0x00]) {
+        Some("ico")
+    } else if data.len() >= 44
+        && data.starts_with(&[0x01, 0x00, 0x00, 0x00])
+        && &data[40..44] == b" EMF"
+    {
+        Some("emf")
+    } else if data.starts_with(&[0xD7, 0xCD, 0xC6, 0x9A])
+        || data.starts_with(&[0x01, 0x00, 0x09, 0x00])
+        || data.starts_with(&[0x02, 0x00, 0x09, 0x00])
+    {
+        Some("wmf")
+    } else if looks_like_svg(data) {
+        Some("svg")
+    } else {
+        None
+    }
+}
+
+/// SVG is text, so look for an `<svg` element near the start of the document
+fn looks_like_svg(data: &[u8]) -> bool {
+    let head = &data[..data.len().min(1024)];
+    let text = String::from_utf8_lossy(head).to_lowercase();
+    let trimmed = text.trim_start_matches('\u{feff}').trim_start();
+    trimmed.starts_with('<') && text.contains("<svg")
+}
+
+/// Maps an extension to its canonical form (`jpeg` -> `jpg`, `tif` -> `tiff`)
+pub fn canonical_extension(ext: &str) -> String {
+    match ext.to_lowercase().as_str() {
+        "jpeg" | "jpe" => "jpg".to_string(),
+        "tif" => "tiff".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Returns the sniffed format if it differs from the declared extension.
+/// Content that cannot be identified at all counts as a mismatch (`Some("unknown")`),
+/// unless the declared extension is one of the `extra` formats.
+pub fn format_mismatch(
+    declared_ext: &str,
+    data: &[u8],
+    extra: &[ExtraFormat],
+) -> Option<&'static str> {
+    match sniff_image_format(data) {
+        Some(actual) if actual == canonical_extension(declared_ext) => None,
+        Some(actual) => Some(actual),
+        // Extra formats have no signature here, so their content is taken on trust
+        None if extra_format_extension(extra, declared_ext).is_some() => None,
+        None => Some("unknown"),
+    }
+}
+
+/// Picks the extension to extract an image as: the sniffed format when the content is a
+/// different format than declared, otherwise the declared extension (so `jpeg` stays `jpeg`
+/// and content that cannot be identified keeps its name)
+pub fn detect_extension(declared: &str, head: &[u8], extra: &[ExtraFormat]) -> String {
+    match format_mismatch(declared, head, extra) {
+        Some(actual) if actual != "unknown" => actual.to_string(),
+        _ => declared.to_lowercase(),
+    }
+}
+
+/// Reads the first `SNIFF_LEN` bytes of `reader`, or all of it if shorter
+pub fn read_head(reader: &mut dyn Read) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    reader.take(SNIFF_LEN).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_image_format() {
+        assert_eq!(
+            sniff_image_format(b"\xFF\xD8\xFF\xE0\0\x10JFIF"),
+            Some("jpg")
+        );
+        assert_eq!(sniff_image_format(b"\x89PNG\r\n\x1a\n\0\0"), Some("png"));
+        assert_eq!(sniff_image_format(b"GIF89a\x01\0"), Some("gif"));
+        assert_eq!(sniff_image_format(b"RIFF\0\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(
+            sniff_image_format(
+                b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>"
+            ),
+            Some("svg")
+        );
+        assert_eq!(sniff_image_format(b"hello world"), None);
+    }
+
+    #[test]
+    fn test_format_mismatch() {
+        let jpeg = b"\xFF\xD8\xFF\xE0\0\x10JFIF";
+        assert_eq!(format_mismatch("jpeg", jpeg, &[]), None);
+        assert_eq!(format_mismatch("JPG", jpeg, &[]), None);
+        assert_eq!(format_mismatch("png", jpeg, &[]), Some("jpg"));
+        assert_eq!(format_mismatch("gif", b"garbage", &[]), Some("unknown"));
+        assert_eq!(format_mismatch("tif", b"II*\0\x08\0\0\0", &[]), None);
+    }
+
+    #[test]
+    fn test_detect_extension() {
+        let jpeg = b"\xFF\xD8\xFF\xE0\0\x10JFIF";
+        assert_eq!(detect_extension("png", jpeg, &[]), "jpg");
+        assert_eq!(detect_extension("JPEG", jpeg, &[]), "jpeg");
+        assert_eq!(detect_extension("jp2", b"\0\0\0\x0cjP  ", &[]), "jp2");
+    }
+}
@@ -0,0 +1,160 @@
+//! Removal of embedded metadata from extracted images (`--strip-metadata`)
+//!
+//! Metadata segments and chunks are dropped from the file structure without decoding the
+//! image, so the pixels are untouched. EXIF (camera settings, GPS position), XMP, IPTC,
+//! ICC profiles and text comments are removed from JPEG, PNG and WebP images.
+
+use crate::sniff::sniff_image_format;
+
+/// Returns `data` without its metadata, or None if the image has none to remove or is
+/// not a JPEG, PNG or WebP
+pub fn strip_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    let stripped = match sniff_image_format(data)? {
+        "jpg" => strip_jpeg(data),
+        "png" => strip_png(data),
+        "webp" => strip_webp(data),
+        _ => None,
+    }?;
+    (stripped.len() != data.len()).then_some(stripped)
+}
+
+/// Drops APP1 (EXIF, XMP), APP2 (ICC), APP13 (IPTC) and COM segments before the image data
+fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = data[..2].to_vec();
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        if marker == 0xFF {
+            // Fill byte
+            pos += 1;
+            continue;
+        }
+        if marker == 0xDA || marker == 0xD9 {
+            // Start of scan: the rest is entropy-coded data
+            out.extend_from_slice(&data[pos..]);
+            return Some(out);
+        }
+        let length = u16::from_be_bytes(data.get(pos + 2..pos + 4)?.try_into().ok()?) as usize;
+        let end = pos + 2 + length;
+        let segment = data.get(pos..end)?;
+        if !matches!(marker, 0xE1 | 0xE2 | 0xED | 0xFE) {
+            out.extend_from_slice(segment);
+        }
+        pos = end;
+    }
+}
+
+/// Drops the eXIf, iCCP, tEXt, zTXt, iTXt (which holds XMP) and tIME chunks
+fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = data[..8].to_vec();
+    let mut pos = 8;
+    while pos < data.len() {
+        let length = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let end = pos.checked_add(12)?.checked_add(length)?;
+        let chunk = data.get(pos..end)?;
+        if !matches!(
+            &chunk[4..8],
+            b"eXIf" | b"iCCP" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME"
+        ) {
+            out.extend_from_slice(chunk);
+        }
+        pos = end;
+        if &chunk[4..8] == b"IEND" {
+            break;
+        }
+    }
+    Some(out)
+}
+
+/// Drops the EXIF, XMP and ICCP chunks and clears their flags in the VP8X header
+fn strip_webp(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = data[..12].to_vec();
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let length = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        // Chunks are padded to an even length
+        let end = (pos + 8)
+            .checked_add(length + (length & 1))?
+            .min(data.len());
+        let chunk = data.get(pos..end)?;
+        match &chunk[..4] {
+            b"EXIF" | b"XMP " | b"ICCP" => {}
+            b"VP8X" => {
+                let mut chunk = chunk.to_vec();
+                if let Some(flags) = chunk.get_mut(8) {
+                    *flags &= !(0x20 | 0x08 | 0x04);
+                }
+                out.extend_from_slice(&chunk);
+            }
+            _ => out.extend_from_slice(chunk),
+        }
+        pos = end;
+    }
+    let riff_size = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_jpeg() {
+        let mut jpeg = b"\xFF\xD8".to_vec();
+        jpeg.extend_from_slice(b"\xFF\xE0\x00\x06JFIF");
+        jpeg.extend_from_slice(b"\xFF\xE1\x00\x08Exif\0\0");
+        jpeg.extend_from_slice(b"\xFF\xFE\x00\x04hi");
+        jpeg.extend_from_slice(b"\xFF\xDA\x00\x02\xFF\xE1scan\xFF\xD9");
+
+        let stripped = strip_metadata(&jpeg).unwrap();
+        assert_eq!(
+            stripped,
+            b"\xFF\xD8\xFF\xE0\x00\x06JFIF\xFF\xDA\x00\x02\xFF\xE1scan\xFF\xD9"
+        );
+        assert_eq!(strip_metadata(&stripped), None);
+    }
+
+    #[test]
+    fn test_strip_png() {
+        let chunk = |kind: &[u8], body: &[u8]| {
+            let mut chunk = (body.len() as u32).to_be_bytes().to_vec();
+            chunk.extend_from_slice(kind);
+            chunk.extend_from_slice(body);
+            chunk.extend_from_slice(&[0; 4]);
+            chunk
+        };
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(chunk(b"IHDR", &[0; 13]));
+        png.extend(chunk(b"tEXt", b"Author\0me"));
+        png.extend(chunk(b"IDAT", b"pixels"));
+        png.extend(chunk(b"IEND", b""));
+
+        let mut expected = b"\x89PNG\r\n\x1a\n".to_vec();
+        expected.extend(chunk(b"IHDR", &[0; 13]));
+        expected.extend(chunk(b"IDAT", b"pixels"));
+        expected.extend(chunk(b"IEND", b""));
+        assert_eq!(strip_metadata(&png), Some(expected));
+    }
+
+    #[test]
+    fn test_strip_webp() {
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend_from_slice(b"VP8X\x0a\0\0\0\x2C\0\0\0\0\0\0\0\0\0");
+        webp.extend_from_slice(b"VP8L\x01\0\0\0\x2F\0");
+        webp.extend_from_slice(b"EXIF\x03\0\0\0abc\0");
+        let size = (webp.len() - 8) as u32;
+        webp[4..8].copy_from_slice(&size.to_le_bytes());
+
+        let stripped = strip_metadata(&webp).unwrap();
+        assert_eq!(stripped.len(), webp.len() - 12);
+        assert_eq!(stripped[20], 0);
+        assert_eq!(
+            u32::from_le_bytes(stripped[4..8].try_into().unwrap()) as usize,
+            stripped.len() - 8
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Time spent per phase of extraction, reported by `--timings`
+
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::json::Json;
+use crate::report::DocumentResult;
+
+/// Starts timing a phase, for `OutputSink::record_time`. Nothing is timed on wasm32,
+/// where there is no clock and `Instant::now` panics.
+pub fn start() -> Option<Instant> {
+    if cfg!(target_arch = "wasm32") {
+        None
+    } else {
+        Some(Instant::now())
+    }
+}
+
+/// Number of slowest documents listed
+const SLOWEST_DOCUMENTS: usize = 10;
+
+/// A phase of processing a document
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    /// Opening the document's container, e.g. reading a zip's central directory
+    Open,
+    /// Finding the images: listing entries, parsing metadata, layout and references
+    Scan,
+    /// Reading image data out of the document
+    Read,
+    /// Converting, stripping and thumbnailing images
+    Convert,
+    /// Writing output files and archive entries
+    Write,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 5] = [
+        Phase::Open,
+        Phase::Scan,
+        Phase::Read,
+        Phase::Convert,
+        Phase::Write,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Open => "open",
+            Phase::Scan => "scan",
+            Phase::Read => "read",
+            Phase::Convert => "convert",
+            Phase::Write => "write",
+        }
+    }
+}
+
+/// Time spent in each phase. Formats that are not instrumented, and the parsing work
+/// outside the phases, only show in a document's total time.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PhaseTimes([Duration; 5]);
+
+impl PhaseTimes {
+    pub fn add(&mut self, phase: Phase, duration: Duration) {
+        self.0[phase as usize] += duration;
+    }
+
+    pub fn get(&self, phase: Phase) -> Duration {
+        self.0[phase as usize]
+    }
+
+    /// Adds all phases of `other`
+    pub fn merge(&mut self, other: &PhaseTimes) {
+        for phase in Phase::ALL {
+            self.add(phase, other.get(phase));
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.0.iter().sum()
+    }
+}
+
+/// Breakdown of where a run spent its time, printed by `--timings`
+#[derive(Debug, Default)]
+pub struct Timings {
+    /// Phase times summed over all documents
+    pub phases: PhaseTimes,
+    /// Time spent on documents outside the phases (parsing, other formats)
+    pub other: Duration,
+    /// Every document with its total time and phases, slowest first
+    pub documents: Vec<(PathBuf, Duration, PhaseTimes)>,
+    pub elapsed: Duration,
+}
+
+impl Timings {
+    pub fn new(results: &[DocumentResult], elapsed: Duration) -> Self {
+        let mut timings = Timings {
+            elapsed,
+            ..Default::default()
+        };
+        for result in results {
+            timings.phases.merge(&result.timings);
+            timings.other += result.elapsed.saturating_sub(result.timings.total());
+            timings
+                .documents
+                .push((result.path.clone(), result.elapsed, result.timings));
+        }
+        timings
+            .documents
+            .sort_by_key(|(_, elapsed, _)| std::cmp::Reverse(*elapsed));
+        timings
+    }
+
+    /// Renders the timings as indented text for the terminal
+    pub fn render(&self) -> String {
+        let document_time = self.phases.total() + self.other;
+        let mut text = String::from("Timings:\n  Phases (all documents):\n");
+        let phases = Phase::ALL
+            .iter()
+            .map(|phase| (phase.name(), self.phases.get(*phase)))
+            .chain([("other", self.other)]);
+        for (name, duration) in phases {
+            let share = if document_time.is_zero() {
+                0.0
+            } else {
+                100.0 * duration.as_secs_f64() / document_time.as_secs_f64()
+            };
+            let _ = writeln!(
+                text,
+                "    {:<8} {:>9.3}s {:>5.1}%",
+                name,
+                duration.as_secs_f64(),
+                share
+            );
+        }
+        if !self.documents.is_empty() {
+            let _ = writeln!(text, "  Slowest documents (of {}):", self.documents.len());
+            for (path, elapsed, phases) in self.documents.iter().take(SLOWEST_DOCUMENTS) {
+                let breakdown: Vec<String> = Phase::ALL
+                    .iter()
+                    .filter(|phase| !phases.get(**phase).is_zero())
+                    .map(|phase| {
+                        format!("{} {:.3}s", phase.name(), phases.get(*phase).as_secs_f64())
+                    })
+                    .collect();
+                let _ = write!(
+                    text,
+                    "    {:>8.3}s  {}",
+                    elapsed.as_secs_f64(),
+                    path.display()
+                );
+                if !breakdown.is_empty() {
+                    let _ = write!(text, " ({})", breakdown.join(", "));
+                }
+                text.push('\n');
+            }
+        }
+        let _ = writeln!(text, "  Elapsed: {:.2}s", self.elapsed.as_secs_f64());
+        text
+    }
+
+    pub fn to_json(&self) -> Json {
+        let phases = |times: &PhaseTimes| {
+            Json::object(Phase::ALL.iter().map(|phase| {
+                (
+                    phase.name(),
+                    Json::from(times.get(*phase).as_millis() as u64),
+                )
+            }))
+        };
+        Json::object([
+            ("phases_ms", phases(&self.phases)),
+            ("other_ms", Json::from(self.other.as_millis() as u64)),
+            (
+                "documents",
+                Json::Array(
+                    self.documents
+                        .iter()
+                        .map(|(path, elapsed, times)| {
+                            Json::object([
+                                ("path", Json::from(path.display().to_string())),
+                                ("elapsed_ms", Json::from(elapsed.as_millis() as u64)),
+                                ("phases_ms", phases(times)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+            ("elapsed_ms", Json::from(self.elapsed.as_millis() as u64)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings() {
+        let mut times = PhaseTimes::default();
+        times.add(Phase::Open, Duration::from_millis(100));
+        times.add(Phase::Write, Duration::from_millis(300));
+        let results = vec![
+            DocumentResult {
+                path: PathBuf::from("quick.html"),
+                elapsed: Duration::from_millis(50),
+                ..Default::default()
+            },
+            DocumentResult {
+                path: PathBuf::from("big.docx"),
+                elapsed: Duration::from_millis(600),
+                timings: times,
+                ..Default::default()
+            },
+        ];
+        let timings = Timings::new(&results, Duration::from_secs(1));
+        assert_eq!(timings.phases.get(Phase::Write), Duration::from_millis(300));
+        assert_eq!(timings.other, Duration::from_millis(250));
+        assert_eq!(timings.documents[0].0, PathBuf::from("big.docx"));
+
+        let text = timings.render();
+        assert!(text.contains("    write        0.300s  46.2%\n"));
+        assert!(text.contains("  Slowest documents (of 2):\n"));
+        assert!(text.contains("       0.600s  big.docx (open 0.100s, write 0.300s)\n"));
+        assert!(text.contains("       0.050s  quick.html\n"));
+        let json = timings.to_json().to_pretty_string();
+        assert!(json.contains("\"other_ms\": 250"));
+    }
+}
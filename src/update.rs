@@ -0,0 +1,207 @@
+//! `self-update`: replaces the running binary with the latest release build.
+//!
+//! Downloads go through the system `curl` (shipped with Windows 10+, macOS and most
+//! Linux distributions) rather than bundling an HTTP and TLS stack. Release binaries
+//! are named `word-image-extractor-{arch}-{os}[.exe]` and listed with their SHA-256 in
+//! the release's `SHA256SUMS` file, which every download is checked against. The
+//! checksums come from the same release as the binary, so they catch corrupted or
+//! truncated downloads but not a tampered release: releases are not signed, and no
+//! signature is checked.
+
+use anyhow::{Context, Result};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::common::{sha256, to_hex};
+
+/// Release page of the project; `/latest` redirects to the newest tag
+const RELEASES_URL: &str = "https://github.com/evildarkarchon/Word-GIF-Extractor/releases";
+/// Checksum file published with every release
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+
+/// Name of the release asset built for this platform
+pub fn asset_name() -> String {
+    let suffix = if cfg!(windows) { ".exe" } else { "" };
+    format!(
+        "word-image-extractor-{}-{}{}",
+        env::consts::ARCH,
+        env::consts::OS,
+        suffix
+    )
+}
+
+/// Parses a `1.2.3` (optionally `v`-prefixed) version for comparison
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.trim().trim_start_matches('v').splitn(3, '.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    // Ignore pre-release and build suffixes such as `-rc1`
+    let patch = parts
+        .next()?
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+    Some((major, minor, patch))
+}
+
+/// Extracts the tag from the URL `/releases/latest` redirects to
+fn tag_from_url(url: &str) -> Option<&str> {
+    let tag = url.trim().rsplit_once("/tag/")?.1;
+    (!tag.is_empty()).then_some(tag)
+}
+
+/// Finds the expected checksum of `asset` in a `sha256sum`-style listing
+fn expected_checksum<'a>(sums: &'a str, asset: &str) -> Option<&'a str> {
+    sums.lines().find_map(|line| {
+        let (hash, name) = line.trim().split_once(char::is_whitespace)?;
+        // `sha256sum -b` marks binary files with a leading `*`
+        (name.trim().trim_start_matches('*') == asset).then_some(hash)
+    })
+}
+
+/// Runs curl with the given arguments, failing on HTTP errors
+fn curl(args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        .args(args)
+        .output()
+        .context("Failed to run curl; it is required for self-update")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Download failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Looks up the tag of the latest release
+fn latest_tag() -> Result<String> {
+    let latest = format!("{}/latest", RELEASES_URL);
+    let effective = curl(&[
+        "--output",
+        if cfg!(windows) { "NUL" } else { "/dev/null" },
+        "--write-out",
+        "%{url_effective}",
+        &latest,
+    ])?;
+    let effective = String::from_utf8_lossy(&effective);
+    tag_from_url(&effective)
+        .map(str::to_string)
+        .with_context(|| format!("Could not find a release tag in {}", effective))
+}
+
+/// Moves the checked binary at `new_path` over the running executable at `exe`
+fn replace_executable(exe: &Path, new_path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(exe).map_or(0o755, |m| m.permissions().mode());
+        fs::set_permissions(new_path, fs::Permissions::from_mode(mode))?;
+    }
+    // A running executable can't be overwritten on Windows, but it can be renamed
+    if cfg!(windows) {
+        let old_path = exe.with_extension("old.exe");
+        let _ = fs::remove_file(&old_path);
+        fs::rename(exe, &old_path)
+            .with_context(|| format!("Failed to move aside {}", exe.display()))?;
+        if let Err(e) = fs::rename(new_path, exe) {
+            // Put the old binary back rather than leave no executable at `exe`
+            let _ = fs::rename(&old_path, exe);
+            return Err(e).with_context(|| format!("Failed to replace {}", exe.display()));
+        }
+        return Ok(());
+    }
+    fs::rename(new_path, exe).with_context(|| format!("Failed to replace {}", exe.display()))
+}
+
+/// Checks for a newer release and, unless `check_only`, installs it over the running binary
+pub fn self_update(check_only: bool, force: bool) -> Result<()> {
+    let current = env!("CARGO_PKG_VERSION");
+    let tag = latest_tag()?;
+    let newer = match (parse_version(&tag), parse_version(current)) {
+        (Some(latest), Some(installed)) => latest > installed,
+        _ => anyhow::bail!("Could not compare release '{}' with {}", tag, current),
+    };
+
+    if !newer && !force {
+        println!("word-image-extractor {} is up to date.", current);
+        return Ok(());
+    }
+    println!("Latest release: {} (installed: {})", tag, current);
+    if check_only {
+        return Ok(());
+    }
+
+    let asset = asset_name();
+    let download = |name: &str| curl(&[&format!("{}/download/{}/{}", RELEASES_URL, tag, name)]);
+    let sums = download(CHECKSUMS_ASSET)?;
+    let sums = String::from_utf8_lossy(&sums);
+    let expected = expected_checksum(&sums, &asset)
+        .with_context(|| format!("Release {} has no build named {}", tag, asset))?;
+
+    println!("Downloading {}...", asset);
+    let binary = download(&asset)?;
+    let actual = to_hex(&sha256(&binary));
+    if !actual.eq_ignore_ascii_case(expected) {
+        anyhow::bail!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            asset,
+            expected,
+            actual
+        );
+    }
+
+    let exe = env::current_exe().context("Could not locate the running executable")?;
+    let mut new_name = exe.as_os_str().to_owned();
+    new_name.push(".new");
+    let new_path = PathBuf::from(new_name);
+    fs::write(&new_path, &binary)
+        .with_context(|| format!("Failed to write {}", new_path.display()))?;
+    if let Err(e) = replace_executable(&exe, &new_path) {
+        let _ = fs::remove_file(&new_path);
+        return Err(e);
+    }
+
+    println!("Updated {} to {}.", exe.display(), tag);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("v0.4.0"), Some((0, 4, 0)));
+        assert_eq!(parse_version("1.2.3-rc1"), Some((1, 2, 3)));
+        assert!(parse_version("v0.10.0") > parse_version("0.9.9"));
+        assert_eq!(parse_version("latest"), None);
+    }
+
+    #[test]
+    fn test_tag_from_url() {
+        assert_eq!(
+            tag_from_url("https://github.com/o/r/releases/tag/v0.4.0\n"),
+            Some("v0.4.0")
+        );
+        assert_eq!(tag_from_url("https://github.com/o/r/releases"), None);
+    }
+
+    #[test]
+    fn test_expected_checksum() {
+        let sums = "abc123  word-image-extractor-x86_64-linux\n\
+                    def456 *word-image-extractor-x86_64-windows.exe\n";
+        assert_eq!(
+            expected_checksum(sums, "word-image-extractor-x86_64-windows.exe"),
+            Some("def456")
+        );
+        assert_eq!(
+            expected_checksum(sums, "word-image-extractor-arm-linux"),
+            None
+        );
+    }
+}
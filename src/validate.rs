@@ -0,0 +1,537 @@
+//! Pre-flight checks that report whether documents can be extracted, without writing anything
+
+use epub::doc::EpubDoc;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+use crate::cfb::CFB_SIGNATURE;
+use crate::common::{get_supported_extensions, is_safe_archive_path};
+use crate::doc;
+use crate::extract::{DocumentType, SUPPORTED_DOCUMENTS, get_document_type};
+use crate::fb2;
+use crate::html;
+use crate::json::Json;
+use crate::mobi;
+use crate::obfuscation::{obfuscation_for, parse_encryption_xml};
+use crate::odf;
+use crate::pdf;
+use crate::rtf;
+
+/// Kind of problem found in a document
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IssueKind {
+    Missing,
+    Unsupported,
+    Encrypted,
+    Drm,
+    Corrupt,
+    UnsafePath,
+    NoImages,
+}
+
+impl IssueKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            IssueKind::Missing => "missing",
+            IssueKind::Unsupported => "unsupported",
+            IssueKind::Encrypted => "encrypted",
+            IssueKind::Drm => "drm",
+            IssueKind::Corrupt => "corrupt",
+            IssueKind::UnsafePath => "unsafe-path",
+            IssueKind::NoImages => "no-images",
+        }
+    }
+
+    /// Whether the issue prevents (some) images from being extracted.
+    /// A document without images is valid, just not useful.
+    pub fn is_error(self) -> bool {
+        self != IssueKind::NoImages
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub detail: String,
+}
+
+/// Result of validating one document
+#[derive(Debug)]
+pub struct Validation {
+    pub path: PathBuf,
+    pub document_type: Option<DocumentType>,
+    /// Number of images that would be extracted with the default formats
+    pub images: usize,
+    pub issues: Vec<Issue>,
+}
+
+impl Validation {
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(|i| i.kind.is_error())
+    }
+
+    fn push(&mut self, kind: IssueKind, detail: impl Into<String>) {
+        self.issues.push(Issue {
+            kind,
+            detail: detail.into(),
+        });
+    }
+
+    pub fn to_json(&self) -> Json {
+        let document_type = self.document_type.map(DocumentType::name);
+        Json::object([
+            ("path", Json::from(self.path.display().to_string())),
+            ("type", Json::from(document_type)),
+            ("images", Json::from(self.images)),
+            ("ok", Json::from(!self.has_errors())),
+            (
+                "issues",
+                Json::Array(
+                    self.issues
+                        .iter()
+                        .map(|i| {
+                            Json::object([
+                                ("kind", Json::from(i.kind.name())),
+                                (
+                                    "severity",
+                                    Json::from(if i.kind.is_error() {
+                                        "error"
+                                    } else {
+                                        "warning"
+                                    }),
+                                ),
+                                ("detail", Json::from(i.detail.as_str())),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+}
+
+/// Checks a single document for problems that would stop or limit extraction
+pub fn validate_document(path: &Path) -> Validation {
+    let mut validation = Validation {
+        path: path.to_path_buf(),
+        document_type: get_document_type(path),
+        images: 0,
+        issues: Vec::new(),
+    };
+
+    if !path.is_file() {
+        validation.push(IssueKind::Missing, "file does not exist");
+        return validation;
+    }
+
+    let Some(document_type) = validation.document_type else {
+        validation.push(
+            IssueKind::Unsupported,
+            format!("not a supported document type ({})", SUPPORTED_DOCUMENTS),
+        );
+        return validation;
+    };
+
+    let check: Option<fn(&mut Validation)> = match document_type {
+        DocumentType::Doc => Some(check_doc),
+        DocumentType::Mobi => Some(check_mobi),
+        DocumentType::Fb2 => Some(check_fb2),
+        DocumentType::Rtf => Some(check_rtf),
+        DocumentType::Html => Some(check_html),
+        DocumentType::Pdf => Some(check_pdf),
+        _ => None,
+    };
+    if let Some(check) = check {
+        check(&mut validation);
+        flag_no_images(&mut validation);
+        return validation;
+    }
+
+    let mut header = [0u8; 8];
+    let header_read = fs::File::open(path).and_then(|mut f| f.read_exact(&mut header));
+    if let Err(e) = header_read {
+        validation.push(IssueKind::Corrupt, format!("cannot read file: {}", e));
+        return validation;
+    }
+    if header == CFB_SIGNATURE {
+        validation.push(
+            IssueKind::Encrypted,
+            "password-protected (stored as an encrypted OLE container; extract with --password)",
+        );
+        return validation;
+    }
+
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            validation.push(IssueKind::Corrupt, format!("cannot open file: {}", e));
+            return validation;
+        }
+    };
+    let mut archive = match ZipArchive::new(file) {
+        Ok(archive) => archive,
+        Err(e) => {
+            validation.push(
+                IssueKind::Corrupt,
+                format!("not a valid zip archive: {}", e),
+            );
+            return validation;
+        }
+    };
+
+    let allowed = get_supported_extensions(&[]);
+    let mut encryption_xml = None;
+    let mut has_rights_xml = false;
+    let mut zip_images = 0;
+    for i in 0..archive.len() {
+        let (name, encrypted) = match archive.by_index_raw(i) {
+            Ok(entry) => (entry.name().to_string(), entry.encrypted()),
+            Err(e) => {
+                validation.push(IssueKind::Corrupt, format!("entry #{}: {}", i, e));
+                continue;
+            }
+        };
+
+        if !is_safe_archive_path(&name) {
+            validation.push(IssueKind::UnsafePath, format!("entry '{}'", name));
+            continue;
+        }
+        if encrypted {
+            validation.push(
+                IssueKind::Encrypted,
+                format!("entry '{}' is password-protected", name),
+            );
+            continue;
+        }
+
+        // Reading every entry verifies its CRC
+        let mut data = Vec::new();
+        let read = archive
+            .by_index(i)
+            .map_err(io::Error::other)
+            .and_then(|mut entry| {
+                if name == "META-INF/encryption.xml" {
+                    entry.read_to_end(&mut data).map(|_| ())
+                } else {
+                    io::copy(&mut entry, &mut io::sink()).map(|_| ())
+                }
+            });
+        if let Err(e) = read {
+            validation.push(IssueKind::Corrupt, format!("entry '{}': {}", name, e));
+            continue;
+        }
+
+        match name.as_str() {
+            "META-INF/encryption.xml" => encryption_xml = Some(data),
+            "META-INF/rights.xml" => has_rights_xml = true,
+            _ => {}
+        }
+        let is_image = Path::new(&name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| allowed.contains(e.to_lowercase().as_str()))
+            && (document_type != DocumentType::Odf || odf::is_picture(&name));
+        if is_image {
+            zip_images += 1;
+        }
+    }
+
+    validation.images = match document_type {
+        DocumentType::Docx | DocumentType::Pptx | DocumentType::Xlsx | DocumentType::Odf => {
+            zip_images
+        }
+        DocumentType::Epub => {
+            check_epub_drm(&mut validation, encryption_xml.as_deref(), has_rights_xml);
+            match EpubDoc::new(path) {
+                Ok(doc) => doc
+                    .resources
+                    .values()
+                    .filter(|item| item.mime.starts_with("image/"))
+                    .count(),
+                Err(e) => {
+                    validation.push(IssueKind::Corrupt, format!("invalid EPUB package: {}", e));
+                    0
+                }
+            }
+        }
+        DocumentType::Doc
+        | DocumentType::Mobi
+        | DocumentType::Fb2
+        | DocumentType::Rtf
+        | DocumentType::Html
+        | DocumentType::Pdf => {
+            unreachable!("checked before reading the archive")
+        }
+    };
+
+    flag_no_images(&mut validation);
+    validation
+}
+
+fn flag_no_images(validation: &mut Validation) {
+    if validation.images == 0 && !validation.has_errors() {
+        validation.push(IssueKind::NoImages, "no extractable images");
+    }
+}
+
+/// Counts the pictures in a legacy Word file, flagging encrypted or unreadable files
+fn check_doc(validation: &mut Validation) {
+    let data = match fs::read(&validation.path) {
+        Ok(data) => data,
+        Err(e) => {
+            validation.push(IssueKind::Corrupt, format!("cannot read file: {}", e));
+            return;
+        }
+    };
+    match doc::collect_images(&data, &validation.path) {
+        Ok(images) => validation.images = images.len(),
+        Err(e) if e.to_string().contains("password-protected") => {
+            validation.push(IssueKind::Encrypted, "password-protected Word document");
+        }
+        Err(e) => validation.push(IssueKind::Corrupt, format!("{:#}", e)),
+    }
+}
+
+/// Counts the images in a Kindle book, flagging DRM
+fn check_mobi(validation: &mut Validation) {
+    let data = match fs::read(&validation.path) {
+        Ok(data) => data,
+        Err(e) => {
+            validation.push(IssueKind::Corrupt, format!("cannot read file: {}", e));
+            return;
+        }
+    };
+    match mobi::parse(&data) {
+        Ok(book) => {
+            if book.encrypted {
+                validation.push(IssueKind::Drm, "book text is DRM-encrypted");
+            }
+            validation.images = book.images.len();
+        }
+        Err(e) => validation.push(IssueKind::Corrupt, format!("invalid MOBI book: {:#}", e)),
+    }
+}
+
+/// Counts the images in a FictionBook, flagging binaries that fail to decode
+fn check_fb2(validation: &mut Validation) {
+    let data = match fs::read(&validation.path) {
+        Ok(data) => data,
+        Err(e) => {
+            validation.push(IssueKind::Corrupt, format!("cannot read file: {}", e));
+            return;
+        }
+    };
+    match fb2::parse(&data, &[]) {
+        Ok(book) => {
+            for id in &book.corrupt {
+                validation.push(
+                    IssueKind::Corrupt,
+                    format!("binary '{}' is not valid base64", id),
+                );
+            }
+            validation.images = book.images.len();
+        }
+        Err(e) => validation.push(IssueKind::Corrupt, format!("invalid FB2 book: {:#}", e)),
+    }
+}
+
+/// Counts the pictures in an RTF document
+fn check_rtf(validation: &mut Validation) {
+    let data = match fs::read(&validation.path) {
+        Ok(data) => data,
+        Err(e) => {
+            validation.push(IssueKind::Corrupt, format!("cannot read file: {}", e));
+            return;
+        }
+    };
+    match rtf::collect_images(&data, &validation.path) {
+        Ok(images) => validation.images = images.len(),
+        Err(e) => validation.push(IssueKind::Corrupt, format!("{:#}", e)),
+    }
+}
+
+/// Counts the images embedded in a saved web page
+fn check_html(validation: &mut Validation) {
+    match fs::read(&validation.path) {
+        Ok(data) => validation.images = html::collect_images(&data, &[]).len(),
+        Err(e) => validation.push(IssueKind::Corrupt, format!("cannot read file: {}", e)),
+    }
+}
+
+/// Counts the images a PDF would yield, flagging encrypted or unreadable files
+fn check_pdf(validation: &mut Validation) {
+    let doc = match fs::read(&validation.path).map(|data| lopdf::Document::load_mem(&data)) {
+        Ok(Ok(doc)) if !doc.is_encrypted() => doc,
+        Ok(Ok(_) | Err(lopdf::Error::InvalidPassword | lopdf::Error::Decryption(_))) => {
+            validation.push(IssueKind::Encrypted, "password-protected PDF");
+            return;
+        }
+        Ok(Err(e)) => {
+            validation.push(IssueKind::Corrupt, format!("invalid PDF: {}", e));
+            return;
+        }
+        Err(e) => {
+            validation.push(IssueKind::Corrupt, format!("cannot read file: {}", e));
+            return;
+        }
+    };
+    validation.images = pdf::page_images(&doc)
+        .iter()
+        .filter(|image| {
+            doc.get_object(image.id)
+                .and_then(lopdf::Object::as_stream)
+                .is_ok_and(|stream| pdf::image_extension(stream).is_some())
+        })
+        .count();
+}
+
+/// Flags resources encrypted with anything other than font obfuscation
+fn check_epub_drm(validation: &mut Validation, encryption_xml: Option<&[u8]>, rights_xml: bool) {
+    if rights_xml {
+        validation.push(IssueKind::Drm, "META-INF/rights.xml present (Adobe ADEPT)");
+    }
+    let Some(xml) = encryption_xml else {
+        return;
+    };
+    let mut encrypted: Vec<String> = parse_encryption_xml(&String::from_utf8_lossy(xml))
+        .into_iter()
+        .filter(|(_, algorithm)| obfuscation_for(algorithm).is_none())
+        .map(|(path, _)| path)
+        .collect();
+    if !encrypted.is_empty() {
+        encrypted.sort();
+        validation.push(
+            IssueKind::Drm,
+            format!(
+                "{} encrypted resource(s), e.g. '{}'",
+                encrypted.len(),
+                encrypted[0]
+            ),
+        );
+    }
+}
+
+/// Prints validation results as human-readable text
+pub fn print_text(results: &[Validation]) {
+    for result in results {
+        if result.issues.is_empty() {
+            println!(
+                "OK     {} ({} images)",
+                result.path.display(),
+                result.images
+            );
+            continue;
+        }
+        let label = if result.has_errors() { "ERROR" } else { "WARN" };
+        println!("{:<6} {}", label, result.path.display());
+        for issue in &result.issues {
+            println!("         {}: {}", issue.kind.name(), issue.detail);
+        }
+    }
+
+    let errors = results.iter().filter(|r| r.has_errors()).count();
+    println!(
+        "Validated {} document(s): {} with errors.",
+        results.len(),
+        errors
+    );
+}
+
+/// Renders validation results as a JSON document
+pub fn to_json(results: &[Validation]) -> Json {
+    let errors = results.iter().filter(|r| r.has_errors()).count();
+    Json::object([
+        (
+            "documents",
+            Json::Array(results.iter().map(Validation::to_json).collect()),
+        ),
+        ("total", Json::from(results.len())),
+        ("errors", Json::from(errors)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::{temp_dir, zip_bytes};
+
+    /// Writes `contents` to `name` in `dir`
+    fn write_file(dir: &Path, name: &str, contents: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn kinds(validation: &Validation) -> Vec<IssueKind> {
+        validation.issues.iter().map(|i| i.kind).collect()
+    }
+
+    #[test]
+    fn test_valid_docx() {
+        let dir = temp_dir("validate");
+        let docx = zip_bytes(&[("word/media/image1.png", b"png")]);
+        let validation = validate_document(&write_file(&dir, "ok.docx", &docx));
+        assert_eq!(validation.images, 1);
+        assert!(validation.issues.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_problem_documents() {
+        let missing = validate_document(Path::new("does-not-exist.docx"));
+        assert_eq!(kinds(&missing), vec![IssueKind::Missing]);
+
+        let dir = temp_dir("validate-problems");
+        let unsupported_path = write_file(&dir, "notes.txt", b"text");
+        assert_eq!(
+            kinds(&validate_document(&unsupported_path)),
+            vec![IssueKind::Unsupported]
+        );
+
+        let corrupt_path = write_file(&dir, "corrupt.docx", b"PK\x03\x04 truncated");
+        assert_eq!(
+            kinds(&validate_document(&corrupt_path)),
+            vec![IssueKind::Corrupt]
+        );
+
+        let mut cfb = CFB_SIGNATURE.to_vec();
+        cfb.extend_from_slice(&[0; 504]);
+        let encrypted_path = write_file(&dir, "encrypted.docx", &cfb);
+        assert_eq!(
+            kinds(&validate_document(&encrypted_path)),
+            vec![IssueKind::Encrypted]
+        );
+
+        let docx = zip_bytes(&[("word/document.xml", b"<w/>")]);
+        let empty_path = write_file(&dir, "empty.docx", &docx);
+        let empty = validate_document(&empty_path);
+        assert_eq!(kinds(&empty), vec![IssueKind::NoImages]);
+        assert!(!empty.has_errors());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_epub_drm_detection() {
+        let mut validation = Validation {
+            path: PathBuf::from("book.epub"),
+            document_type: Some(DocumentType::Epub),
+            images: 0,
+            issues: Vec::new(),
+        };
+        let xml = br#"<encryption xmlns:enc="http://www.w3.org/2001/04/xmlenc#">
+  <enc:EncryptedData>
+    <enc:EncryptionMethod Algorithm="http://www.idpf.org/2008/embedding"/>
+    <enc:CipherData><enc:CipherReference URI="font.otf"/></enc:CipherData>
+  </enc:EncryptedData>
+  <enc:EncryptedData>
+    <enc:EncryptionMethod Algorithm="http://www.w3.org/2001/04/xmlenc#aes128-cbc"/>
+    <enc:CipherData><enc:CipherReference URI="OEBPS/chapter1.xhtml"/></enc:CipherData>
+  </enc:EncryptedData>
+</encryption>"#;
+        check_epub_drm(&mut validation, Some(xml), false);
+        assert_eq!(kinds(&validation), vec![IssueKind::Drm]);
+        assert!(validation.issues[0].detail.contains("OEBPS/chapter1.xhtml"));
+    }
+}
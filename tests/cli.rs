@@ -0,0 +1,91 @@
+//! Runs the command line tool on small documents and checks what it reports
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// A fresh directory holding `page.html` with a single 1x1 GIF
+fn page_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "word-image-extractor-cli-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("page.html"),
+        "<img src=\"data:image/gif;base64,R0lGODlhAQABAA==\">",
+    )
+    .unwrap();
+    dir
+}
+
+fn run(dir: &PathBuf, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_word-image-extractor"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_filtered_images_are_not_counted() {
+    let dir = page_dir("filtered");
+    let output = run(&dir, &["page.html", "-o", "out", "--min-size", "1M"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(stdout.contains("No images found"), "{}", stdout);
+    // No images to extract
+    assert_eq!(output.status.code(), Some(3));
+    assert!(fs::read_dir(dir.join("out")).map_or(true, |mut d| d.next().is_none()));
+
+    let output = run(&dir, &["page.html", "-o", "out"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Extracted 1 images"));
+    assert_eq!(output.status.code(), Some(0));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_skipped_duplicates_and_existing_files_are_not_counted() {
+    let dir = page_dir("dedupe");
+    fs::copy(dir.join("page.html"), dir.join("copy.html")).unwrap();
+    let output = run(&dir, &["page.html", "copy.html", "-o", "out", "--dedupe"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Extracted 1 images"), "{}", stdout);
+    assert_eq!(fs::read_dir(dir.join("out")).unwrap().count(), 1);
+
+    let output = run(&dir, &["page.html", "-o", "out", "--on-conflict", "skip"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("No images found"), "{}", stdout);
+    assert!(
+        stdout.contains("Skipped 1 image(s) whose output already exists"),
+        "{}",
+        stdout
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    // Re-running into the same directory finds the identical file already there
+    let output = run(&dir, &["page.html", "-o", "out"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("whose output already exists"), "{}", stdout);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(fs::read_dir(dir.join("out")).unwrap().count(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_stdout_takes_a_single_only_position() {
+    let dir = page_dir("stdout");
+    let output = run(&dir, &["page.html", "--stdout", "--only", "1"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.starts_with(b"GIF89a"));
+
+    let output = run(&dir, &["page.html", "--stdout", "--only", "1-2"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("can only write one image"), "{}", stderr);
+
+    fs::remove_dir_all(&dir).unwrap();
+}